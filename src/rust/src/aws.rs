@@ -0,0 +1,584 @@
+//! AWS profile and assumed role credentials
+//!
+//! delta-rs resolves AWS credentials from storage options, the environment
+//! and the default provider chain, and only for AWS itself, not for stores
+//! given an endpoint. This module resolves them with the AWS SDK whenever a
+//! profile or a role to assume is given in the storage options, so that:
+//!
+//! - profiles of the shared config files work, including SSO profiles
+//!   signed in with `aws sso login` and profiles that assume roles;
+//! - a role can be assumed with an external id and session duration, on
+//!   top of the profile or the default credentials.
+//!
+//! Tables are opened through an `s3` object store factory that uses these
+//! credentials when their options are set and otherwise defers to delta-rs.
+//! Credentials are cached and fetched again five minutes before they expire.
+//!
+//! Server-side encryption options are checked whenever a store is built:
+//! object_store adds the encryption headers to every PUT, multipart upload
+//! and copy, so to writes, optimizes, checkpoints and commits alike, but
+//! silently drops a key given without an encryption type.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{BehaviorVersion, Region};
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use deltalake::aws::storage::{S3ObjectStoreFactory, S3StorageBackend};
+use deltalake::logstore::object_store::aws::{AmazonS3Builder, AmazonS3ConfigKey, AwsCredential};
+use deltalake::logstore::object_store::client::SpawnedReqwestConnector;
+use deltalake::logstore::object_store::Error as ObjectStoreError;
+use deltalake::logstore::object_store::{CredentialProvider, ObjectStoreScheme};
+use deltalake::logstore::{
+    object_store_factories, ObjectStoreFactory, ObjectStoreRef, StorageConfig,
+};
+use deltalake::{DeltaResult, DeltaTableError, Path};
+use url::Url;
+
+/// Storage options read by this module; the profile, role and session name
+/// are the options delta-rs reads
+pub(crate) const PROFILE: &str = "aws_profile";
+pub(crate) const ROLE_ARN: &str = "aws_iam_role_arn";
+pub(crate) const EXTERNAL_ID: &str = "aws_iam_role_external_id";
+pub(crate) const SESSION_NAME: &str = "aws_iam_role_session_name";
+pub(crate) const SESSION_DURATION: &str = "aws_iam_role_session_duration";
+
+/// Server-side encryption options, read by object_store
+pub(crate) const SSE: &str = "aws_server_side_encryption";
+pub(crate) const SSE_KMS_KEY_ID: &str = "aws_sse_kms_key_id";
+pub(crate) const SSE_BUCKET_KEY: &str = "aws_sse_bucket_key_enabled";
+pub(crate) const SSE_CUSTOMER_KEY: &str = "aws_sse_customer_key_base64";
+
+/// The server-side encryption types of S3
+const SSE_TYPES: [&str; 4] = ["AES256", "aws:kms", "aws:kms:dsse", "sse-c"];
+
+/// Shortest and longest session STS grants, in seconds
+pub(crate) const MIN_SESSION_DURATION: u64 = 900;
+pub(crate) const MAX_SESSION_DURATION: u64 = 43200;
+
+/// Region of the STS requests when neither the options nor the profile
+/// give one
+const DEFAULT_REGION: &str = "us-east-1";
+/// Credentials are refreshed when they expire in less than this
+const MIN_TTL: Duration = Duration::from_secs(300);
+
+/// Static key options, which would be ignored next to a profile; a role
+/// is assumed with them when they are given
+const ACCESS_KEY_ID: &str = "aws_access_key_id";
+const SECRET_ACCESS_KEY: &str = "aws_secret_access_key";
+const SESSION_TOKEN: &str = "aws_session_token";
+
+// ============================================================================
+// Settings
+// ============================================================================
+
+/// Look up an option regardless of its case
+pub(crate) fn option<'a>(options: &'a HashMap<String, String>, key: &str) -> Option<&'a String> {
+    options
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v)
+}
+
+fn invalid(message: String) -> DeltaTableError {
+    DeltaTableError::Generic(message)
+}
+
+/// Whether `arn` looks like the ARN of an IAM role, which STS otherwise
+/// reports as a bare validation error
+pub(crate) fn is_role_arn(arn: &str) -> bool {
+    let parts: Vec<&str> = arn.splitn(6, ':').collect();
+    parts.len() == 6
+        && parts[0] == "arn"
+        && parts[1].starts_with("aws")
+        && parts[2] == "iam"
+        && parts[4].len() == 12
+        && parts[4].chars().all(|c| c.is_ascii_digit())
+        && parts[5]
+            .strip_prefix("role/")
+            .is_some_and(|r| !r.is_empty())
+}
+
+/// Whether `name` is a valid role session name
+fn is_session_name(name: &str) -> bool {
+    (2..=64).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+=,.@-_".contains(c))
+}
+
+/// Whether `key` looks like a KMS key: an id, an alias, or the ARN of
+/// either, which S3 otherwise only rejects on the first write
+pub(crate) fn is_kms_key(key: &str) -> bool {
+    let is_id = |id: &str| {
+        let id = id.strip_prefix("mrk-").unwrap_or(id);
+        !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+    };
+    let is_alias = |alias: &str| alias.strip_prefix("alias/").is_some_and(|a| !a.is_empty());
+    let parts: Vec<&str> = key.splitn(6, ':').collect();
+    if parts.len() == 6 && parts[0] == "arn" {
+        return parts[1].starts_with("aws")
+            && parts[2] == "kms"
+            && !parts[3].is_empty()
+            && parts[4].len() == 12
+            && parts[4].chars().all(|c| c.is_ascii_digit())
+            && (parts[5].strip_prefix("key/").is_some_and(is_id) || is_alias(parts[5]));
+    }
+    is_id(key) || is_alias(key)
+}
+
+/// Whether `key` is a base64-encoded 256-bit key, as SSE-C needs
+fn is_customer_key(key: &str) -> bool {
+    // 32 bytes encode to 43 characters and one padding character
+    key.len() == 44
+        && key.ends_with('=')
+        && key[..43]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+}
+
+/// Check the server-side encryption options
+///
+/// object_store only sends the encryption headers with an encryption type;
+/// a KMS key or customer key given without one would silently be ignored
+/// and the objects written with the bucket's default encryption.
+fn validate_encryption(options: &HashMap<String, String>) -> DeltaResult<()> {
+    let sse = option(options, SSE).map(String::as_str);
+    if let Some(sse) = sse {
+        if !SSE_TYPES.contains(&sse) {
+            return Err(invalid(format!(
+                "Server-side encryption must be one of \"{}\", not \"{}\"",
+                SSE_TYPES.join("\", \""),
+                sse
+            )));
+        }
+    }
+    let kms = matches!(sse, Some("aws:kms" | "aws:kms:dsse"));
+
+    if let Some(key) = option(options, SSE_KMS_KEY_ID) {
+        if !kms {
+            return Err(invalid(format!(
+                "'{}' requires '{}' = \"aws:kms\" or \"aws:kms:dsse\"",
+                SSE_KMS_KEY_ID, SSE
+            )));
+        }
+        if !is_kms_key(key) {
+            return Err(invalid(format!(
+                "'{}' is not a KMS key id, alias or ARN (arn:aws:kms:us-east-1:123456789012:key/...)",
+                key
+            )));
+        }
+    }
+    if let Some(enabled) = option(options, SSE_BUCKET_KEY) {
+        if sse != Some("aws:kms") {
+            return Err(invalid(format!(
+                "'{}' requires '{}' = \"aws:kms\"",
+                SSE_BUCKET_KEY, SSE
+            )));
+        }
+        if !["true", "false"].contains(&enabled.to_ascii_lowercase().as_str()) {
+            return Err(invalid(format!(
+                "'{}' must be \"true\" or \"false\", not \"{}\"",
+                SSE_BUCKET_KEY, enabled
+            )));
+        }
+    }
+
+    let customer_key = option(options, SSE_CUSTOMER_KEY);
+    match (sse == Some("sse-c"), customer_key) {
+        (true, None) => {
+            return Err(invalid(format!(
+                "SSE-C encryption requires a customer key in '{}'",
+                SSE_CUSTOMER_KEY
+            )))
+        }
+        (false, Some(_)) => {
+            return Err(invalid(format!(
+                "'{}' requires '{}' = \"sse-c\"",
+                SSE_CUSTOMER_KEY, SSE
+            )))
+        }
+        (true, Some(key)) if !is_customer_key(key) => {
+            return Err(invalid(
+                "The SSE-C customer key must be a base64-encoded 256-bit key".to_string(),
+            ))
+        }
+        _ => {}
+    }
+    if sse == Some("sse-c")
+        && option(options, "aws_endpoint_url")
+            .or_else(|| option(options, "aws_endpoint"))
+            .is_some_and(|endpoint| endpoint.starts_with("http://"))
+    {
+        return Err(invalid(
+            "SSE-C encryption requires an https:// endpoint, as S3 rejects customer keys sent unencrypted"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The shared config and credentials files, as the AWS SDK finds them
+fn shared_files() -> Vec<std::path::PathBuf> {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(std::path::PathBuf::from);
+    let file = |var: &str, name: &str| {
+        std::env::var_os(var)
+            .map(std::path::PathBuf::from)
+            .or_else(|| home.as_ref().map(|h| h.join(".aws").join(name)))
+    };
+    [
+        file("AWS_CONFIG_FILE", "config"),
+        file("AWS_SHARED_CREDENTIALS_FILE", "credentials"),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|path| path.is_file())
+    .collect()
+}
+
+/// Whether the shared files define `profile`, or `None` without any file
+///
+/// Profiles are `[profile name]` sections of the config file and `[name]`
+/// sections of the credentials file; `default` is either.
+fn profile_defined(profile: &str) -> Option<bool> {
+    let files = shared_files();
+    if files.is_empty() {
+        return None;
+    }
+    let defined = files.iter().any(|path| {
+        std::fs::read_to_string(path).is_ok_and(|text| {
+            text.lines().any(|line| {
+                let Some(section) = line
+                    .trim()
+                    .strip_prefix('[')
+                    .and_then(|l| l.strip_suffix(']'))
+                else {
+                    return false;
+                };
+                let section = section.trim();
+                section == profile
+                    || section.strip_prefix("profile ").map(str::trim) == Some(profile)
+            })
+        })
+    });
+    Some(defined)
+}
+
+/// Check the AWS credential and encryption options, as given to
+/// `s3_storage_options()` or directly as storage options
+pub(crate) fn validate_options(options: &HashMap<String, String>) -> DeltaResult<()> {
+    validate_encryption(options)?;
+    let profile = option(options, PROFILE);
+    let role_arn = option(options, ROLE_ARN);
+
+    if let Some(profile) = profile {
+        if profile_defined(profile) == Some(false) {
+            return Err(invalid(format!(
+                "AWS profile '{}' is not defined in the shared config or credentials file",
+                profile
+            )));
+        }
+    }
+    if let Some(arn) = role_arn {
+        if !is_role_arn(arn) {
+            return Err(invalid(format!(
+                "'{}' is not an IAM role ARN (arn:aws:iam::123456789012:role/name)",
+                arn
+            )));
+        }
+    }
+    for key in [EXTERNAL_ID, SESSION_NAME, SESSION_DURATION] {
+        if option(options, key).is_some() && role_arn.is_none() {
+            return Err(invalid(format!("'{}' requires a role to assume", key)));
+        }
+    }
+    if let Some(name) = option(options, SESSION_NAME) {
+        if !is_session_name(name) {
+            return Err(invalid(format!(
+                "Role session name '{}' must be 2 to 64 letters, digits or +=,.@-_",
+                name
+            )));
+        }
+    }
+    if let Some(duration) = option(options, SESSION_DURATION) {
+        match duration.parse::<u64>() {
+            Ok(seconds) if (MIN_SESSION_DURATION..=MAX_SESSION_DURATION).contains(&seconds) => {}
+            _ => {
+                return Err(invalid(format!(
+                    "Role session duration must be between {} and {} seconds, not '{}'",
+                    MIN_SESSION_DURATION, MAX_SESSION_DURATION, duration
+                )));
+            }
+        }
+    }
+    if profile.is_some() {
+        if let Some(key) = [ACCESS_KEY_ID, SECRET_ACCESS_KEY]
+            .into_iter()
+            .find(|k| option(options, k).is_some())
+        {
+            return Err(invalid(format!(
+                "An AWS profile cannot be combined with '{}'",
+                key
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct AssumeRole {
+    arn: String,
+    external_id: Option<String>,
+    session_name: String,
+    duration: Option<Duration>,
+}
+
+// ============================================================================
+// Credential Provider
+// ============================================================================
+
+#[derive(Debug)]
+struct CachedCredential {
+    credential: Arc<AwsCredential>,
+    expires: Option<SystemTime>,
+}
+
+/// Gets AWS credentials of a profile or an assumed role
+#[derive(Debug)]
+struct ProfileCredentials {
+    profile: Option<String>,
+    /// Static keys the role is assumed with
+    keys: Option<Credentials>,
+    region: Option<String>,
+    role: Option<AssumeRole>,
+    cached: Mutex<Option<CachedCredential>>,
+}
+
+fn credential_error(message: impl Into<String>) -> ObjectStoreError {
+    ObjectStoreError::Generic {
+        store: "S3",
+        source: message.into().into(),
+    }
+}
+
+impl ProfileCredentials {
+    /// Create a provider from storage options, or `None` when neither a
+    /// profile nor a role is given
+    fn from_options(options: &HashMap<String, String>) -> DeltaResult<Option<Self>> {
+        let profile = option(options, PROFILE).cloned();
+        let role = option(options, ROLE_ARN).map(|arn| AssumeRole {
+            arn: arn.clone(),
+            external_id: option(options, EXTERNAL_ID).cloned(),
+            session_name: option(options, SESSION_NAME)
+                .cloned()
+                .unwrap_or_else(|| format!("deltaR_{}", chrono::Utc::now().timestamp_millis())),
+            duration: option(options, SESSION_DURATION)
+                .and_then(|d| d.parse().ok())
+                .map(Duration::from_secs),
+        });
+        if profile.is_none() && role.is_none() {
+            return Ok(None);
+        }
+        validate_options(options)?;
+
+        let keys = match (
+            option(options, ACCESS_KEY_ID),
+            option(options, SECRET_ACCESS_KEY),
+        ) {
+            (Some(key_id), Some(secret)) => Some(Credentials::from_keys(
+                key_id,
+                secret,
+                option(options, SESSION_TOKEN).cloned(),
+            )),
+            _ => None,
+        };
+        Ok(Some(ProfileCredentials {
+            profile,
+            keys,
+            region: option(options, "aws_region").cloned(),
+            role,
+            cached: Mutex::new(None),
+        }))
+    }
+
+    /// Load the credentials provider of the profile, or of the default
+    /// chain, and assume the role with it (or with the static keys) if one
+    /// is given
+    async fn provider(&self) -> Result<SharedCredentialsProvider, ObjectStoreError> {
+        let region = RegionProviderChain::first_try(self.region.clone().map(Region::new))
+            .or_default_provider()
+            .or_else(Region::new(DEFAULT_REGION));
+        let mut loader = aws_config::defaults(BehaviorVersion::latest()).region(region);
+        if let Some(profile) = &self.profile {
+            loader = loader.profile_name(profile);
+        }
+        let config = loader.load().await;
+
+        let Some(role) = &self.role else {
+            return config
+                .credentials_provider()
+                .ok_or_else(|| credential_error("No AWS credentials provider found"));
+        };
+        let mut builder = AssumeRoleProvider::builder(&role.arn)
+            .session_name(&role.session_name)
+            .configure(&config);
+        if let Some(external_id) = &role.external_id {
+            builder = builder.external_id(external_id);
+        }
+        if let Some(duration) = role.duration {
+            builder = builder.session_length(duration);
+        }
+        let provider = match &self.keys {
+            Some(keys) => builder.build_from_provider(keys.clone()).await,
+            None => builder.build().await,
+        };
+        Ok(SharedCredentialsProvider::new(provider))
+    }
+
+    async fn fetch(&self) -> Result<CachedCredential, ObjectStoreError> {
+        let provider = self.provider().await?;
+        let credentials = provider.provide_credentials().await.map_err(|e| {
+            let source = match (&self.role, &self.profile) {
+                (Some(role), _) => format!("assuming role {}", role.arn),
+                (None, Some(profile)) => format!("profile '{}'", profile),
+                (None, None) => "the default provider chain".to_string(),
+            };
+            let hint = match &self.profile {
+                Some(profile) => format!(
+                    "; if the profile uses SSO, sign in with `aws sso login --profile {}`",
+                    profile
+                ),
+                None => String::new(),
+            };
+            credential_error(format!(
+                "Failed to load AWS credentials from {}: {}{}",
+                source,
+                aws_error_message(&e),
+                hint
+            ))
+        })?;
+        Ok(CachedCredential {
+            credential: Arc::new(AwsCredential {
+                key_id: credentials.access_key_id().to_string(),
+                secret_key: credentials.secret_access_key().to_string(),
+                token: credentials.session_token().map(str::to_string),
+            }),
+            expires: credentials.expiry(),
+        })
+    }
+}
+
+/// The message of an SDK error with its sources, which the SDK keeps out
+/// of its own message
+fn aws_error_message(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+#[async_trait]
+impl CredentialProvider for ProfileCredentials {
+    type Credential = AwsCredential;
+
+    async fn get_credential(&self) -> Result<Arc<AwsCredential>, ObjectStoreError> {
+        if let Some(cached) = self.cached.lock().unwrap().as_ref() {
+            let fresh = cached.expires.is_none_or(|expires| {
+                expires
+                    .duration_since(SystemTime::now())
+                    .is_ok_and(|ttl| ttl > MIN_TTL)
+            });
+            if fresh {
+                return Ok(cached.credential.clone());
+            }
+        }
+        let credential = self.fetch().await?;
+        let result = credential.credential.clone();
+        *self.cached.lock().unwrap() = Some(credential);
+        Ok(result)
+    }
+}
+
+// ============================================================================
+// Object Store Factory
+// ============================================================================
+
+/// Opens `s3` URLs, with the credentials of this module when requested
+#[derive(Debug, Default)]
+struct AwsFactory {
+    inner: S3ObjectStoreFactory,
+}
+
+impl ObjectStoreFactory for AwsFactory {
+    fn parse_url_opts(
+        &self,
+        url: &Url,
+        config: &StorageConfig,
+    ) -> DeltaResult<(ObjectStoreRef, Path)> {
+        validate_encryption(&config.raw)?;
+        let Some(provider) = ProfileCredentials::from_options(&config.raw)? else {
+            return self.inner.parse_url_opts(url, config);
+        };
+
+        let mut builder = AmazonS3Builder::from_env()
+            .with_url(url.to_string())
+            .with_retry(config.retry.clone())
+            .with_credentials(Arc::new(provider));
+        if let Some(runtime) = &config.runtime {
+            builder =
+                builder.with_http_connector(SpawnedReqwestConnector::new(runtime.get_handle()));
+        }
+        for (key, value) in &config.raw {
+            if let Ok(key) = AmazonS3ConfigKey::from_str(&key.to_ascii_lowercase()) {
+                builder = builder.with_config(key, value.clone());
+            }
+        }
+        let store: ObjectStoreRef = Arc::new(
+            builder
+                .build()
+                .map_err(|e| DeltaTableError::Generic(e.to_string()))?,
+        );
+
+        // As delta-rs does, tables locked through DynamoDB or allowed to
+        // rename unsafely write their commits with renames
+        let truthy = |key: &str| {
+            option(&config.raw, key).is_some_and(|v| {
+                ["1", "true", "on", "yes", "y"].contains(&v.to_ascii_lowercase().as_str())
+            })
+        };
+        let dynamodb =
+            option(&config.raw, "aws_s3_locking_provider").is_some_and(|p| p == "dynamodb");
+        let store: ObjectStoreRef = if dynamodb || truthy("aws_s3_allow_unsafe_rename") {
+            Arc::new(S3StorageBackend::try_new(store, true)?)
+        } else {
+            store
+        };
+
+        let (_, path) =
+            ObjectStoreScheme::parse(url).map_err(|e| DeltaTableError::Generic(e.to_string()))?;
+        Ok((store, Path::parse(path)?))
+    }
+}
+
+/// Route `s3` and `s3a` URLs through [AwsFactory]
+///
+/// Called after the delta-rs S3 handlers are registered, whose log store
+/// factory is kept.
+pub(crate) fn register_handlers() {
+    for scheme in ["s3", "s3a"] {
+        let url = Url::parse(&format!("{}://", scheme)).unwrap();
+        object_store_factories().insert(url, Arc::new(AwsFactory::default()));
+    }
+}
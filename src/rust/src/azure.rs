@@ -0,0 +1,244 @@
+//! Azure SAS token renewal
+//!
+//! A SAS token given as an R function is called when a table is opened, and
+//! object_store would sign every request with that one token, so operations
+//! that outlast it fail part way. Tables opened with a token callback are
+//! built by an Azure object store factory whose credential provider asks the
+//! callback for a new token five minutes before the current one expires.
+//!
+//! The R API may only be called on R's main thread, which waits for the
+//! operation in [crate::block_on] while it runs. The provider queues a
+//! request for a token and the main thread answers it between its checks.
+//! When no answer comes, e.g. while another thread pulls a stream, the
+//! current token is used for as long as it lasts.
+
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deltalake::azure::AzureFactory;
+use deltalake::logstore::object_store::azure::{
+    AzureConfigKey, AzureCredential, MicrosoftAzureBuilder,
+};
+use deltalake::logstore::object_store::client::SpawnedReqwestConnector;
+use deltalake::logstore::object_store::{CredentialProvider, ObjectStoreScheme};
+use deltalake::logstore::{
+    object_store_factories, ObjectStoreFactory, ObjectStoreRef, StorageConfig,
+};
+use deltalake::{DeltaResult, DeltaTableError, Path};
+use extendr_api::prelude::*;
+use tokio::sync::oneshot;
+use url::Url;
+
+use deltalake::logstore::object_store::Error as ObjectStoreError;
+
+use crate::storage;
+
+/// Storage option holding the id of the SAS token callback of a table
+pub(crate) const SAS_CALLBACK: &str = "deltar_azure_sas_callback";
+
+/// Tokens are renewed when they expire in less than this
+const MIN_TTL: Duration = Duration::from_secs(300);
+/// How long a renewal waits for R to call the callback
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+const SCHEMES: &[&str] = &["az", "adl", "azure", "abfs", "abfss"];
+
+thread_local! {
+    /// SAS token callbacks, by id; only used on R's main thread
+    static CALLBACKS: RefCell<Vec<Robj>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A pending request for a token from a callback, with where to send it
+type TokenRequest = (usize, oneshot::Sender<std::result::Result<String, String>>);
+
+static REQUESTS: Mutex<Vec<TokenRequest>> = Mutex::new(Vec::new());
+
+// ============================================================================
+// Callbacks
+// ============================================================================
+
+/// Keep a SAS token callback for renewals and return its id
+///
+/// A callback given again, e.g. when the same options open several tables,
+/// keeps its id, so that their clients can still be shared.
+pub(crate) fn register_callback(callback: &Robj) -> usize {
+    CALLBACKS.with_borrow_mut(|callbacks| {
+        if let Some(id) = callbacks.iter().position(|c| c == callback) {
+            return id;
+        }
+        callbacks.push(callback.clone());
+        callbacks.len() - 1
+    })
+}
+
+/// Call the callbacks of the pending token requests
+///
+/// Run on R's main thread while it waits for an operation. Tokens are
+/// checked like those given when the table is opened.
+pub(crate) fn answer_token_requests() {
+    let requests = std::mem::take(&mut *REQUESTS.lock().unwrap());
+    for (id, reply) in requests {
+        let token = CALLBACKS
+            .with_borrow(|callbacks| callbacks.get(id).cloned())
+            .ok_or_else(|| format!("Unknown SAS token callback {}", id))
+            .and_then(|callback| {
+                storage::storage_option_value("azure_storage_sas_key", &callback)
+                    .map_err(|e| e.to_string())?
+                    .ok_or_else(|| "SAS token callback returned no token".to_string())
+            });
+        // The request may have timed out meanwhile
+        let _ = reply.send(token);
+    }
+}
+
+/// Ask R's main thread for a token from callback `id`
+async fn request_token(id: usize) -> std::result::Result<String, String> {
+    let (reply, answer) = oneshot::channel();
+    REQUESTS.lock().unwrap().push((id, reply));
+    match tokio::time::timeout(REQUEST_TIMEOUT, answer).await {
+        Ok(Ok(token)) => token,
+        Ok(Err(_)) => Err("SAS token request was dropped".to_string()),
+        Err(_) => Err(format!(
+            "SAS token callback was not called within {} seconds",
+            REQUEST_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+// ============================================================================
+// Token Provider
+// ============================================================================
+
+#[derive(Debug)]
+struct CachedToken {
+    credential: Arc<AzureCredential>,
+    expires: Option<DateTime<Utc>>,
+}
+
+impl CachedToken {
+    fn new(token: &str) -> Self {
+        let fields = storage::sas_token_fields(token);
+        CachedToken {
+            expires: storage::sas_token_expiry(&fields),
+            credential: Arc::new(AzureCredential::SASToken(fields)),
+        }
+    }
+
+    /// Whether the token is good for at least `ttl` more
+    fn lasts(&self, ttl: Duration) -> bool {
+        self.expires
+            .is_none_or(|t| (t - Utc::now()).to_std().is_ok_and(|left| left > ttl))
+    }
+}
+
+/// Signs requests with the SAS tokens of an R callback
+#[derive(Debug)]
+struct SasTokenProvider {
+    callback: usize,
+    /// Held while a token is renewed, so that concurrent requests call the
+    /// callback once
+    cached: tokio::sync::Mutex<CachedToken>,
+}
+
+fn token_error(message: String) -> ObjectStoreError {
+    ObjectStoreError::Generic {
+        store: "MicrosoftAzure",
+        source: message.into(),
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for SasTokenProvider {
+    type Credential = AzureCredential;
+
+    async fn get_credential(&self) -> std::result::Result<Arc<AzureCredential>, ObjectStoreError> {
+        let mut cached = self.cached.lock().await;
+        if cached.lasts(MIN_TTL) {
+            return Ok(cached.credential.clone());
+        }
+        match request_token(self.callback).await {
+            Ok(token) => {
+                *cached = CachedToken::new(&token);
+                Ok(cached.credential.clone())
+            }
+            Err(_) if cached.lasts(Duration::ZERO) => Ok(cached.credential.clone()),
+            Err(e) => Err(token_error(format!(
+                "Cannot renew the Azure SAS token: {}",
+                e
+            ))),
+        }
+    }
+}
+
+// ============================================================================
+// Object Store Factory
+// ============================================================================
+
+/// Opens Azure URLs, renewing SAS tokens through their callback if any
+#[derive(Debug, Default)]
+struct AzureSasFactory {
+    inner: AzureFactory,
+}
+
+impl ObjectStoreFactory for AzureSasFactory {
+    fn parse_url_opts(
+        &self,
+        url: &Url,
+        config: &StorageConfig,
+    ) -> DeltaResult<(ObjectStoreRef, Path)> {
+        let Some(callback) = config.raw.get(SAS_CALLBACK).and_then(|id| id.parse().ok()) else {
+            return self.inner.parse_url_opts(url, config);
+        };
+        let token = config
+            .raw
+            .iter()
+            .find(|(key, _)| storage::is_sas_key(key))
+            .map(|(_, token)| token.as_str())
+            .unwrap_or_default();
+        let provider = SasTokenProvider {
+            callback,
+            cached: tokio::sync::Mutex::new(CachedToken::new(token)),
+        };
+
+        // Other credentials in the environment are overridden by the token
+        let mut builder = MicrosoftAzureBuilder::from_env()
+            .with_url(url.to_string())
+            .with_retry(config.retry.clone())
+            .with_credentials(Arc::new(provider));
+        if let Some(runtime) = &config.runtime {
+            builder =
+                builder.with_http_connector(SpawnedReqwestConnector::new(runtime.get_handle()));
+        }
+        for (key, value) in &config.raw {
+            if storage::is_sas_key(key) {
+                continue;
+            }
+            if let Ok(key) = AzureConfigKey::from_str(&key.to_ascii_lowercase()) {
+                builder = builder.with_config(key, value.clone());
+            }
+        }
+        let store = builder
+            .build()
+            .map_err(|e| DeltaTableError::Generic(e.to_string()))?;
+
+        let (_, path) =
+            ObjectStoreScheme::parse(url).map_err(|e| DeltaTableError::Generic(e.to_string()))?;
+        Ok((Arc::new(store), Path::parse(path)?))
+    }
+}
+
+/// Route Azure URLs through [AzureSasFactory]
+///
+/// Called after the delta-rs Azure handlers are registered, whose log store
+/// factory is kept.
+pub(crate) fn register_handlers() {
+    let factory = Arc::new(AzureSasFactory::default());
+    for scheme in SCHEMES {
+        let url = Url::parse(&format!("{}://", scheme)).unwrap();
+        object_store_factories().insert(url, factory.clone());
+    }
+}
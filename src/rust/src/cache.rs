@@ -0,0 +1,112 @@
+//! Table snapshot cache
+//!
+//! Keeps the snapshots of tables opened with `cache = TRUE` for the life of
+//! the R session, keyed by table URI, storage options and whether the files
+//! were loaded. Opening a cached table again only reads the commits made
+//! since it was cached, instead of reading the checkpoint and replaying the
+//! log after it.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use deltalake::DeltaTable;
+use extendr_api::prelude::*;
+
+use crate::{block_on, path_to_url};
+
+/// What identifies a cached table
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    uri: String,
+    storage_options: Vec<(String, String)>,
+    load_files: bool,
+}
+
+impl CacheKey {
+    pub(crate) fn new(
+        uri: &url::Url,
+        storage_options: Option<HashMap<String, String>>,
+        load_files: bool,
+    ) -> Self {
+        let mut storage_options: Vec<(String, String)> =
+            storage_options.unwrap_or_default().into_iter().collect();
+        storage_options.sort();
+        CacheKey {
+            uri: uri.to_string(),
+            storage_options,
+            load_files,
+        }
+    }
+}
+
+static TABLE_CACHE: LazyLock<Mutex<HashMap<CacheKey, DeltaTable>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Open a table through the cache
+///
+/// A cached snapshot is brought up to date with the commits made since it
+/// was cached; if that fails (e.g. because those commits were cleaned up),
+/// the table is opened with `open` instead. The result is cached either way.
+pub(crate) fn cached_table<F>(key: CacheKey, open: F) -> Result<DeltaTable>
+where
+    F: FnOnce() -> Result<DeltaTable>,
+{
+    let cached = TABLE_CACHE.lock().unwrap().get(&key).cloned();
+    let table = match cached {
+        Some(mut table) => match block_on(async { table.update_state().await })? {
+            Ok(()) => table,
+            Err(_) => open()?,
+        },
+        None => open()?,
+    };
+    TABLE_CACHE.lock().unwrap().insert(key, table.clone());
+    Ok(table)
+}
+
+/// Remove tables from the snapshot cache
+///
+/// Returns the number of cached snapshots removed.
+///
+/// @param table_uri Path of the table to remove, or NULL to clear the cache
+#[extendr]
+pub fn table_cache_clear(table_uri: Nullable<&str>) -> Result<i32> {
+    let mut cache = TABLE_CACHE.lock().unwrap();
+    let before = cache.len();
+    match table_uri {
+        Nullable::NotNull(path) => {
+            let uri = path_to_url(path).map_err(Error::from)?.to_string();
+            cache.retain(|key, _| key.uri != uri);
+        }
+        Nullable::Null => cache.clear(),
+    }
+    Ok((before - cache.len()) as i32)
+}
+
+/// List the tables in the snapshot cache
+///
+/// Returns a data.frame with the `table_uri`, cached `version` and
+/// `load_files` of each cached snapshot.
+#[extendr]
+pub fn table_cache_entries() -> Robj {
+    let cache = TABLE_CACHE.lock().unwrap();
+    let mut entries: Vec<(&CacheKey, i64)> = cache
+        .iter()
+        .map(|(key, table)| (key, table.version().unwrap_or(-1)))
+        .collect();
+    entries.sort_by(|a, b| (&a.0.uri, a.0.load_files).cmp(&(&b.0.uri, b.0.load_files)));
+
+    let uris: Vec<String> = entries.iter().map(|(key, _)| key.uri.clone()).collect();
+    let versions: Vec<f64> = entries.iter().map(|(_, v)| *v as f64).collect();
+    let load_files: Vec<bool> = entries.iter().map(|(key, _)| key.load_files).collect();
+    data_frame!(
+        table_uri = uris,
+        version = versions,
+        load_files = load_files
+    )
+}
+
+extendr_module! {
+    mod cache;
+    fn table_cache_clear;
+    fn table_cache_entries;
+}
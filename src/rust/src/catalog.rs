@@ -0,0 +1,415 @@
+//! Catalog listings
+//!
+//! Lists the schemas of a catalog and the tables of a schema, so tables can
+//! be discovered from R. Unity Catalog is listed through the workspace's
+//! REST API (see [crate::unity]); the AWS Glue Data Catalog through the
+//! Glue API, with requests signed with the credentials of the AWS default
+//! provider chain, or of a profile or static keys given in the options.
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::{BehaviorVersion, Region};
+use aws_credential_types::provider::ProvideCredentials;
+use aws_credential_types::Credentials;
+use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+use aws_sigv4::sign::v4;
+use extendr_api::prelude::*;
+use serde_json::{json, Value};
+
+use crate::{block_on, parse_storage_options};
+
+/// A schema or table listed in a catalog
+pub(crate) struct CatalogEntry {
+    pub(crate) name: String,
+    pub(crate) full_name: Option<String>,
+    pub(crate) location: Option<String>,
+    pub(crate) format: Option<String>,
+    /// Kind of table, e.g. `MANAGED`, `EXTERNAL` or `VIEW`
+    pub(crate) kind: Option<String>,
+    pub(crate) comment: Option<String>,
+}
+
+// ============================================================================
+// AWS Glue
+// ============================================================================
+
+/// Region of the Glue requests when neither the options nor the
+/// environment give one
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// A Glue Data Catalog, with the credentials its requests are signed with
+struct GlueCatalog {
+    client: reqwest::Client,
+    region: String,
+    credentials: Credentials,
+    catalog_id: Option<String>,
+}
+
+impl GlueCatalog {
+    async fn new(
+        catalog_id: Option<String>,
+        options: &HashMap<String, String>,
+    ) -> std::result::Result<Self, String> {
+        let option = |key: &str| {
+            options
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.clone())
+        };
+        let region = RegionProviderChain::first_try(option("aws_region").map(Region::new))
+            .or_default_provider()
+            .or_else(Region::new(DEFAULT_REGION));
+        let mut loader = aws_config::defaults(BehaviorVersion::latest()).region(region);
+        if let Some(profile) = option(crate::aws::PROFILE) {
+            loader = loader.profile_name(profile);
+        }
+        if let (Some(key_id), Some(secret)) =
+            (option("aws_access_key_id"), option("aws_secret_access_key"))
+        {
+            loader = loader.credentials_provider(Credentials::from_keys(
+                key_id,
+                secret,
+                option("aws_session_token"),
+            ));
+        }
+        let config = loader.load().await;
+
+        let provider = config
+            .credentials_provider()
+            .ok_or_else(|| "No AWS credentials provider found".to_string())?;
+        let credentials = provider
+            .provide_credentials()
+            .await
+            .map_err(|e| format!("Failed to load AWS credentials: {}", e))?;
+        Ok(GlueCatalog {
+            client: crate::storage::http_client(options)?,
+            region: config
+                .region()
+                .map(|r| r.to_string())
+                .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+            credentials,
+            catalog_id,
+        })
+    }
+
+    /// Send a signed Glue API request
+    async fn call(&self, action: &str, mut body: Value) -> std::result::Result<Value, String> {
+        if let Some(id) = &self.catalog_id {
+            body["CatalogId"] = json!(id);
+        }
+        let url = format!("https://glue.{}.amazonaws.com/", self.region);
+        let body = serde_json::to_vec(&body).map_err(|e| e.to_string())?;
+        let target = format!("AWSGlue.{}", action);
+        let headers = [
+            ("content-type", "application/x-amz-json-1.1"),
+            ("x-amz-target", target.as_str()),
+        ];
+
+        let identity = self.credentials.clone().into();
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name("glue")
+            .time(SystemTime::now())
+            .settings(SigningSettings::default())
+            .build()
+            .map_err(|e| format!("Failed to sign Glue request: {}", e))?
+            .into();
+        let signable = SignableRequest::new(
+            "POST",
+            &url,
+            headers.iter().copied(),
+            SignableBody::Bytes(&body),
+        )
+        .map_err(|e| format!("Failed to sign Glue request: {}", e))?;
+        let (instructions, _) = sign(signable, &params)
+            .map_err(|e| format!("Failed to sign Glue request: {}", e))?
+            .into_parts();
+
+        let mut request = self.client.post(&url).body(body.clone());
+        for (name, value) in headers.iter().copied().chain(instructions.headers()) {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Glue {} request failed: {}", action, e))?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| format!("Glue {} request failed: {}", action, e))?;
+        let value: Value = serde_json::from_str(&text).unwrap_or(Value::Null);
+        if !status.is_success() {
+            let message = value["Message"]
+                .as_str()
+                .or_else(|| value["message"].as_str())
+                .unwrap_or(&text);
+            return Err(format!("Glue {} returned {}: {}", action, status, message));
+        }
+        Ok(value)
+    }
+
+    /// Get every item of a paginated Glue listing
+    async fn list(
+        &self,
+        action: &str,
+        body: Value,
+        field: &str,
+    ) -> std::result::Result<Vec<Value>, String> {
+        let mut items = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let mut request = body.clone();
+            if let Some(token) = &next_token {
+                request["NextToken"] = json!(token);
+            }
+            let mut page = self.call(action, request).await?;
+            if let Value::Array(page_items) = page[field].take() {
+                items.extend(page_items);
+            }
+            match page["NextToken"].as_str().filter(|t| !t.is_empty()) {
+                Some(token) => next_token = Some(token.to_string()),
+                None => return Ok(items),
+            }
+        }
+    }
+}
+
+/// The format of a Glue table, from the parameters Delta, Iceberg and Spark
+/// writers register it with
+fn glue_format(table: &Value) -> Option<String> {
+    let parameters = &table["Parameters"];
+    ["table_type", "spark.sql.sources.provider", "classification"]
+        .iter()
+        .find_map(|key| parameters[key].as_str())
+        .map(str::to_uppercase)
+}
+
+/// The storage location of a Glue table
+///
+/// Spark registers Delta tables with a placeholder location and the real one
+/// in the `path` parameter of the serializer.
+fn glue_location(table: &Value) -> Option<String> {
+    let descriptor = &table["StorageDescriptor"];
+    descriptor["Location"]
+        .as_str()
+        .filter(|l| !l.is_empty() && !l.contains("__PLACEHOLDER__"))
+        .or_else(|| descriptor["SerdeInfo"]["Parameters"]["path"].as_str())
+        .or_else(|| table["Parameters"]["path"].as_str())
+        .map(str::to_string)
+}
+
+async fn glue_schemas(
+    catalog: &str,
+    catalog_id: Option<String>,
+    options: &HashMap<String, String>,
+) -> std::result::Result<Vec<CatalogEntry>, String> {
+    let glue = GlueCatalog::new(catalog_id, options).await?;
+    let databases = glue.list("GetDatabases", json!({}), "DatabaseList").await?;
+    Ok(databases
+        .iter()
+        .map(|database| {
+            let name = database["Name"].as_str().unwrap_or_default().to_string();
+            CatalogEntry {
+                full_name: Some(format!("{}.{}", catalog, name)),
+                name,
+                location: database["LocationUri"].as_str().map(str::to_string),
+                format: None,
+                kind: None,
+                comment: database["Description"].as_str().map(str::to_string),
+            }
+        })
+        .collect())
+}
+
+async fn glue_tables(
+    catalog: &str,
+    catalog_id: Option<String>,
+    schema: &str,
+    options: &HashMap<String, String>,
+) -> std::result::Result<Vec<CatalogEntry>, String> {
+    let glue = GlueCatalog::new(catalog_id, options).await?;
+    let tables = glue
+        .list("GetTables", json!({ "DatabaseName": schema }), "TableList")
+        .await?;
+    Ok(tables
+        .iter()
+        .map(|table| {
+            let name = table["Name"].as_str().unwrap_or_default().to_string();
+            CatalogEntry {
+                full_name: Some(format!("{}.{}.{}", catalog, schema, name)),
+                name,
+                location: glue_location(table),
+                format: glue_format(table),
+                kind: table["TableType"].as_str().map(str::to_string),
+                comment: table["Description"].as_str().map(str::to_string),
+            }
+        })
+        .collect())
+}
+
+/// The storage location of a Delta table registered in Glue
+async fn glue_table_location(
+    catalog: &str,
+    catalog_id: Option<String>,
+    schema: &str,
+    table: &str,
+    options: &HashMap<String, String>,
+) -> std::result::Result<String, String> {
+    let glue = GlueCatalog::new(catalog_id, options).await?;
+    let response = glue
+        .call("GetTable", json!({ "DatabaseName": schema, "Name": table }))
+        .await?;
+    let name = format!("{}.{}.{}", catalog, schema, table);
+    let table = &response["Table"];
+    match glue_format(table) {
+        Some(format) if format != "DELTA" => {
+            return Err(format!(
+                "Table {} is not a Delta table: its format is {}",
+                name, format
+            ))
+        }
+        _ => {}
+    }
+    glue_location(table).ok_or_else(|| format!("Table {} has no storage location", name))
+}
+
+// ============================================================================
+// R Interface
+// ============================================================================
+
+/// Convert catalog entries to a named list of columns
+fn entries_to_list(entries: Vec<CatalogEntry>, tables: bool) -> List {
+    let column = |f: fn(&CatalogEntry) -> Option<String>| -> Robj {
+        entries
+            .iter()
+            .map(|e| f(e).as_deref().map(Rstr::from).unwrap_or_else(Rstr::na))
+            .collect::<Strings>()
+            .into_robj()
+    };
+    let mut columns: Vec<(&str, Robj)> = vec![
+        ("name", column(|e| Some(e.name.clone()))),
+        ("full_name", column(|e| e.full_name.clone())),
+        ("location", column(|e| e.location.clone())),
+    ];
+    if tables {
+        columns.push(("format", column(|e| e.format.clone())));
+        columns.push(("type", column(|e| e.kind.clone())));
+    }
+    columns.push(("comment", column(|e| e.comment.clone())));
+    List::from_pairs(columns)
+}
+
+/// Options of a catalog as a map of strings
+fn catalog_options(storage_options: &Nullable<List>) -> Result<HashMap<String, String>> {
+    match storage_options {
+        Nullable::NotNull(opts) => parse_storage_options(opts),
+        Nullable::Null => Ok(HashMap::new()),
+    }
+}
+
+/// List the schemas of a catalog
+///
+/// Returns a named list of columns: `name`, `full_name`, `location` and
+/// `comment`.
+///
+/// @param kind Kind of catalog: "unity" or "glue"
+/// @param catalog Name of the catalog
+/// @param catalog_id Id of a Glue catalog, if not the account's own (optional)
+/// @param storage_options Options the catalog is accessed with (optional)
+#[extendr]
+pub fn catalog_list_schemas(
+    kind: &str,
+    catalog: &str,
+    catalog_id: Nullable<String>,
+    storage_options: Nullable<List>,
+) -> Result<List> {
+    let options = catalog_options(&storage_options)?;
+    let entries = match kind {
+        "unity" => block_on(crate::unity::list_schemas(&options, catalog))?,
+        "glue" => block_on(glue_schemas(catalog, catalog_id.into_option(), &options))?,
+        other => return Err(Error::from(format!("Unknown catalog kind: '{}'", other))),
+    }
+    .map_err(Error::from)?;
+    Ok(entries_to_list(entries, false))
+}
+
+/// List the tables of a schema in a catalog
+///
+/// Returns a named list of columns: `name`, `full_name`, `location`,
+/// `format`, `type` and `comment`.
+///
+/// @param kind Kind of catalog: "unity" or "glue"
+/// @param catalog Name of the catalog
+/// @param catalog_id Id of a Glue catalog, if not the account's own (optional)
+/// @param schema Name of the schema (a Glue database)
+/// @param storage_options Options the catalog is accessed with (optional)
+#[extendr]
+pub fn catalog_list_tables(
+    kind: &str,
+    catalog: &str,
+    catalog_id: Nullable<String>,
+    schema: &str,
+    storage_options: Nullable<List>,
+) -> Result<List> {
+    let options = catalog_options(&storage_options)?;
+    let entries = match kind {
+        "unity" => block_on(crate::unity::list_tables(&options, catalog, schema))?,
+        "glue" => block_on(glue_tables(
+            catalog,
+            catalog_id.into_option(),
+            schema,
+            &options,
+        ))?,
+        other => return Err(Error::from(format!("Unknown catalog kind: '{}'", other))),
+    }
+    .map_err(Error::from)?;
+    Ok(entries_to_list(entries, true))
+}
+
+/// Get the location a table registered in a catalog is opened at
+///
+/// Unity Catalog tables are opened by name through the `uc://` scheme, which
+/// vends their credentials; Glue tables at their storage location.
+///
+/// @param kind Kind of catalog: "unity" or "glue"
+/// @param catalog Name of the catalog
+/// @param catalog_id Id of a Glue catalog, if not the account's own (optional)
+/// @param schema Name of the schema (a Glue database)
+/// @param table Name of the table
+/// @param storage_options Options the catalog is accessed with (optional)
+#[extendr]
+pub fn catalog_table_location(
+    kind: &str,
+    catalog: &str,
+    catalog_id: Nullable<String>,
+    schema: &str,
+    table: &str,
+    storage_options: Nullable<List>,
+) -> Result<String> {
+    match kind {
+        "unity" => Ok(format!("uc://{}.{}.{}", catalog, schema, table)),
+        "glue" => {
+            let options = catalog_options(&storage_options)?;
+            block_on(glue_table_location(
+                catalog,
+                catalog_id.into_option(),
+                schema,
+                table,
+                &options,
+            ))?
+            .map_err(Error::from)
+        }
+        other => Err(Error::from(format!("Unknown catalog kind: '{}'", other))),
+    }
+}
+
+extendr_module! {
+    mod catalog;
+    fn catalog_list_schemas;
+    fn catalog_list_tables;
+    fn catalog_table_location;
+}
@@ -0,0 +1,170 @@
+//! Multi-part checkpoints
+//!
+//! delta-rs writes a checkpoint as a single Parquet file, which for a table
+//! with millions of files becomes one very large object to write and read.
+//! The protocol also allows a classic checkpoint to be split into parts,
+//! `<version>.checkpoint.<part>.<parts>.parquet`, each holding a share of
+//! the actions. This module writes such checkpoints from the actions
+//! delta_kernel reconciles for the checkpoint, and the `_last_checkpoint`
+//! hint recording the number of parts.
+
+use std::sync::Arc;
+
+use arrow::array::{Array, BooleanArray, RecordBatch};
+use arrow::compute::filter_record_batch;
+use delta_kernel::engine::arrow_data::ArrowEngineData;
+use delta_kernel::snapshot::Snapshot as KernelSnapshot;
+use delta_kernel::table_features::TableFeature;
+use deltalake::kernel::schema::cast_record_batch;
+use deltalake::parquet::arrow::async_writer::{AsyncArrowWriter, ParquetObjectWriter};
+use deltalake::{DeltaTable, Path};
+use extendr_api::prelude::*;
+
+use crate::block_on;
+
+/// How to split a checkpoint into parts
+pub(crate) enum CheckpointParts {
+    /// A given number of parts
+    Count(usize),
+    /// As many parts as needed for each to hold at most this many bytes of
+    /// actions in memory
+    MaxBytes(usize),
+}
+
+/// Write a multi-part checkpoint of the loaded version of `table`
+///
+/// Returns the number of parts written, or `None` when the actions fit in
+/// one part, in which case nothing is written and the caller writes a
+/// classic checkpoint.
+pub(crate) fn create_multipart_checkpoint(
+    table: &DeltaTable,
+    parts: CheckpointParts,
+) -> Result<Option<usize>> {
+    let version = table
+        .version()
+        .ok_or_else(|| Error::from("Table has no loaded version"))?;
+    let engine = table.log_store().engine(None);
+    let snapshot = KernelSnapshot::builder_for(table.table_url().clone())
+        .at_version(version as u64)
+        .build(engine.as_ref())
+        .map_err(|e| Error::from(format!("Failed to load table: {}", e)))?;
+    if snapshot
+        .table_configuration()
+        .is_feature_supported(&TableFeature::V2Checkpoint)
+    {
+        return Err(Error::from(
+            "Multi-part checkpoints cannot be written for tables with the v2Checkpoint feature",
+        ));
+    }
+
+    let batches = checkpoint_batches(snapshot, engine.as_ref())?;
+    let num_actions: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+    let num_parts = match parts {
+        CheckpointParts::Count(count) => count,
+        CheckpointParts::MaxBytes(max_bytes) => {
+            let bytes: usize = batches
+                .iter()
+                .map(|batch| batch.get_array_memory_size())
+                .sum();
+            bytes.div_ceil(max_bytes)
+        }
+    }
+    .min(num_actions);
+    if num_parts <= 1 {
+        return Ok(None);
+    }
+
+    let store = table.log_store().object_store(None);
+    let log_path = table.log_store().log_path().clone();
+    let rows_per_part = num_actions.div_ceil(num_parts);
+    let mut size_in_bytes = 0u64;
+    let mut num_add_files = 0usize;
+    let schema = batches[0].schema();
+    let mut remaining = batches.iter().cloned();
+    let mut carry: Option<RecordBatch> = None;
+
+    for part in 1..=num_parts {
+        let location = log_path.child(format!(
+            "{:020}.checkpoint.{:010}.{:010}.parquet",
+            version, part, num_parts
+        ));
+        let mut rows = 0;
+        let mut part_batches = Vec::new();
+        while rows < rows_per_part {
+            let Some(batch) = carry.take().or_else(|| remaining.next()) else {
+                break;
+            };
+            let take = (rows_per_part - rows).min(batch.num_rows());
+            if take < batch.num_rows() {
+                carry = Some(batch.slice(take, batch.num_rows() - take));
+            }
+            rows += take;
+            part_batches.push(batch.slice(0, take));
+        }
+        num_add_files += part_batches
+            .iter()
+            .filter_map(|batch| batch.column_by_name("add"))
+            .map(|add| add.len() - add.null_count())
+            .sum::<usize>();
+
+        size_in_bytes += block_on(async {
+            let writer = ParquetObjectWriter::new(store.clone(), location.clone());
+            let mut writer = AsyncArrowWriter::try_new(writer, schema.clone(), None)?;
+            for batch in &part_batches {
+                writer.write(batch).await?;
+            }
+            writer.close().await?;
+            Ok::<_, deltalake::DeltaTableError>(store.head(&location).await?.size)
+        })?
+        .map_err(|e| Error::from(format!("Failed to write checkpoint part {}: {}", part, e)))?;
+    }
+
+    let hint = serde_json::json!({
+        "version": version,
+        "size": num_actions,
+        "parts": num_parts,
+        "sizeInBytes": size_in_bytes,
+        "numOfAddFiles": num_add_files,
+    });
+    let location: Path = log_path.child("_last_checkpoint");
+    block_on(async { store.put(&location, hint.to_string().into()).await })?
+        .map_err(|e| Error::from(format!("Failed to write _last_checkpoint: {}", e)))?;
+
+    Ok(Some(num_parts))
+}
+
+/// The actions of a checkpoint of `snapshot`, with the schema of the first
+/// batch
+///
+/// Like delta-rs, later batches are cast to the first schema: actions read
+/// back from an older checkpoint may come with a slightly different one.
+fn checkpoint_batches(
+    snapshot: Arc<KernelSnapshot>,
+    engine: &dyn delta_kernel::Engine,
+) -> Result<Vec<RecordBatch>> {
+    let to_error =
+        |e: &dyn std::fmt::Display| Error::from(format!("Failed to read actions: {}", e));
+    let writer = snapshot.checkpoint().map_err(|e| to_error(&e))?;
+    let data = writer.checkpoint_data(engine).map_err(|e| to_error(&e))?;
+
+    let mut batches: Vec<RecordBatch> = Vec::new();
+    for filtered in data {
+        let (data, mut selection) = filtered.map_err(|e| to_error(&e))?.into_parts();
+        let data = ArrowEngineData::try_from_engine_data(data).map_err(|e| to_error(&e))?;
+        let batch = data.record_batch();
+        // Rows past the end of the selection vector are selected
+        selection.resize(batch.num_rows(), true);
+        let batch =
+            filter_record_batch(batch, &BooleanArray::from(selection)).map_err(|e| to_error(&e))?;
+        let batch = match batches.first() {
+            Some(first) if first.schema() != batch.schema() => {
+                cast_record_batch(&batch, first.schema(), true, true).map_err(|e| to_error(&e))?
+            }
+            _ => batch,
+        };
+        if batch.num_rows() > 0 {
+            batches.push(batch);
+        }
+    }
+    Ok(batches)
+}
@@ -0,0 +1,224 @@
+//! Object store client cache
+//!
+//! Building an object store client for a cloud table sets up its HTTP
+//! connection pool and credential provider, and its first request has to
+//! authenticate. The clients are cached for the life of the R session, keyed
+//! by the store root (scheme, authority and bucket or container) and the
+//! storage options, so opening many tables in the same bucket with the same
+//! options shares one client and its credentials.
+//!
+//! Local and in-memory stores are not cached: they are cheap to build, and
+//! each `memory://` store holds its own data.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
+
+use deltalake::logstore::object_store::ObjectStoreScheme;
+use deltalake::logstore::{
+    object_store_factories, ObjectStoreFactory, ObjectStoreRef, StorageConfig,
+};
+use deltalake::{DeltaResult, DeltaTableError, Path};
+use extendr_api::prelude::*;
+use url::Url;
+
+use crate::path_to_url;
+
+/// Most clients kept; the least recently used is dropped beyond this, e.g.
+/// when SAS token callbacks give new options for every operation
+const MAX_CLIENTS: usize = 64;
+
+/// Schemes whose stores are not cached
+const UNCACHED_SCHEMES: &[&str] = &["file", "memory"];
+
+/// What identifies a cached client
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    /// Scheme, authority and bucket, e.g. `s3://bucket`
+    root: String,
+    options: Vec<(String, String)>,
+    retry: String,
+}
+
+struct CachedClient {
+    store: ObjectStoreRef,
+    created: Instant,
+    last_used: Instant,
+    hits: u64,
+}
+
+static CLIENT_CACHE: LazyLock<Mutex<HashMap<ClientKey, CachedClient>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The root of the store holding `url` and the path of `url` in that store
+///
+/// The store path is what object_store parses from the URL; the root is the
+/// rest of it, which keeps the container of Azure `https://` URLs given in
+/// their path.
+fn store_root(url: &Url) -> DeltaResult<(String, Path)> {
+    // A Unity Catalog table is a store of its own
+    if url.scheme() == crate::unity::SCHEME {
+        return Ok((
+            url[..url::Position::BeforePath].to_string(),
+            Path::default(),
+        ));
+    }
+    let (_, path) = ObjectStoreScheme::parse(url).map_err(|e| DeltaTableError::GenericError {
+        source: Box::new(e),
+    })?;
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let in_store = path.parts().count().min(segments.len());
+    let mut root = url[..url::Position::BeforePath].to_string();
+    for segment in &segments[..segments.len() - in_store] {
+        root.push('/');
+        root.push_str(segment);
+    }
+    Ok((root, path))
+}
+
+/// Wraps another factory to reuse the clients it builds
+struct CachingFactory {
+    inner: Arc<dyn ObjectStoreFactory>,
+}
+
+impl std::fmt::Debug for CachingFactory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CachingFactory")
+    }
+}
+
+impl ObjectStoreFactory for CachingFactory {
+    fn parse_url_opts(
+        &self,
+        url: &Url,
+        config: &StorageConfig,
+    ) -> DeltaResult<(ObjectStoreRef, Path)> {
+        // Stores bound to a dedicated IO runtime cannot outlive it
+        if config.runtime.is_some() {
+            return self.inner.parse_url_opts(url, config);
+        }
+        let (root, path) = store_root(url)?;
+        let mut options: Vec<(String, String)> = config
+            .raw
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        options.sort();
+        let key = ClientKey {
+            root,
+            options,
+            retry: format!("{:?}", config.retry),
+        };
+
+        if let Some(client) = CLIENT_CACHE.lock().unwrap().get_mut(&key) {
+            client.last_used = Instant::now();
+            client.hits += 1;
+            return Ok((client.store.clone(), path));
+        }
+
+        let (store, path) = self.inner.parse_url_opts(url, config)?;
+        let mut cache = CLIENT_CACHE.lock().unwrap();
+        if cache.len() >= MAX_CLIENTS {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, client)| client.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        let now = Instant::now();
+        let client = CachedClient {
+            store: store.clone(),
+            created: now,
+            last_used: now,
+            hits: 0,
+        };
+        cache.insert(key, client);
+        Ok((store, path))
+    }
+}
+
+/// Wrap the registered factories of remote stores to cache their clients
+///
+/// Called after the storage handlers are registered.
+pub(crate) fn register_handlers() {
+    let registry = object_store_factories();
+    let schemes: Vec<Url> = registry
+        .iter()
+        .map(|entry| entry.key().clone())
+        .filter(|scheme| !UNCACHED_SCHEMES.contains(&scheme.scheme()))
+        .collect();
+    for scheme in schemes {
+        let Some(inner) = registry.get(&scheme).map(|entry| entry.value().clone()) else {
+            continue;
+        };
+        registry.insert(scheme, Arc::new(CachingFactory { inner }));
+    }
+}
+
+/// Remove clients from the object store client cache
+///
+/// Returns the number of clients removed.
+///
+/// @param path Path of a table whose store clients to remove, or NULL to
+///   clear the cache
+#[extendr]
+pub fn client_cache_clear(path: Nullable<&str>) -> Result<i32> {
+    let mut cache = CLIENT_CACHE.lock().unwrap();
+    let before = cache.len();
+    match path {
+        Nullable::NotNull(path) => {
+            let url = path_to_url(path).map_err(Error::from)?;
+            let (root, _) = store_root(&url).map_err(|e| Error::from(e.to_string()))?;
+            cache.retain(|key, _| key.root != root);
+        }
+        Nullable::Null => cache.clear(),
+    }
+    Ok((before - cache.len()) as i32)
+}
+
+/// List the clients in the object store client cache
+///
+/// Returns a data.frame with the `store` root, number of storage `options`,
+/// `age` and `idle` seconds and `hits` of each cached client.
+#[extendr]
+pub fn client_cache_entries() -> Robj {
+    let cache = CLIENT_CACHE.lock().unwrap();
+    let mut entries: Vec<(&ClientKey, &CachedClient)> = cache.iter().collect();
+    entries.sort_by_key(|(_, client)| client.created);
+
+    let stores: Vec<String> = entries.iter().map(|(key, _)| key.root.clone()).collect();
+    let options: Vec<i32> = entries
+        .iter()
+        .map(|(key, _)| key.options.len() as i32)
+        .collect();
+    let age: Vec<f64> = entries
+        .iter()
+        .map(|(_, client)| client.created.elapsed().as_secs_f64())
+        .collect();
+    let idle: Vec<f64> = entries
+        .iter()
+        .map(|(_, client)| client.last_used.elapsed().as_secs_f64())
+        .collect();
+    let hits: Vec<f64> = entries
+        .iter()
+        .map(|(_, client)| client.hits as f64)
+        .collect();
+    data_frame!(
+        store = stores,
+        options = options,
+        age = age,
+        idle = idle,
+        hits = hits
+    )
+}
+
+extendr_module! {
+    mod clients;
+    fn client_cache_clear;
+    fn client_cache_entries;
+}
@@ -0,0 +1,376 @@
+//! Column mapping
+//!
+//! Tables with column mapping (`delta.columnMapping.mode` of `name` or `id`)
+//! store their columns in Parquet under physical names (and field ids) that
+//! are recorded in the schema, so columns can be renamed or dropped without
+//! rewriting the data. Reads go through delta-kernel, which maps the physical
+//! columns back to the table's. delta-rs does not write such tables yet, so
+//! appends and overwrites are written here: the batches are renamed to the
+//! physical schema, written with a RecordBatchWriter and committed by hand.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::array::{make_array, ArrayData, RecordBatch, RecordBatchReader};
+use arrow::datatypes::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use arrow::error::ArrowError;
+use delta_kernel::engine::arrow_conversion::TryIntoArrow;
+use delta_kernel::schema::{ColumnMetadataKey, MetadataValue};
+use delta_kernel::table_features::ColumnMappingMode;
+use delta_kernel::table_properties::TableProperties;
+use deltalake::kernel::schema::cast_record_batch;
+use deltalake::kernel::transaction::{CommitBuilder, TableReference};
+use deltalake::kernel::{
+    Action, ArrayType, DataType as KernelDT, EagerSnapshot, MapType, Metadata, Protocol,
+    StructField, StructType, Transaction,
+};
+use deltalake::protocol::{DeltaOperation, SaveMode};
+use deltalake::writer::{DeltaWriter, RecordBatchWriter};
+use deltalake::{DeltaResult, DeltaTable, DeltaTableError};
+use futures::TryStreamExt;
+
+use crate::block_on;
+use crate::commit::commit_properties;
+use crate::uniform::iceberg_compatible;
+use crate::write::StatsOptions;
+
+/// Target size of the files written when none is given (100 MiB, as for
+/// the WriteBuilder)
+const DEFAULT_TARGET_FILE_SIZE: usize = 104_857_600;
+
+/// Arrow metadata key the Parquet writer reads field ids from
+const PARQUET_FIELD_ID: &str = "PARQUET:field_id";
+
+/// Column mapping mode of a table, `None` for tables that do not exist yet
+pub(crate) fn column_mapping_mode(table: &DeltaTable) -> ColumnMappingMode {
+    table
+        .state
+        .as_ref()
+        .and_then(|state| state.snapshot().table_properties().column_mapping_mode)
+        .unwrap_or(ColumnMappingMode::None)
+}
+
+/// Whether a table configuration turns column mapping on
+pub(crate) fn requests_column_mapping(configuration: &HashMap<String, Option<String>>) -> bool {
+    matches!(
+        configuration
+            .get("delta.columnMapping.mode")
+            .and_then(|mode| mode.as_deref()),
+        Some("name") | Some("id")
+    )
+}
+
+// ============================================================================
+// Table Creation
+// ============================================================================
+
+/// Give the columns of a new table their column mapping metadata
+///
+/// Every field, including the fields nested in structs, arrays and maps,
+/// gets a column id and a random physical name. The configuration records
+/// the largest id and the protocol versions that introduced column mapping.
+pub(crate) fn with_column_mapping(
+    columns: Vec<StructField>,
+    configuration: &mut HashMap<String, Option<String>>,
+) -> Vec<StructField> {
+    let mut max_id = 0;
+    let columns = columns
+        .into_iter()
+        .map(|field| annotate_field(field, &mut max_id))
+        .collect();
+
+    configuration.insert(
+        "delta.columnMapping.maxColumnId".to_string(),
+        Some(max_id.to_string()),
+    );
+    for (key, version) in [
+        ("delta.minReaderVersion", "2"),
+        ("delta.minWriterVersion", "5"),
+    ] {
+        configuration
+            .entry(key.to_string())
+            .or_insert_with(|| Some(version.to_string()));
+    }
+    columns
+}
+
+fn annotate_field(mut field: StructField, max_id: &mut i64) -> StructField {
+    field.data_type = annotate_type(field.data_type, max_id);
+    *max_id += 1;
+    field.metadata.insert(
+        ColumnMetadataKey::ColumnMappingId.as_ref().to_string(),
+        MetadataValue::Number(*max_id),
+    );
+    field.metadata.insert(
+        ColumnMetadataKey::ColumnMappingPhysicalName
+            .as_ref()
+            .to_string(),
+        MetadataValue::String(format!("col-{}", uuid::Uuid::new_v4())),
+    );
+    field
+}
+
+fn annotate_type(data_type: KernelDT, max_id: &mut i64) -> KernelDT {
+    match data_type {
+        KernelDT::Struct(fields) => {
+            let fields: Vec<StructField> = fields
+                .fields()
+                .cloned()
+                .map(|field| annotate_field(field, max_id))
+                .collect();
+            // The field names are those of a valid struct, so this cannot fail
+            KernelDT::Struct(Box::new(
+                StructType::try_new(fields).expect("struct fields are unique"),
+            ))
+        }
+        KernelDT::Array(array) => KernelDT::Array(Box::new(ArrayType::new(
+            annotate_type(array.element_type, max_id),
+            array.contains_null,
+        ))),
+        KernelDT::Map(map) => KernelDT::Map(Box::new(MapType::new(
+            annotate_type(map.key_type, max_id),
+            annotate_type(map.value_type, max_id),
+            map.value_contains_null,
+        ))),
+        other => other,
+    }
+}
+
+// ============================================================================
+// Writes
+// ============================================================================
+
+/// Append or overwrite the data of a table with column mapping
+///
+/// The batches are cast to the table schema and renamed to its physical
+/// schema, written to files of about `target_file_size` bytes and committed
+/// as a single WRITE. `mode = "error"` fails and `mode = "ignore"` writes
+/// nothing, since the table exists.
+pub(crate) fn write_column_mapped(
+    mut table: DeltaTable,
+    reader: Box<dyn RecordBatchReader + Send>,
+    save_mode: SaveMode,
+    target_file_size: Option<usize>,
+    stats: &StatsOptions,
+    storage_options: Option<HashMap<String, String>>,
+    app_txn: Option<Transaction>,
+) -> DeltaResult<DeltaTable> {
+    match save_mode {
+        SaveMode::ErrorIfExists => {
+            return Err(DeltaTableError::Generic(format!(
+                "A table already exists at: {}",
+                table.table_url()
+            )))
+        }
+        SaveMode::Ignore => return Ok(table),
+        SaveMode::Append | SaveMode::Overwrite => {}
+    }
+
+    let state = table.snapshot()?.clone();
+    let snapshot = state.snapshot();
+    let mode = column_mapping_mode(&table);
+    let logical_schema = snapshot.arrow_schema();
+    let schema = snapshot.schema();
+    // Iceberg readers match columns by field id, whatever the mapping mode
+    let id_mode = if iceberg_compatible(&table) {
+        ColumnMappingMode::Id
+    } else {
+        mode
+    };
+    let physical_schema: ArrowSchema = (&schema.make_physical(id_mode)).try_into_arrow()?;
+    let physical_schema = Arc::new(with_field_ids(physical_schema));
+    let partition_columns = snapshot
+        .metadata()
+        .partition_columns()
+        .iter()
+        .map(|column| physical_name(&schema, column, mode))
+        .collect::<DeltaResult<Vec<_>>>()?;
+
+    let mut writer = RecordBatchWriter::try_new(
+        table.table_url().as_str(),
+        physical_schema.clone(),
+        Some(partition_columns),
+        storage_options,
+    )?;
+    let file_name =
+        |column: &str| physical_name(&schema, column, mode).unwrap_or_else(|_| column.to_string());
+    if let Some(properties) = stats.writer_properties(file_name) {
+        writer = writer.with_writer_properties(properties);
+    }
+    let target_file_size = target_file_size.unwrap_or(DEFAULT_TARGET_FILE_SIZE);
+    let log_store = table.log_store();
+
+    let version = block_on(async {
+        let mut actions: Vec<Action> = Vec::new();
+        for batch in reader {
+            let batch = cast_record_batch(&batch?, logical_schema.clone(), false, false)?;
+            writer.write(to_physical(&batch, &physical_schema)?).await?;
+            if writer.buffer_len() >= target_file_size {
+                actions.extend(writer.flush().await?.into_iter().map(Action::Add));
+            }
+        }
+        actions.extend(writer.flush().await?.into_iter().map(Action::Add));
+
+        if save_mode == SaveMode::Overwrite {
+            let removes: Vec<Action> = snapshot
+                .file_views(&log_store, None)
+                .map_ok(|file| Action::Remove(file.remove_action(true)))
+                .try_collect()
+                .await?;
+            actions.extend(removes);
+        }
+
+        let mut properties = commit_properties();
+        if let Some(txn) = app_txn {
+            properties = properties.with_application_transaction(txn);
+        }
+        let operation = DeltaOperation::Write {
+            mode: save_mode,
+            partition_by: None,
+            predicate: None,
+        };
+        let reference = MappedTable::new(snapshot)?;
+        let commit = CommitBuilder::from(properties)
+            .with_actions(actions)
+            .build(Some(&reference), log_store.clone(), operation)
+            .await?;
+        Ok::<_, DeltaTableError>(commit.version())
+    })??;
+
+    block_on(async { table.load_version(version).await })??;
+    Ok(table)
+}
+
+/// Physical name of a top-level column
+fn physical_name(
+    schema: &StructType,
+    column: &str,
+    mode: ColumnMappingMode,
+) -> DeltaResult<String> {
+    let field = schema.field(column).ok_or_else(|| {
+        DeltaTableError::Generic(format!("Column {} is not in the table", column))
+    })?;
+    Ok(field.physical_name(mode).to_string())
+}
+
+/// Copy the column ids of `id` mode to the metadata key the Parquet writer
+/// records field ids from, so readers can match the columns by id
+fn with_field_ids(schema: ArrowSchema) -> ArrowSchema {
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .map(|field| field_with_id(field))
+        .collect();
+    ArrowSchema::new_with_metadata(fields, schema.metadata().clone())
+}
+
+fn field_with_id(field: &Field) -> Field {
+    let data_type = match field.data_type() {
+        DataType::Struct(fields) => {
+            DataType::Struct(fields.iter().map(|f| field_with_id(f)).collect())
+        }
+        DataType::List(element) => DataType::List(Arc::new(field_with_id(element))),
+        DataType::LargeList(element) => DataType::LargeList(Arc::new(field_with_id(element))),
+        DataType::Map(entries, sorted) => DataType::Map(Arc::new(field_with_id(entries)), *sorted),
+        other => other.clone(),
+    };
+    let mut metadata = field.metadata().clone();
+    if let Some(id) = metadata
+        .get(ColumnMetadataKey::ParquetFieldId.as_ref())
+        .cloned()
+    {
+        metadata.insert(PARQUET_FIELD_ID.to_string(), id);
+    }
+    field
+        .clone()
+        .with_data_type(data_type)
+        .with_metadata(metadata)
+}
+
+/// Rename the columns of a batch in the table schema to the physical schema
+///
+/// Both schemas only differ in their field names and metadata, so the
+/// column data is reused as is.
+fn to_physical(batch: &RecordBatch, physical_schema: &SchemaRef) -> DeltaResult<RecordBatch> {
+    let columns = batch
+        .columns()
+        .iter()
+        .zip(physical_schema.fields())
+        .map(|(column, field)| Ok(make_array(retype(column.to_data(), field.data_type())?)))
+        .collect::<std::result::Result<Vec<_>, ArrowError>>()?;
+    Ok(RecordBatch::try_new(physical_schema.clone(), columns)?)
+}
+
+fn retype(data: ArrayData, data_type: &DataType) -> std::result::Result<ArrayData, ArrowError> {
+    let children: Vec<&DataType> = match data_type {
+        DataType::Struct(fields) => fields.iter().map(|field| field.data_type()).collect(),
+        DataType::List(element) | DataType::LargeList(element) | DataType::Map(element, _) => {
+            vec![element.data_type()]
+        }
+        _ => return Ok(data),
+    };
+    let child_data = data
+        .child_data()
+        .iter()
+        .zip(children)
+        .map(|(child, data_type)| retype(child.clone(), data_type))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    data.into_builder()
+        .data_type(data_type.clone())
+        .child_data(child_data)
+        .build()
+}
+
+/// Snapshot the commit of a column-mapped write is checked against
+///
+/// The commit checks refuse tables with column mapping or Iceberg
+/// compatibility, which delta-rs cannot write itself. The files of these
+/// writes already carry the physical names and field ids those features
+/// require, so they are checked against the table's protocol without them
+/// instead; the commit itself leaves the protocol and properties as they are.
+struct MappedTable<'a> {
+    snapshot: &'a EagerSnapshot,
+    protocol: Protocol,
+}
+
+impl<'a> MappedTable<'a> {
+    fn new(snapshot: &'a EagerSnapshot) -> DeltaResult<Self> {
+        let mut protocol = serde_json::to_value(snapshot.protocol())?;
+        for (key, version, base) in [("minReaderVersion", 2, 1), ("minWriterVersion", 5, 4)] {
+            if protocol[key] == version {
+                protocol[key] = base.into();
+            }
+        }
+        for key in ["readerFeatures", "writerFeatures"] {
+            if let Some(features) = protocol[key].as_array_mut() {
+                features.retain(|feature| {
+                    !["columnMapping", "icebergCompatV1", "icebergCompatV2"]
+                        .iter()
+                        .any(|written| feature == written)
+                });
+            }
+        }
+        Ok(Self {
+            snapshot,
+            protocol: serde_json::from_value(protocol)?,
+        })
+    }
+}
+
+impl TableReference for MappedTable<'_> {
+    fn config(&self) -> &TableProperties {
+        self.snapshot.table_properties()
+    }
+
+    fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+
+    fn metadata(&self) -> &Metadata {
+        self.snapshot.metadata()
+    }
+
+    fn eager_snapshot(&self) -> &EagerSnapshot {
+        self.snapshot
+    }
+}
@@ -0,0 +1,453 @@
+//! Commit inspection helpers
+//!
+//! Every mutating operation returns the same result shape to R: the new
+//! version, the commit timestamp and operation name, a list of metrics and the
+//! user metadata recorded in the commit. This module reads that information
+//! back from the commit log.
+//!
+//! Every mutating operation can also record an application transaction (a
+//! `txn` action with an app id and version) in its commit, and is skipped
+//! when the table already records that version for the app id, so
+//! orchestrators can retry any step of a pipeline without applying it twice.
+//!
+//! Commits are made with [commit_properties], so the checkpoints delta-rs
+//! writes every `delta.checkpointInterval` versions can be turned off for
+//! the session.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use deltalake::kernel::transaction::{CommitBuilder, CommitProperties};
+use deltalake::kernel::{Action, CommitInfo, Transaction};
+use deltalake::logstore::{commit_uri_from_version, get_actions};
+use deltalake::protocol::DeltaOperation;
+use deltalake::table::config::TablePropertiesExt;
+use deltalake::DeltaTable;
+use extendr_api::prelude::*;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::context::{kind_error, ErrorKind, RaisedResult};
+use crate::{block_on, debug, file_concurrency};
+
+/// Whether commits write the checkpoints `delta.checkpointInterval` asks for
+static AUTO_CHECKPOINT: AtomicBool = AtomicBool::new(true);
+
+/// Properties of the commits of every operation
+///
+/// By default, delta-rs writes a checkpoint after a commit when the table
+/// reached a multiple of `delta.checkpointInterval` versions (100 by
+/// default); these properties leave that out while automatic checkpoints
+/// are off. Operations committing with other properties, such as an
+/// application transaction, start from these.
+pub(crate) fn commit_properties() -> CommitProperties {
+    CommitProperties::default().with_create_checkpoint(AUTO_CHECKPOINT.load(Ordering::Relaxed))
+}
+
+/// Turn automatic checkpoints on or off
+///
+/// Returns whether they were on.
+///
+/// @param enable Whether commits write a checkpoint every `delta.checkpointInterval` versions
+#[extendr]
+pub fn set_auto_checkpoint(enable: bool) -> bool {
+    AUTO_CHECKPOINT.swap(enable, Ordering::Relaxed)
+}
+
+/// Keys in `commitInfo` written by delta-rs itself rather than by the user
+const RESERVED_COMMIT_KEYS: &[&str] = &["operationMetrics", "clientVersion", "readVersion"];
+
+/// Start of the message of the error raised for operations that would
+/// remove or change rows of an append-only table, an
+/// [ErrorKind::AppendOnly] that R gives the `deltaR_append_only_error` class
+const APPEND_ONLY_ERROR: &str = "Table is append-only (delta.appendOnly = true)";
+
+/// Fail if the table is append-only
+///
+/// Called before planning operations that remove or rewrite existing rows,
+/// so they fail with the same error whatever delta-rs would have done: it
+/// checks some operations only when committing, and others not at all.
+/// Tables that do not exist yet have no properties and pass.
+pub(crate) fn check_append_only(table: &DeltaTable, operation: &str) -> RaisedResult<()> {
+    match table.state.as_ref() {
+        Some(state) if state.snapshot().table_properties().append_only() => Err(kind_error(
+            ErrorKind::AppendOnly,
+            format!(
+                "{}: {} would remove or change existing rows. Only appends are allowed; \
+                     set delta.appendOnly to false to change existing rows",
+                APPEND_ONLY_ERROR, operation
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Read the actions of the table's current version
+///
+/// Returns an empty vector when no new version was committed since
+/// `read_version` (e.g. a dry run or `mode = "ignore"`).
+pub(crate) fn new_commit_actions(
+    table: &DeltaTable,
+    read_version: Option<i64>,
+) -> Result<Vec<Action>> {
+    let version = match table.version() {
+        Some(v) if read_version != Some(v) => v,
+        _ => return Ok(Vec::new()),
+    };
+
+    let log_store = table.log_store();
+    let bytes = block_on(async { log_store.read_commit_entry(version).await })?
+        .map_err(|e| Error::from(format!("Failed to read commit {}: {}", version, e)))?;
+
+    match bytes {
+        Some(bytes) => get_actions(version, &bytes)
+            .map_err(|e| Error::from(format!("Failed to read commit {}: {}", version, e))),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Read the actions of every version committed since `read_version`
+///
+/// Operations that commit in several steps (e.g. an optimize with a minimum
+/// commit interval) spread their actions over the versions after the one
+/// they read.
+pub(crate) fn commit_actions_since(
+    table: &DeltaTable,
+    read_version: Option<i64>,
+) -> Result<Vec<Action>> {
+    let Some(version) = table.version() else {
+        return Ok(Vec::new());
+    };
+    let first = read_version.map_or(0, |v| v + 1);
+
+    let log_store = table.log_store();
+    let mut actions = Vec::new();
+    for v in first..=version {
+        let bytes = block_on(async { log_store.read_commit_entry(v).await })?
+            .map_err(|e| Error::from(format!("Failed to read commit {}: {}", v, e)))?;
+        if let Some(bytes) = bytes {
+            actions.extend(
+                get_actions(v, &bytes)
+                    .map_err(|e| Error::from(format!("Failed to read commit {}: {}", v, e)))?,
+            );
+        }
+    }
+    Ok(actions)
+}
+
+/// Read the `commitInfo` of each of `versions`, concurrently
+///
+/// A version whose commit file no longer exists (e.g. removed by log
+/// cleanup) is `None`; a commit without a `commitInfo` gets a default one.
+pub(crate) fn read_commit_infos(
+    table: &DeltaTable,
+    versions: &[i64],
+) -> Result<Vec<Option<CommitInfo>>> {
+    let log_store = table.log_store();
+    block_on(async {
+        futures::stream::iter(versions.iter().copied())
+            .map(|v| {
+                let log_store = log_store.clone();
+                async move {
+                    let Some(bytes) = log_store.read_commit_entry(v).await? else {
+                        return Ok(None);
+                    };
+                    let info = get_actions(v, &bytes)?
+                        .into_iter()
+                        .find_map(|action| match action {
+                            Action::CommitInfo(info) => Some(info),
+                            _ => None,
+                        })
+                        .unwrap_or_default();
+                    Ok::<_, deltalake::DeltaTableError>(Some(info))
+                }
+            })
+            .buffered(file_concurrency(table))
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to read commits: {}", e)))
+}
+
+/// The timestamp of a commit, in milliseconds since the epoch
+///
+/// This is the timestamp recorded in the commit's `commitInfo`, or the
+/// modification time of the commit file when there is none. Returns `None`
+/// when the commit file does not exist.
+pub(crate) fn commit_timestamp(table: &DeltaTable, version: i64) -> Result<Option<i64>> {
+    let Some(info) = read_commit_infos(table, &[version])?.pop().flatten() else {
+        return Ok(None);
+    };
+    if let Some(ts) = info.timestamp {
+        return Ok(Some(ts));
+    }
+    let meta = block_on(async {
+        table
+            .log_store()
+            .object_store(None)
+            .head(&commit_uri_from_version(version))
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to read commit {}: {}", version, e)))?;
+    Ok(Some(meta.last_modified.timestamp_millis()))
+}
+
+/// The earliest version of a table that can still be loaded
+///
+/// That is version 0 while its commit exists; once log cleanup has removed
+/// the first commits, it is the oldest checkpoint (or the oldest commit when
+/// there is no checkpoint).
+pub(crate) fn earliest_version(table: &DeltaTable) -> Result<i64> {
+    let log_store = table.log_store();
+    let files: Vec<_> = block_on(async {
+        log_store
+            .object_store(None)
+            .list(Some(log_store.log_path()))
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to list the transaction log: {}", e)))?;
+
+    let mut commits: Option<i64> = None;
+    let mut checkpoints: Option<i64> = None;
+    for meta in files {
+        let Some(name) = meta.location.filename() else {
+            continue;
+        };
+        let (Some(digits), Some(rest)) = (name.get(..20), name.get(20..)) else {
+            continue;
+        };
+        let Ok(version) = digits.parse::<i64>() else {
+            continue;
+        };
+        if rest == ".json" {
+            commits = Some(commits.map_or(version, |v| v.min(version)));
+        } else if rest.starts_with(".checkpoint") && rest.ends_with(".parquet") {
+            checkpoints = Some(checkpoints.map_or(version, |v| v.min(version)));
+        }
+    }
+
+    match (commits, checkpoints) {
+        (Some(0), _) => Ok(0),
+        (_, Some(checkpoint)) => Ok(checkpoint),
+        (Some(commit), None) => Ok(commit),
+        (None, None) => Err(Error::from("The transaction log has no commits")),
+    }
+}
+
+/// The version of the newest checkpoint in the transaction log, if any
+pub(crate) fn latest_checkpoint(table: &DeltaTable) -> Result<Option<i64>> {
+    let log_store = table.log_store();
+    let files: Vec<_> = block_on(async {
+        log_store
+            .object_store(None)
+            .list(Some(log_store.log_path()))
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to list the transaction log: {}", e)))?;
+
+    Ok(files
+        .iter()
+        .filter_map(|meta| {
+            let name = meta.location.filename()?;
+            let rest = name.get(20..)?;
+            if !(rest.starts_with(".checkpoint") && rest.ends_with(".parquet")) {
+                return None;
+            }
+            name.get(..20)?.parse::<i64>().ok()
+        })
+        .max())
+}
+
+/// Convert a JSON value from the commit log to an R value
+pub(crate) fn json_to_robj(value: &serde_json::Value) -> Robj {
+    match value {
+        serde_json::Value::Null => ().into_robj(),
+        serde_json::Value::Bool(b) => b.into_robj(),
+        serde_json::Value::Number(n) => n.as_f64().into_robj(),
+        serde_json::Value::String(s) => s.into_robj(),
+        other => other.to_string().into_robj(),
+    }
+}
+
+/// Build the standard result list for an operation
+///
+/// `operation` is used when nothing was committed; otherwise the operation
+/// recorded in the commit is reported. The timestamp is in milliseconds since
+/// the epoch (`NA` when nothing was committed).
+pub(crate) fn operation_result(
+    table: &DeltaTable,
+    actions: &[Action],
+    operation: &str,
+    metrics: List,
+) -> Result<List> {
+    let version = table.version().unwrap_or(-1);
+
+    let commit_info = actions.iter().find_map(|action| match action {
+        Action::CommitInfo(info) => Some(info),
+        _ => None,
+    });
+
+    let timestamp = commit_info
+        .and_then(|info| info.timestamp)
+        .map(|ts| ts as f64);
+    let operation = commit_info
+        .and_then(|info| info.operation.clone())
+        .unwrap_or_else(|| operation.to_string());
+    if let Some(info) = commit_info.filter(|_| debug::enabled()) {
+        let info = serde_json::to_string(info).unwrap_or_default();
+        debug::log(
+            &format!("committed {} as version {}", operation, version),
+            &info,
+        );
+    }
+
+    let user_metadata = match commit_info {
+        Some(info) => {
+            let mut entries: Vec<(&String, &serde_json::Value)> = info
+                .info
+                .iter()
+                .filter(|(key, _)| !RESERVED_COMMIT_KEYS.contains(&key.as_str()))
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            List::from_pairs(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.as_str(), json_to_robj(value))),
+            )
+        }
+        None => List::new(0),
+    };
+
+    Ok(list!(
+        version = version,
+        timestamp = timestamp,
+        operation = operation,
+        metrics = metrics,
+        user_metadata = user_metadata
+    ))
+}
+
+/// Add metrics to the result list built by [`operation_result`]
+pub(crate) fn append_metrics(result: List, extra: List) -> Result<List> {
+    let pairs: Vec<(String, Robj)> = result
+        .iter()
+        .map(|(name, value)| {
+            if name != "metrics" {
+                return Ok((name.to_string(), value));
+            }
+            let metrics = value
+                .as_list()
+                .ok_or_else(|| Error::from("Operation metrics must be a list"))?;
+            let merged: Vec<(String, Robj)> = metrics
+                .iter()
+                .chain(extra.iter())
+                .map(|(name, value)| (name.to_string(), value))
+                .collect();
+            Ok((name.to_string(), List::from_pairs(merged).into_robj()))
+        })
+        .collect::<Result<_>>()?;
+    Ok(List::from_pairs(pairs))
+}
+
+/// An application transaction to record with an operation
+pub(crate) struct AppTransaction {
+    pub(crate) txn: Transaction,
+    /// Whether the table already records this version (or a later one) for
+    /// the app id, i.e. the operation was already applied
+    pub(crate) skipped: bool,
+}
+
+impl AppTransaction {
+    /// Read an application transaction passed from R as
+    /// `list(app_id = , version = )` and check it against the table
+    pub(crate) fn from_r(
+        table: &DeltaTable,
+        app_transaction: &Nullable<List>,
+    ) -> Result<Option<Self>> {
+        let Nullable::NotNull(app_transaction) = app_transaction else {
+            return Ok(None);
+        };
+        let fields: std::collections::HashMap<&str, Robj> = app_transaction.iter().collect();
+        let app_id = fields
+            .get("app_id")
+            .and_then(|id| id.as_str())
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| Error::from("An application transaction needs an 'app_id'"))?;
+        let version = fields
+            .get("version")
+            .and_then(|v| v.as_real().or_else(|| v.as_integer().map(f64::from)))
+            .filter(|v| v.is_finite() && *v >= 0.0 && v.fract() == 0.0)
+            .ok_or_else(|| {
+                Error::from("An application transaction needs a non-negative whole 'version'")
+            })? as i64;
+
+        let committed = match table.state.as_ref() {
+            Some(state) => block_on(async {
+                state
+                    .transaction_version(table.log_store().as_ref(), app_id)
+                    .await
+            })?
+            .map_err(|e| Error::from(format!("Failed to read application transaction: {}", e)))?,
+            None => None,
+        };
+        Ok(Some(AppTransaction {
+            txn: Transaction::new_with_last_update(
+                app_id,
+                version,
+                Some(chrono::Utc::now().timestamp_millis()),
+            ),
+            skipped: committed.is_some_and(|v| v >= version),
+        }))
+    }
+
+    /// Commit properties recording the transaction
+    pub(crate) fn commit_properties(&self) -> CommitProperties {
+        commit_properties().with_application_transaction(self.txn.clone())
+    }
+
+    /// Record the transaction in a commit of its own after `operation`
+    ///
+    /// delta-rs leaves application transactions out of the commits of an
+    /// optimize, so they are recorded in the next version. Optimizing again
+    /// after a failure between the two commits does not change the data.
+    pub(crate) fn commit_after(
+        &self,
+        mut table: DeltaTable,
+        operation: DeltaOperation,
+    ) -> Result<DeltaTable> {
+        let snapshot = table
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?
+            .clone();
+        let commit = block_on(async {
+            CommitBuilder::from(self.commit_properties())
+                .build(Some(&snapshot), table.log_store(), operation)
+                .await
+        })?
+        .map_err(|e| Error::from(format!("Failed to commit application transaction: {}", e)))?;
+        block_on(async { table.load_version(commit.version()).await })?
+            .map_err(|e| Error::from(e.to_string()))?;
+        Ok(table)
+    }
+
+    /// The `app_id`, `app_version` and `skipped` metrics of the operation
+    pub(crate) fn metrics(&self) -> List {
+        list!(
+            app_id = self.txn.app_id.as_str(),
+            app_version = self.txn.version,
+            skipped = self.skipped
+        )
+    }
+
+    /// The result of an operation skipped because it was already applied:
+    /// the current version, without operation metrics
+    pub(crate) fn skipped_result(&self, table: &DeltaTable, operation: &str) -> Result<List> {
+        let result = operation_result(table, &[], operation, List::new(0))?;
+        append_metrics(result, self.metrics())
+    }
+}
+
+extendr_module! {
+    mod commit;
+    fn set_auto_checkpoint;
+}
@@ -0,0 +1,539 @@
+//! Table constraints
+//!
+//! Lists the CHECK constraints and column invariants defined on a table, and
+//! checks SQL boolean expressions against the rows already in a table, so a
+//! CHECK constraint can be vetted before it is added.
+//!
+//! Data written to a table is checked against its constraints as it streams
+//! in, so a violation is reported with the constraint, the number of
+//! violating rows and a sample of their values. delta-rs only reports the
+//! first violating row of a batch.
+
+use std::sync::{Arc, Mutex};
+
+use arrow::array::{BooleanArray, RecordBatch, RecordBatchReader};
+use arrow::datatypes::SchemaRef;
+use arrow::error::ArrowError;
+use arrow::util::display::array_value_to_string;
+use deltalake::arrow::array::{Array, Int64Array, StringArray};
+use deltalake::arrow::datatypes::DataType;
+use deltalake::datafusion::common::DFSchema;
+use deltalake::datafusion::dataframe::DataFrame;
+use deltalake::datafusion::functions_aggregate::expr_fn::count;
+use deltalake::datafusion::physical_expr::PhysicalExpr;
+use deltalake::datafusion::prelude::{cast, ident, lit, not, Expr, SessionContext};
+use deltalake::delta_datafusion::{DeltaScanConfigBuilder, DeltaTableProvider};
+use deltalake::kernel::{Action, Metadata, StructTypeExt};
+use deltalake::logstore::get_actions;
+use deltalake::{DeltaResult, DeltaTable, DeltaTableError};
+use extendr_api::prelude::*;
+use futures::StreamExt;
+
+use crate::block_on;
+use crate::context::{kind_error, ErrorKind, RaisedError};
+use crate::read::session_context;
+
+/// Prefix of the table properties that hold CHECK constraints
+const CONSTRAINT_PREFIX: &str = "delta.constraints.";
+
+/// A CHECK constraint or column invariant
+#[derive(PartialEq)]
+pub(crate) struct ConstraintDefinition {
+    /// `"check"` or `"invariant"`
+    pub(crate) kind: &'static str,
+    /// The constraint name, or the column path of an invariant
+    pub(crate) name: String,
+    pub(crate) expression: String,
+}
+
+/// The constraints defined by a metadata action
+fn metadata_constraints(metadata: &Metadata) -> DeltaResult<Vec<ConstraintDefinition>> {
+    let mut constraints: Vec<ConstraintDefinition> = metadata
+        .configuration()
+        .iter()
+        .filter_map(|(key, value)| {
+            key.strip_prefix(CONSTRAINT_PREFIX)
+                .map(|name| ConstraintDefinition {
+                    kind: "check",
+                    name: name.to_string(),
+                    expression: value.clone(),
+                })
+        })
+        .collect();
+    constraints.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let invariants = metadata.parse_schema()?.get_invariants()?;
+    constraints.extend(
+        invariants
+            .into_iter()
+            .map(|invariant| ConstraintDefinition {
+                kind: "invariant",
+                name: invariant.field_name,
+                expression: invariant.invariant_sql,
+            }),
+    );
+    Ok(constraints)
+}
+
+/// List the constraints of a table with the version that added each one
+///
+/// The log is read backwards from the loaded version until the metaData
+/// action that introduced each constraint (in its current form) is found.
+/// The version is `None` when that commit is no longer in the log.
+pub(crate) fn table_constraints(
+    table: &DeltaTable,
+) -> Result<Vec<(ConstraintDefinition, Option<i64>)>> {
+    let snapshot = table.snapshot().map_err(|e| Error::from(e.to_string()))?;
+    let current = metadata_constraints(snapshot.metadata())
+        .map_err(|e| Error::from(format!("Failed to read table constraints: {}", e)))?;
+    if current.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut added: Vec<Option<i64>> = vec![None; current.len()];
+    let mut resolved = vec![false; current.len()];
+
+    let log_store = table.log_store();
+    block_on(async {
+        let mut commits = futures::stream::iter((0..=snapshot.version()).rev())
+            .map(|version| {
+                let log_store = log_store.clone();
+                async move {
+                    let bytes = log_store.read_commit_entry(version).await?;
+                    Ok::<_, DeltaTableError>((version, bytes))
+                }
+            })
+            .buffered(16);
+
+        while let Some(commit) = commits.next().await {
+            let (version, bytes) = commit?;
+            let Some(bytes) = bytes else {
+                // Older commits were cleaned up; the versions found so far
+                // may not be where the constraints were added
+                for (version, done) in added.iter_mut().zip(&resolved) {
+                    if !done {
+                        *version = None;
+                    }
+                }
+                break;
+            };
+            let metadata =
+                get_actions(version, &bytes)?
+                    .into_iter()
+                    .find_map(|action| match action {
+                        Action::Metadata(metadata) => Some(metadata),
+                        _ => None,
+                    });
+            let Some(metadata) = metadata else {
+                continue;
+            };
+
+            let defined = metadata_constraints(&metadata)?;
+            for (i, constraint) in current.iter().enumerate() {
+                if resolved[i] {
+                    continue;
+                }
+                if defined.contains(constraint) {
+                    added[i] = Some(version);
+                } else {
+                    resolved[i] = true;
+                }
+            }
+            if resolved.iter().all(|done| *done) {
+                break;
+            }
+        }
+        Ok::<_, DeltaTableError>(())
+    })?
+    .map_err(|e| Error::from(format!("Failed to read the transaction log: {}", e)))?;
+
+    Ok(current.into_iter().zip(added).collect())
+}
+
+/// Rows of a table that violate a constraint expression
+pub(crate) struct ConstraintViolations {
+    /// Files with at least one violating row, sorted by path
+    pub(crate) files: Vec<String>,
+    /// Number of violating rows in each of `files`
+    pub(crate) counts: Vec<f64>,
+    /// Up to `sample_size` violating rows, with the path of their file in
+    /// the last column
+    pub(crate) sample: DataFrame,
+}
+
+/// Find the rows of a table that violate a constraint expression
+///
+/// Like delta-rs when it enforces a CHECK constraint, a row violates the
+/// constraint when the expression is false for it; rows for which it is
+/// NULL pass.
+pub(crate) fn constraint_violations(
+    table: &DeltaTable,
+    expression: &str,
+    sample_size: usize,
+) -> Result<ConstraintViolations> {
+    let snapshot = table
+        .snapshot()
+        .map_err(|e| Error::from(e.to_string()))?
+        .snapshot()
+        .clone();
+    let config = DeltaScanConfigBuilder::new()
+        .with_file_column(true)
+        .build(&snapshot)
+        .map_err(|e| Error::from(e.to_string()))?;
+    let file_column = config
+        .file_column_name
+        .clone()
+        .ok_or_else(|| Error::from("Failed to add the file path column to the scan"))?;
+    let provider = DeltaTableProvider::try_new(snapshot, table.log_store(), config)
+        .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+
+    let ctx = session_context(None);
+    let df = ctx
+        .read_table(Arc::new(provider))
+        .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+    let check = df
+        .parse_sql_expr(expression)
+        .map_err(|e| Error::from(format!("Invalid constraint '{}': {}", expression, e)))?;
+
+    // The path column is dictionary encoded; decode it so the sample gets a
+    // plain character column
+    let file_path = cast(ident(&file_column), DataType::Utf8);
+    let columns: Vec<Expr> = df
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| {
+            if field.name() == &file_column {
+                file_path.clone().alias(&file_column)
+            } else {
+                ident(field.name())
+            }
+        })
+        .collect();
+    let violations = df
+        .filter(not(check))
+        .and_then(|df| df.select(columns))
+        .map_err(|e| Error::from(format!("Invalid constraint '{}': {}", expression, e)))?;
+
+    let batches = block_on(async {
+        violations
+            .clone()
+            .aggregate(
+                vec![ident(&file_column)],
+                vec![count(lit(1)).alias("num_violations")],
+            )?
+            .sort(vec![ident(&file_column).sort(true, false)])?
+            .collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to validate constraint: {}", e)))?;
+
+    let mut files = Vec::new();
+    let mut counts = Vec::new();
+    for batch in &batches {
+        let paths = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| Error::from("Unexpected file path column type"))?;
+        let num_rows = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| Error::from("Unexpected violation count type"))?;
+        for i in 0..batch.num_rows() {
+            files.push(paths.value(i).to_string());
+            counts.push(num_rows.value(i) as f64);
+        }
+    }
+
+    let sample = violations
+        .limit(0, Some(sample_size))
+        .map_err(|e| Error::from(format!("Failed to validate constraint: {}", e)))?;
+
+    Ok(ConstraintViolations {
+        files,
+        counts,
+        sample,
+    })
+}
+
+// ============================================================================
+// Write-time checks
+// ============================================================================
+
+/// Start of the message of constraint violation errors, which are raised
+/// as [ErrorKind::Constraint] so that R gives them the
+/// `deltaR_constraint_error` class
+const VIOLATION_ERROR: &str = "Constraint violation";
+
+/// Most violating rows reported for each constraint
+const MAX_SAMPLE_ROWS: usize = 5;
+
+/// A constraint compiled against the schema of the data being written
+struct WriteCheck {
+    definition: ConstraintDefinition,
+    check: Arc<dyn PhysicalExpr>,
+    /// Index and name of the columns the expression refers to
+    columns: Vec<(usize, String)>,
+    violations: u64,
+    sample: Vec<String>,
+}
+
+impl WriteCheck {
+    /// A violating row as `row 3: x = -1, y = 'a'`
+    fn describe(&self, batch: &RecordBatch, row: usize, position: u64) -> String {
+        let values: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(index, name)| {
+                let column = batch.column(*index);
+                let value = if column.is_null(row) {
+                    "NULL".to_string()
+                } else {
+                    let value = array_value_to_string(column, row).unwrap_or_default();
+                    match column.data_type() {
+                        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => {
+                            format!("'{}'", value)
+                        }
+                        _ => value,
+                    }
+                };
+                format!("{} = {}", name, value)
+            })
+            .collect();
+        format!("row {}: {}", position, values.join(", "))
+    }
+}
+
+/// The outcome of the constraint checks of a write
+///
+/// The reader is consumed by the write, so the report is read back through
+/// this handle when the write fails.
+#[derive(Clone)]
+pub(crate) struct ConstraintReport {
+    report: Arc<Mutex<Option<RaisedError>>>,
+}
+
+impl ConstraintReport {
+    /// The violations found, if any
+    pub(crate) fn violations(&self) -> Option<RaisedError> {
+        self.report.lock().unwrap().clone()
+    }
+}
+
+/// A RecordBatchReader that checks each batch against the table constraints
+///
+/// At the first violation the rest of the input is read to count every
+/// violating row, and the read fails with the report, so nothing is
+/// committed.
+struct ConstraintCheckReader {
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    checks: Vec<WriteCheck>,
+    /// Rows read before the current batch
+    offset: u64,
+    report: ConstraintReport,
+}
+
+impl ConstraintCheckReader {
+    /// Count the violations of a batch; returns whether there were any
+    fn check(&mut self, batch: &RecordBatch) -> std::result::Result<bool, ArrowError> {
+        let offset = self.offset;
+        self.offset += batch.num_rows() as u64;
+        let mut violated = false;
+
+        for check in self.checks.iter_mut() {
+            let result = check
+                .check
+                .evaluate(batch)
+                .and_then(|value| value.into_array(batch.num_rows()))
+                .map_err(|e| ArrowError::ComputeError(e.to_string()))?;
+            let Some(result) = result.as_any().downcast_ref::<BooleanArray>() else {
+                continue;
+            };
+            // A row violates a constraint when it is false; NULL passes
+            let count = result.false_count();
+            if count == 0 {
+                continue;
+            }
+            violated = true;
+            check.violations += count as u64;
+            let rows: Vec<usize> = (0..result.len())
+                .filter(|row| result.is_valid(*row) && !result.value(*row))
+                .take(MAX_SAMPLE_ROWS.saturating_sub(check.sample.len()))
+                .collect();
+            for row in rows {
+                let description = check.describe(batch, row, offset + row as u64 + 1);
+                check.sample.push(description);
+            }
+        }
+        Ok(violated)
+    }
+
+    fn failure(&self) -> RaisedError {
+        let lines: Vec<String> = self
+            .checks
+            .iter()
+            .filter(|check| check.violations > 0)
+            .map(|check| {
+                let kind = match check.definition.kind {
+                    "check" => "constraint",
+                    _ => "invariant on",
+                };
+                format!(
+                    "{}: {} '{}' ({}) is violated by {} rows of the data, e.g.\n  {}",
+                    VIOLATION_ERROR,
+                    kind,
+                    check.definition.name,
+                    check.definition.expression,
+                    check.violations,
+                    check.sample.join("\n  ")
+                )
+            })
+            .collect();
+        kind_error(ErrorKind::Constraint, lines.join("\n"))
+    }
+}
+
+impl RecordBatchReader for ConstraintCheckReader {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Iterator for ConstraintCheckReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = match self.input.next()? {
+            Ok(batch) => batch,
+            Err(e) => return Some(Err(e)),
+        };
+        match self.check(&batch) {
+            Ok(false) => Some(Ok(batch)),
+            Ok(true) => {
+                while let Some(batch) = self.input.next() {
+                    if let Err(e) = batch.and_then(|batch| self.check(&batch)) {
+                        return Some(Err(e));
+                    }
+                }
+                let failure = self.failure();
+                *self.report.report.lock().unwrap() = Some(failure.clone());
+                Some(Err(failure.into()))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Wrap a reader so every batch is checked against the constraints of
+/// `table`
+///
+/// Constraints on nested fields, or on columns the data does not have, are
+/// left to delta-rs. Returns the input unchanged, and no report, when the
+/// table has no constraints to check.
+pub(crate) fn with_constraint_checks(
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    table: &DeltaTable,
+) -> Result<(
+    Box<dyn RecordBatchReader + Send + 'static>,
+    Option<ConstraintReport>,
+)> {
+    let Some(state) = table.state.as_ref() else {
+        return Ok((input, None));
+    };
+    let definitions = metadata_constraints(state.metadata())
+        .map_err(|e| Error::from(format!("Failed to read table constraints: {}", e)))?;
+    if definitions.is_empty() {
+        return Ok((input, None));
+    }
+
+    let input_schema = input.schema();
+    let schema = DFSchema::try_from(input_schema.as_ref().clone())
+        .map_err(|e| Error::from(e.to_string()))?;
+    let ctx = SessionContext::new();
+    let checks: Vec<WriteCheck> = definitions
+        .into_iter()
+        .filter(|definition| !definition.name.contains('.') || definition.kind == "check")
+        .filter_map(|definition| {
+            let expr = ctx.parse_sql_expr(&definition.expression, &schema).ok()?;
+            let mut columns: Vec<(usize, String)> = expr
+                .column_refs()
+                .into_iter()
+                .filter_map(|column| {
+                    input_schema
+                        .index_of(&column.name)
+                        .ok()
+                        .map(|index| (index, column.name.clone()))
+                })
+                .collect();
+            columns.sort();
+            let check = ctx.create_physical_expr(expr, &schema).ok()?;
+            Some(WriteCheck {
+                definition,
+                check,
+                columns,
+                violations: 0,
+                sample: Vec::new(),
+            })
+        })
+        .collect();
+    if checks.is_empty() {
+        return Ok((input, None));
+    }
+
+    let report = ConstraintReport {
+        report: Arc::new(Mutex::new(None)),
+    };
+    let reader = ConstraintCheckReader {
+        input,
+        checks,
+        offset: 0,
+        report: report.clone(),
+    };
+    Ok((Box::new(reader), Some(report)))
+}
+
+/// Rewrite a constraint violation reported by delta-rs while it wrote the
+/// rows of an operation, naming the violated constraint
+///
+/// delta-rs reports the expression and the values of the first violating
+/// row of each batch, in a debug-formatted list of messages. Returns `None`
+/// for other errors.
+pub(crate) fn violation_error(table: &DeltaTable, error: &str, rows: &str) -> Option<RaisedError> {
+    const START: &str = "Check or Invariant (";
+    const VALUES: &str = ") violated by value in row: [";
+
+    let error = error.replace("\\\"", "\"");
+    let mut rest = error.as_str();
+
+    let definitions = table
+        .state
+        .as_ref()
+        .and_then(|state| metadata_constraints(state.metadata()).ok())
+        .unwrap_or_default();
+    let mut lines = Vec::new();
+    while let Some(start) = rest.find(START) {
+        let after = &rest[start + START.len()..];
+        let Some(end) = after.find(VALUES) else {
+            break;
+        };
+        let expression = &after[..end];
+        let values = &after[end + VALUES.len()..];
+        let values_end = values.find(']').unwrap_or(values.len());
+        let name = definitions
+            .iter()
+            .find(|definition| definition.expression == expression)
+            .map(|definition| format!(" '{}'", definition.name))
+            .unwrap_or_default();
+        lines.push(format!(
+            "{}: constraint{} ({}) is violated by {}, e.g. a row with values [{}]",
+            VIOLATION_ERROR,
+            name,
+            expression,
+            rows,
+            &values[..values_end]
+        ));
+        rest = &values[values_end..];
+    }
+    (!lines.is_empty()).then(|| kind_error(ErrorKind::Constraint, lines.join("\n")))
+}
@@ -0,0 +1,258 @@
+//! Error context of table operations
+//!
+//! Errors of operations on a table reach R as structured conditions: the
+//! message, together with the operation that failed, the URI of the table
+//! and, where known, the version the operation worked on and the column the
+//! error is about. Pipelines working with many tables can then tell which
+//! table a failure came from without parsing messages. An operation opens
+//! an [`ErrorContext`] for as long as it runs, and returns an
+//! [`OperationResult`], whose errors take that context when `?` builds them.
+//!
+//! The column an error is about, and the kind of errors R gives a class of
+//! their own, are part of the [`RaisedError`] built where the error is
+//! raised, with [`column_error`] or [`kind_error`]. Errors raised in a
+//! stream of batches are boxed into the stream's Arrow error, and found
+//! again in the chain of sources of the error the operation fails with.
+
+use std::cell::RefCell;
+use std::error::Error as StdError;
+use std::fmt;
+
+use arrow::error::ArrowError;
+use extendr_api::prelude::*;
+
+use crate::{path_to_url, Interrupted};
+
+/// Result of an operation whose errors carry their context
+pub(crate) type OperationResult<T> = std::result::Result<T, OperationError>;
+
+/// Result of a step whose errors may say what they are about
+pub(crate) type RaisedResult<T> = std::result::Result<T, RaisedError>;
+
+/// Kinds of errors that R gives a condition class of their own
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ErrorKind {
+    /// An operation would change the rows of an append-only table
+    AppendOnly,
+    /// Written rows violate a constraint of the table
+    Constraint,
+}
+
+impl ErrorKind {
+    fn name(self) -> &'static str {
+        match self {
+            ErrorKind::AppendOnly => "append_only",
+            ErrorKind::Constraint => "constraint",
+        }
+    }
+}
+
+/// An error raised by deltaR, with what it is about beyond its message
+#[derive(Clone, Debug)]
+pub(crate) struct RaisedError {
+    message: String,
+    column: Option<String>,
+    kind: Option<ErrorKind>,
+}
+
+impl RaisedError {
+    /// The same error, reported with `message`
+    pub(crate) fn with_message(&self, message: String) -> Self {
+        RaisedError {
+            message,
+            ..self.clone()
+        }
+    }
+
+    /// An error with `message` that keeps the column and kind of the error
+    /// raised by deltaR that caused `source`, if any
+    pub(crate) fn caused_by(message: String, source: &(dyn StdError + 'static)) -> Self {
+        match find_raised(source) {
+            Some(raised) => raised.with_message(message),
+            None => Error::Other(message).into(),
+        }
+    }
+}
+
+impl fmt::Display for RaisedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for RaisedError {}
+
+impl From<Error> for RaisedError {
+    fn from(error: Error) -> Self {
+        let message = match error {
+            Error::Other(message) => message,
+            other => other.to_string(),
+        };
+        RaisedError {
+            message,
+            column: None,
+            kind: None,
+        }
+    }
+}
+
+impl From<Interrupted> for RaisedError {
+    fn from(error: Interrupted) -> Self {
+        Error::from(error).into()
+    }
+}
+
+impl From<RaisedError> for Error {
+    fn from(error: RaisedError) -> Self {
+        Error::Other(error.message)
+    }
+}
+
+impl From<RaisedError> for ArrowError {
+    fn from(error: RaisedError) -> Self {
+        ArrowError::ExternalError(Box::new(error))
+    }
+}
+
+/// An error about `column`
+pub(crate) fn column_error(column: &str, message: String) -> RaisedError {
+    RaisedError {
+        message,
+        column: Some(column.to_string()),
+        kind: None,
+    }
+}
+
+/// An error of a kind R gives a class of its own
+pub(crate) fn kind_error(kind: ErrorKind, message: String) -> RaisedError {
+    RaisedError {
+        message,
+        column: None,
+        kind: Some(kind),
+    }
+}
+
+/// The error raised by deltaR in the chain of sources of `error`, if any
+pub(crate) fn find_raised<'a>(error: &'a (dyn StdError + 'static)) -> Option<&'a RaisedError> {
+    let mut error = Some(error);
+    while let Some(current) = error {
+        if let Some(raised) = current.downcast_ref::<RaisedError>() {
+            return Some(raised);
+        }
+        error = current.source();
+    }
+    None
+}
+
+/// An operation running on this thread
+#[derive(Clone)]
+struct Operation {
+    name: &'static str,
+    table_uri: String,
+    version: Option<i64>,
+}
+
+thread_local! {
+    /// Operations running on this thread, the innermost last
+    static OPERATIONS: RefCell<Vec<Operation>> = const { RefCell::new(Vec::new()) };
+}
+
+/// What an operation works on, for the errors it raises
+///
+/// The context lasts until the value is dropped at the end of the
+/// operation. Operations run within another one, e.g. when a transaction
+/// loads its table, report their own context meanwhile.
+pub(crate) struct ErrorContext {
+    depth: usize,
+}
+
+impl ErrorContext {
+    /// Context of `operation` on the table at `table_uri`
+    ///
+    /// Paths are reported as the URI they resolve to, so errors name a table
+    /// the same way however it was given.
+    pub(crate) fn new(operation: &'static str, table_uri: &str) -> Self {
+        let operation = Operation {
+            name: operation,
+            table_uri: path_to_url(table_uri)
+                .map(String::from)
+                .unwrap_or_else(|_| table_uri.to_string()),
+            version: None,
+        };
+        OPERATIONS.with_borrow_mut(|operations| {
+            operations.push(operation);
+            ErrorContext {
+                depth: operations.len() - 1,
+            }
+        })
+    }
+
+    /// Record the version of the table the operation works on, once known
+    pub(crate) fn set_version(&self, version: Option<i64>) {
+        OPERATIONS.with_borrow_mut(|operations| {
+            if let Some(operation) = operations.get_mut(self.depth) {
+                operation.version = version;
+            }
+        });
+    }
+}
+
+impl Drop for ErrorContext {
+    fn drop(&mut self) {
+        OPERATIONS.with_borrow_mut(|operations| operations.truncate(self.depth));
+    }
+}
+
+/// An error of an operation, with the table and version it worked on
+pub(crate) struct OperationError {
+    error: RaisedError,
+    operation: Option<Operation>,
+}
+
+impl From<RaisedError> for OperationError {
+    fn from(error: RaisedError) -> Self {
+        OperationError {
+            error,
+            operation: OPERATIONS.with_borrow(|operations| operations.last().cloned()),
+        }
+    }
+}
+
+impl From<Error> for OperationError {
+    fn from(error: Error) -> Self {
+        RaisedError::from(error).into()
+    }
+}
+
+impl From<Interrupted> for OperationError {
+    fn from(error: Interrupted) -> Self {
+        RaisedError::from(error).into()
+    }
+}
+
+impl From<OperationError> for Robj {
+    fn from(error: OperationError) -> Self {
+        let (operation, table_uri, version) = match error.operation {
+            Some(operation) => (
+                Rstr::from(operation.name),
+                Rstr::from(operation.table_uri),
+                operation
+                    .version
+                    .map_or(Rfloat::na(), |version| Rfloat::from(version as f64)),
+            ),
+            None => (Rstr::na(), Rstr::na(), Rfloat::na()),
+        };
+        list!(
+            message = error.error.message,
+            operation = operation,
+            table_uri = table_uri,
+            version = version,
+            column = error.error.column.as_deref().map_or(Rstr::na(), Rstr::from),
+            kind = error
+                .error
+                .kind
+                .map_or(Rstr::na(), |kind| Rstr::from(kind.name()))
+        )
+        .into()
+    }
+}
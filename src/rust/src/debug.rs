@@ -0,0 +1,170 @@
+//! Debug logging
+//!
+//! With `delta_debug(TRUE)`, or with the `DELTAR_DEBUG_LOG` environment
+//! variable set to a file name, the logical and physical plan of every query
+//! DataFusion executes, every commit attempt delta-rs makes and every
+//! committed operation are appended to a log file. That is what support
+//! requests need and what is otherwise lost inside the Rust libraries.
+//!
+//! Commit attempts are only reported by delta-rs as tracing events, which
+//! the subscriber in [crate::profile] forwards here.
+
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use deltalake::datafusion::dataframe::DataFrame;
+use deltalake::datafusion::error::Result as DataFusionResult;
+use deltalake::datafusion::execution::TaskContext;
+use deltalake::datafusion::physical_plan::{
+    displayable, execute_stream as execute_stream_plan, ExecutionPlan, SendableRecordBatchStream,
+};
+use extendr_api::prelude::*;
+use tracing::field::{Field, Visit};
+use tracing::Event;
+
+/// Environment variable naming a log file to write from the start of the
+/// session
+const LOG_FILE_VAR: &str = "DELTAR_DEBUG_LOG";
+
+/// The log file, if debug logging is on
+static LOG_FILE: LazyLock<Mutex<Option<PathBuf>>> = LazyLock::new(|| {
+    let file = std::env::var_os(LOG_FILE_VAR)
+        .filter(|file| !file.is_empty())
+        .map(PathBuf::from);
+    ENABLED.store(file.is_some(), Ordering::Relaxed);
+    Mutex::new(file)
+});
+
+/// Whether debug logging is on, checked before anything is formatted
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether entries should be written to the log
+pub(crate) fn enabled() -> bool {
+    LazyLock::force(&LOG_FILE);
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Append an entry to the log
+///
+/// Logging must never make an operation fail, so a log file that cannot be
+/// written is ignored.
+pub(crate) fn log(section: &str, text: &str) {
+    if !enabled() {
+        return;
+    }
+    let guard = LOG_FILE.lock().unwrap();
+    let Some(path) = guard.as_ref() else {
+        return;
+    };
+    let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+    else {
+        return;
+    };
+    let _ = writeln!(
+        file,
+        "[{}] {}\n{}\n",
+        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        section,
+        text.trim_end()
+    );
+}
+
+/// Log the logical plan of a DataFrame and the physical plan it runs as
+pub(crate) fn log_plans(df: &DataFrame, plan: &dyn ExecutionPlan) {
+    if !enabled() {
+        return;
+    }
+    log(
+        "logical plan",
+        &df.logical_plan().display_indent().to_string(),
+    );
+    log("physical plan", &displayable(plan).indent(true).to_string());
+}
+
+/// Execute a DataFrame as [DataFrame::execute_stream] does, logging its
+/// plans first
+pub(crate) async fn execute_stream(df: DataFrame) -> DataFusionResult<SendableRecordBatchStream> {
+    if !enabled() {
+        return df.execute_stream().await;
+    }
+    let (plan, task_ctx) = physical_plan(df).await?;
+    execute_stream_plan(plan, task_ctx)
+}
+
+/// Create the physical plan of a DataFrame and the context to execute it in,
+/// logging its plans
+pub(crate) async fn physical_plan(
+    df: DataFrame,
+) -> DataFusionResult<(Arc<dyn ExecutionPlan>, Arc<TaskContext>)> {
+    let task_ctx = Arc::new(df.task_ctx());
+    let plan = df.clone().create_physical_plan().await?;
+    log_plans(&df, plan.as_ref());
+    Ok((plan, task_ctx))
+}
+
+/// Log a tracing event from delta-rs with its fields
+pub(crate) fn log_event(event: &Event<'_>) {
+    struct Fields {
+        message: String,
+        fields: String,
+    }
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                let _ = write!(self.message, "{:?}", value);
+            } else {
+                let _ = write!(self.fields, " {}={:?}", field.name(), value);
+            }
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "message" {
+                self.message.push_str(value);
+            } else {
+                let _ = write!(self.fields, " {}={}", field.name(), value);
+            }
+        }
+    }
+
+    let mut fields = Fields {
+        message: String::new(),
+        fields: String::new(),
+    };
+    event.record(&mut fields);
+    let metadata = event.metadata();
+    log(
+        &format!("{} {}", metadata.level(), metadata.target()),
+        &format!("{}{}", fields.message, fields.fields),
+    );
+}
+
+/// Turn debug logging on or off
+///
+/// Returns the previous log file, or NULL if logging was off.
+///
+/// @param file Log file to append to, or NULL to stop logging
+#[extendr]
+pub fn debug_log_file(file: Nullable<String>) -> Nullable<String> {
+    let mut guard = LOG_FILE.lock().unwrap();
+    let file = file.into_option().map(PathBuf::from);
+    ENABLED.store(file.is_some(), Ordering::Relaxed);
+    if file.is_some() {
+        crate::profile::install_recorder();
+    }
+    match std::mem::replace(&mut *guard, file) {
+        Some(previous) => Nullable::NotNull(previous.to_string_lossy().into_owned()),
+        None => Nullable::Null,
+    }
+}
+
+extendr_module! {
+    mod debug;
+    fn debug_log_file;
+}
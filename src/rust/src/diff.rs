@@ -0,0 +1,207 @@
+//! Row-level differences between two tables
+//!
+//! Two tables, or two versions of one table, are registered in a DataFusion
+//! session as `a` and `b` and compared on key columns: anti-joins find the
+//! keys only one side has (rows added in `b` or removed from `a`), and an
+//! inner join the matched rows whose other columns differ. Only counts
+//! leave the session, unless the differing rows are asked for, in which
+//! case they are streamed to R.
+
+use deltalake::arrow::array::{Array, Int64Array};
+use deltalake::datafusion::prelude::SessionContext;
+use deltalake::DeltaTable;
+use extendr_api::prelude::*;
+
+use crate::block_on;
+use crate::context::column_error;
+use crate::execution::ExecutionOptions;
+use crate::predicates::quote_identifier;
+use crate::read::{dataframe_to_stream, table_provider};
+use crate::sql::table_from_robj;
+
+/// Resolve one side of a diff, at `version` when given
+fn diff_table(
+    name: &str,
+    table: &Robj,
+    version: Nullable<f64>,
+    storage_options: &Nullable<List>,
+) -> Result<DeltaTable> {
+    let mut table = table_from_robj(name, table, storage_options)?;
+    if let Nullable::NotNull(version) = version {
+        block_on(async { table.load_version(version as i64).await })?
+            .map_err(|e| Error::from(format!("Failed to load version {}: {}", version, e)))?;
+    }
+    Ok(table)
+}
+
+/// The column names of a table
+fn table_columns(table: &DeltaTable) -> Result<Vec<String>> {
+    let snapshot = table.snapshot().map_err(|e| Error::from(e.to_string()))?;
+    Ok(snapshot
+        .schema()
+        .fields()
+        .map(|field| field.name().to_string())
+        .collect())
+}
+
+/// Run a `SELECT count(*)` query
+fn count(ctx: &SessionContext, query: &str) -> Result<f64> {
+    let batches = block_on(async { ctx.sql(query).await?.collect().await })?
+        .map_err(|e| Error::from(format!("Failed to compare tables: {}", e)))?;
+    Ok(batches
+        .first()
+        .and_then(|batch| batch.column(0).as_any().downcast_ref::<Int64Array>())
+        .filter(|counts| !counts.is_empty())
+        .map_or(0.0, |counts| counts.value(0) as f64))
+}
+
+/// Compare two Delta tables row by row on key columns
+///
+/// Rows are matched on `keys`; `columns` (by default all columns both tables
+/// have) are compared on matched rows, with nulls equal to each other.
+/// Returns the number of rows `added` (only in `b`), `removed` (only in
+/// `a`), `changed` and `unchanged`, the columns only one table has, and with
+/// `rows` an Arrow stream of the differing rows with a `_change_type` column
+/// (`"added"`, `"removed"` or `"changed"`, with the values of `b` for
+/// changed rows).
+///
+/// @param table_a Table URI or DeltaTableInternal handle of the first table
+/// @param version_a Version of the first table (optional)
+/// @param table_b Table URI or DeltaTableInternal handle of the second table
+/// @param version_b Version of the second table (optional)
+/// @param keys Columns identifying a row
+/// @param columns Columns to compare (optional)
+/// @param rows Whether to also return the differing rows
+/// @param storage_options Storage backend options used to open table URIs (optional)
+/// @param execution Named list of DataFusion execution options (optional)
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+pub fn delta_diff_tables(
+    table_a: Robj,
+    version_a: Nullable<f64>,
+    table_b: Robj,
+    version_b: Nullable<f64>,
+    keys: Vec<String>,
+    columns: Nullable<Vec<String>>,
+    rows: bool,
+    storage_options: Nullable<List>,
+    execution: Nullable<List>,
+) -> Result<List> {
+    let a = diff_table("table_a", &table_a, version_a, &storage_options)?;
+    let b = diff_table("table_b", &table_b, version_b, &storage_options)?;
+    let columns_a = table_columns(&a)?;
+    let columns_b = table_columns(&b)?;
+    for key in &keys {
+        if !columns_a.contains(key) || !columns_b.contains(key) {
+            return Err(Error::from(format!(
+                "Key column '{}' must be in both tables",
+                key
+            )));
+        }
+    }
+    let only_a: Vec<String> = columns_a
+        .iter()
+        .filter(|column| !columns_b.contains(column))
+        .cloned()
+        .collect();
+    let only_b: Vec<String> = columns_b
+        .iter()
+        .filter(|column| !columns_a.contains(column))
+        .cloned()
+        .collect();
+    let compared: Vec<String> = match columns {
+        Nullable::NotNull(columns) => {
+            if let Some(missing) = columns
+                .iter()
+                .find(|column| !columns_a.contains(column) || !columns_b.contains(column))
+            {
+                return Err(column_error(
+                    missing,
+                    format!("Column '{}' must be in both tables", missing),
+                )
+                .into());
+            }
+            columns
+        }
+        Nullable::Null => columns_a
+            .iter()
+            .filter(|column| columns_b.contains(column))
+            .cloned()
+            .collect(),
+    }
+    .into_iter()
+    .filter(|column| !keys.contains(column))
+    .collect();
+
+    let ctx = ExecutionOptions::from_list(&execution)?.session_context()?;
+    for (name, table) in [("a", a), ("b", b)] {
+        ctx.register_table(name, table_provider(table)?)
+            .map_err(|e| Error::from(format!("Failed to register table: {}", e)))?;
+    }
+
+    let quoted = |columns: &[String]| -> Result<Vec<String>> {
+        columns.iter().map(|c| quote_identifier(c)).collect()
+    };
+    let keys = quoted(&keys)?;
+    let compared = quoted(&compared)?;
+    let on = keys
+        .iter()
+        .map(|key| format!("a.{key} = b.{key}"))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let differs = if compared.is_empty() {
+        "false".to_string()
+    } else {
+        compared
+            .iter()
+            .map(|column| format!("a.{column} IS DISTINCT FROM b.{column}"))
+            .collect::<Vec<_>>()
+            .join(" OR ")
+    };
+    let added = format!("FROM b LEFT ANTI JOIN a ON {on}");
+    let removed = format!("FROM a LEFT ANTI JOIN b ON {on}");
+    let changed = format!("FROM b JOIN a ON {on} WHERE {differs}");
+    let matched = format!("FROM b JOIN a ON {on}");
+
+    let num_added = count(&ctx, &format!("SELECT count(*) {added}"))?;
+    let num_removed = count(&ctx, &format!("SELECT count(*) {removed}"))?;
+    let num_changed = count(&ctx, &format!("SELECT count(*) {changed}"))?;
+    let num_matched = count(&ctx, &format!("SELECT count(*) {matched}"))?;
+
+    let rows = if rows {
+        let select = |side: &str| {
+            keys.iter()
+                .chain(compared.iter())
+                .map(|column| format!("{side}.{column}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let query = format!(
+            "SELECT 'added' AS _change_type, {b} {added} \
+             UNION ALL SELECT 'removed' AS _change_type, {a} {removed} \
+             UNION ALL SELECT 'changed' AS _change_type, {b} {changed}",
+            a = select("a"),
+            b = select("b"),
+        );
+        let df = block_on(async { ctx.sql(&query).await })?
+            .map_err(|e| Error::from(format!("Failed to compare tables: {}", e)))?;
+        dataframe_to_stream(df)?
+    } else {
+        ().into_robj()
+    };
+
+    Ok(list!(
+        num_added = num_added,
+        num_removed = num_removed,
+        num_changed = num_changed,
+        num_unchanged = num_matched - num_changed,
+        columns_only_in_a = only_a,
+        columns_only_in_b = only_b,
+        rows = rows
+    ))
+}
+
+extendr_module! {
+    mod diff;
+    fn delta_diff_tables;
+}
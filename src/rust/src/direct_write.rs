@@ -0,0 +1,205 @@
+//! Direct writes
+//!
+//! Plain appends and overwrites of existing tables can skip the WriteBuilder:
+//! the batches go straight to Parquet writers and their files are committed
+//! in one version, without building and planning a DataFusion query. This
+//! is what makes two things possible:
+//!
+//! * Parallel encoding. delta-rs encodes all the files of a write in a single
+//!   task, so a large write from R keeps one core busy however many the
+//!   runtime has. Direct writes spread the encoding over several writers on
+//!   the shared runtime. Writers take turns by chunks of the target file
+//!   size divided by the number of writers, cutting batches where needed,
+//!   so a write of one file's worth of data keeps every writer busy while
+//!   much smaller writes still produce a single file.
+//! * Fast appends. Planning the write is a noticeable part of the latency of
+//!   small frequent appends, which a single direct writer avoids.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Instant;
+
+use arrow::array::{RecordBatch, RecordBatchReader};
+use deltalake::delta_datafusion::DeltaDataChecker;
+use deltalake::kernel::schema::cast_record_batch;
+use deltalake::kernel::transaction::CommitBuilder;
+use deltalake::kernel::{Action, StructTypeExt, Transaction};
+use deltalake::operations::get_num_idx_cols_and_stats_columns;
+use deltalake::operations::write::writer::{DeltaWriter, WriterConfig};
+use deltalake::parquet::file::properties::WriterProperties;
+use deltalake::protocol::{DeltaOperation, SaveMode};
+use deltalake::table::config::TablePropertiesExt;
+use deltalake::{DeltaResult, DeltaTable, DeltaTableError};
+use futures::TryStreamExt;
+use tokio::sync::mpsc;
+
+use crate::block_on;
+use crate::commit::commit_properties;
+
+/// Smallest chunk of incoming data a writer takes its turn with
+///
+/// Writers start a file once the target size is reached, so chunks cut
+/// from a batch for a tiny target file size would become tiny files.
+const MIN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Batches queued for each writer, unless the write limits the batches in
+/// flight
+const WRITER_QUEUE_SIZE: usize = 2;
+
+/// Whether a table can be written directly
+///
+/// Generated columns are computed by the WriteBuilder, so tables with them
+/// are written through it.
+pub(crate) fn supports_direct_write(table: &DeltaTable) -> bool {
+    table.state.as_ref().is_some_and(|state| {
+        state
+            .snapshot()
+            .schema()
+            .get_generated_columns()
+            .is_ok_and(|columns| columns.is_empty())
+    })
+}
+
+/// Append to or overwrite an existing table with `parallelism` writers
+///
+/// `max_in_flight` splits that many queued batches between the writers.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_direct(
+    mut table: DeltaTable,
+    reader: Box<dyn RecordBatchReader + Send>,
+    save_mode: SaveMode,
+    target_file_size: Option<usize>,
+    writer_properties: Option<WriterProperties>,
+    parallelism: usize,
+    max_in_flight: Option<usize>,
+    app_txn: Option<Transaction>,
+) -> DeltaResult<DeltaTable> {
+    let start = Instant::now();
+    let queue_size =
+        max_in_flight.map_or(WRITER_QUEUE_SIZE, |batches| (batches / parallelism).max(1));
+    let state = table.snapshot()?.clone();
+    let snapshot = state.snapshot();
+    let schema = snapshot.arrow_schema();
+    let partition_columns = snapshot.metadata().partition_columns().to_vec();
+    let properties = snapshot.table_properties();
+    let target_file_size =
+        target_file_size.unwrap_or_else(|| properties.target_file_size().get() as usize);
+    let (num_indexed_cols, stats_columns) =
+        get_num_idx_cols_and_stats_columns(Some(properties), HashMap::new());
+    let checker = DeltaDataChecker::new(snapshot);
+    let log_store = table.log_store();
+    let object_store = log_store.object_store(None);
+
+    let version = block_on(async {
+        let mut senders = Vec::with_capacity(parallelism);
+        let mut writers = Vec::with_capacity(parallelism);
+        for _ in 0..parallelism {
+            let (tx, mut rx) = mpsc::channel::<RecordBatch>(queue_size);
+            let config = WriterConfig::new(
+                schema.clone(),
+                partition_columns.clone(),
+                writer_properties.clone(),
+                Some(target_file_size),
+                None,
+                num_indexed_cols,
+                stats_columns.clone(),
+            );
+            let mut writer = DeltaWriter::new(object_store.clone(), config);
+            let checker = checker.clone();
+            senders.push(tx);
+            writers.push(tokio::spawn(async move {
+                while let Some(batch) = rx.recv().await {
+                    checker.check_batch(&batch).await?;
+                    writer.write(&batch).await?;
+                }
+                writer.close().await
+            }));
+        }
+
+        // A writer that stopped early failed; its error is collected below
+        let chunk_size = (target_file_size / parallelism).max(MIN_CHUNK_SIZE);
+        let mut num_added_rows = 0;
+        let mut bytes = 0;
+        'batches: for batch in reader {
+            let batch = cast_record_batch(&batch?, schema.clone(), false, false)?;
+            num_added_rows += batch.num_rows();
+            // Slices share the buffers of the batch, so their size is
+            // estimated from the average row size
+            let row_size = (batch.get_array_memory_size() / batch.num_rows().max(1)).max(1);
+            let mut offset = 0;
+            while offset < batch.num_rows() {
+                let room = chunk_size - bytes % chunk_size;
+                let rows = (room / row_size).clamp(1, batch.num_rows() - offset);
+                let writer = (bytes / chunk_size) % parallelism;
+                bytes += rows * row_size;
+                if senders[writer]
+                    .send(batch.slice(offset, rows))
+                    .await
+                    .is_err()
+                {
+                    break 'batches;
+                }
+                offset += rows;
+            }
+        }
+        drop(senders);
+
+        let mut actions: Vec<Action> = Vec::new();
+        for writer in writers {
+            let adds = writer
+                .await
+                .map_err(|e| DeltaTableError::Generic(format!("Writer failed: {}", e)))??;
+            actions.extend(adds.into_iter().map(Action::Add));
+        }
+        let num_added_files = actions.len();
+        let num_partitions = if partition_columns.is_empty() {
+            0
+        } else {
+            actions
+                .iter()
+                .filter_map(|action| match action {
+                    Action::Add(add) => {
+                        Some(add.partition_values.iter().collect::<BTreeMap<_, _>>())
+                    }
+                    _ => None,
+                })
+                .collect::<BTreeSet<_>>()
+                .len()
+        };
+
+        if save_mode == SaveMode::Overwrite {
+            let removes: Vec<Action> = snapshot
+                .file_views(&log_store, None)
+                .map_ok(|file| Action::Remove(file.remove_action(true)))
+                .try_collect()
+                .await?;
+            actions.extend(removes);
+        }
+        let num_removed_files = actions.len() - num_added_files;
+
+        let metrics = serde_json::json!({
+            "num_added_rows": num_added_rows,
+            "num_added_files": num_added_files,
+            "num_removed_files": num_removed_files,
+            "num_partitions": num_partitions,
+            "execution_time_ms": start.elapsed().as_millis() as u64,
+        });
+        let mut properties =
+            commit_properties().with_metadata([("operationMetrics".to_string(), metrics)]);
+        if let Some(txn) = app_txn {
+            properties = properties.with_application_transaction(txn);
+        }
+        let operation = DeltaOperation::Write {
+            mode: save_mode,
+            partition_by: (!partition_columns.is_empty()).then(|| partition_columns.clone()),
+            predicate: None,
+        };
+        let commit = CommitBuilder::from(properties)
+            .with_actions(actions)
+            .build(Some(snapshot), log_store.clone(), operation)
+            .await?;
+        Ok::<_, DeltaTableError>(commit.version())
+    })??;
+
+    block_on(async { table.load_version(version).await })??;
+    Ok(table)
+}
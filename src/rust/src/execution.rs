@@ -0,0 +1,169 @@
+//! DataFusion execution settings
+//!
+//! Writes, merges and SQL queries run in a DataFusion session whose memory
+//! is unbounded by default, so a large merge or sort can exhaust the memory
+//! of the R session. Execution options cap the memory the session may use,
+//! which makes operators that support it (sorts, joins, aggregations) spill
+//! to disk instead of failing or growing further.
+//!
+//! Scans read the files of each partition one after the other, and small
+//! tables are not split over partitions at all, so on object stores with a
+//! high latency a read mostly waits. The read options set how many files
+//! are read at once and how many batches are read ahead of the consumer,
+//! and the batch size and number of batches in flight bound the memory a
+//! stream holds when its consumer is slower than the reads.
+
+use std::sync::Arc;
+
+use deltalake::datafusion::execution::memory_pool::FairSpillPool;
+use deltalake::datafusion::execution::runtime_env::RuntimeEnvBuilder;
+use deltalake::datafusion::prelude::SessionContext;
+use deltalake::delta_datafusion::DeltaSessionContext;
+use extendr_api::prelude::*;
+
+/// Settings of the DataFusion session an operation runs in
+#[derive(Default)]
+pub(crate) struct ExecutionOptions {
+    pub(crate) memory_limit: Option<usize>,
+    pub(crate) spill_dir: Option<String>,
+    pub(crate) batch_size: Option<usize>,
+    pub(crate) target_partitions: Option<usize>,
+    pub(crate) read_concurrency: Option<usize>,
+    pub(crate) prefetch: Option<usize>,
+    pub(crate) max_in_flight_batches: Option<usize>,
+}
+
+/// Number of batches a result stream reads ahead of its consumer
+///
+/// Stored as an extension of the session config, so every stream executed
+/// in the session picks it up.
+pub(crate) struct Prefetch(pub(crate) usize);
+
+/// Number of batches a result stream may hold that its consumer has not
+/// taken yet, stored like [Prefetch]
+pub(crate) struct MaxInFlight(pub(crate) usize);
+
+/// Read a positive whole number from an R value
+fn positive_number(name: &str, value: &Robj) -> Result<usize> {
+    let number = value
+        .as_real()
+        .or_else(|| value.as_integer().map(f64::from))
+        .filter(|n| n.is_finite() && *n >= 1.0 && n.fract() == 0.0);
+    number.map(|n| n as usize).ok_or_else(|| {
+        Error::from(format!(
+            "Execution option '{}' must be a positive number",
+            name
+        ))
+    })
+}
+
+impl ExecutionOptions {
+    /// Parse execution options from a named R list
+    ///
+    /// `NULL` gives the default session, as do `NULL` elements of the list.
+    pub(crate) fn from_list(options: &Nullable<List>) -> Result<Self> {
+        let mut execution = ExecutionOptions::default();
+        let Nullable::NotNull(options) = options else {
+            return Ok(execution);
+        };
+
+        for (name, value) in options.iter() {
+            if value.is_null() {
+                continue;
+            }
+            match name {
+                "memory_limit" => execution.memory_limit = Some(positive_number(name, &value)?),
+                "spill_dir" => {
+                    let dir = value.as_str().ok_or_else(|| {
+                        Error::from("Execution option 'spill_dir' must be a directory path")
+                    })?;
+                    execution.spill_dir = Some(dir.to_string());
+                }
+                "batch_size" => execution.batch_size = Some(positive_number(name, &value)?),
+                "target_partitions" => {
+                    execution.target_partitions = Some(positive_number(name, &value)?)
+                }
+                "read_concurrency" => {
+                    execution.read_concurrency = Some(positive_number(name, &value)?)
+                }
+                "prefetch" => execution.prefetch = Some(positive_number(name, &value)?),
+                "max_in_flight_batches" => {
+                    execution.max_in_flight_batches = Some(positive_number(name, &value)?)
+                }
+                other => {
+                    return Err(Error::from(format!(
+                        "Unknown execution option: '{}'",
+                        other
+                    )));
+                }
+            }
+        }
+        Ok(execution)
+    }
+
+    /// Whether every setting is left at DataFusion's default
+    pub(crate) fn is_default(&self) -> bool {
+        self.memory_limit.is_none()
+            && self.spill_dir.is_none()
+            && self.batch_size.is_none()
+            && self.target_partitions.is_none()
+            && self.read_concurrency.is_none()
+            && self.prefetch.is_none()
+            && self.max_in_flight_batches.is_none()
+    }
+
+    /// Whether a setting applies to the DataFusion session itself
+    ///
+    /// The batch size and the batches in flight also bound the streams of
+    /// writes that do not run in a session.
+    pub(crate) fn needs_session(&self) -> bool {
+        self.memory_limit.is_some()
+            || self.spill_dir.is_some()
+            || self.target_partitions.is_some()
+            || self.read_concurrency.is_some()
+            || self.prefetch.is_some()
+    }
+
+    /// Create a Delta session with these settings
+    ///
+    /// The memory limit is shared fairly between the operators that can
+    /// spill, so one large sort cannot starve a join running next to it.
+    ///
+    /// With a read concurrency, scans are split over that many partitions
+    /// whatever the size of the table, as the files are read by one task per
+    /// partition.
+    pub(crate) fn session_context(&self) -> Result<SessionContext> {
+        let mut runtime = RuntimeEnvBuilder::new();
+        if let Some(limit) = self.memory_limit {
+            runtime = runtime.with_memory_pool(Arc::new(FairSpillPool::new(limit)));
+        }
+        if let Some(dir) = &self.spill_dir {
+            runtime = runtime.with_temp_file_path(dir);
+        }
+        let runtime = runtime
+            .build_arc()
+            .map_err(|e| Error::from(format!("Failed to create execution runtime: {}", e)))?;
+
+        let mut state = DeltaSessionContext::with_runtime_env(runtime).state();
+        let config = state.config_mut();
+        if let Some(depth) = self.prefetch {
+            config.set_extension(Arc::new(Prefetch(depth)));
+        }
+        if let Some(batches) = self.max_in_flight_batches {
+            config.set_extension(Arc::new(MaxInFlight(batches)));
+        }
+        let options = config.options_mut();
+        if let Some(rows) = self.batch_size {
+            options.execution.batch_size = rows;
+        }
+        if let Some(partitions) = self.target_partitions {
+            options.execution.target_partitions = partitions;
+        }
+        if let Some(files) = self.read_concurrency {
+            options.execution.target_partitions = files;
+            options.optimizer.repartition_file_scans = true;
+            options.optimizer.repartition_file_min_size = 0;
+        }
+        Ok(SessionContext::new_with_state(state))
+    }
+}
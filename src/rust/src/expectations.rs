@@ -0,0 +1,426 @@
+//! Write-time data quality expectations
+//!
+//! Expectations are SQL boolean expressions evaluated on every batch of the
+//! data being written. Rows for which an expectation is false violate it;
+//! depending on the policy the write then fails, the violating rows are
+//! dropped, or they are written and only counted.
+//!
+//! Nulls in the columns a table declares non-nullable are checked the same
+//! way before the write, so the error names the column and rows instead of
+//! coming from the Parquet encoder; those rows can also be dropped.
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use arrow::array::{Array, BooleanArray, RecordBatch};
+use arrow::compute::filter_record_batch;
+use arrow::datatypes::{Schema as ArrowSchema, SchemaRef};
+use arrow::error::ArrowError;
+use deltalake::arrow::array::RecordBatchReader;
+use deltalake::datafusion::common::DFSchema;
+use deltalake::datafusion::physical_expr::PhysicalExpr;
+use deltalake::datafusion::prelude::SessionContext;
+use extendr_api::prelude::*;
+
+use crate::context::{column_error, RaisedError};
+
+/// What to do with rows that violate an expectation
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum ViolationPolicy {
+    /// Abort the operation at the first violating row
+    Fail,
+    /// Leave the violating rows out
+    Drop,
+    /// Keep the violating rows and only report them
+    Warn,
+}
+
+impl FromStr for ViolationPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fail" => Ok(ViolationPolicy::Fail),
+            "drop" => Ok(ViolationPolicy::Drop),
+            "warn" => Ok(ViolationPolicy::Warn),
+            other => Err(Error::from(format!(
+                "Invalid on_violation: {}. Expected 'fail', 'drop' or 'warn'",
+                other
+            ))),
+        }
+    }
+}
+
+/// A named expectation compiled against the schema of the data
+struct Expectation {
+    name: String,
+    expression: String,
+    check: Arc<dyn PhysicalExpr>,
+}
+
+/// Violation counts shared between a reader and its caller
+///
+/// The reader is consumed by the write, so the counts are read back through
+/// this handle once the operation has finished.
+#[derive(Clone)]
+pub(crate) struct ExpectationSummary {
+    names: Vec<String>,
+    expressions: Vec<String>,
+    counts: Arc<Mutex<Vec<u64>>>,
+    dropped: Arc<Mutex<u64>>,
+}
+
+impl ExpectationSummary {
+    /// The expectation metrics added to an operation result
+    pub(crate) fn to_metrics(&self) -> List {
+        let counts: Vec<f64> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| *n as f64)
+            .collect();
+        let dropped = *self.dropped.lock().unwrap() as f64;
+        list!(
+            expectations = data_frame!(
+                name = self.names.clone(),
+                expression = self.expressions.clone(),
+                num_violations = counts
+            ),
+            num_dropped_rows = dropped
+        )
+    }
+}
+
+/// A RecordBatchReader that checks each batch against the expectations
+struct ExpectationReader {
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    expectations: Vec<Expectation>,
+    policy: ViolationPolicy,
+    summary: ExpectationSummary,
+}
+
+impl ExpectationReader {
+    fn check(&self, batch: RecordBatch) -> std::result::Result<RecordBatch, ArrowError> {
+        let mut keep: Option<BooleanArray> = None;
+
+        for (i, expectation) in self.expectations.iter().enumerate() {
+            let result = expectation
+                .check
+                .evaluate(&batch)
+                .and_then(|value| value.into_array(batch.num_rows()))
+                .map_err(|e| ArrowError::ComputeError(e.to_string()))?;
+            let result = result
+                .as_any()
+                .downcast_ref::<BooleanArray>()
+                .ok_or_else(|| {
+                    ArrowError::ComputeError(format!(
+                        "Expectation '{}' is not a boolean expression",
+                        expectation.name
+                    ))
+                })?;
+
+            let violations = result.false_count();
+            if violations == 0 {
+                continue;
+            }
+            self.summary.counts.lock().unwrap()[i] += violations as u64;
+
+            match self.policy {
+                ViolationPolicy::Fail => {
+                    return Err(ArrowError::ComputeError(format!(
+                        "Expectation '{}' ({}) failed for {} rows",
+                        expectation.name, expectation.expression, violations
+                    )))
+                }
+                ViolationPolicy::Drop => {
+                    // NULL results pass, like for CHECK constraints
+                    let passed: BooleanArray = result
+                        .iter()
+                        .map(|value| Some(value != Some(false)))
+                        .collect();
+                    keep = Some(match keep {
+                        Some(keep) => arrow::compute::and(&keep, &passed)?,
+                        None => passed,
+                    });
+                }
+                ViolationPolicy::Warn => {}
+            }
+        }
+
+        match keep {
+            Some(keep) => {
+                *self.summary.dropped.lock().unwrap() += keep.false_count() as u64;
+                filter_record_batch(&batch, &keep)
+            }
+            None => Ok(batch),
+        }
+    }
+}
+
+impl RecordBatchReader for ExpectationReader {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Iterator for ExpectationReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.input.next() {
+            Some(Ok(batch)) => Some(self.check(batch)),
+            other => other,
+        }
+    }
+}
+
+/// Wrap a reader so every batch is checked against `expectations`
+///
+/// `expectations` is a named list of SQL expressions. The returned summary
+/// holds the violation counts once the reader has been consumed.
+pub(crate) fn with_expectations(
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    expectations: &List,
+    policy: ViolationPolicy,
+) -> Result<(
+    Box<dyn RecordBatchReader + Send + 'static>,
+    ExpectationSummary,
+)> {
+    let schema = DFSchema::try_from(input.schema().as_ref().clone())
+        .map_err(|e| Error::from(e.to_string()))?;
+    let ctx = SessionContext::new();
+
+    let compiled = expectations
+        .iter()
+        .map(|(name, value)| {
+            let expression = value
+                .as_str()
+                .ok_or_else(|| Error::from(format!("Expectation '{}' must be a string", name)))?;
+            let check = ctx
+                .parse_sql_expr(expression, &schema)
+                .and_then(|expr| ctx.create_physical_expr(expr, &schema))
+                .map_err(|e| Error::from(format!("Invalid expectation '{}': {}", expression, e)))?;
+            Ok(Expectation {
+                name: name.to_string(),
+                expression: expression.to_string(),
+                check,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let summary = ExpectationSummary {
+        names: compiled.iter().map(|e| e.name.clone()).collect(),
+        expressions: compiled.iter().map(|e| e.expression.clone()).collect(),
+        counts: Arc::new(Mutex::new(vec![0; compiled.len()])),
+        dropped: Arc::new(Mutex::new(0)),
+    };
+    let reader = ExpectationReader {
+        input,
+        expectations: compiled,
+        policy,
+        summary: summary.clone(),
+    };
+    Ok((Box::new(reader), summary))
+}
+
+// ============================================================================
+// Nullability
+// ============================================================================
+
+/// Most row positions reported for the nulls of a column
+const MAX_NULL_ROWS: usize = 5;
+
+/// Parse what to do with rows holding nulls in non-nullable columns
+///
+/// Nulls cannot be written to such columns, so they can only fail the write
+/// or be dropped.
+pub(crate) fn null_policy(s: &str) -> Result<ViolationPolicy> {
+    match s {
+        "fail" => Ok(ViolationPolicy::Fail),
+        "drop" => Ok(ViolationPolicy::Drop),
+        other => Err(Error::from(format!(
+            "Invalid on_null: {}. Expected 'fail' or 'drop'",
+            other
+        ))),
+    }
+}
+
+/// Null counts and positions shared between a reader and its caller
+#[derive(Clone)]
+pub(crate) struct NullSummary {
+    columns: Vec<String>,
+    counts: Arc<Mutex<Vec<u64>>>,
+    rows: Arc<Mutex<Vec<Vec<u64>>>>,
+    dropped: Arc<Mutex<u64>>,
+    /// The error the reader failed with, read back when the write fails
+    failure: Arc<Mutex<Option<RaisedError>>>,
+}
+
+impl NullSummary {
+    /// The null that failed the check, if any
+    pub(crate) fn failure(&self) -> Option<RaisedError> {
+        self.failure.lock().unwrap().clone()
+    }
+
+    /// The null check metrics added to an operation result
+    pub(crate) fn to_metrics(&self) -> List {
+        let counts: Vec<f64> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| *n as f64)
+            .collect();
+        let rows: Vec<String> = self
+            .rows
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|rows| format_rows(rows))
+            .collect();
+        let dropped = *self.dropped.lock().unwrap() as f64;
+        list!(
+            null_violations = data_frame!(
+                column = self.columns.clone(),
+                num_nulls = counts,
+                rows = rows
+            ),
+            num_null_dropped_rows = dropped
+        )
+    }
+}
+
+fn format_rows(rows: &[u64]) -> String {
+    rows.iter()
+        .map(|row| row.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A RecordBatchReader that checks the non-nullable columns of each batch
+struct NullCheckReader {
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    /// Index in the input and name of each non-nullable column
+    columns: Vec<(usize, String)>,
+    policy: ViolationPolicy,
+    /// Rows read before the current batch
+    offset: u64,
+    summary: NullSummary,
+}
+
+impl NullCheckReader {
+    fn check(&mut self, batch: RecordBatch) -> std::result::Result<RecordBatch, ArrowError> {
+        let offset = self.offset;
+        self.offset += batch.num_rows() as u64;
+        let mut nulls: Option<BooleanArray> = None;
+
+        for (i, (index, name)) in self.columns.iter().enumerate() {
+            let column = batch.column(*index);
+            let count = column.null_count();
+            if count == 0 {
+                continue;
+            }
+            self.summary.counts.lock().unwrap()[i] += count as u64;
+            let mut rows = self.summary.rows.lock().unwrap();
+            let positions = (0..column.len())
+                .filter(|row| column.is_null(*row))
+                .map(|row| offset + row as u64 + 1)
+                .take(MAX_NULL_ROWS - rows[i].len().min(MAX_NULL_ROWS));
+            rows[i].extend(positions);
+
+            match self.policy {
+                ViolationPolicy::Drop => {
+                    let is_null = arrow::compute::is_null(column)?;
+                    nulls = Some(match nulls {
+                        Some(nulls) => arrow::compute::or(&nulls, &is_null)?,
+                        None => is_null,
+                    });
+                }
+                _ => {
+                    let message = format!(
+                        "Column '{}' is not nullable, but {} rows of the data have nulls in it \
+                         (e.g. rows {})",
+                        name,
+                        count,
+                        format_rows(&rows[i])
+                    );
+                    let failure = column_error(name, message);
+                    *self.summary.failure.lock().unwrap() = Some(failure.clone());
+                    return Err(failure.into());
+                }
+            }
+        }
+
+        match nulls {
+            Some(nulls) => {
+                *self.summary.dropped.lock().unwrap() += nulls.true_count() as u64;
+                filter_record_batch(&batch, &arrow::compute::not(&nulls)?)
+            }
+            None => Ok(batch),
+        }
+    }
+}
+
+impl RecordBatchReader for NullCheckReader {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Iterator for NullCheckReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.input.next() {
+            Some(Ok(batch)) => Some(self.check(batch)),
+            other => other,
+        }
+    }
+}
+
+/// Wrap a reader so the columns of every batch that are not nullable in
+/// `table_schema` are checked for nulls
+///
+/// Only top-level columns are checked. Returns the input unchanged, and no
+/// summary, when none of its columns is non-nullable in the table.
+pub(crate) fn with_null_checks(
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    table_schema: &ArrowSchema,
+    policy: ViolationPolicy,
+) -> (
+    Box<dyn RecordBatchReader + Send + 'static>,
+    Option<NullSummary>,
+) {
+    let input_schema = input.schema();
+    let columns: Vec<(usize, String)> = table_schema
+        .fields()
+        .iter()
+        .filter(|field| !field.is_nullable())
+        .filter_map(|field| {
+            input_schema
+                .index_of(field.name())
+                .ok()
+                .map(|index| (index, field.name().clone()))
+        })
+        .collect();
+    if columns.is_empty() {
+        return (input, None);
+    }
+
+    let summary = NullSummary {
+        columns: columns.iter().map(|(_, name)| name.clone()).collect(),
+        counts: Arc::new(Mutex::new(vec![0; columns.len()])),
+        rows: Arc::new(Mutex::new(vec![Vec::new(); columns.len()])),
+        dropped: Arc::new(Mutex::new(0)),
+        failure: Arc::new(Mutex::new(None)),
+    };
+    let reader = NullCheckReader {
+        input,
+        columns,
+        policy,
+        offset: 0,
+        summary: summary.clone(),
+    };
+    (Box::new(reader), Some(summary))
+}
@@ -0,0 +1,280 @@
+//! Export of tables to flat files
+//!
+//! A scan of the table is streamed batch by batch into an Arrow CSV or JSON
+//! writer, so a table larger than memory can be handed to tools that do not
+//! read Parquet or Delta. A partitioned export scans each partition on its
+//! own, from the files of that partition only, and writes it to a
+//! `col=value/` directory.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use arrow::array::RecordBatch;
+use arrow::csv::{Writer as CsvWriter, WriterBuilder as CsvWriterBuilder};
+use arrow::error::ArrowError;
+use arrow::json::{ArrayWriter, LineDelimitedWriter};
+use deltalake::datafusion::prelude::{DataFrame, SessionContext};
+use deltalake::kernel::Add;
+use deltalake::DeltaTable;
+use extendr_api::prelude::*;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::block_on;
+use crate::execution::ExecutionOptions;
+use crate::optimize::partition_name;
+use crate::read::{file_view_to_add, files_table_provider, project_and_filter, table_dataframe};
+use crate::sql::table_from_robj;
+
+/// Format of the exported files
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// A single JSON array of objects
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            other => Err(Error::from(format!(
+                "Invalid format: {}. Expected 'csv', 'json' or 'ndjson'",
+                other
+            ))),
+        }
+    }
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// An Arrow writer for one exported file
+enum FileWriter {
+    Csv(Box<CsvWriter<BufWriter<File>>>),
+    Json(ArrayWriter<BufWriter<File>>),
+    Ndjson(LineDelimitedWriter<BufWriter<File>>),
+}
+
+impl FileWriter {
+    fn create(path: &Path, format: ExportFormat) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match format {
+            ExportFormat::Csv => Self::Csv(Box::new(
+                CsvWriterBuilder::new().with_header(true).build(file),
+            )),
+            ExportFormat::Json => Self::Json(ArrayWriter::new(file)),
+            ExportFormat::Ndjson => Self::Ndjson(LineDelimitedWriter::new(file)),
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> std::result::Result<(), ArrowError> {
+        match self {
+            Self::Csv(writer) => writer.write(batch),
+            Self::Json(writer) => writer.write(batch),
+            Self::Ndjson(writer) => writer.write(batch),
+        }
+    }
+
+    fn finish(self) -> std::result::Result<(), ArrowError> {
+        let mut file = match self {
+            Self::Csv(writer) => writer.into_inner(),
+            Self::Json(mut writer) => {
+                writer.finish()?;
+                writer.into_inner()
+            }
+            Self::Ndjson(mut writer) => {
+                writer.finish()?;
+                writer.into_inner()
+            }
+        };
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// One file written by an export
+struct ExportedFile {
+    path: PathBuf,
+    num_rows: usize,
+}
+
+/// Stream the rows of `df` into a file at `path`
+///
+/// With `skip_empty`, no file is created when `df` has no rows; otherwise
+/// an empty CSV file still gets its header row.
+fn export_dataframe(
+    df: DataFrame,
+    path: PathBuf,
+    format: ExportFormat,
+    skip_empty: bool,
+) -> Result<Option<ExportedFile>> {
+    let schema = df.schema().as_arrow().clone();
+    let failed = |e: &dyn std::fmt::Display| e.to_string();
+
+    block_on(async {
+        let mut stream = df.execute_stream().await.map_err(|e| failed(&e))?;
+        let mut writer = None;
+        let mut num_rows = 0;
+        while let Some(batch) = stream.next().await {
+            let batch = batch.map_err(|e| failed(&e))?;
+            if batch.num_rows() == 0 {
+                continue;
+            }
+            let writer = match writer.as_mut() {
+                Some(writer) => writer,
+                None => writer.insert(FileWriter::create(&path, format).map_err(|e| failed(&e))?),
+            };
+            writer.write(&batch).map_err(|e| failed(&e))?;
+            num_rows += batch.num_rows();
+        }
+
+        let writer = match writer {
+            Some(writer) => writer,
+            None if skip_empty => return Ok(None),
+            None => {
+                let mut writer = FileWriter::create(&path, format).map_err(|e| failed(&e))?;
+                writer
+                    .write(&RecordBatch::new_empty(schema.into()))
+                    .map_err(|e| failed(&e))?;
+                writer
+            }
+        };
+        writer.finish().map_err(|e| failed(&e))?;
+        Ok(Some(ExportedFile {
+            path: path.clone(),
+            num_rows,
+        }))
+    })?
+    .map_err(|e: String| Error::from(format!("Failed to export to {}: {}", path.display(), e)))
+}
+
+/// The active files of `table`, grouped by partition name
+fn files_by_partition(
+    table: &DeltaTable,
+    columns: &[String],
+) -> Result<BTreeMap<String, Vec<Add>>> {
+    let files: Vec<Add> = block_on(async {
+        table
+            .get_active_add_actions_by_partitions(&[])
+            .map_ok(|file| file_view_to_add(&file))
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(e.to_string()))?;
+
+    let mut partitions: BTreeMap<String, Vec<Add>> = BTreeMap::new();
+    for add in files {
+        partitions
+            .entry(partition_name(columns, &add.partition_values))
+            .or_default()
+            .push(add);
+    }
+    Ok(partitions)
+}
+
+/// Export a Delta table to CSV or JSON files
+///
+/// Without `partitioned`, `path` is the file to write. With it, `path` is a
+/// directory holding one `part-00000.<format>` file per partition in
+/// `col=value/` subdirectories, without the partition columns; partitions
+/// without selected rows get no file. Returns a data.frame of the written
+/// files.
+///
+/// @param table Table URI or DeltaTableInternal handle
+/// @param path Local path of the file, or of the directory when partitioned
+/// @param format "csv", "json" or "ndjson"
+/// @param predicate SQL filter expression (optional)
+/// @param columns Columns to export (optional)
+/// @param partitioned Whether to write a file per partition
+/// @param storage_options Storage backend options used to open table URIs (optional)
+/// @param execution Named list of DataFusion execution options (optional)
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+pub fn delta_export_table(
+    table: Robj,
+    path: &str,
+    format: &str,
+    predicate: Nullable<&str>,
+    columns: Nullable<Vec<String>>,
+    partitioned: bool,
+    storage_options: Nullable<List>,
+    execution: Nullable<List>,
+) -> Result<Robj> {
+    let format = ExportFormat::from_str(format)?;
+    let table = table_from_robj("table", &table, &storage_options)?;
+    let ctx: SessionContext = ExecutionOptions::from_list(&execution)?.session_context()?;
+
+    let mut exported = Vec::new();
+    if partitioned {
+        let partition_columns = table
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?
+            .metadata()
+            .partition_columns()
+            .to_vec();
+        if partition_columns.is_empty() {
+            return Err(Error::from(
+                "partitioned = TRUE requires a partitioned table",
+            ));
+        }
+        let drop: Vec<&str> = partition_columns.iter().map(String::as_str).collect();
+        for (partition, files) in files_by_partition(&table, &partition_columns)? {
+            let provider = files_table_provider(&table, files)?;
+            let df = ctx
+                .read_table(provider)
+                .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+            let df = project_and_filter(df, &columns, &predicate)?
+                .drop_columns(&drop)
+                .map_err(|e| Error::from(format!("Invalid column selection: {}", e)))?;
+            let file = Path::new(path)
+                .join(&partition)
+                .join(format!("part-00000.{}", format.extension()));
+            exported.extend(export_dataframe(df, file, format, true)?);
+        }
+    } else {
+        let df = project_and_filter(table_dataframe(&ctx, table)?, &columns, &predicate)?;
+        exported.extend(export_dataframe(df, PathBuf::from(path), format, false)?);
+    }
+
+    let sizes: Vec<f64> = exported
+        .iter()
+        .map(|file| std::fs::metadata(&file.path).map_or(0.0, |m| m.len() as f64))
+        .collect();
+    Ok(data_frame!(
+        path = exported
+            .iter()
+            .map(|file| file.path.display().to_string())
+            .collect::<Vec<_>>(),
+        num_rows = exported
+            .iter()
+            .map(|file| file.num_rows as f64)
+            .collect::<Vec<_>>(),
+        size_bytes = sizes
+    )
+    .into_robj())
+}
+
+extendr_module! {
+    mod export;
+    fn delta_export_table;
+}
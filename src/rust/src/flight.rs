@@ -0,0 +1,456 @@
+//! Arrow Flight SQL server
+//!
+//! Serves named Delta tables to Flight SQL clients, such as the ADBC and
+//! JDBC drivers or pyarrow, from the R process. Queries are planned in a
+//! DataFusion session like those of [crate::sql], so filters and column
+//! selections are pushed down into each table scan, only the results leave
+//! the process, and statements that write or change settings are rejected.
+//!
+//! The server runs on the shared runtime, whose workers answer requests
+//! while the R thread goes on with other work. Tables given as URIs are
+//! brought to their latest version before each query; tables given as
+//! DeltaTable handles are served at the version they were loaded at. Each
+//! query is planned in its own session over one version of every table.
+//!
+//! The server speaks plain gRPC, without TLS.
+
+use std::sync::{Arc, LazyLock};
+
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::sql::metadata::{SqlInfoData, SqlInfoDataBuilder};
+use arrow_flight::sql::server::FlightSqlService;
+use arrow_flight::sql::{
+    CommandGetCatalogs, CommandGetDbSchemas, CommandGetSqlInfo, CommandGetTables,
+    CommandStatementQuery, ProstMessageExt, SqlInfo, TicketStatementQuery,
+};
+use arrow_flight::{FlightDescriptor, FlightEndpoint, FlightInfo, Ticket};
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::datatypes::{Schema, SchemaRef};
+use deltalake::datafusion::catalog::{
+    CatalogProvider, CatalogProviderList, MemoryCatalogProvider, MemoryCatalogProviderList,
+    MemorySchemaProvider, SchemaProvider, TableProvider,
+};
+use deltalake::datafusion::dataframe::DataFrame;
+use deltalake::datafusion::execution::{SessionState, SessionStateBuilder};
+use deltalake::datafusion::prelude::SessionContext;
+use deltalake::DeltaTable;
+use extendr_api::prelude::*;
+use futures::{Stream, TryStreamExt};
+use prost::Message;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::block_on;
+use crate::execution::ExecutionOptions;
+use crate::read::table_provider;
+use crate::sql::{read_only, table_from_robj};
+
+/// Catalog and schema of the served tables, DataFusion's defaults
+const CATALOG: &str = "datafusion";
+const SCHEMA: &str = "public";
+
+type FlightResult<T> = std::result::Result<T, Status>;
+type DoGetStream = <DeltaFlightService as FlightService>::DoGetStream;
+
+/// What the server tells clients about itself
+static SQL_INFO: LazyLock<SqlInfoData> = LazyLock::new(|| {
+    let mut builder = SqlInfoDataBuilder::new();
+    builder.append(SqlInfo::FlightSqlServerName, "deltaR");
+    builder.append(SqlInfo::FlightSqlServerVersion, deltalake::crate_version());
+    builder.append(SqlInfo::FlightSqlServerReadOnly, true);
+    builder.build().expect("Invalid Flight SQL server info")
+});
+
+/// A served table
+enum ServedTable {
+    /// Given as a URI, brought up to date before each query
+    Latest(Box<Mutex<DeltaTable>>),
+    /// Given as a DeltaTable handle, served at the version it was loaded at
+    Fixed(Arc<dyn TableProvider>),
+}
+
+/// Flight SQL service over a set of tables
+#[derive(Clone)]
+struct DeltaFlightService {
+    /// Session every query is planned in, without the tables
+    state: Arc<SessionState>,
+    /// Served tables, in the order they were given
+    tables: Arc<Vec<(String, ServedTable)>>,
+}
+
+impl DeltaFlightService {
+    /// Session over the current versions of the tables
+    ///
+    /// Every query gets its own session, so it reads one version of each
+    /// table however many times it scans it, even while another query
+    /// brings the tables up to date. A table without new commits costs a
+    /// listing of its log.
+    async fn session(&self) -> FlightResult<SessionContext> {
+        let schema = MemorySchemaProvider::new();
+        for (name, table) in self.tables.iter() {
+            let provider = match table {
+                ServedTable::Latest(table) => {
+                    let mut table = table.lock().await;
+                    table.update_state().await.map_err(|e| {
+                        Status::internal(format!("Failed to load table '{}': {}", name, e))
+                    })?;
+                    table_provider(table.clone()).map_err(|e| {
+                        Status::internal(format!("Failed to load table '{}': {}", name, e))
+                    })?
+                }
+                ServedTable::Fixed(provider) => provider.clone(),
+            };
+            schema.register_table(name.clone(), provider).map_err(|e| {
+                Status::internal(format!("Failed to register table '{}': {}", name, e))
+            })?;
+        }
+        let catalog = MemoryCatalogProvider::new();
+        catalog
+            .register_schema(SCHEMA, Arc::new(schema))
+            .map_err(|e| Status::internal(format!("Failed to register schema: {}", e)))?;
+        let catalogs = MemoryCatalogProviderList::new();
+        catalogs.register_catalog(CATALOG.to_string(), Arc::new(catalog));
+
+        let state = SessionStateBuilder::new_from_existing(self.state.as_ref().clone())
+            .with_catalog_list(Arc::new(catalogs))
+            .build();
+        Ok(SessionContext::new_with_state(state))
+    }
+
+    /// Plan a query over the current versions of the tables
+    async fn plan(&self, query: &str) -> FlightResult<DataFrame> {
+        self.session()
+            .await?
+            .sql_with_options(query, read_only())
+            .await
+            .map_err(|e| Status::invalid_argument(format!("Failed to plan query: {}", e)))
+    }
+}
+
+/// Whether `a` equals `b`, in a time that depends only on their lengths
+///
+/// Comparing tokens this way keeps the time a request takes to be rejected
+/// from telling how much of its token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Describe the single endpoint that serves `ticket`
+fn flight_info(
+    schema: &Schema,
+    ticket: Vec<u8>,
+    descriptor: FlightDescriptor,
+) -> FlightResult<Response<FlightInfo>> {
+    let info = FlightInfo::new()
+        .try_with_schema(schema)
+        .map_err(|e| Status::internal(format!("Failed to encode schema: {}", e)))?
+        .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(ticket)))
+        .with_descriptor(descriptor);
+    Ok(Response::new(info))
+}
+
+/// Encode record batches as a Flight data stream
+fn encode<S>(schema: SchemaRef, batches: S) -> Response<DoGetStream>
+where
+    S: Stream<Item = std::result::Result<RecordBatch, FlightError>> + Send + 'static,
+{
+    let stream = FlightDataEncoderBuilder::new()
+        .with_schema(schema)
+        .build(batches)
+        .map_err(Status::from);
+    Response::new(Box::pin(stream))
+}
+
+/// Encode a single record batch as a Flight data stream
+fn encode_batch<E>(
+    schema: SchemaRef,
+    batch: std::result::Result<RecordBatch, E>,
+) -> Response<DoGetStream>
+where
+    FlightError: From<E>,
+{
+    let batch = batch.map_err(FlightError::from);
+    encode(schema, futures::stream::once(async move { batch }))
+}
+
+#[tonic::async_trait]
+impl FlightSqlService for DeltaFlightService {
+    type FlightService = DeltaFlightService;
+
+    async fn get_flight_info_statement(
+        &self,
+        query: CommandStatementQuery,
+        request: Request<FlightDescriptor>,
+    ) -> FlightResult<Response<FlightInfo>> {
+        let df = self.plan(&query.query).await?;
+        // The ticket carries the query, which is planned again when it is
+        // redeemed
+        let ticket = TicketStatementQuery {
+            statement_handle: query.query.into_bytes().into(),
+        };
+        flight_info(
+            df.schema().as_arrow(),
+            ticket.as_any().encode_to_vec(),
+            request.into_inner(),
+        )
+    }
+
+    async fn do_get_statement(
+        &self,
+        ticket: TicketStatementQuery,
+        _request: Request<Ticket>,
+    ) -> FlightResult<Response<DoGetStream>> {
+        let query = std::str::from_utf8(&ticket.statement_handle)
+            .map_err(|_| Status::invalid_argument("Invalid statement handle"))?;
+        let stream = self
+            .plan(query)
+            .await?
+            .execute_stream()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to execute query: {}", e)))?;
+        let schema = stream.schema();
+        Ok(encode(
+            schema,
+            stream.map_err(|e| FlightError::ExternalError(Box::new(e))),
+        ))
+    }
+
+    async fn get_flight_info_catalogs(
+        &self,
+        query: CommandGetCatalogs,
+        request: Request<FlightDescriptor>,
+    ) -> FlightResult<Response<FlightInfo>> {
+        let ticket = query.as_any().encode_to_vec();
+        flight_info(&query.into_builder().schema(), ticket, request.into_inner())
+    }
+
+    async fn do_get_catalogs(
+        &self,
+        query: CommandGetCatalogs,
+        _request: Request<Ticket>,
+    ) -> FlightResult<Response<DoGetStream>> {
+        let mut builder = query.into_builder();
+        builder.append(CATALOG);
+        let schema = builder.schema();
+        Ok(encode_batch(schema, builder.build()))
+    }
+
+    async fn get_flight_info_schemas(
+        &self,
+        query: CommandGetDbSchemas,
+        request: Request<FlightDescriptor>,
+    ) -> FlightResult<Response<FlightInfo>> {
+        let ticket = query.as_any().encode_to_vec();
+        flight_info(&query.into_builder().schema(), ticket, request.into_inner())
+    }
+
+    async fn do_get_schemas(
+        &self,
+        query: CommandGetDbSchemas,
+        _request: Request<Ticket>,
+    ) -> FlightResult<Response<DoGetStream>> {
+        let mut builder = query.into_builder();
+        builder.append(CATALOG, SCHEMA);
+        let schema = builder.schema();
+        Ok(encode_batch(schema, builder.build()))
+    }
+
+    async fn get_flight_info_tables(
+        &self,
+        query: CommandGetTables,
+        request: Request<FlightDescriptor>,
+    ) -> FlightResult<Response<FlightInfo>> {
+        let ticket = query.as_any().encode_to_vec();
+        flight_info(&query.into_builder().schema(), ticket, request.into_inner())
+    }
+
+    async fn do_get_tables(
+        &self,
+        query: CommandGetTables,
+        _request: Request<Ticket>,
+    ) -> FlightResult<Response<DoGetStream>> {
+        let ctx = self.session().await?;
+        let mut builder = query.into_builder();
+        for (name, _) in self.tables.iter() {
+            let provider = ctx
+                .table_provider(name.as_str())
+                .await
+                .map_err(|e| Status::internal(format!("Failed to find table '{}': {}", name, e)))?;
+            builder
+                .append(CATALOG, SCHEMA, name, "TABLE", provider.schema().as_ref())
+                .map_err(|e| Status::internal(format!("Failed to list table '{}': {}", name, e)))?;
+        }
+        let schema = builder.schema();
+        Ok(encode_batch(schema, builder.build()))
+    }
+
+    async fn get_flight_info_sql_info(
+        &self,
+        query: CommandGetSqlInfo,
+        request: Request<FlightDescriptor>,
+    ) -> FlightResult<Response<FlightInfo>> {
+        let ticket = query.as_any().encode_to_vec();
+        let schema = query.into_builder(&SQL_INFO).schema();
+        flight_info(&schema, ticket, request.into_inner())
+    }
+
+    async fn do_get_sql_info(
+        &self,
+        query: CommandGetSqlInfo,
+        _request: Request<Ticket>,
+    ) -> FlightResult<Response<DoGetStream>> {
+        let builder = query.into_builder(&SQL_INFO);
+        let schema = builder.schema();
+        Ok(encode_batch(schema, builder.build()))
+    }
+
+    async fn register_sql_info(&self, _id: i32, _result: &SqlInfo) {}
+}
+
+/// Open every named table of `tables`
+fn served_tables(
+    tables: &List,
+    storage_options: &Nullable<List>,
+) -> Result<Vec<(String, ServedTable)>> {
+    let mut served = Vec::with_capacity(tables.len());
+    for (name, table) in tables.iter() {
+        if name.is_empty() || name == "NA" {
+            return Err(Error::from("All tables must be named"));
+        }
+        let is_uri = table.as_str().is_some();
+        let table = table_from_robj(name, &table, storage_options)?;
+        let table = if is_uri {
+            ServedTable::Latest(Box::new(Mutex::new(table)))
+        } else {
+            ServedTable::Fixed(table_provider(table)?)
+        };
+        served.push((name.to_string(), table));
+    }
+    Ok(served)
+}
+
+/// An Arrow Flight SQL server running in the background
+///
+/// Dropping the handle, e.g. when R garbage-collects it, shuts the server
+/// down like [DeltaFlightServerInternal::stop] without waiting for it.
+#[extendr]
+pub struct DeltaFlightServerInternal {
+    port: u16,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<std::result::Result<(), tonic::transport::Error>>>,
+}
+
+#[extendr]
+impl DeltaFlightServerInternal {
+    /// Start serving tables on `host` and `port`
+    ///
+    /// Port 0 listens on a port chosen by the system. With an `auth_token`,
+    /// every request must carry it in an `authorization: Bearer <token>`
+    /// header.
+    ///
+    /// @param tables Named list of table URIs or DeltaTableInternal handles
+    /// @param host Address to listen on
+    /// @param port Port to listen on
+    /// @param auth_token Token clients must send (optional)
+    /// @param storage_options Storage backend options used to open table URIs (optional)
+    /// @param execution Named list of DataFusion execution options (optional)
+    fn new(
+        tables: List,
+        host: &str,
+        port: i32,
+        auth_token: Nullable<String>,
+        storage_options: Nullable<List>,
+        execution: Nullable<List>,
+    ) -> Result<Self> {
+        let port =
+            u16::try_from(port).map_err(|_| Error::from(format!("Invalid port: {}", port)))?;
+        let ctx = ExecutionOptions::from_list(&execution)?.session_context()?;
+        let service = DeltaFlightService {
+            state: Arc::new(ctx.state()),
+            tables: Arc::new(served_tables(&tables, &storage_options)?),
+        };
+
+        let expected = match auth_token {
+            Nullable::NotNull(token) => Some(Arc::<str>::from(format!("Bearer {}", token))),
+            Nullable::Null => None,
+        };
+        let service = FlightServiceServer::with_interceptor(service, move |request: Request<()>| {
+            let Some(expected) = &expected else {
+                return Ok(request);
+            };
+            match request.metadata().get("authorization") {
+                Some(value) if constant_time_eq(value.as_bytes(), expected.as_bytes()) => {
+                    Ok(request)
+                }
+                _ => Err(Status::unauthenticated("Missing or invalid bearer token")),
+            }
+        });
+
+        let addr = if host.contains(':') {
+            format!("[{}]:{}", host, port)
+        } else {
+            format!("{}:{}", host, port)
+        };
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let (port, server) = block_on(async {
+            let listener = TcpListener::bind(&addr).await?;
+            let port = listener.local_addr()?.port();
+            let server = tokio::spawn(
+                Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                        // Also when the handle, and with it the sender, is dropped
+                        let _ = stopped.await;
+                    }),
+            );
+            Ok::<_, std::io::Error>((port, server))
+        })?
+        .map_err(|e| Error::from(format!("Failed to listen on {}: {}", addr, e)))?;
+
+        Ok(DeltaFlightServerInternal {
+            port,
+            shutdown: Some(shutdown),
+            server: Some(server),
+        })
+    }
+
+    /// Get the port the server listens on
+    fn port(&self) -> i32 {
+        self.port as i32
+    }
+
+    /// Whether the server is still serving
+    fn running(&self) -> bool {
+        self.server
+            .as_ref()
+            .is_some_and(|server| !server.is_finished())
+    }
+
+    /// Stop the server
+    ///
+    /// Waits for the queries being answered to finish. Stopping a stopped
+    /// server does nothing.
+    fn stop(&mut self) -> Result<()> {
+        let Some(server) = self.server.take() else {
+            return Ok(());
+        };
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        block_on(server)?
+            .map_err(|e| Error::from(format!("Flight server failed: {}", e)))?
+            .map_err(|e| Error::from(format!("Flight server failed: {}", e)))
+    }
+}
+
+// Export the module functions
+extendr_module! {
+    mod flight;
+    impl DeltaFlightServerInternal;
+}
@@ -0,0 +1,523 @@
+//! Google Cloud Storage credentials
+//!
+//! object_store authenticates to GCS with a service account key, the
+//! application default credentials or the metadata server, which on GKE
+//! already gives the workload identity of the pod. This module adds the two
+//! flows it lacks, so that jobs can authenticate without a key file:
+//!
+//! - impersonation: the source credentials are exchanged for a short-lived
+//!   token of a target service account through the IAM Credentials API;
+//! - workload identity federation: a token issued outside Google Cloud
+//!   (read from a file, e.g. a Kubernetes projected token) is exchanged for
+//!   a Google token through the Security Token Service.
+//!
+//! Tables are opened through a `gs` object store factory that uses these
+//! flows when their options are set and otherwise defers to delta-rs. Tokens
+//! are cached and fetched again five minutes before they expire.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use deltalake::gcp::GcpFactory;
+use deltalake::logstore::object_store::client::SpawnedReqwestConnector;
+use deltalake::logstore::object_store::gcp::{
+    GcpCredential, GoogleCloudStorageBuilder, GoogleConfigKey,
+};
+use deltalake::logstore::object_store::{CredentialProvider, ObjectStoreScheme};
+use deltalake::logstore::{
+    object_store_factories, ObjectStoreFactory, ObjectStoreRef, StorageConfig,
+};
+use deltalake::{DeltaResult, DeltaTableError, Path};
+use serde_json::{json, Value};
+use url::Url;
+
+use deltalake::logstore::object_store::Error as ObjectStoreError;
+
+/// Storage options read by this module
+pub(crate) const IMPERSONATE_SERVICE_ACCOUNT: &str = "google_impersonate_service_account";
+pub(crate) const IMPERSONATE_DELEGATES: &str = "google_impersonate_delegates";
+pub(crate) const IMPERSONATE_LIFETIME: &str = "google_impersonate_lifetime";
+pub(crate) const SCOPES: &str = "google_scopes";
+pub(crate) const WORKLOAD_IDENTITY_AUDIENCE: &str = "google_workload_identity_audience";
+pub(crate) const SUBJECT_TOKEN_FILE: &str = "google_workload_identity_subject_token_file";
+pub(crate) const SUBJECT_TOKEN_TYPE: &str = "google_workload_identity_subject_token_type";
+
+/// Scope of the tokens used when no `scopes` are given
+pub(crate) const DEFAULT_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+pub(crate) const DEFAULT_SUBJECT_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:jwt";
+/// Longest lifetime the IAM Credentials API grants, in seconds
+pub(crate) const MAX_LIFETIME: u64 = 43200;
+
+const METADATA_HOST: &str = "metadata.google.internal";
+const STS_URL: &str = "https://sts.googleapis.com/v1/token";
+const OAUTH_URL: &str = "https://oauth2.googleapis.com/token";
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+/// Tokens are refreshed when they expire in less than this
+const MIN_TTL: Duration = Duration::from_secs(300);
+
+// ============================================================================
+// Settings
+// ============================================================================
+
+/// Credentials the token of the target (or the token used directly) is
+/// obtained with
+#[derive(Debug, Clone)]
+enum SourceCredentials {
+    /// The metadata server of GCE, Cloud Run or GKE (workload identity)
+    Metadata,
+    /// User credentials from `gcloud auth application-default login`
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    },
+    /// Workload identity federation
+    ExternalAccount {
+        audience: String,
+        subject_token_file: String,
+        subject_token_type: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Impersonation {
+    service_account: String,
+    delegates: Vec<String>,
+    lifetime: u64,
+}
+
+/// Whether `email` looks like a service account, which the IAM Credentials
+/// API otherwise reports as a bare 404
+pub(crate) fn is_service_account(email: &str) -> bool {
+    email
+        .split_once('@')
+        .is_some_and(|(name, domain)| !name.is_empty() && domain.ends_with(".gserviceaccount.com"))
+}
+
+fn invalid(message: String) -> DeltaTableError {
+    DeltaTableError::Generic(message)
+}
+
+/// Check the GCS credential options, as given to `gcs_storage_options()` or
+/// directly as storage options
+pub(crate) fn validate_options(options: &HashMap<String, String>) -> DeltaResult<()> {
+    if let Some(account) = options.get(IMPERSONATE_SERVICE_ACCOUNT) {
+        if !is_service_account(account) {
+            return Err(invalid(format!(
+                "'{}' is not a service account email (name@project.iam.gserviceaccount.com)",
+                account
+            )));
+        }
+    }
+    if let Some(delegates) = options.get(IMPERSONATE_DELEGATES) {
+        if let Some(bad) = delegates.split(',').find(|d| !is_service_account(d.trim())) {
+            return Err(invalid(format!(
+                "Delegate '{}' is not a service account email",
+                bad
+            )));
+        }
+    }
+    for key in [IMPERSONATE_DELEGATES, IMPERSONATE_LIFETIME] {
+        if options.contains_key(key) && !options.contains_key(IMPERSONATE_SERVICE_ACCOUNT) {
+            return Err(invalid(format!(
+                "'{}' requires a service account to impersonate",
+                key
+            )));
+        }
+    }
+    if let Some(lifetime) = options.get(IMPERSONATE_LIFETIME) {
+        match lifetime.parse::<u64>() {
+            Ok(seconds) if (1..=MAX_LIFETIME).contains(&seconds) => {}
+            _ => {
+                return Err(invalid(format!(
+                    "Token lifetime must be between 1 and {} seconds, not '{}'",
+                    MAX_LIFETIME, lifetime
+                )));
+            }
+        }
+    }
+    if let Some(scopes) = options.get(SCOPES) {
+        if let Some(bad) = scopes
+            .split_whitespace()
+            .find(|s| !s.starts_with("https://www.googleapis.com/auth/"))
+        {
+            return Err(invalid(format!(
+                "'{}' is not an OAuth scope (https://www.googleapis.com/auth/...)",
+                bad
+            )));
+        }
+    }
+
+    let audience = options.get(WORKLOAD_IDENTITY_AUDIENCE);
+    let token_file = options.get(SUBJECT_TOKEN_FILE);
+    match (audience, token_file) {
+        (Some(audience), Some(_)) => {
+            if !audience.starts_with("//iam.googleapis.com/") {
+                return Err(invalid(format!(
+                    "Workload identity audience '{}' must be a provider name \
+                     (//iam.googleapis.com/projects/.../providers/...)",
+                    audience
+                )));
+            }
+        }
+        (Some(_), None) => {
+            return Err(invalid(
+                "A workload identity audience requires a subject token file".to_string(),
+            ));
+        }
+        (None, Some(_)) => {
+            return Err(invalid(
+                "A subject token file requires a workload identity audience".to_string(),
+            ));
+        }
+        (None, None) => {
+            if options.contains_key(SUBJECT_TOKEN_TYPE) {
+                return Err(invalid(
+                    "A subject token type requires a workload identity audience".to_string(),
+                ));
+            }
+        }
+    }
+    if audience.is_some() {
+        let key_options = [
+            "google_service_account",
+            "google_service_account_path",
+            "google_service_account_key",
+            "service_account",
+            "service_account_path",
+            "service_account_key",
+        ];
+        if let Some(key) = key_options.iter().find(|k| options.contains_key(**k)) {
+            return Err(invalid(format!(
+                "Workload identity federation cannot be combined with '{}'",
+                key
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Read the application default credentials file, if it holds user
+/// credentials
+fn authorized_user() -> Option<SourceCredentials> {
+    let path = std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            let config = std::env::var_os("CLOUDSDK_CONFIG")
+                .map(std::path::PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME")
+                        .map(|h| std::path::Path::new(&h).join(".config/gcloud"))
+                })?;
+            Some(config.join("application_default_credentials.json"))
+        })?;
+    let text = std::fs::read_to_string(path).ok()?;
+    let file: Value = serde_json::from_str(&text).ok()?;
+    if file["type"] != "authorized_user" {
+        return None;
+    }
+    Some(SourceCredentials::AuthorizedUser {
+        client_id: file["client_id"].as_str()?.to_string(),
+        client_secret: file["client_secret"].as_str()?.to_string(),
+        refresh_token: file["refresh_token"].as_str()?.to_string(),
+    })
+}
+
+// ============================================================================
+// Token Provider
+// ============================================================================
+
+#[derive(Debug)]
+struct CachedToken {
+    credential: Arc<GcpCredential>,
+    expires: Instant,
+}
+
+/// Gets GCS bearer tokens through impersonation or federation
+#[derive(Debug)]
+struct TokenProvider {
+    client: reqwest::Client,
+    source: SourceCredentials,
+    impersonation: Option<Impersonation>,
+    scopes: Vec<String>,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+fn token_error(message: impl Into<String>) -> ObjectStoreError {
+    ObjectStoreError::Generic {
+        store: "GCS",
+        source: message.into().into(),
+    }
+}
+
+/// Send a token request and parse its JSON response
+async fn token_request(
+    request: reqwest::RequestBuilder,
+    what: &str,
+) -> Result<Value, ObjectStoreError> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| token_error(format!("{} request failed: {}", what, e)))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| token_error(format!("{} request failed: {}", what, e)))?;
+    if !status.is_success() {
+        return Err(token_error(format!(
+            "{} returned {}: {}",
+            what, status, body
+        )));
+    }
+    serde_json::from_str(&body)
+        .map_err(|e| token_error(format!("Invalid {} response: {}", what, e)))
+}
+
+/// Read an OAuth token response with `access_token` and `expires_in`
+fn oauth_token(response: &Value, what: &str) -> Result<(String, Duration), ObjectStoreError> {
+    let token = response["access_token"]
+        .as_str()
+        .ok_or_else(|| token_error(format!("{} response has no access token", what)))?;
+    let expires_in = response["expires_in"].as_u64().unwrap_or(3600);
+    Ok((token.to_string(), Duration::from_secs(expires_in)))
+}
+
+impl TokenProvider {
+    /// Create a provider from storage options, or `None` when none of the
+    /// flows of this module is requested
+    fn from_options(options: &HashMap<String, String>) -> DeltaResult<Option<Self>> {
+        let impersonation = options
+            .get(IMPERSONATE_SERVICE_ACCOUNT)
+            .map(|account| Impersonation {
+                service_account: account.clone(),
+                delegates: options
+                    .get(IMPERSONATE_DELEGATES)
+                    .map(|d| d.split(',').map(|s| s.trim().to_string()).collect())
+                    .unwrap_or_default(),
+                lifetime: options
+                    .get(IMPERSONATE_LIFETIME)
+                    .and_then(|l| l.parse().ok())
+                    .unwrap_or(3600),
+            });
+        let source = match options.get(WORKLOAD_IDENTITY_AUDIENCE) {
+            Some(audience) => SourceCredentials::ExternalAccount {
+                audience: audience.clone(),
+                subject_token_file: options.get(SUBJECT_TOKEN_FILE).cloned().unwrap_or_default(),
+                subject_token_type: options
+                    .get(SUBJECT_TOKEN_TYPE)
+                    .cloned()
+                    .unwrap_or_else(|| DEFAULT_SUBJECT_TOKEN_TYPE.to_string()),
+            },
+            None if impersonation.is_some() => {
+                authorized_user().unwrap_or(SourceCredentials::Metadata)
+            }
+            None => return Ok(None),
+        };
+        validate_options(options)?;
+
+        let scopes = options
+            .get(SCOPES)
+            .map(|s| s.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_else(|| vec![DEFAULT_SCOPE.to_string()]);
+        let client = crate::storage::http_client(options).map_err(DeltaTableError::Generic)?;
+        Ok(Some(TokenProvider {
+            client,
+            source,
+            impersonation,
+            scopes,
+            cached: Mutex::new(None),
+        }))
+    }
+
+    /// Get a token with the source credentials
+    ///
+    /// A token that will be exchanged for one of the target service account
+    /// needs the cloud-platform scope; a token used directly gets the
+    /// requested scopes.
+    async fn source_token(&self) -> Result<(String, Duration), ObjectStoreError> {
+        let scope = if self.impersonation.is_some() {
+            CLOUD_PLATFORM_SCOPE.to_string()
+        } else {
+            self.scopes.join(" ")
+        };
+        match &self.source {
+            SourceCredentials::Metadata => {
+                let host = std::env::var("GCE_METADATA_HOST")
+                    .unwrap_or_else(|_| METADATA_HOST.to_string());
+                let url = format!(
+                    "http://{}/computeMetadata/v1/instance/service-accounts/default/token",
+                    host
+                );
+                let request = self
+                    .client
+                    .get(url)
+                    .header("Metadata-Flavor", "Google")
+                    .query(&[("scopes", scope.replace(' ', ","))]);
+                let response = token_request(request, "Metadata server").await?;
+                oauth_token(&response, "Metadata server")
+            }
+            SourceCredentials::AuthorizedUser {
+                client_id,
+                client_secret,
+                refresh_token,
+            } => {
+                let request = self.client.post(OAUTH_URL).form(&[
+                    ("grant_type", "refresh_token"),
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                    ("refresh_token", refresh_token),
+                ]);
+                let response = token_request(request, "OAuth token").await?;
+                oauth_token(&response, "OAuth token")
+            }
+            SourceCredentials::ExternalAccount {
+                audience,
+                subject_token_file,
+                subject_token_type,
+            } => {
+                // Projected tokens are rotated on disk, so read it each time
+                let subject_token = std::fs::read_to_string(subject_token_file).map_err(|e| {
+                    token_error(format!(
+                        "Cannot read subject token file {}: {}",
+                        subject_token_file, e
+                    ))
+                })?;
+                let body = json!({
+                    "grantType": "urn:ietf:params:oauth:grant-type:token-exchange",
+                    "audience": audience,
+                    "scope": scope,
+                    "requestedTokenType": "urn:ietf:params:oauth:token-type:access_token",
+                    "subjectToken": subject_token.trim(),
+                    "subjectTokenType": subject_token_type,
+                });
+                let request = self.client.post(STS_URL).json(&body);
+                let response = token_request(request, "Security Token Service").await?;
+                oauth_token(&response, "Security Token Service")
+            }
+        }
+    }
+
+    /// Exchange the source token for a token of the target service account
+    async fn impersonate(
+        &self,
+        impersonation: &Impersonation,
+        source_token: &str,
+    ) -> Result<(String, Duration), ObjectStoreError> {
+        let url = format!(
+            "https://iamcredentials.googleapis.com/v1/projects/-/serviceAccounts/{}:generateAccessToken",
+            impersonation.service_account
+        );
+        let delegates: Vec<String> = impersonation
+            .delegates
+            .iter()
+            .map(|d| format!("projects/-/serviceAccounts/{}", d))
+            .collect();
+        let body = json!({
+            "delegates": delegates,
+            "scope": self.scopes,
+            "lifetime": format!("{}s", impersonation.lifetime),
+        });
+        let request = self.client.post(url).bearer_auth(source_token).json(&body);
+        let what = format!("Impersonation of {}", impersonation.service_account);
+        let response = token_request(request, &what).await?;
+
+        let token = response["accessToken"]
+            .as_str()
+            .ok_or_else(|| token_error(format!("{} response has no access token", what)))?;
+        let ttl = response["expireTime"]
+            .as_str()
+            .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+            .and_then(|t| {
+                (t.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                    .to_std()
+                    .ok()
+            })
+            .unwrap_or(Duration::from_secs(impersonation.lifetime));
+        Ok((token.to_string(), ttl))
+    }
+
+    async fn fetch(&self) -> Result<CachedToken, ObjectStoreError> {
+        let fetched = Instant::now();
+        let (mut token, mut ttl) = self.source_token().await?;
+        if let Some(impersonation) = &self.impersonation {
+            (token, ttl) = self.impersonate(impersonation, &token).await?;
+        }
+        Ok(CachedToken {
+            credential: Arc::new(GcpCredential { bearer: token }),
+            expires: fetched + ttl,
+        })
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for TokenProvider {
+    type Credential = GcpCredential;
+
+    async fn get_credential(&self) -> Result<Arc<GcpCredential>, ObjectStoreError> {
+        if let Some(cached) = self.cached.lock().unwrap().as_ref() {
+            if cached.expires.saturating_duration_since(Instant::now()) > MIN_TTL {
+                return Ok(cached.credential.clone());
+            }
+        }
+        let token = self.fetch().await?;
+        let credential = token.credential.clone();
+        *self.cached.lock().unwrap() = Some(token);
+        Ok(credential)
+    }
+}
+
+// ============================================================================
+// Object Store Factory
+// ============================================================================
+
+/// Opens `gs` URLs, with the credentials of this module when requested
+#[derive(Debug, Default)]
+struct GcsFactory {
+    inner: GcpFactory,
+}
+
+impl ObjectStoreFactory for GcsFactory {
+    fn parse_url_opts(
+        &self,
+        url: &Url,
+        config: &StorageConfig,
+    ) -> DeltaResult<(ObjectStoreRef, Path)> {
+        let Some(provider) = TokenProvider::from_options(&config.raw)? else {
+            return self.inner.parse_url_opts(url, config);
+        };
+
+        let mut builder = GoogleCloudStorageBuilder::new()
+            .with_url(url.to_string())
+            .with_retry(config.retry.clone())
+            .with_credentials(Arc::new(provider));
+        if let Some(runtime) = &config.runtime {
+            builder =
+                builder.with_http_connector(SpawnedReqwestConnector::new(runtime.get_handle()));
+        }
+        for (key, value) in &config.raw {
+            if let Ok(key) = GoogleConfigKey::from_str(&key.to_ascii_lowercase()) {
+                builder = builder.with_config(key, value.clone());
+            }
+        }
+        let store = builder
+            .build()
+            .map_err(|e| DeltaTableError::Generic(e.to_string()))?;
+
+        let (_, path) =
+            ObjectStoreScheme::parse(url).map_err(|e| DeltaTableError::Generic(e.to_string()))?;
+        Ok((Arc::new(store), Path::parse(path)?))
+    }
+}
+
+/// Route `gs` URLs through [GcsFactory]
+///
+/// Called after the delta-rs GCS handlers are registered, whose log store
+/// factory is kept.
+pub(crate) fn register_handlers() {
+    let url = Url::parse("gs://").unwrap();
+    object_store_factories().insert(url, Arc::new(GcsFactory::default()));
+}
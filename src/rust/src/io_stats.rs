@@ -0,0 +1,446 @@
+//! Object store request metrics
+//!
+//! Every object store opened through delta-rs is wrapped in a store that
+//! counts its requests, errors and bytes and records their latency in a
+//! histogram, by store (scheme and bucket) and operation. Retries happen
+//! inside object_store, which reports them only as tracing events, so they
+//! are counted from those events by the subscriber in [crate::profile].
+//!
+//! The metrics cover the life of the R session until they are reset, which
+//! tells whether a slow pipeline waits on many small requests, on large
+//! transfers or on a throttled store.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use deltalake::logstore::object_store::path::Path as StorePath;
+use deltalake::logstore::object_store::{
+    GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore,
+    PutMultipartOptions, PutOptions, PutPayload, PutResult, Result as StoreResult, UploadPart,
+};
+use deltalake::logstore::{
+    object_store_factories, ObjectStoreFactory, ObjectStoreRef, StorageConfig,
+};
+use deltalake::{DeltaResult, Path};
+use extendr_api::prelude::*;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use url::Url;
+
+/// Upper bounds of the latency histogram buckets, in milliseconds; the last
+/// bucket holds everything slower
+const LATENCY_BUCKETS_MS: &[f64] = &[
+    1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+];
+
+// ============================================================================
+// Metrics
+// ============================================================================
+
+/// Metrics of one operation on one store
+#[derive(Clone, Default)]
+struct OperationStats {
+    requests: u64,
+    errors: u64,
+    bytes: u64,
+    latency: Duration,
+    /// Counts per bucket of [LATENCY_BUCKETS_MS], plus one for slower
+    histogram: Vec<u64>,
+}
+
+impl OperationStats {
+    fn record(&mut self, latency: Duration, bytes: u64, failed: bool) {
+        if self.histogram.is_empty() {
+            self.histogram = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+        }
+        self.requests += 1;
+        self.errors += u64::from(failed);
+        self.bytes += bytes;
+        self.latency += latency;
+        let ms = latency.as_secs_f64() * 1000.0;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.histogram[bucket] += 1;
+    }
+
+    /// Estimate a latency quantile as the upper bound of its bucket
+    fn quantile_ms(&self, q: f64) -> f64 {
+        let target = (self.requests as f64 * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in self.histogram.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return LATENCY_BUCKETS_MS.get(i).copied().unwrap_or(f64::INFINITY);
+            }
+        }
+        f64::NAN
+    }
+}
+
+static STATS: LazyLock<Mutex<HashMap<(String, &'static str), OperationStats>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Retries of object store requests, counted by [crate::profile]
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn record_retry() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+fn record(store: &str, operation: &'static str, start: Instant, bytes: u64, failed: bool) {
+    STATS
+        .lock()
+        .unwrap()
+        .entry((store.to_string(), operation))
+        .or_default()
+        .record(start.elapsed(), bytes, failed);
+}
+
+// ============================================================================
+// Metered Store
+// ============================================================================
+
+/// An object store that records the metrics of its requests
+#[derive(Debug)]
+struct MeteredStore {
+    inner: ObjectStoreRef,
+    /// Scheme and bucket, e.g. `s3://bucket`
+    label: Arc<str>,
+}
+
+impl fmt::Display for MeteredStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Metered({})", self.inner)
+    }
+}
+
+impl MeteredStore {
+    async fn timed<T>(
+        &self,
+        operation: &'static str,
+        bytes: impl FnOnce(&T) -> u64,
+        request: impl std::future::Future<Output = StoreResult<T>>,
+    ) -> StoreResult<T> {
+        let start = Instant::now();
+        let result = request.await;
+        let transferred = result.as_ref().map(bytes).unwrap_or(0);
+        record(&self.label, operation, start, transferred, result.is_err());
+        result
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MeteredStore {
+    async fn put_opts(
+        &self,
+        location: &StorePath,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> StoreResult<PutResult> {
+        let size = payload.content_length() as u64;
+        self.timed(
+            "put",
+            |_| size,
+            self.inner.put_opts(location, payload, opts),
+        )
+        .await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &StorePath,
+        opts: PutMultipartOptions,
+    ) -> StoreResult<Box<dyn MultipartUpload>> {
+        let upload = self
+            .timed(
+                "multipart_start",
+                |_| 0,
+                self.inner.put_multipart_opts(location, opts),
+            )
+            .await?;
+        Ok(Box::new(MeteredUpload {
+            inner: upload,
+            label: self.label.clone(),
+        }))
+    }
+
+    async fn get_opts(&self, location: &StorePath, options: GetOptions) -> StoreResult<GetResult> {
+        let operation = if options.head { "head" } else { "get" };
+        self.timed(
+            operation,
+            |r: &GetResult| r.range.end - r.range.start,
+            self.inner.get_opts(location, options),
+        )
+        .await
+    }
+
+    async fn get_range(&self, location: &StorePath, range: Range<u64>) -> StoreResult<Bytes> {
+        self.timed(
+            "get",
+            |b: &Bytes| b.len() as u64,
+            self.inner.get_range(location, range),
+        )
+        .await
+    }
+
+    async fn get_ranges(
+        &self,
+        location: &StorePath,
+        ranges: &[Range<u64>],
+    ) -> StoreResult<Vec<Bytes>> {
+        self.timed(
+            "get",
+            |parts: &Vec<Bytes>| parts.iter().map(|b| b.len() as u64).sum(),
+            self.inner.get_ranges(location, ranges),
+        )
+        .await
+    }
+
+    async fn head(&self, location: &StorePath) -> StoreResult<ObjectMeta> {
+        self.timed("head", |_| 0, self.inner.head(location)).await
+    }
+
+    async fn delete(&self, location: &StorePath) -> StoreResult<()> {
+        self.timed("delete", |_| 0, self.inner.delete(location))
+            .await
+    }
+
+    fn list(&self, prefix: Option<&StorePath>) -> BoxStream<'static, StoreResult<ObjectMeta>> {
+        metered_list(self.label.clone(), self.inner.list(prefix))
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&StorePath>,
+        offset: &StorePath,
+    ) -> BoxStream<'static, StoreResult<ObjectMeta>> {
+        metered_list(
+            self.label.clone(),
+            self.inner.list_with_offset(prefix, offset),
+        )
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&StorePath>) -> StoreResult<ListResult> {
+        self.timed("list", |_| 0, self.inner.list_with_delimiter(prefix))
+            .await
+    }
+
+    async fn copy(&self, from: &StorePath, to: &StorePath) -> StoreResult<()> {
+        self.timed("copy", |_| 0, self.inner.copy(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &StorePath, to: &StorePath) -> StoreResult<()> {
+        self.timed("copy", |_| 0, self.inner.copy_if_not_exists(from, to))
+            .await
+    }
+
+    async fn rename(&self, from: &StorePath, to: &StorePath) -> StoreResult<()> {
+        self.timed("rename", |_| 0, self.inner.rename(from, to))
+            .await
+    }
+
+    async fn rename_if_not_exists(&self, from: &StorePath, to: &StorePath) -> StoreResult<()> {
+        self.timed("rename", |_| 0, self.inner.rename_if_not_exists(from, to))
+            .await
+    }
+}
+
+/// Record a listing as one request, timed until the stream ends
+fn metered_list(
+    label: Arc<str>,
+    stream: BoxStream<'static, StoreResult<ObjectMeta>>,
+) -> BoxStream<'static, StoreResult<ObjectMeta>> {
+    let start = Instant::now();
+    let failed = Arc::new(AtomicU64::new(0));
+    let seen = failed.clone();
+    stream
+        .inspect(move |item| {
+            if item.is_err() {
+                seen.store(1, Ordering::Relaxed);
+            }
+        })
+        .chain(futures::stream::poll_fn(move |_| {
+            record(
+                &label,
+                "list",
+                start,
+                0,
+                failed.load(Ordering::Relaxed) == 1,
+            );
+            std::task::Poll::Ready(None)
+        }))
+        .boxed()
+}
+
+/// A multipart upload that records each part as a request
+#[derive(Debug)]
+struct MeteredUpload {
+    inner: Box<dyn MultipartUpload>,
+    label: Arc<str>,
+}
+
+#[async_trait]
+impl MultipartUpload for MeteredUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        let size = data.content_length() as u64;
+        let label = self.label.clone();
+        let part = self.inner.put_part(data);
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = part.await;
+            record(&label, "put_part", start, size, result.is_err());
+            result
+        })
+    }
+
+    async fn complete(&mut self) -> StoreResult<PutResult> {
+        let start = Instant::now();
+        let result = self.inner.complete().await;
+        record(&self.label, "multipart_complete", start, 0, result.is_err());
+        result
+    }
+
+    async fn abort(&mut self) -> StoreResult<()> {
+        let start = Instant::now();
+        let result = self.inner.abort().await;
+        record(&self.label, "multipart_abort", start, 0, result.is_err());
+        result
+    }
+}
+
+// ============================================================================
+// Factories
+// ============================================================================
+
+/// Wraps the stores of another factory in [MeteredStore]
+struct MeteredFactory {
+    inner: Arc<dyn ObjectStoreFactory>,
+}
+
+impl fmt::Debug for MeteredFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MeteredFactory")
+    }
+}
+
+impl ObjectStoreFactory for MeteredFactory {
+    fn parse_url_opts(
+        &self,
+        url: &Url,
+        config: &StorageConfig,
+    ) -> DeltaResult<(ObjectStoreRef, Path)> {
+        let (store, path) = self.inner.parse_url_opts(url, config)?;
+        let label = match url.host_str() {
+            Some(host) if !host.is_empty() => format!("{}://{}", url.scheme(), host),
+            _ => format!("{}://", url.scheme()),
+        };
+        let store = MeteredStore {
+            inner: store,
+            label: label.into(),
+        };
+        Ok((Arc::new(store), path))
+    }
+}
+
+/// Wrap every registered object store factory so its stores are metered
+///
+/// Called once, after all storage handlers are registered.
+pub(crate) fn register_handlers() {
+    crate::profile::install_recorder();
+    let registry = object_store_factories();
+    let schemes: Vec<Url> = registry.iter().map(|entry| entry.key().clone()).collect();
+    for scheme in schemes {
+        let Some(inner) = registry.get(&scheme).map(|entry| entry.value().clone()) else {
+            continue;
+        };
+        registry.insert(scheme, Arc::new(MeteredFactory { inner }));
+    }
+}
+
+// ============================================================================
+// R Interface
+// ============================================================================
+
+/// Get the object store request metrics
+///
+/// Returns a list with `operations` (a data.frame with one row per store and
+/// operation), `latency` (the latency histogram of each, in long form) and
+/// the number of `retries`.
+///
+/// @param reset Whether to reset the metrics after reading them
+#[extendr]
+pub fn io_stats(reset: bool) -> List {
+    let mut stats = STATS.lock().unwrap();
+    let mut entries: Vec<(&(String, &'static str), &OperationStats)> = stats.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let stores: Vec<&str> = entries.iter().map(|(k, _)| k.0.as_str()).collect();
+    let operations: Vec<&str> = entries.iter().map(|(k, _)| k.1).collect();
+    let requests: Vec<f64> = entries.iter().map(|(_, s)| s.requests as f64).collect();
+    let errors: Vec<f64> = entries.iter().map(|(_, s)| s.errors as f64).collect();
+    let bytes: Vec<f64> = entries.iter().map(|(_, s)| s.bytes as f64).collect();
+    let seconds: Vec<f64> = entries
+        .iter()
+        .map(|(_, s)| s.latency.as_secs_f64())
+        .collect();
+    let quantile = |q: f64| -> Vec<f64> { entries.iter().map(|(_, s)| s.quantile_ms(q)).collect() };
+    let operations_df = data_frame!(
+        store = stores.clone(),
+        operation = operations.clone(),
+        requests = requests,
+        errors = errors,
+        bytes = bytes,
+        seconds = seconds,
+        p50_ms = quantile(0.5),
+        p90_ms = quantile(0.9),
+        p99_ms = quantile(0.99)
+    );
+
+    let mut h_store: Vec<&str> = Vec::new();
+    let mut h_operation: Vec<&str> = Vec::new();
+    let mut h_le: Vec<f64> = Vec::new();
+    let mut h_count: Vec<f64> = Vec::new();
+    for ((store, operation), s) in &entries {
+        for (i, count) in s.histogram.iter().enumerate() {
+            h_store.push(store);
+            h_operation.push(operation);
+            h_le.push(LATENCY_BUCKETS_MS.get(i).copied().unwrap_or(f64::INFINITY));
+            h_count.push(*count as f64);
+        }
+    }
+    let latency_df = data_frame!(
+        store = h_store,
+        operation = h_operation,
+        le_ms = h_le,
+        requests = h_count
+    );
+
+    let retries = if reset {
+        RETRIES.swap(0, Ordering::Relaxed)
+    } else {
+        RETRIES.load(Ordering::Relaxed)
+    };
+    let result = list!(
+        operations = operations_df,
+        latency = latency_df,
+        retries = retries as f64
+    );
+    drop(entries);
+    if reset {
+        stats.clear();
+    }
+    result
+}
+
+extendr_module! {
+    mod io_stats;
+    fn io_stats;
+}
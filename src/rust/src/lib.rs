@@ -1,17 +1,83 @@
+// The crate name must match the R package name
+#![allow(non_snake_case)]
+
+mod aws;
+mod azure;
+mod cache;
+mod catalog;
+mod checkpoint;
+mod clients;
+mod column_mapping;
+mod commit;
+mod constraints;
+mod context;
+mod debug;
+mod diff;
+mod direct_write;
+mod execution;
+mod expectations;
+mod export;
+mod flight;
+mod gcs;
+mod io_stats;
+mod locking;
 mod merge;
+mod optimize;
+mod paths;
+mod predicates;
+mod profile;
+mod read;
+mod sharing;
+mod sql;
+mod stats;
+mod storage;
+mod transaction;
+mod uniform;
+mod unity;
+mod verify;
+mod watch;
 mod write;
+mod writer;
 
 use arrow_extendr::to::IntoArrowRobj;
+use delta_kernel::table_features::{ColumnMappingMode, TableFeature};
+use delta_kernel::table_properties::DataSkippingNumIndexedCols;
 use deltalake::arrow::datatypes::{
     DataType as ArrowDataType, Field as ArrowField, Schema as ArrowSchema,
     TimeUnit as ArrowTimeUnit,
 };
-use deltalake::kernel::{DataType as KernelDataType, PrimitiveType, StructField, StructType};
-use deltalake::operations::optimize::OptimizeType;
-use deltalake::{DeltaTable, PartitionFilter, PartitionValue};
+use deltalake::kernel::scalars::ScalarExt;
+use deltalake::kernel::{
+    DataType as KernelDataType, LogicalFileView, PrimitiveType, StorageType, StructField,
+    StructType,
+};
+use deltalake::operations::optimize::{create_session_state_for_optimize, OptimizeType};
+use deltalake::table::config::TablePropertiesExt;
+use deltalake::{DeltaTable, DeltaTableBuilder, ObjectMeta, Path};
+
+use crate::checkpoint::{create_multipart_checkpoint, CheckpointParts};
+use crate::column_mapping::column_mapping_mode;
+use crate::commit::{
+    append_metrics, commit_properties, commit_timestamp, earliest_version, latest_checkpoint,
+    new_commit_actions, operation_result, read_commit_infos, AppTransaction,
+};
+use crate::context::{ErrorContext, OperationResult};
+use crate::optimize::{
+    compact_selected, optimize_metrics, optimize_result, optimized_partitions,
+    parse_partition_filters, partition_file_stats, FileSelection,
+};
+pub(crate) use crate::paths::path_to_url;
+use crate::read::{
+    dataframe_to_stream, file_view_to_add, files_table_provider, resolve_version, session_context,
+};
 use extendr_api::prelude::*;
-use std::collections::HashMap;
-use std::sync::Arc;
+use futures::{StreamExt, TryStreamExt};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::{c_int, c_void};
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::thread::ThreadId;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 
 /// Register cloud storage handlers (GCS, S3, Azure) for deltalake
@@ -20,10 +86,49 @@ use tokio::runtime::Runtime;
 fn register_cloud_handlers() {
     // Register GCS handler
     deltalake::gcp::register_handlers(None);
+    gcs::register_handlers();
     // Register S3 handler
     deltalake::aws::register_handlers(None);
+    aws::register_handlers();
     // Register Azure handler
     deltalake::azure::register_handlers(None);
+    azure::register_handlers();
+    // Register Unity Catalog handler
+    unity::register_handlers();
+    // Lock the commits of local tables when asked to
+    locking::register_handlers();
+    // Reuse the clients of remote stores, and meter every store
+    clients::register_handlers();
+    io_stats::register_handlers();
+}
+
+/// Identify deltaR and R in the commits of this process
+///
+/// delta-rs records its version in every commit, as `engineInfo`
+/// (`delta-rs:<version>`) and `clientVersion` (`delta-rs.<version>`). The
+/// version it reports can be set once per process; deltaR sets it when
+/// loaded to `<delta-rs version> deltaR/<version> R/<version>`, followed by
+/// the custom `client` when given, so commits made from R can be told
+/// apart in the table history.
+#[extendr]
+fn init_engine_info(deltar_version: &str, r_version: &str, client: Nullable<&str>) {
+    let mut version = format!(
+        "{} deltaR/{} R/{}",
+        deltalake::crate_version(),
+        deltar_version,
+        r_version
+    );
+    if let Nullable::NotNull(client) = client {
+        version.push(' ');
+        version.push_str(client);
+    }
+    deltalake::init_client_version(&version);
+}
+
+/// Get the engine info recorded in the commits of this process
+#[extendr]
+fn engine_info() -> String {
+    format!("delta-rs:{}", deltalake::crate_version())
 }
 
 /// Convert a kernel DataType to an Arrow DataType
@@ -75,6 +180,7 @@ fn kernel_type_to_arrow(kernel_type: &KernelDataType) -> ArrowDataType {
             ArrowDataType::Map(Arc::new(entries_field), false)
         }
         KernelDataType::Struct(s) => {
+            #[allow(clippy::redundant_closure)]
             let fields: Vec<ArrowField> = s.fields().map(|f| kernel_field_to_arrow(f)).collect();
             ArrowDataType::Struct(fields.into())
         }
@@ -93,49 +199,212 @@ fn kernel_field_to_arrow(field: &StructField) -> ArrowField {
 
 /// Convert a kernel StructType (schema) to an Arrow Schema
 fn kernel_schema_to_arrow(schema: &StructType) -> ArrowSchema {
+    #[allow(clippy::redundant_closure)]
     let fields: Vec<ArrowField> = schema.fields().map(|f| kernel_field_to_arrow(f)).collect();
     ArrowSchema::new(fields)
 }
 
-// Thread-local runtime for async operations to bridge R (sync) and Delta-rs (async)
-thread_local! {
-    static RUNTIME: Runtime = Runtime::new().expect("Failed to create Tokio runtime");
+// Runtime for async operations to bridge R (sync) and Delta-rs (async)
+static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .thread_name("deltaR-worker")
+        .enable_all()
+        .build()
+        .expect("Failed to create Tokio runtime")
+});
+
+/// How often a waiting R call checks for a user interrupt
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+extern "C" {
+    fn R_ToplevelExec(fun: extern "C" fn(*mut c_void), data: *mut c_void) -> c_int;
+    fn R_CheckUserInterrupt();
 }
 
-/// Execute an async block in the thread-local runtime
-pub(crate) fn block_on<F>(future: F) -> F::Output
-where
-    F: std::future::Future,
-{
-    RUNTIME.with(|rt| rt.block_on(future))
+extern "C" fn check_interrupt(_: *mut c_void) {
+    unsafe { R_CheckUserInterrupt() }
 }
 
-/// Helper to parse storage options from R List
-pub(crate) fn parse_storage_options(opts: &List) -> HashMap<String, String> {
-    let mut options: HashMap<String, String> = HashMap::new();
-    for (key, value) in opts.iter() {
-        if let Some(v) = value.as_str() {
-            options.insert(key.to_string(), v.to_string());
+/// Whether the user has asked R to interrupt the current call
+///
+/// `R_CheckUserInterrupt()` jumps out of the calling frame when there is an
+/// interrupt, which must never happen across Rust frames; in a top-level
+/// context the jump only ends that context. It also runs R's event loop.
+fn interrupt_pending() -> bool {
+    unsafe { R_ToplevelExec(check_interrupt, std::ptr::null_mut()) == 0 }
+}
+
+/// R's main thread, recorded when the package is loaded
+static MAIN_THREAD: OnceLock<ThreadId> = OnceLock::new();
+
+/// Record the calling thread as R's main thread
+/// Called from R's .onLoad; only that thread may call the R API
+#[extendr]
+fn init_main_thread() {
+    let _ = MAIN_THREAD.set(std::thread::current().id());
+}
+
+/// Whether the calling thread is R's main thread
+fn on_main_thread() -> bool {
+    MAIN_THREAD.get() == Some(&std::thread::current().id())
+}
+
+/// Error of an operation the user interrupted
+#[derive(Debug)]
+pub(crate) struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Operation interrupted by the user")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+impl From<Interrupted> for Error {
+    fn from(e: Interrupted) -> Self {
+        Error::from(e.to_string())
+    }
+}
+
+impl From<Interrupted> for deltalake::DeltaTableError {
+    fn from(e: Interrupted) -> Self {
+        deltalake::DeltaTableError::GenericError {
+            source: Box::new(e),
         }
     }
-    options
 }
 
-/// Helper to convert a path string to URL
-pub(crate) fn path_to_url(path: &str) -> std::result::Result<url::Url, String> {
-    // Try parsing as URL first
-    if let Ok(url) = url::Url::parse(path) {
-        return Ok(url);
+/// A task running a future that borrows from the frame that spawned it
+///
+/// Dropping the task, e.g. while unwinding, aborts it and waits until the
+/// future is dropped, so it never outlives what it borrows.
+struct ScopedTask<T>(Option<tokio::task::JoinHandle<T>>);
+
+impl<T> Drop for ScopedTask<T> {
+    fn drop(&mut self) {
+        if let Some(task) = self.0.take() {
+            task.abort();
+            let _ = RUNTIME.block_on(task);
+        }
     }
+}
 
-    // Treat as local path
-    let path_buf = std::path::Path::new(path);
-    let canonical = path_buf
-        .canonicalize()
-        .unwrap_or_else(|_| path_buf.to_path_buf());
+/// Run an async block on the shared runtime and wait for it
+///
+/// On R's main thread the future runs as a task on the runtime's workers,
+/// while the R thread waits for it in slices of [INTERRUPT_POLL_INTERVAL];
+/// between them it checks for user interrupts, keeps processing R events
+/// and calls the SAS token callbacks the operation asks for. On an
+/// interrupt the task is aborted, which cancels its requests, and the call
+/// fails with [Interrupted].
+///
+/// Other threads, such as those of a consumer pulling a stream, must not
+/// call the R API, so they run the future themselves on the runtime and
+/// cannot be interrupted. Inside the runtime, e.g. on one of its workers or
+/// blocking-pool threads, the future is run directly on that thread.
+pub(crate) fn block_on<F>(future: F) -> std::result::Result<F::Output, Interrupted>
+where
+    F: std::future::Future + Send,
+    F::Output: Send + 'static,
+{
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        return Ok(tokio::task::block_in_place(|| handle.block_on(future)));
+    }
+    if !on_main_thread() {
+        return Ok(RUNTIME.block_on(future));
+    }
+
+    let future: Pin<Box<dyn std::future::Future<Output = F::Output> + Send + '_>> =
+        Box::pin(future);
+    // SAFETY: the task is only spawned here, and `ScopedTask` waits for it
+    // to end before this frame is left, also when unwinding, so the future
+    // never outlives what it borrows.
+    let future: Pin<Box<dyn std::future::Future<Output = F::Output> + Send + 'static>> =
+        unsafe { std::mem::transmute(future) };
+    let mut task = ScopedTask(Some(RUNTIME.spawn(future)));
+    while let Some(handle) = task.0.as_mut() {
+        let slice = tokio::time::timeout(INTERRUPT_POLL_INTERVAL, handle);
+        if let Ok(result) = RUNTIME.block_on(slice) {
+            task.0 = None;
+            return match result {
+                Ok(output) => Ok(output),
+                // The task only ends without output when it panics or the
+                // runtime shuts down
+                Err(e) => match e.try_into_panic() {
+                    Ok(panic) => std::panic::resume_unwind(panic),
+                    Err(_) => Err(Interrupted),
+                },
+            };
+        }
+        azure::answer_token_requests();
+        if interrupt_pending() {
+            break;
+        }
+    }
+    drop(task);
+    Err(Interrupted)
+}
+
+/// Number of files a table's metadata operations process concurrently
+pub(crate) fn file_concurrency(table: &DeltaTable) -> usize {
+    table.config.log_buffer_size.max(1)
+}
 
-    url::Url::from_file_path(&canonical)
-        .map_err(|_| format!("Failed to create URL from path: {}", path))
+/// Apply `f` to every file, spreading the files over the runtime's workers
+///
+/// The files are split into one chunk per unit of `concurrency`; the
+/// results keep the order of `files`.
+pub(crate) fn map_files<T, F>(
+    files: Vec<LogicalFileView>,
+    concurrency: usize,
+    f: F,
+) -> Result<Vec<T>>
+where
+    T: Send + 'static,
+    F: Fn(&LogicalFileView) -> T + Send + Sync + 'static,
+{
+    let n = files.len();
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    let chunk_size = n.div_ceil(concurrency.max(1));
+    let f = Arc::new(f);
+    block_on(async {
+        let tasks: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let chunk = chunk.to_vec();
+                let f = f.clone();
+                tokio::spawn(async move { chunk.iter().map(|file| f(file)).collect::<Vec<T>>() })
+            })
+            .collect();
+        let mut results = Vec::with_capacity(n);
+        for task in tasks {
+            results.extend(task.await?);
+        }
+        Ok::<_, tokio::task::JoinError>(results)
+    })?
+    .map_err(|e| Error::from(e.to_string()))
+}
+
+/// Helper to parse storage options from R List
+///
+/// Options given as functions, such as a SAS token callback, are called for
+/// their current value. A SAS token callback is also kept, so that the
+/// token can be renewed during long operations.
+pub(crate) fn parse_storage_options(opts: &List) -> Result<HashMap<String, String>> {
+    let mut options: HashMap<String, String> = HashMap::new();
+    for (key, value) in opts.iter() {
+        if let Some(v) = storage::storage_option_value(key, &value)? {
+            if value.is_function() && storage::is_sas_key(key) {
+                let callback = azure::register_callback(&value);
+                options.insert(azure::SAS_CALLBACK.to_string(), callback.to_string());
+            }
+            options.insert(key.to_string(), v);
+        }
+    }
+    Ok(options)
 }
 
 /// A wrapper around deltalake::DeltaTable
@@ -145,6 +414,15 @@ pub struct DeltaTableInternal {
     inner: DeltaTable,
 }
 
+impl DeltaTableInternal {
+    /// Context of the errors of `operation` on the loaded version
+    fn context(&self, operation: &'static str) -> ErrorContext {
+        let context = ErrorContext::new(operation, self.inner.table_url().as_str());
+        context.set_version(self.inner.version());
+        context
+    }
+}
+
 #[extendr]
 impl DeltaTableInternal {
     /// Get the current version of the Delta Table
@@ -167,14 +445,312 @@ impl DeltaTableInternal {
         Ok(file_uris)
     }
 
+    /// Get the number of files, bytes and small files of each partition
+    ///
+    /// Files smaller than `small_file_size` bytes count as small. Partitions
+    /// with the most small files come first.
+    fn partition_file_stats(
+        &self,
+        small_file_size: f64,
+        partition_filters: Nullable<Vec<String>>,
+    ) -> Result<Robj> {
+        let filters = parse_partition_filters(partition_filters);
+        partition_file_stats(&self.inner, &filters, small_file_size as i64)
+    }
+
+    /// Get the active files with their size, modification time and partition
+    ///
+    /// Returns a named list of columns: `path` (relative to the table root),
+    /// `uri`, `size_bytes`, `modification_time` (milliseconds since the
+    /// epoch) and one character column per partition column, `NA` for null
+    /// partition values. Only files matching the `column=value`
+    /// `partition_filters` are listed.
+    fn files_detail(&self, partition_filters: Nullable<Vec<String>>) -> Result<List> {
+        let filters = parse_partition_filters(partition_filters);
+        let files: Vec<LogicalFileView> = block_on(async {
+            self.inner
+                .get_active_add_actions_by_partitions(&filters)
+                .try_collect()
+                .await
+        })?
+        .map_err(|e| Error::from(e.to_string()))?;
+        let columns = self
+            .inner
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?
+            .metadata()
+            .partition_columns()
+            .to_vec();
+
+        let log_store = self.inner.log_store();
+        let n = files.len();
+        let mut paths: Vec<String> = Vec::with_capacity(n);
+        let mut uris: Vec<String> = Vec::with_capacity(n);
+        let mut sizes: Vec<f64> = Vec::with_capacity(n);
+        let mut modification_times: Vec<f64> = Vec::with_capacity(n);
+        let mut partitions: Vec<Vec<Rstr>> = vec![Vec::with_capacity(n); columns.len()];
+
+        for file in &files {
+            let path = file.path();
+            let location = Path::parse(path.as_ref()).unwrap_or_else(|_| Path::from(path.as_ref()));
+            uris.push(log_store.to_uri(&location));
+            paths.push(path.into_owned());
+            sizes.push(file.size() as f64);
+            modification_times.push(file.modification_time() as f64);
+
+            let values = file_view_to_add(file).partition_values;
+            for (column, partition) in columns.iter().zip(partitions.iter_mut()) {
+                partition.push(match values.get(column) {
+                    Some(Some(value)) => Rstr::from(value.as_str()),
+                    _ => Rstr::na(),
+                });
+            }
+        }
+
+        let mut pairs: Vec<(String, Robj)> = vec![
+            ("path".to_string(), paths.into_robj()),
+            ("uri".to_string(), uris.into_robj()),
+            ("size_bytes".to_string(), sizes.into_robj()),
+            (
+                "modification_time".to_string(),
+                modification_times.into_robj(),
+            ),
+        ];
+        for (column, values) in columns.into_iter().zip(partitions) {
+            pairs.push((column, Strings::from_values(values).into_robj()));
+        }
+        Ok(List::from_pairs(pairs))
+    }
+
+    /// Get the files of the current snapshot for an Arrow Dataset
+    ///
+    /// Returns a list with the `paths` (local paths, or URIs for remote
+    /// tables) of the files without a deletion vector, the table's `schema`
+    /// and `partition_columns`, and `deletion_vector_rows`: an Arrow stream of
+    /// the live rows of the files with a deletion vector, or NULL when all
+    /// files are in `paths`. Partition values are left to be read from the file paths, so
+    /// the files of a partitioned table must be in `column=value/`
+    /// directories.
+    fn dataset_fragments(&self) -> Result<List> {
+        if column_mapping_mode(&self.inner) != ColumnMappingMode::None {
+            return Err(Error::from(
+                "Tables with column mapping cannot be read as an Arrow Dataset; use read_deltalake()",
+            ));
+        }
+        let snapshot = self
+            .inner
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?;
+        let partition_columns = snapshot.metadata().partition_columns().to_vec();
+        let schema = kernel_schema_to_arrow(snapshot.schema().as_ref());
+        let files: Vec<LogicalFileView> = block_on(async {
+            self.inner
+                .get_active_add_actions_by_partitions(&[])
+                .try_collect()
+                .await
+        })?
+        .map_err(|e| Error::from(e.to_string()))?;
+
+        let log_store = self.inner.log_store();
+        let mut paths: Vec<String> = Vec::new();
+        let mut deleted: Vec<deltalake::kernel::Add> = Vec::new();
+        for file in &files {
+            let path = file.path();
+            let hive = partition_columns.iter().all(|column| {
+                path.split('/')
+                    .any(|segment| segment.starts_with(&format!("{}=", column)))
+            });
+            if !hive {
+                return Err(Error::from(format!(
+                    "File '{}' is not in a column=value partition directory, so its partition values cannot be read by Arrow",
+                    path
+                )));
+            }
+            if file.deletion_vector_descriptor().is_some() {
+                deleted.push(file_view_to_add(file));
+                continue;
+            }
+            let location = Path::parse(path.as_ref()).unwrap_or_else(|_| Path::from(path.as_ref()));
+            let uri = log_store.to_uri(&location);
+            paths.push(match url::Url::parse(&uri) {
+                Ok(url) if url.scheme() == "file" => url
+                    .to_file_path()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or(uri),
+                _ => uri,
+            });
+        }
+
+        // A table without files gets an empty stream, so the Dataset still
+        // has its schema
+        let deletion_vector_rows = if deleted.is_empty() && !paths.is_empty() {
+            ().into_robj()
+        } else {
+            let provider = files_table_provider(&self.inner, deleted)?;
+            let df = session_context(None)
+                .read_table(provider)
+                .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+            dataframe_to_stream(df)?
+        };
+
+        Ok(list!(
+            paths = paths,
+            schema = schema
+                .into_arrow_robj()
+                .map_err(|e| Error::from(e.to_string()))?,
+            partition_columns = partition_columns,
+            deletion_vector_rows = deletion_vector_rows
+        ))
+    }
+
+    /// Get the add actions of the current snapshot as a data.frame
+    ///
+    /// One row per active file, with its size, modification time, record
+    /// count and deletion vector descriptor. Files without a deletion vector
+    /// have `NA` in the `deletion_vector_*` columns.
+    fn add_actions(&self) -> Result<Robj> {
+        let files: Vec<LogicalFileView> = block_on(async {
+            self.inner
+                .get_active_add_actions_by_partitions(&[])
+                .try_collect()
+                .await
+        })?
+        .map_err(|e| Error::from(e.to_string()))?;
+
+        let rows = map_files(files, file_concurrency(&self.inner), |file| {
+            let deletion_vector = file.deletion_vector_descriptor().map(|dv| {
+                let storage_type = match dv.storage_type {
+                    StorageType::UuidRelativePath => "uuid_relative_path",
+                    StorageType::Inline => "inline",
+                    StorageType::AbsolutePath => "absolute_path",
+                };
+                (storage_type, dv.cardinality as f64, dv.size_in_bytes as f64)
+            });
+            (
+                file.path().to_string(),
+                file.size() as f64,
+                file.modification_time() as f64,
+                file.num_records().map(|n| n as f64),
+                deletion_vector,
+            )
+        })?;
+
+        let n = rows.len();
+        let mut paths: Vec<String> = Vec::with_capacity(n);
+        let mut sizes: Vec<f64> = Vec::with_capacity(n);
+        let mut modification_times: Vec<f64> = Vec::with_capacity(n);
+        let mut num_records: Vec<Rfloat> = Vec::with_capacity(n);
+        let mut dv_storage_types: Vec<Rstr> = Vec::with_capacity(n);
+        let mut dv_cardinalities: Vec<Rfloat> = Vec::with_capacity(n);
+        let mut dv_sizes: Vec<Rfloat> = Vec::with_capacity(n);
+
+        for (path, size, modification_time, records, deletion_vector) in rows {
+            paths.push(path);
+            sizes.push(size);
+            modification_times.push(modification_time);
+            num_records.push(records.map(Rfloat::from).unwrap_or_else(Rfloat::na));
+
+            match deletion_vector {
+                Some((storage_type, cardinality, size_in_bytes)) => {
+                    dv_storage_types.push(Rstr::from(storage_type));
+                    dv_cardinalities.push(cardinality.into());
+                    dv_sizes.push(size_in_bytes.into());
+                }
+                None => {
+                    dv_storage_types.push(Rstr::na());
+                    dv_cardinalities.push(Rfloat::na());
+                    dv_sizes.push(Rfloat::na());
+                }
+            }
+        }
+
+        let df = data_frame!(
+            path = paths,
+            size_bytes = sizes,
+            modification_time = modification_times,
+            num_records = Doubles::from_values(num_records),
+            deletion_vector_storage_type = Strings::from_values(dv_storage_types),
+            deletion_vector_cardinality = Doubles::from_values(dv_cardinalities),
+            deletion_vector_size_bytes = Doubles::from_values(dv_sizes)
+        );
+
+        Ok(df.into_robj())
+    }
+
     /// Get the number of files in the current snapshot
     fn num_files(&self) -> i64 {
         self.inner
-            .get_file_uris()
-            .map(|iter| iter.count() as i64)
+            .snapshot()
+            .map(|state| state.log_data().num_files() as i64)
             .unwrap_or(0)
     }
 
+    /// Get what a print of the table shows, in one call
+    ///
+    /// Returns the table URI, version, commit timestamp (milliseconds since
+    /// the epoch), number and total size of the active files (`NA` when the
+    /// table was opened without its files), partition columns, the `delta.*`
+    /// table properties and the protocol.
+    fn summary(&self) -> Result<List> {
+        let snapshot = self
+            .inner
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?;
+        let version = snapshot.version();
+        let timestamp = match snapshot.version_timestamp(version) {
+            Some(ts) => Some(ts),
+            None => commit_timestamp(&self.inner, version)?,
+        };
+
+        let (num_files, size_bytes) = if self.inner.config.require_files {
+            let log_data = snapshot.log_data();
+            let size: i64 = log_data.iter().map(|file| file.size()).sum();
+            (Some(log_data.num_files() as f64), Some(size as f64))
+        } else {
+            (None, None)
+        };
+
+        let metadata = snapshot.metadata();
+        let properties: BTreeMap<&String, &String> = metadata
+            .configuration()
+            .iter()
+            .filter(|(key, _)| key.starts_with("delta."))
+            .collect();
+        let mut property_values = properties
+            .values()
+            .map(|v| v.as_str())
+            .collect::<Vec<_>>()
+            .into_robj();
+        if !properties.is_empty() {
+            property_values.set_names(properties.keys().map(|k| k.as_str()))?;
+        }
+
+        let protocol = snapshot.protocol();
+        let features = |features: Option<&[TableFeature]>| -> Vec<String> {
+            features
+                .unwrap_or_default()
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect()
+        };
+
+        Ok(list!(
+            uri = self.inner.table_url().to_string(),
+            version = version,
+            timestamp = timestamp.map(|ts| ts as f64),
+            num_files = num_files,
+            size_bytes = size_bytes,
+            partition_columns = metadata.partition_columns().to_vec(),
+            properties = property_values,
+            protocol = list!(
+                min_reader_version = protocol.min_reader_version(),
+                min_writer_version = protocol.min_writer_version(),
+                reader_features = features(protocol.reader_features()),
+                writer_features = features(protocol.writer_features())
+            )
+        ))
+    }
+
     /// Get table metadata as a list
     fn metadata(&self) -> Result<List> {
         let snapshot = self
@@ -183,7 +759,7 @@ impl DeltaTableInternal {
             .map_err(|e| Error::from(e.to_string()))?;
         let metadata = snapshot.metadata();
 
-        let mut result = List::new(6);
+        let mut result = List::new(7);
 
         // Set values
         result.set_elt(0, metadata.id().to_string().into_robj())?;
@@ -226,6 +802,7 @@ impl DeltaTableInternal {
             config_robj.set_names(config_names).ok();
         }
         result.set_elt(5, config_robj)?;
+        result.set_elt(6, uniform::uniform_metadata(&self.inner)?)?;
 
         // Set names
         result.set_names([
@@ -235,6 +812,7 @@ impl DeltaTableInternal {
             "partition_columns",
             "created_time",
             "configuration",
+            "uniform",
         ])?;
 
         Ok(result)
@@ -256,54 +834,131 @@ impl DeltaTableInternal {
     }
 
     /// Get commit history
-    fn history(&self, limit: Nullable<i64>) -> Result<Robj> {
-        let limit_val = match limit {
-            Nullable::NotNull(l) => Some(l as usize),
-            Nullable::Null => None,
-        };
+    ///
+    /// Commits are read newest first, a batch at a time, and only those with
+    /// one of `operations` and a timestamp (in milliseconds since the epoch)
+    /// between `start_ms` and `end_ms` are returned, up to `limit` of them.
+    /// Commit timestamps are assumed to increase with the version, so reading
+    /// stops at the first commit before `start_ms`.
+    fn history(
+        &self,
+        limit: Nullable<i64>,
+        operations: Nullable<Vec<String>>,
+        start_ms: Nullable<f64>,
+        end_ms: Nullable<f64>,
+        oldest_first: bool,
+    ) -> OperationResult<Robj> {
+        let _context = self.context("HISTORY");
+        let limit = limit.into_option().map(|l| l.max(0) as usize);
+        let operations: Option<Vec<String>> = operations
+            .into_option()
+            .map(|ops| ops.iter().map(|op| op.to_uppercase()).collect());
+        let start_ms = start_ms.into_option();
+        let end_ms = end_ms.into_option();
 
-        let history: Vec<_> = block_on(async { self.inner.history(limit_val).await })
-            .map_err(|e| Error::from(e.to_string()))?
-            .collect();
+        let mut versions: Vec<i64> = Vec::new();
+        let mut timestamps: Vec<Rfloat> = Vec::new();
+        let mut operation_names: Vec<String> = Vec::new();
+        let mut user_ids: Vec<String> = Vec::new();
+        let mut user_names: Vec<String> = Vec::new();
+        let mut engine_infos: Vec<Rstr> = Vec::new();
+        let mut client_versions: Vec<Rstr> = Vec::new();
 
-        // Convert history to vectors for dataframe construction
-        let n = history.len();
-        let mut versions: Vec<i64> = Vec::with_capacity(n);
-        let mut timestamps: Vec<i64> = Vec::with_capacity(n);
-        let mut operations: Vec<String> = Vec::with_capacity(n);
-        let mut user_ids: Vec<String> = Vec::with_capacity(n);
-        let mut user_names: Vec<String> = Vec::with_capacity(n);
+        let batch_size = file_concurrency(&self.inner) as i64;
+        let mut next = self.inner.version().unwrap_or(-1);
+        'read: while next >= 0 && limit.is_none_or(|l| versions.len() < l) {
+            let batch: Vec<i64> = (0.max(next - batch_size + 1)..=next).rev().collect();
+            next -= batch_size;
+            for (version, info) in batch.iter().zip(read_commit_infos(&self.inner, &batch)?) {
+                // Older commits were removed by log cleanup
+                let Some(info) = info else {
+                    break 'read;
+                };
+                let timestamp = info.timestamp.map(|ts| ts as f64);
+                if let (Some(start), Some(ts)) = (start_ms, timestamp) {
+                    if ts < start {
+                        break 'read;
+                    }
+                }
+                if let (Some(end), Some(ts)) = (end_ms, timestamp) {
+                    if ts > end {
+                        continue;
+                    }
+                }
+                let operation = info.operation.unwrap_or_default();
+                if let Some(ops) = &operations {
+                    if !ops.contains(&operation.to_uppercase()) {
+                        continue;
+                    }
+                }
 
-        for (idx, commit) in history.into_iter().enumerate() {
-            // Version is inferred from position in history (most recent first)
-            versions.push(idx as i64);
-            timestamps.push(commit.timestamp.unwrap_or(0));
-            operations.push(commit.operation.unwrap_or_default());
-            user_ids.push(commit.user_id.unwrap_or_default());
-            user_names.push(commit.user_name.unwrap_or_default());
+                versions.push(*version);
+                timestamps.push(timestamp.map(Rfloat::from).unwrap_or_else(Rfloat::na));
+                operation_names.push(operation);
+                user_ids.push(info.user_id.unwrap_or_default());
+                user_names.push(info.user_name.unwrap_or_default());
+                client_versions.push(
+                    info.info
+                        .get("clientVersion")
+                        .and_then(|v| v.as_str())
+                        .map_or(Rstr::na(), Rstr::from),
+                );
+                engine_infos.push(info.engine_info.as_deref().map_or(Rstr::na(), Rstr::from));
+                if limit.is_some_and(|l| versions.len() >= l) {
+                    break 'read;
+                }
+            }
+        }
+
+        if oldest_first {
+            versions.reverse();
+            timestamps.reverse();
+            operation_names.reverse();
+            user_ids.reverse();
+            user_names.reverse();
+            engine_infos.reverse();
+            client_versions.reverse();
         }
 
         // Create a data.frame
         let df = data_frame!(
             version = versions,
             timestamp = timestamps,
-            operation = operations,
+            operation = operation_names,
             user_id = user_ids,
-            user_name = user_names
+            user_name = user_names,
+            engine_info = Strings::from_values(engine_infos),
+            client_version = Strings::from_values(client_versions)
         );
 
         Ok(df.into_robj())
     }
 
+    /// Load the latest version of the table
+    fn update(&mut self) -> OperationResult<()> {
+        let _context = self.context("LOAD");
+        block_on(async { self.inner.update_state().await })?
+            .map_err(|e| Error::from(e.to_string()))?;
+        Ok(())
+    }
+
     /// Load a specific version of the table
-    fn load_version(&mut self, version: i64) -> Result<()> {
-        block_on(async { self.inner.load_version(version).await })
+    ///
+    /// Negative versions count back from the latest one: `-1` is the version
+    /// before it.
+    fn load_version(&mut self, version: i64) -> OperationResult<()> {
+        let context = self.context("LOAD");
+        context.set_version(Some(version));
+        let version = resolve_version(&self.inner, version)?;
+        context.set_version(Some(version));
+        block_on(async { self.inner.load_version(version).await })?
             .map_err(|e| Error::from(e.to_string()))?;
         Ok(())
     }
 
     /// Load table at a specific datetime (ISO 8601 format)
-    fn load_datetime(&mut self, datetime_str: &str) -> Result<()> {
+    fn load_datetime(&mut self, datetime_str: &str) -> OperationResult<()> {
+        let _context = self.context("LOAD");
         let datetime = chrono::DateTime::parse_from_rfc3339(datetime_str)
             .map_err(|e| Error::from(format!("Invalid datetime format: {}", e)))?;
 
@@ -311,22 +966,177 @@ impl DeltaTableInternal {
             self.inner
                 .load_with_datetime(datetime.with_timezone(&chrono::Utc))
                 .await
-        })
+        })?
         .map_err(|e| Error::from(e.to_string()))?;
 
         Ok(())
     }
 
+    /// Get the earliest version of the table that can still be loaded
+    fn earliest_version(&self) -> Result<i64> {
+        earliest_version(&self.inner)
+    }
+
+    /// Get the version of the table that was current at `timestamp_ms`
+    ///
+    /// Binary search over the commit timestamps of the versions in the log,
+    /// including versions committed after the one this handle has loaded;
+    /// the handle itself is left at its version.
+    fn version_at(&self, timestamp_ms: f64) -> Result<i64> {
+        let target = timestamp_ms as i64;
+        let timestamp = |version: i64| {
+            commit_timestamp(&self.inner, version)?.ok_or_else(|| {
+                Error::from(format!(
+                    "Commit {} is missing from the transaction log",
+                    version
+                ))
+            })
+        };
+
+        let mut low = earliest_version(&self.inner)?;
+        let mut high = block_on(async {
+            self.inner
+                .log_store()
+                .get_latest_version(self.inner.version().unwrap_or(0))
+                .await
+        })?
+        .map_err(|e| Error::from(format!("Failed to read the transaction log: {}", e)))?;
+
+        if timestamp(low)? > target {
+            let datetime = chrono::DateTime::from_timestamp_millis(target)
+                .map(|dt| dt.to_rfc3339())
+                .unwrap_or_else(|| target.to_string());
+            return Err(Error::from(format!(
+                "The table has no version at {}: its earliest version ({}) was committed later",
+                datetime, low
+            )));
+        }
+        while low < high {
+            let pivot = low + (high - low + 1) / 2;
+            if timestamp(pivot)? <= target {
+                low = pivot;
+            } else {
+                high = pivot - 1;
+            }
+        }
+        Ok(low)
+    }
+
     /// Optimize the table (compact files)
+    ///
+    /// Without a file selection the whole table (or the filtered partitions)
+    /// is compacted by delta-rs; otherwise only the selected files are.
+    #[allow(clippy::too_many_arguments)]
     fn compact(
         &self,
         target_size: Nullable<i64>,
         max_concurrent_tasks: Nullable<i32>,
         min_commit_interval_ms: Nullable<f64>,
         partition_filters: Nullable<Vec<String>>,
-    ) -> Result<List> {
-        let (_, metrics) = block_on(async {
-            let mut builder = self.inner.clone().optimize();
+        max_file_size: Nullable<f64>,
+        min_file_age_hours: Nullable<f64>,
+        max_rewrite_bytes: Nullable<f64>,
+        app_transaction: Nullable<List>,
+    ) -> OperationResult<List> {
+        let _context = self.context("OPTIMIZE");
+        let app = AppTransaction::from_r(&self.inner, &app_transaction)?;
+        if let Some(app) = app.as_ref().filter(|app| app.skipped) {
+            return Ok(app.skipped_result(&self.inner, "OPTIMIZE")?);
+        }
+        let read_version = self.inner.version();
+        let started_ms = chrono::Utc::now().timestamp_millis();
+        let p_filters = parse_partition_filters(partition_filters);
+        let selection = FileSelection {
+            max_file_size: max_file_size.into_option().map(|bytes| bytes as i64),
+            min_file_age_hours: min_file_age_hours.into_option(),
+            max_rewrite_bytes: max_rewrite_bytes.into_option().map(|bytes| bytes as i64),
+        };
+        let (table, metrics) = if selection.is_default() {
+            block_on(async {
+                let mut builder = self
+                    .inner
+                    .clone()
+                    .optimize()
+                    .with_commit_properties(commit_properties());
+
+                if let Nullable::NotNull(size) = target_size {
+                    builder = builder.with_target_size(size as u64);
+                }
+
+                if let Nullable::NotNull(tasks) = max_concurrent_tasks {
+                    builder = builder.with_max_concurrent_tasks(tasks as usize);
+                }
+
+                if let Nullable::NotNull(ms) = min_commit_interval_ms {
+                    builder = builder
+                        .with_min_commit_interval(std::time::Duration::from_millis(ms as u64));
+                }
+
+                if !p_filters.is_empty() {
+                    builder = builder.with_filters(&p_filters);
+                }
+
+                builder.with_type(OptimizeType::Compact).await
+            })?
+            .map_err(|e| Error::from(e.to_string()))?
+        } else {
+            compact_selected(
+                &self.inner,
+                &selection,
+                &p_filters,
+                target_size.clone().into_option(),
+            )?
+        };
+
+        let partitions = optimized_partitions(&self.inner, &table, started_ms)?;
+        let metrics = optimize_metrics(&metrics);
+        Ok(optimize_result(
+            table,
+            read_version,
+            metrics,
+            partitions,
+            app,
+            target_size,
+        )?)
+    }
+
+    /// Optimize the table by z-ordering its files on `columns`
+    ///
+    /// Z-ordering sorts each partition by an interleaving of the columns,
+    /// which spills to disk once `max_spill_size` bytes are held in memory.
+    #[allow(clippy::too_many_arguments)]
+    fn z_order(
+        &self,
+        columns: Vec<String>,
+        target_size: Nullable<i64>,
+        max_concurrent_tasks: Nullable<i32>,
+        max_spill_size: Nullable<f64>,
+        max_temp_directory_size: Nullable<f64>,
+        min_commit_interval_ms: Nullable<f64>,
+        partition_filters: Nullable<Vec<String>>,
+        app_transaction: Nullable<List>,
+    ) -> OperationResult<List> {
+        let _context = self.context("OPTIMIZE");
+        let app = AppTransaction::from_r(&self.inner, &app_transaction)?;
+        if let Some(app) = app.as_ref().filter(|app| app.skipped) {
+            return Ok(app.skipped_result(&self.inner, "OPTIMIZE")?);
+        }
+        let read_version = self.inner.version();
+        let started_ms = chrono::Utc::now().timestamp_millis();
+        let session = create_session_state_for_optimize(
+            max_spill_size.into_option().map(|bytes| bytes as usize),
+            max_temp_directory_size
+                .into_option()
+                .map(|bytes| bytes as u64),
+        );
+        let p_filters = parse_partition_filters(partition_filters);
+        let (table, metrics) = block_on(async {
+            let mut builder = self
+                .inner
+                .clone()
+                .optimize()
+                .with_commit_properties(commit_properties())
+                .with_session_state(Arc::new(session));
 
             if let Nullable::NotNull(size) = target_size {
                 builder = builder.with_target_size(size as u64);
@@ -341,50 +1151,24 @@ impl DeltaTableInternal {
                     builder.with_min_commit_interval(std::time::Duration::from_millis(ms as u64));
             }
 
-            let p_filters: Vec<PartitionFilter> = match partition_filters {
-                Nullable::NotNull(filters) => filters
-                    .into_iter()
-                    .filter_map(|f| {
-                        f.split_once('=').map(|(col, val)| PartitionFilter {
-                            key: col.trim().to_string(),
-                            value: PartitionValue::Equal(val.trim().to_string()),
-                        })
-                    })
-                    .collect(),
-                Nullable::Null => Vec::new(),
-            };
-
             if !p_filters.is_empty() {
                 builder = builder.with_filters(&p_filters);
             }
 
-            builder.with_type(OptimizeType::Compact).await
-        })
+            builder.with_type(OptimizeType::ZOrder(columns)).await
+        })?
         .map_err(|e| Error::from(e.to_string()))?;
 
-        Ok(list!(
-            numFilesAdded = metrics.num_files_added as i32,
-            numFilesRemoved = metrics.num_files_removed as i32,
-            filesAdded = list!(
-                min = metrics.files_added.min as f64,
-                max = metrics.files_added.max as f64,
-                avg = metrics.files_added.avg as f64,
-                totalFiles = metrics.files_added.total_files as i32,
-                totalSize = metrics.files_added.total_size as f64
-            ),
-            filesRemoved = list!(
-                min = metrics.files_removed.min as f64,
-                max = metrics.files_removed.max as f64,
-                avg = metrics.files_removed.avg as f64,
-                totalFiles = metrics.files_removed.total_files as i32,
-                totalSize = metrics.files_removed.total_size as f64
-            ),
-            partitionsOptimized = metrics.partitions_optimized as i32,
-            numBatches = metrics.num_batches as i32,
-            totalConsideredFiles = metrics.total_considered_files as i32,
-            totalFilesSkipped = metrics.total_files_skipped as i32,
-            preserveInsertionOrder = metrics.preserve_insertion_order
-        ))
+        let partitions = optimized_partitions(&self.inner, &table, started_ms)?;
+        let metrics = optimize_metrics(&metrics);
+        Ok(optimize_result(
+            table,
+            read_version,
+            metrics,
+            partitions,
+            app,
+            target_size,
+        )?)
     }
 
     /// Vacuum the table (remove old files)
@@ -393,9 +1177,15 @@ impl DeltaTableInternal {
         retention_hours: Nullable<f64>,
         dry_run: bool,
         enforce_retention_duration: bool,
-    ) -> Result<Vec<String>> {
-        let (_, metrics) = block_on(async {
-            let mut vacuum_builder = self.inner.clone().vacuum();
+    ) -> OperationResult<List> {
+        let _context = self.context("VACUUM");
+        let read_version = self.inner.version();
+        let (table, metrics) = block_on(async {
+            let mut vacuum_builder = self
+                .inner
+                .clone()
+                .vacuum()
+                .with_commit_properties(commit_properties());
 
             if let Nullable::NotNull(hours) = retention_hours {
                 vacuum_builder =
@@ -407,10 +1197,367 @@ impl DeltaTableInternal {
                 vacuum_builder.with_enforce_retention_duration(enforce_retention_duration);
 
             vacuum_builder.await
-        })
+        })?
         .map_err(|e| Error::from(e.to_string()))?;
 
-        Ok(metrics.files_deleted)
+        let num_files_deleted = metrics.files_deleted.len() as i32;
+        let metrics = if dry_run {
+            let candidates = vacuum_candidates(&table, metrics.files_deleted)?;
+            list!(
+                num_files_deleted = num_files_deleted,
+                files_deleted = candidates.files,
+                reclaimable_bytes = candidates.total_bytes,
+                dry_run = dry_run
+            )
+        } else {
+            list!(
+                num_files_deleted = num_files_deleted,
+                files_deleted = metrics.files_deleted,
+                dry_run = dry_run
+            )
+        };
+        let actions = new_commit_actions(&table, read_version)?;
+        Ok(operation_result(&table, &actions, "VACUUM", metrics)?)
+    }
+
+    /// Restore the table to an earlier version or datetime
+    ///
+    /// Exactly one of `version` and `datetime` (RFC 3339) must be given. With
+    /// `dry_run`, nothing is committed and the metrics describe the files
+    /// the restore would add back and remove.
+    fn restore(
+        &self,
+        version: Nullable<i64>,
+        datetime: Nullable<&str>,
+        dry_run: bool,
+        ignore_missing_files: bool,
+        protocol_downgrade_allowed: bool,
+        app_transaction: Nullable<List>,
+    ) -> OperationResult<List> {
+        let _context = self.context("RESTORE");
+        let app = AppTransaction::from_r(&self.inner, &app_transaction)?;
+        if let Some(app) = app.as_ref().filter(|app| app.skipped) {
+            return Ok(app.skipped_result(&self.inner, "RESTORE")?);
+        }
+        let read_version = self.inner.version();
+        let datetime = match datetime {
+            Nullable::NotNull(s) => Some(
+                chrono::DateTime::parse_from_rfc3339(s)
+                    .map_err(|e| Error::from(format!("Invalid datetime format: {}", e)))?
+                    .with_timezone(&chrono::Utc),
+            ),
+            Nullable::Null => None,
+        };
+
+        let version = match version {
+            Nullable::NotNull(v) => Nullable::NotNull(resolve_version(&self.inner, v)?),
+            Nullable::Null => Nullable::Null,
+        };
+        let mut target = self.inner.clone();
+        block_on(async {
+            match (version, datetime) {
+                (Nullable::NotNull(v), None) => target.load_version(v).await,
+                (Nullable::Null, Some(dt)) => target.load_with_datetime(dt).await,
+                _ => Err(deltalake::DeltaTableError::Generic(
+                    "Exactly one of version and datetime must be given".to_string(),
+                )),
+            }
+        })?
+        .map_err(|e| Error::from(e.to_string()))?;
+
+        let restored_version = target.version().unwrap_or(-1);
+        let current_version = read_version.unwrap_or(-1);
+        if restored_version >= current_version {
+            return Err(Error::from(format!(
+                "Version to restore ({}) must be lower than the table version ({})",
+                restored_version, current_version
+            ))
+            .into());
+        }
+
+        let plan = restore_plan(&self.inner, &target)?;
+        let table = if dry_run {
+            self.inner.clone()
+        } else {
+            let (table, _) = block_on(async {
+                let mut builder = self
+                    .inner
+                    .clone()
+                    .restore()
+                    .with_commit_properties(commit_properties())
+                    .with_ignore_missing_files(ignore_missing_files)
+                    .with_protocol_downgrade_allowed(protocol_downgrade_allowed);
+                if let Some(app) = &app {
+                    builder = builder.with_commit_properties(app.commit_properties());
+                }
+                builder = match datetime {
+                    Some(dt) => builder.with_datetime_to_restore(dt),
+                    None => builder.with_version_to_restore(restored_version),
+                };
+                builder.await
+            })?
+            .map_err(|e| Error::from(e.to_string()))?;
+            table
+        };
+
+        let metrics = list!(
+            restored_version = restored_version,
+            num_restored_files = plan.num_restored_files as i32,
+            num_removed_files = plan.num_removed_files as i32,
+            table_size_change = plan.table_size_change,
+            dry_run = dry_run
+        );
+        let actions = new_commit_actions(&table, read_version)?;
+        let result = operation_result(&table, &actions, "RESTORE", metrics)?;
+        Ok(match app.filter(|_| !dry_run) {
+            Some(app) => append_metrics(result, app.metrics())?,
+            None => result,
+        })
+    }
+
+    /// Recompute file statistics from the Parquet footers (ANALYZE)
+    ///
+    /// With `missing_only`, only files without statistics are analyzed.
+    fn analyze(&self, missing_only: bool) -> OperationResult<List> {
+        let _context = self.context(stats::ANALYZE_OPERATION);
+        let read_version = self.inner.version();
+        let (table, metrics) = stats::recompute_statistics(&self.inner, missing_only)?;
+
+        let metrics = list!(
+            num_files_analyzed = metrics.num_files_analyzed as i32,
+            num_files_skipped = metrics.num_files_skipped as i32,
+            missing_only = missing_only
+        );
+        let actions = new_commit_actions(&table, read_version)?;
+        Ok(operation_result(
+            &table,
+            &actions,
+            stats::ANALYZE_OPERATION,
+            metrics,
+        )?)
+    }
+
+    /// Get the last version an application committed with a transaction
+    ///
+    /// Returns `NA` when the application has not committed to the table.
+    fn app_transaction_version(&self, app_id: &str) -> Result<Rfloat> {
+        let snapshot = self
+            .inner
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?;
+        let version = block_on(async {
+            snapshot
+                .transaction_version(self.inner.log_store().as_ref(), app_id)
+                .await
+        })?
+        .map_err(|e| Error::from(format!("Failed to read application transaction: {}", e)))?;
+        Ok(version
+            .map(|v| Rfloat::from(v as f64))
+            .unwrap_or_else(Rfloat::na))
+    }
+
+    /// Read the rows added after `version` as an Arrow stream
+    ///
+    /// Returns the stream, the table version it reads up to (the new
+    /// high-watermark) and its source: "appends" or "cdf". `columns` and
+    /// `predicate` are pushed into the scan; without `include_preimage`,
+    /// `update_preimage` rows of the change data feed are left out.
+    fn changes_since(
+        &self,
+        version: i64,
+        columns: Nullable<Vec<String>>,
+        predicate: Nullable<&str>,
+        include_preimage: bool,
+    ) -> Result<List> {
+        let ctx = read::session_context(None);
+        let (df, source) = read::changes_since(
+            &ctx,
+            &self.inner,
+            version,
+            &columns,
+            &predicate,
+            include_preimage,
+        )?;
+        Ok(list!(
+            stream = read::dataframe_to_stream(df)?,
+            version = self.inner.version().unwrap_or(-1),
+            source = source.as_str()
+        ))
+    }
+
+    /// Load the commits made after `version`, if there are any
+    ///
+    /// Only the commit file of the next version is requested, so waiting for
+    /// new commits costs a single request per check. Returns whether a
+    /// version after `version` is loaded.
+    fn update_after(&mut self, version: i64) -> Result<bool> {
+        if self.inner.version().is_some_and(|loaded| loaded > version) {
+            return Ok(true);
+        }
+        let next = block_on(async { self.inner.log_store().read_commit_entry(version + 1).await })?
+            .map_err(|e| Error::from(format!("Failed to read the transaction log: {}", e)))?;
+        if next.is_none() {
+            return Ok(false);
+        }
+        block_on(async { self.inner.update_state().await })?
+            .map_err(|e| Error::from(e.to_string()))?;
+        Ok(true)
+    }
+
+    /// List the CHECK constraints and column invariants of the table
+    ///
+    /// Returns a data.frame with the `name` (the column path for
+    /// invariants), `kind` ("check" or "invariant"), `expression` and
+    /// `added_version` of each one.
+    fn constraints(&self) -> Result<Robj> {
+        let constraints = constraints::table_constraints(&self.inner)?;
+
+        let n = constraints.len();
+        let mut names: Vec<String> = Vec::with_capacity(n);
+        let mut kinds: Vec<&str> = Vec::with_capacity(n);
+        let mut expressions: Vec<String> = Vec::with_capacity(n);
+        let mut added_versions: Vec<Rfloat> = Vec::with_capacity(n);
+        for (constraint, added_version) in constraints {
+            names.push(constraint.name);
+            kinds.push(constraint.kind);
+            expressions.push(constraint.expression);
+            added_versions.push(
+                added_version
+                    .map(|v| Rfloat::from(v as f64))
+                    .unwrap_or_else(Rfloat::na),
+            );
+        }
+
+        let df = data_frame!(
+            name = names,
+            kind = kinds,
+            expression = expressions,
+            added_version = Doubles::from_values(added_versions)
+        );
+        Ok(df.into_robj())
+    }
+
+    /// Find the rows that violate a constraint expression
+    ///
+    /// Returns the number of violating rows per file and a sample of at most
+    /// `sample_size` violating rows as an Arrow stream.
+    fn validate_constraint(&self, expression: &str, sample_size: i32) -> Result<List> {
+        if sample_size < 0 {
+            return Err(Error::from("sample_size must be non-negative"));
+        }
+        let violations =
+            constraints::constraint_violations(&self.inner, expression, sample_size as usize)?;
+        let by_file = data_frame!(file = violations.files, num_violations = violations.counts);
+        Ok(list!(
+            by_file = by_file,
+            sample = read::dataframe_to_stream(violations.sample)?
+        ))
+    }
+
+    /// Write a checkpoint at the loaded version
+    ///
+    /// Tables with the `v2Checkpoint` feature get a v2 spec checkpoint (with
+    /// a checkpointMetadata action), other tables a classic one. With
+    /// `parts` or `part_size` (bytes of actions per part) a classic
+    /// checkpoint is split into several files. Returns the version, the
+    /// format written ("v2", "classic" or "multi-part") and the number of
+    /// parts.
+    fn create_checkpoint(
+        &self,
+        parts: Nullable<i32>,
+        part_size: Nullable<f64>,
+    ) -> OperationResult<List> {
+        let _context = self.context("CHECKPOINT");
+        let snapshot = self
+            .inner
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?;
+        let is_v2 = snapshot
+            .protocol()
+            .reader_features()
+            .is_some_and(|features| features.contains(&TableFeature::V2Checkpoint));
+        let version = self.inner.version().unwrap_or(-1);
+
+        let split = match (parts, part_size) {
+            (Nullable::NotNull(parts), _) => {
+                if parts < 1 {
+                    return Err(Error::from("parts must be a positive number").into());
+                }
+                Some(CheckpointParts::Count(parts as usize))
+            }
+            (_, Nullable::NotNull(size)) => {
+                if size.is_nan() || size <= 0.0 {
+                    return Err(Error::from("part_size must be a positive number of bytes").into());
+                }
+                Some(CheckpointParts::MaxBytes(size.max(1.0) as usize))
+            }
+            _ => None,
+        };
+        if let Some(split) = split {
+            if let Some(parts) = create_multipart_checkpoint(&self.inner, split)? {
+                return Ok(list!(
+                    version = version,
+                    format = "multi-part",
+                    parts = parts as i32
+                ));
+            }
+        }
+
+        block_on(async { deltalake::checkpoints::create_checkpoint(&self.inner, None).await })?
+            .map_err(|e| Error::from(format!("Failed to create checkpoint: {}", e)))?;
+
+        Ok(list!(
+            version = version,
+            format = if is_v2 { "v2" } else { "classic" },
+            parts = 1
+        ))
+    }
+
+    /// Get the state of the log for the table health report
+    ///
+    /// Returns the version of the newest checkpoint (`NA` without one), the
+    /// number of remove actions (tombstones) the log keeps and how many of
+    /// them are past the deleted file retention, i.e. whose files vacuum
+    /// would delete.
+    fn log_health(&self) -> Result<List> {
+        let state = self
+            .inner
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?;
+        let retention = state
+            .snapshot()
+            .table_properties()
+            .deleted_file_retention_duration();
+        let cutoff = chrono::Utc::now().timestamp_millis() - retention.as_millis() as i64;
+
+        let log_store = self.inner.log_store();
+        let (num_tombstones, num_expired) = block_on(async {
+            state
+                .all_tombstones(log_store.as_ref())
+                .try_fold((0u64, 0u64), |(total, expired), tombstone| async move {
+                    let is_expired = tombstone.deletion_timestamp().is_some_and(|ts| ts < cutoff);
+                    Ok((total + 1, expired + is_expired as u64))
+                })
+                .await
+        })?
+        .map_err(|e| Error::from(format!("Failed to read tombstones: {}", e)))?;
+
+        Ok(list!(
+            last_checkpoint_version = latest_checkpoint(&self.inner)?
+                .map(|v| Rfloat::from(v as f64))
+                .unwrap_or_else(Rfloat::na),
+            num_tombstones = num_tombstones as f64,
+            num_expired_tombstones = num_expired as f64
+        ))
+    }
+
+    /// Get how the table is stored and how its commits are written
+    ///
+    /// Returns a list with the URI `scheme`, the name of the `log_store`, the
+    /// `storage_options` of the table with the values of credentials
+    /// redacted, and the `locking` mode of its commits.
+    fn storage_info(&self) -> List {
+        storage::storage_info(self.inner.log_store().as_ref())
     }
 
     /// Get partition columns
@@ -421,68 +1568,365 @@ impl DeltaTableInternal {
             .map_err(|e| Error::from(e.to_string()))?;
         Ok(snapshot.metadata().partition_columns().to_vec())
     }
+
+    /// Get the distinct values of a partition column
+    ///
+    /// Answered from the add actions in the snapshot, without reading data
+    /// files. Values are the serialized strings stored in the log, sorted,
+    /// with `NA` for null partitions last.
+    fn partition_values(&self, column: &str) -> Result<Strings> {
+        let snapshot = self
+            .inner
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?;
+        if !snapshot
+            .metadata()
+            .partition_columns()
+            .iter()
+            .any(|c| c == column)
+        {
+            return Err(Error::from(format!(
+                "'{}' is not a partition column of this table",
+                column
+            )));
+        }
+
+        let files: Vec<LogicalFileView> = block_on(async {
+            self.inner
+                .get_active_add_actions_by_partitions(&[])
+                .try_collect()
+                .await
+        })?
+        .map_err(|e| Error::from(e.to_string()))?;
+
+        let mut values: BTreeSet<String> = BTreeSet::new();
+        let mut has_null = false;
+        for file in &files {
+            let value = file.partition_values().and_then(|data| {
+                data.fields()
+                    .iter()
+                    .zip(data.values().iter())
+                    .find(|(field, _)| field.name() == column)
+                    .and_then(|(_, value)| (!value.is_null()).then(|| value.serialize()))
+            });
+            match value {
+                Some(v) => {
+                    values.insert(v);
+                }
+                None => has_null = true,
+            }
+        }
+
+        let mut result: Vec<Rstr> = values.iter().map(|v| Rstr::from(v.as_str())).collect();
+        if has_null {
+            result.push(Rstr::na());
+        }
+        Ok(Strings::from_values(result))
+    }
+
+    /// Get the effective values of common table properties
+    ///
+    /// Properties that are not set have their protocol default. Durations are
+    /// in seconds and `dataSkippingNumIndexedCols` is -1 for all columns.
+    fn table_properties(&self) -> Result<List> {
+        let snapshot = self
+            .inner
+            .snapshot()
+            .map_err(|e| Error::from(e.to_string()))?;
+        let properties = snapshot.snapshot().table_properties();
+        let num_indexed_cols = match properties.num_indexed_cols() {
+            DataSkippingNumIndexedCols::AllColumns => -1,
+            DataSkippingNumIndexedCols::NumColumns(n) => n as i32,
+        };
+
+        Ok(list!(
+            appendOnly = properties.append_only(),
+            logRetentionDuration = properties.log_retention_duration().as_secs_f64(),
+            deletedFileRetentionDuration =
+                properties.deleted_file_retention_duration().as_secs_f64(),
+            checkpointInterval = properties.checkpoint_interval().get() as i32,
+            enableChangeDataFeed = properties.enable_change_data_feed(),
+            dataSkippingNumIndexedCols = num_indexed_cols
+        ))
+    }
+
+    /// Set table properties (e.g. "delta.dataSkippingStatsColumns")
+    ///
+    /// Commits a new version and refreshes this handle to it.
+    fn set_table_properties(
+        &mut self,
+        properties: List,
+        raise_if_not_exists: bool,
+    ) -> OperationResult<i64> {
+        let _context = self.context("SET TBLPROPERTIES");
+        let properties: HashMap<String, String> = properties
+            .iter()
+            .filter_map(|(key, value)| value.as_str().map(|v| (key.to_string(), v.to_string())))
+            .collect();
+
+        let table = block_on(async {
+            self.inner
+                .clone()
+                .set_tbl_properties()
+                .with_commit_properties(commit_properties())
+                .with_properties(properties)
+                .with_raise_if_not_exists(raise_if_not_exists)
+                .await
+        })?
+        .map_err(|e| Error::from(format!("Failed to set table properties: {}", e)))?;
+
+        self.inner = table;
+        Ok(self.inner.version().unwrap_or(-1))
+    }
+}
+
+/// Files a vacuum dry run would delete, with their sizes and ages
+struct VacuumCandidates {
+    files: Robj,
+    total_bytes: f64,
+}
+
+/// Look up the size and modification time of vacuum candidates
+///
+/// Files that are gone by the time they are looked up (e.g. removed by a
+/// concurrent vacuum) are kept with missing sizes and times.
+fn vacuum_candidates(table: &DeltaTable, paths: Vec<String>) -> Result<VacuumCandidates> {
+    let store = table.log_store().object_store(None);
+    let locations: Vec<Path> = paths
+        .iter()
+        .map(|p| Path::parse(p).unwrap_or_else(|_| Path::from(p.as_str())))
+        .collect();
+    let metas: Vec<Option<ObjectMeta>> = block_on(async {
+        futures::stream::iter(locations)
+            .map(|location| {
+                let store = store.clone();
+                async move {
+                    match store.head(&location).await {
+                        Ok(meta) => Ok(Some(meta)),
+                        Err(deltalake::ObjectStoreError::NotFound { .. }) => Ok(None),
+                        Err(e) => Err(e),
+                    }
+                }
+            })
+            .buffered(16)
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to inspect vacuum candidates: {}", e)))?;
+
+    let now = chrono::Utc::now();
+    let field = |f: &dyn Fn(&ObjectMeta) -> f64| -> Vec<Rfloat> {
+        metas
+            .iter()
+            .map(|m| {
+                m.as_ref()
+                    .map(|m| Rfloat::from(f(m)))
+                    .unwrap_or_else(Rfloat::na)
+            })
+            .collect()
+    };
+    let sizes = field(&|m| m.size as f64);
+    let modified = field(&|m| m.last_modified.timestamp_millis() as f64);
+    let ages = field(&|m| (now - m.last_modified).num_seconds() as f64 / 3600.0);
+    let total_bytes = metas.iter().flatten().map(|m| m.size as f64).sum();
+
+    let files = data_frame!(
+        path = paths,
+        size_bytes = sizes,
+        last_modified = modified,
+        age_hours = ages
+    );
+    Ok(VacuumCandidates { files, total_bytes })
+}
+
+/// The effect of restoring a table to the state of another version
+struct RestorePlan {
+    num_restored_files: usize,
+    num_removed_files: usize,
+    table_size_change: f64,
+}
+
+/// Compare the files of the current and the restored version
+///
+/// Files only in `target` are added back and files only in `table` are
+/// removed, as delta-rs does when it commits the restore.
+fn restore_plan(table: &DeltaTable, target: &DeltaTable) -> Result<RestorePlan> {
+    let files = |table: &DeltaTable| -> Result<HashMap<String, i64>> {
+        let files: Vec<LogicalFileView> = block_on(async {
+            table
+                .get_active_add_actions_by_partitions(&[])
+                .try_collect()
+                .await
+        })?
+        .map_err(|e| Error::from(e.to_string()))?;
+        Ok(files
+            .iter()
+            .map(|file| (file.path().to_string(), file.size()))
+            .collect())
+    };
+    let current = files(table)?;
+    let restored = files(target)?;
+
+    let added: Vec<i64> = restored
+        .iter()
+        .filter(|(path, _)| !current.contains_key(*path))
+        .map(|(_, size)| *size)
+        .collect();
+    let removed: Vec<i64> = current
+        .iter()
+        .filter(|(path, _)| !restored.contains_key(*path))
+        .map(|(_, size)| *size)
+        .collect();
+
+    Ok(RestorePlan {
+        num_restored_files: added.len(),
+        num_removed_files: removed.len(),
+        table_size_change: (added.iter().sum::<i64>() - removed.iter().sum::<i64>()) as f64,
+    })
 }
 
 /// Open a Delta Table at the specified path
 ///
+/// Without `load_files`, only the metadata, protocol and version are
+/// loaded; operations that need the list of active files then fail. With
+/// `cache`, the snapshot is kept for later opens of the same table, which
+/// then only read the commits made since. A table opened at a `version` is
+/// loaded from the log up to that version only, so its schema and files are
+/// those of the version; the cache, which holds latest snapshots, is not
+/// used for it.
+///
 /// @param path Path to the Delta table.
+/// @param version Version to load, negative counting back from the latest
+///   (optional, latest by default).
 /// @param storage_options Optional storage options for the backend.
+/// @param load_files Whether to load the list of active files.
+/// @param cache Whether to reuse and keep the snapshot in the table cache.
+/// @param concurrency Number of files processed concurrently by metadata
+///   operations (optional).
 #[extendr]
-fn delta_table_open(path: &str, storage_options: Nullable<List>) -> Result<DeltaTableInternal> {
+fn delta_table_open(
+    path: &str,
+    version: Nullable<i64>,
+    storage_options: Nullable<List>,
+    load_files: bool,
+    cache: bool,
+    concurrency: Nullable<i32>,
+) -> OperationResult<DeltaTableInternal> {
+    let context = ErrorContext::new("LOAD", path);
+    context.set_version(version.clone().into_option());
     let url = path_to_url(path).map_err(Error::from)?;
+    let options = match storage_options {
+        Nullable::NotNull(ref opts) => Some(parse_storage_options(opts)?),
+        Nullable::Null => None,
+    };
 
-    let mut table = block_on(async {
-        match storage_options {
-            Nullable::NotNull(ref opts) => {
-                let options = parse_storage_options(opts);
-                DeltaTable::try_from_url_with_storage_options(url, options).await
+    let open = || -> Result<DeltaTable> {
+        let mut builder =
+            DeltaTableBuilder::from_url(url.clone()).map_err(|e| Error::from(e.to_string()))?;
+        if let Some(options) = options.clone() {
+            builder = builder.with_storage_options(options);
+        }
+        if !load_files {
+            builder = builder.without_files();
+        }
+        if let Nullable::NotNull(concurrency) = concurrency {
+            builder = builder
+                .with_log_buffer_size(concurrency.max(1) as usize)
+                .map_err(|e| Error::from(e.to_string()))?;
+        }
+        let mut table = builder.build().map_err(|e| Error::from(e.to_string()))?;
+        match version {
+            Nullable::NotNull(version) => {
+                let version = resolve_version(&table, version)?;
+                context.set_version(Some(version));
+                block_on(async { table.load_version(version).await })?
             }
-            Nullable::Null => DeltaTable::try_from_url(url).await,
+            Nullable::Null => block_on(async { table.load().await })?,
         }
-    })
-    .map_err(|e| Error::from(e.to_string()))?;
+        .map_err(|e| Error::from(e.to_string()))?;
+        Ok(table)
+    };
 
-    // Load the table
-    block_on(async { table.load().await }).map_err(|e| Error::from(e.to_string()))?;
+    let mut table = if cache && matches!(version, Nullable::Null) {
+        cache::cached_table(
+            cache::CacheKey::new(&url, options.clone(), load_files),
+            open,
+        )?
+    } else {
+        open()?
+    };
+    if let Nullable::NotNull(concurrency) = concurrency {
+        table.config.log_buffer_size = concurrency.max(1) as usize;
+    }
 
     Ok(DeltaTableInternal { inner: table })
 }
 
 /// Check if a path is a Delta Table
 ///
+/// By default only checks that the `_delta_log` directory holds a commit or
+/// a checkpoint, which lists the log until the first one is found. With
+/// `strict`, the protocol and metadata of the latest version are loaded as
+/// well (without the list of files).
+///
 /// @param path Path to check.
 /// @param storage_options Optional storage options for the backend.
+/// @param strict Whether to load the latest version of the table.
 #[extendr]
-fn is_delta_table(path: &str, storage_options: Nullable<List>) -> bool {
-    let url = match path_to_url(path) {
-        Ok(u) => u,
-        Err(_) => return false,
+fn is_delta_table(path: &str, storage_options: Nullable<List>, strict: bool) -> Result<bool> {
+    let Ok(url) = path_to_url(path) else {
+        return Ok(false);
     };
-
-    block_on(async {
-        let result = match storage_options {
-            Nullable::NotNull(ref opts) => {
-                let options = parse_storage_options(opts);
-                DeltaTable::try_from_url_with_storage_options(url, options).await
-            }
-            Nullable::Null => DeltaTable::try_from_url(url).await,
+    let Ok(mut builder) = DeltaTableBuilder::from_url(url) else {
+        return Ok(false);
+    };
+    if let Nullable::NotNull(ref opts) = storage_options {
+        let Ok(options) = parse_storage_options(opts) else {
+            return Ok(false);
         };
+        builder = builder.with_storage_options(options);
+    }
 
-        match result {
-            Ok(mut t) => t.load().await.is_ok(),
-            Err(_) => false,
-        }
-    })
+    if strict {
+        return Ok(block_on(async { builder.without_files().load().await })?.is_ok());
+    }
+    match builder.build() {
+        Ok(table) => Ok(
+            block_on(async { table.log_store().is_delta_table_location().await })?.unwrap_or(false),
+        ),
+        Err(_) => Ok(false),
+    }
 }
 
 // Macro to generate exports.
 // This ensures exported functions are registered with R.
 extendr_module! {
     mod deltaR;
+    use cache;
+    use catalog;
+    use clients;
+    use commit;
+    use debug;
+    use diff;
+    use export;
+    use flight;
+    use io_stats;
     use merge;
+    use paths;
+    use predicates;
+    use read;
+    use sharing;
+    use sql;
+    use storage;
+    use transaction;
+    use watch;
     use write;
+    use writer;
     impl DeltaTableInternal;
     fn register_cloud_handlers;
+    fn init_main_thread;
+    fn init_engine_info;
+    fn engine_info;
     fn delta_table_open;
     fn is_delta_table;
 }
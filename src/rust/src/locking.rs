@@ -0,0 +1,283 @@
+//! Commit locking for local tables
+//!
+//! Commits to local tables rely on the file system to refuse creating a log
+//! file that already exists, which some file systems (network mounts, some
+//! container volumes) do not do atomically. With the storage option
+//! `locking_provider = "file"`, a commit instead holds an exclusive OS lock
+//! of `_delta_log/.commit.lock` while it checks that its version is free and
+//! writes the log file, so R processes on one machine, such as parallel
+//! workers, can append to a table at the same time. A writer that finds its
+//! version taken gets the usual version conflict, and delta-rs retries the
+//! commit on the next version after checking it for conflicts. OS locks are
+//! released when a process dies, so a crashed writer leaves no stale lock.
+
+use std::fs::{File, OpenOptions};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use delta_kernel::Engine;
+use deltalake::kernel::transaction::TransactionError;
+use deltalake::logstore::object_store::{
+    Error as ObjectStoreError, ObjectStore, PutMode, PutOptions,
+};
+use deltalake::logstore::{
+    commit_uri_from_version, default_logstore, logstore_factories, CommitOrBytes, LogStore,
+    LogStoreConfig, LogStoreFactory, ObjectStoreRef, StorageConfig,
+};
+use deltalake::{DeltaResult, DeltaTableError};
+use url::Url;
+use uuid::Uuid;
+
+/// Storage option selecting the locking provider of local tables
+const LOCKING_PROVIDER: &str = "locking_provider";
+
+/// Storage option with the seconds to wait for the commit lock
+const LOCK_TIMEOUT: &str = "lock_timeout";
+
+/// Seconds to wait for the commit lock by default
+const DEFAULT_LOCK_TIMEOUT: f64 = 60.0;
+
+/// Name of the lock file in `_delta_log`
+const LOCK_FILE: &str = ".commit.lock";
+
+/// Try to take an exclusive lock of `file` without waiting
+///
+/// Returns `false` when another process holds the lock. The lock is released
+/// when the file is closed.
+#[cfg(unix)]
+fn try_lock(file: &File) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    match error.kind() {
+        std::io::ErrorKind::WouldBlock => Ok(false),
+        _ => Err(error),
+    }
+}
+
+/// Try to take an exclusive lock of `file` without waiting
+///
+/// Returns `false` when another process holds the lock. The lock is released
+/// when the file is closed.
+#[cfg(windows)]
+fn try_lock(file: &File) -> std::io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+    use windows_sys::Win32::Storage::FileSystem::{
+        LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    };
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    let locked = unsafe {
+        LockFileEx(
+            file.as_raw_handle(),
+            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+    if locked != 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(code) if code == ERROR_LOCK_VIOLATION as i32 => Ok(false),
+        _ => Err(error),
+    }
+}
+
+/// Wait for an exclusive lock of `path`, creating the file if needed
+///
+/// The lock is held until the returned file is dropped.
+fn lock_file(path: &FsPath, timeout: Duration) -> std::io::Result<File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    let started = Instant::now();
+    loop {
+        match try_lock(&file)? {
+            true => return Ok(file),
+            false if started.elapsed() < timeout => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            false => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!(
+                        "Timed out after {}s waiting for the commit lock {}",
+                        timeout.as_secs_f64(),
+                        path.display()
+                    ),
+                ))
+            }
+        }
+    }
+}
+
+/// A log store committing to a local table under a file lock
+struct LockingLogStore {
+    inner: Arc<dyn LogStore>,
+    lock_path: PathBuf,
+    timeout: Duration,
+}
+
+#[async_trait::async_trait]
+impl LogStore for LockingLogStore {
+    fn name(&self) -> String {
+        "LockingLogStore".into()
+    }
+
+    async fn read_commit_entry(&self, version: i64) -> DeltaResult<Option<Bytes>> {
+        self.inner.read_commit_entry(version).await
+    }
+
+    async fn write_commit_entry(
+        &self,
+        version: i64,
+        commit_or_bytes: CommitOrBytes,
+        operation_id: Uuid,
+    ) -> Result<(), TransactionError> {
+        let CommitOrBytes::LogBytes(bytes) = commit_or_bytes else {
+            return self
+                .inner
+                .write_commit_entry(version, commit_or_bytes, operation_id)
+                .await;
+        };
+        let lock_failed = |e: &dyn std::fmt::Display| TransactionError::LogStoreError {
+            msg: format!("Failed to lock the table for the commit: {}", e),
+            source: e.to_string().into(),
+        };
+
+        let path = self.lock_path.clone();
+        let timeout = self.timeout;
+        let lock = tokio::task::spawn_blocking(move || lock_file(&path, timeout))
+            .await
+            .map_err(|e| lock_failed(&e))?
+            .map_err(|e| lock_failed(&e))?;
+
+        // Writers only create log files while holding the lock, so the
+        // version cannot be taken between the check and the write. The log
+        // file is still only created if it does not exist, so that writers
+        // that do not take the lock cannot overwrite the commit.
+        let store = self.inner.object_store(None);
+        let location = commit_uri_from_version(version);
+        match store.head(&location).await {
+            Ok(_) => return Err(TransactionError::VersionAlreadyExists(version)),
+            Err(ObjectStoreError::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let create = PutOptions::from(PutMode::Create);
+        match store.put_opts(&location, bytes.into(), create).await {
+            Ok(_) => {}
+            Err(ObjectStoreError::AlreadyExists { .. }) => {
+                return Err(TransactionError::VersionAlreadyExists(version))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        drop(lock);
+        Ok(())
+    }
+
+    async fn abort_commit_entry(
+        &self,
+        version: i64,
+        commit_or_bytes: CommitOrBytes,
+        operation_id: Uuid,
+    ) -> Result<(), TransactionError> {
+        self.inner
+            .abort_commit_entry(version, commit_or_bytes, operation_id)
+            .await
+    }
+
+    async fn get_latest_version(&self, start_version: i64) -> DeltaResult<i64> {
+        self.inner.get_latest_version(start_version).await
+    }
+
+    fn object_store(&self, operation_id: Option<Uuid>) -> Arc<dyn ObjectStore> {
+        self.inner.object_store(operation_id)
+    }
+
+    fn root_object_store(&self, operation_id: Option<Uuid>) -> Arc<dyn ObjectStore> {
+        self.inner.root_object_store(operation_id)
+    }
+
+    fn engine(&self, operation_id: Option<Uuid>) -> Arc<dyn Engine> {
+        self.inner.engine(operation_id)
+    }
+
+    fn config(&self) -> &LogStoreConfig {
+        self.inner.config()
+    }
+}
+
+/// The commit lock timeout set in the storage options
+fn lock_timeout(options: &StorageConfig) -> DeltaResult<Duration> {
+    let Some(value) = options.raw.get(LOCK_TIMEOUT) else {
+        return Ok(Duration::from_secs_f64(DEFAULT_LOCK_TIMEOUT));
+    };
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| {
+            DeltaTableError::Generic(format!(
+                "Invalid {}: {}. Expected a non-negative number of seconds",
+                LOCK_TIMEOUT, value
+            ))
+        })
+}
+
+/// Creates the log stores of local tables, with a commit lock when asked for
+#[derive(Debug, Default)]
+struct LocalLogStoreFactory;
+
+impl LogStoreFactory for LocalLogStoreFactory {
+    fn with_options(
+        &self,
+        prefixed_store: ObjectStoreRef,
+        root_store: ObjectStoreRef,
+        location: &Url,
+        options: &StorageConfig,
+    ) -> DeltaResult<Arc<dyn LogStore>> {
+        let inner = default_logstore(prefixed_store, root_store, location, options);
+        match options.raw.get(LOCKING_PROVIDER).map(String::as_str) {
+            None => Ok(inner),
+            Some("file") => {
+                let root = location.to_file_path().map_err(|_| {
+                    DeltaTableError::Generic(format!("Not a local table path: {}", location))
+                })?;
+                Ok(Arc::new(LockingLogStore {
+                    inner,
+                    lock_path: root.join("_delta_log").join(LOCK_FILE),
+                    timeout: lock_timeout(options)?,
+                }))
+            }
+            Some(other) => Err(DeltaTableError::Generic(format!(
+                "Invalid {}: {}. Expected 'file' for local tables",
+                LOCKING_PROVIDER, other
+            ))),
+        }
+    }
+}
+
+/// Create the log stores of `file` URIs with [LocalLogStoreFactory]
+pub(crate) fn register_handlers() {
+    logstore_factories().insert(
+        Url::parse("file://").unwrap(),
+        Arc::new(LocalLogStoreFactory),
+    );
+}
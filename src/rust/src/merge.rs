@@ -5,18 +5,27 @@
 //! in R and passed to a single Rust function for execution.
 
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use arrow::ffi_stream::ArrowArrayStreamReader;
-use arrow_extendr::from::FromArrowRobj;
 use deltalake::arrow::array::RecordBatch;
 use deltalake::arrow::array::RecordBatchReader;
 use deltalake::datafusion::datasource::MemTable;
-use deltalake::datafusion::prelude::*;
 use deltalake::operations::merge::MergeBuilder;
 use deltalake::DeltaTable;
 use extendr_api::prelude::*;
 
+use crate::commit::{
+    append_metrics, check_append_only, commit_properties, new_commit_actions, operation_result,
+    AppTransaction,
+};
+use crate::constraints::violation_error;
+use crate::context::{ErrorContext, OperationResult, RaisedError};
+use crate::execution::ExecutionOptions;
+use crate::expectations::{with_expectations, ViolationPolicy};
+use crate::profile::{with_profile, Profile};
+use crate::write::{reader_from_robj, rename_columns_reader};
 use crate::{block_on, parse_storage_options, path_to_url};
 
 /// Execute a Delta Lake MERGE operation
@@ -25,7 +34,8 @@ use crate::{block_on, parse_storage_options, path_to_url};
 /// the merge in a single call, avoiding complex state management.
 ///
 /// @param table_uri Path to the Delta table
-/// @param source_stream Arrow data stream for source data
+/// @param source_stream Source data: a nanoarrow_array_stream, arrow Table, RecordBatch,
+/// RecordBatchReader, or data.frame
 /// @param predicate Main merge predicate (e.g., "target.id = source.id")
 /// @param source_alias Alias for source table in expressions
 /// @param target_alias Alias for target table in expressions
@@ -35,7 +45,15 @@ use crate::{block_on, parse_storage_options, path_to_url};
 /// @param not_matched_by_source_update_clauses List of update clauses for unmatched target rows
 /// @param not_matched_by_source_delete_clauses List of delete clauses for unmatched target rows
 /// @param storage_options Storage backend options (optional)
+/// @param expectations Named list of SQL expressions every source row must satisfy (optional)
+/// @param on_violation What to do with source rows violating an expectation: "fail", "drop" or "warn"
+/// @param execution Named list of DataFusion execution options (optional)
+/// @param app_transaction Application transaction to record, as list(app_id, version) (optional)
+/// @param column_mapping Named list mapping table columns to the source columns holding them (optional)
+/// @param case_insensitive Whether source columns match the table columns whose names only differ in case
+/// @param profile Whether to return the time spent in each stage of the merge
 #[extendr]
+#[allow(clippy::too_many_arguments)]
 pub fn delta_merge_execute(
     table_uri: &str,
     source_stream: Robj,
@@ -48,34 +66,98 @@ pub fn delta_merge_execute(
     not_matched_by_source_update_clauses: List,
     not_matched_by_source_delete_clauses: List,
     storage_options: Nullable<List>,
-) -> Result<List> {
-    // Convert R Arrow stream to reader
-    let reader = ArrowArrayStreamReader::from_arrow_robj(&source_stream)
-        .map_err(|e| Error::from(format!("Failed to read Arrow stream: {:?}", e)))?;
-
-    let schema = reader.schema();
-    let boxed_reader: Box<dyn RecordBatchReader + Send + 'static> = Box::new(reader);
+    expectations: Nullable<List>,
+    on_violation: &str,
+    execution: Nullable<List>,
+    app_transaction: Nullable<List>,
+    column_mapping: Nullable<List>,
+    case_insensitive: bool,
+    profile: bool,
+) -> OperationResult<List> {
+    let context = ErrorContext::new("MERGE", table_uri);
+    let mut profile = Profile::new(profile);
+    let execution = ExecutionOptions::from_list(&execution)?;
+    let policy = ViolationPolicy::from_str(on_violation)?;
+
+    // Convert R source data to reader
+    let mut boxed_reader = reader_from_robj(&source_stream)?;
 
     // Parse URL and open table
     let url = path_to_url(table_uri).map_err(Error::from)?;
+    let options = match storage_options {
+        Nullable::NotNull(ref opts) => Some(parse_storage_options(opts)?),
+        Nullable::Null => None,
+    };
 
-    let mut table = block_on(async {
-        match storage_options {
-            Nullable::NotNull(ref opts) => {
-                let options = parse_storage_options(opts);
-                DeltaTable::try_from_url_with_storage_options(url.clone(), options).await
+    let table = profile.time("log_replay", || -> Result<DeltaTable> {
+        let mut table = block_on(async {
+            match options {
+                Some(options) => {
+                    DeltaTable::try_from_url_with_storage_options(url.clone(), options).await
+                }
+                None => DeltaTable::try_from_url(url.clone()).await,
             }
-            Nullable::Null => DeltaTable::try_from_url(url.clone()).await,
-        }
-    })
-    .map_err(|e| Error::from(e.to_string()))?;
-
-    // Load the table
-    block_on(async { table.load().await }).map_err(|e| Error::from(e.to_string()))?;
+        })?
+        .map_err(|e| Error::from(e.to_string()))?;
+
+        // Load the table
+        block_on(async { table.load().await })?.map_err(|e| Error::from(e.to_string()))?;
+        Ok(table)
+    })?;
+    context.set_version(table.version());
+
+    // Rename the source columns to the table columns they stand for, so
+    // expectations, predicates and clauses use the table's names
+    if let Some(state) = table.state.as_ref() {
+        let mapping = match column_mapping {
+            Nullable::NotNull(ref mapping) => mapping
+                .iter()
+                .map(|(target, source)| {
+                    source
+                        .as_str()
+                        .map(|source| (target.to_string(), source.to_string()))
+                        .ok_or_else(|| Error::from("column_mapping values must be column names"))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Nullable::Null => Vec::new(),
+        };
+        boxed_reader = rename_columns_reader(
+            boxed_reader,
+            state.snapshot().arrow_schema().as_ref(),
+            &mapping,
+            case_insensitive,
+        )?;
+    }
+    let schema = boxed_reader.schema();
+
+    // Check expectations on the source rows before they are matched
+    let mut summary = None;
+    if let Nullable::NotNull(ref expectations) = expectations {
+        let (reader, expectation_summary) = with_expectations(boxed_reader, expectations, policy)?;
+        boxed_reader = reader;
+        summary = Some(expectation_summary);
+    }
+    let boxed_reader = profile.time_reader("scan", boxed_reader);
+
+    let rewrites = matched_update_clauses.len()
+        + matched_delete_clauses.len()
+        + not_matched_by_source_update_clauses.len()
+        + not_matched_by_source_delete_clauses.len();
+    if rewrites > 0 {
+        check_append_only(&table, "a merge with update or delete clauses")?;
+    }
+    let app = AppTransaction::from_r(&table, &app_transaction)?;
+    if let Some(app) = app.as_ref().filter(|app| app.skipped) {
+        return Ok(with_profile(
+            app.skipped_result(&table, "MERGE")?,
+            profile.finish(),
+        ));
+    }
 
     // Collect all record batches from the source stream into memory
     let mut batches: Vec<RecordBatch> = Vec::new();
     let mut reader_box = boxed_reader;
+    #[allow(clippy::while_let_on_iterator)]
     while let Some(batch_result) = reader_box.next() {
         let batch =
             batch_result.map_err(|e| Error::from(format!("Failed to read batch: {}", e)))?;
@@ -87,12 +169,12 @@ pub fn delta_merge_execute(
         .map_err(|e| Error::from(format!("Failed to create memory table: {}", e)))?;
 
     // Create SessionContext and register the source table
-    let ctx = SessionContext::new();
-    block_on(async { ctx.register_table(source_alias, Arc::new(mem_table)) })
+    let ctx = execution.session_context()?;
+    block_on(async { ctx.register_table(source_alias, Arc::new(mem_table)) })?
         .map_err(|e| Error::from(format!("Failed to register source table: {}", e)))?;
 
     // Get the source as a DataFrame
-    let source_df = block_on(async { ctx.table(source_alias).await })
+    let source_df = block_on(async { ctx.table(source_alias).await })?
         .map_err(|e| Error::from(format!("Failed to get source DataFrame: {}", e)))?;
 
     // Get the table state - the table must be loaded
@@ -109,7 +191,14 @@ pub fn delta_merge_execute(
         source_df,
     )
     .with_source_alias(source_alias)
-    .with_target_alias(target_alias);
+    .with_target_alias(target_alias)
+    .with_commit_properties(commit_properties());
+    if !execution.is_default() {
+        merge_builder = merge_builder.with_session_state(Arc::new(ctx.state()));
+    }
+    if let Some(app) = &app {
+        merge_builder = merge_builder.with_commit_properties(app.commit_properties());
+    }
 
     // Add WHEN MATCHED UPDATE clauses
     for clause in matched_update_clauses.iter() {
@@ -147,11 +236,20 @@ pub fn delta_merge_execute(
     }
 
     // Execute merge
-    let (_table, metrics) = block_on(async { merge_builder.await })
-        .map_err(|e| Error::from(format!("Merge failed: {}", e)))?;
+    let read_version = table.version();
+    let (table, metrics) = profile
+        .time("execute", || block_on(async { merge_builder.await })?)
+        .map_err(|e| {
+            let message = e.to_string();
+            match violation_error(&table, &message, "rows the merge writes") {
+                Some(violations) => violations,
+                None => RaisedError::caused_by(format!("Merge failed: {}", message), &e),
+            }
+        })?;
+    profile.add("scan", Duration::from_millis(metrics.scan_time_ms));
 
-    // Return metrics as R list
-    Ok(list!(
+    // Return the standard operation result with the merge metrics
+    let metrics = list!(
         num_target_rows_inserted = metrics.num_target_rows_inserted as i64,
         num_target_rows_updated = metrics.num_target_rows_updated as i64,
         num_target_rows_deleted = metrics.num_target_rows_deleted as i64,
@@ -160,7 +258,16 @@ pub fn delta_merge_execute(
         num_target_rows_copied = metrics.num_target_rows_copied as i64,
         num_output_rows = metrics.num_output_rows as i64,
         execution_time_ms = metrics.execution_time_ms as i64
-    ))
+    );
+    let actions = new_commit_actions(&table, read_version)?;
+    let mut result = operation_result(&table, &actions, "MERGE", metrics)?;
+    if let Some(summary) = summary {
+        result = append_metrics(result, summary.to_metrics())?;
+    }
+    if let Some(app) = &app {
+        result = append_metrics(result, app.metrics())?;
+    }
+    Ok(with_profile(result, profile.finish()))
 }
 
 // ============================================================================
@@ -0,0 +1,510 @@
+//! Table optimization helpers
+//!
+//! Compaction and z-ordering run delta-rs' optimizer; this module parses
+//! their options and reports what they rewrote, partition by partition. It
+//! also implements the compactions that choose their files by size, age or
+//! a byte budget, which the optimizer cannot restrict itself to.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use deltalake::delta_datafusion::{DeltaScanConfigBuilder, DeltaTableProvider};
+use deltalake::kernel::schema::cast_record_batch;
+use deltalake::kernel::transaction::{CommitBuilder, PROTOCOL};
+use deltalake::kernel::{Action, LogicalFileView};
+use deltalake::operations::optimize::{MetricDetails, Metrics as OptimizeMetrics};
+use deltalake::protocol::DeltaOperation;
+use deltalake::table::config::TablePropertiesExt;
+use deltalake::writer::{DeltaWriter, RecordBatchWriter};
+use deltalake::{DeltaTable, DeltaTableError, PartitionFilter, PartitionValue};
+use extendr_api::prelude::*;
+use futures::TryStreamExt;
+
+use crate::commit::{
+    append_metrics, commit_actions_since, commit_properties, new_commit_actions, operation_result,
+    AppTransaction,
+};
+use crate::read::{file_view_to_add, session_context};
+use crate::{block_on, debug};
+
+/// Parse `column=value` partition filters; other filters are ignored
+pub(crate) fn parse_partition_filters(filters: Nullable<Vec<String>>) -> Vec<PartitionFilter> {
+    match filters {
+        Nullable::NotNull(filters) => filters
+            .into_iter()
+            .filter_map(|f| {
+                f.split_once('=').map(|(col, val)| PartitionFilter {
+                    key: col.trim().to_string(),
+                    value: PartitionValue::Equal(val.trim().to_string()),
+                })
+            })
+            .collect(),
+        Nullable::Null => Vec::new(),
+    }
+}
+
+/// The result of a compaction or z-order of `table` read at `read_version`
+///
+/// With an application transaction, it is recorded in a commit of its own
+/// once the optimize has committed.
+pub(crate) fn optimize_result(
+    table: DeltaTable,
+    read_version: Option<i64>,
+    metrics: List,
+    partitions: Robj,
+    app: Option<AppTransaction>,
+    target_size: Nullable<i64>,
+) -> Result<List> {
+    let actions = new_commit_actions(&table, read_version)?;
+    let Some(app) = app else {
+        let result = operation_result(&table, &actions, "OPTIMIZE", metrics)?;
+        return append_metrics(result, list!(partitions = partitions));
+    };
+    let target_size = target_size.into_option().unwrap_or_else(|| {
+        table.state.as_ref().map_or(0, |state| {
+            state.snapshot().table_properties().target_file_size().get() as i64
+        })
+    });
+    let operation = DeltaOperation::Optimize {
+        predicate: None,
+        target_size,
+    };
+    let table = app.commit_after(table, operation)?;
+    let result = operation_result(&table, &actions, "OPTIMIZE", metrics)?;
+    let result = append_metrics(result, list!(partitions = partitions))?;
+    append_metrics(result, app.metrics())
+}
+
+/// The metrics of an optimize, as reported by delta-rs
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn optimize_metrics(metrics: &OptimizeMetrics) -> List {
+    list!(
+        num_files_added = metrics.num_files_added as i32,
+        num_files_removed = metrics.num_files_removed as i32,
+        files_added = list!(
+            min = metrics.files_added.min as f64,
+            max = metrics.files_added.max as f64,
+            avg = metrics.files_added.avg as f64,
+            total_files = metrics.files_added.total_files as i32,
+            total_size = metrics.files_added.total_size as f64
+        ),
+        files_removed = list!(
+            min = metrics.files_removed.min as f64,
+            max = metrics.files_removed.max as f64,
+            avg = metrics.files_removed.avg as f64,
+            total_files = metrics.files_removed.total_files as i32,
+            total_size = metrics.files_removed.total_size as f64
+        ),
+        partitions_optimized = metrics.partitions_optimized as i32,
+        num_batches = metrics.num_batches as i32,
+        total_considered_files = metrics.total_considered_files as i32,
+        total_files_skipped = metrics.total_files_skipped as i32,
+        preserve_insertion_order = metrics.preserve_insertion_order
+    )
+}
+
+/// The name of a partition, `col=value/...` in the order of the partition
+/// columns, or `""` for an unpartitioned table
+pub(crate) fn partition_name(
+    columns: &[String],
+    values: &HashMap<String, Option<String>>,
+) -> String {
+    columns
+        .iter()
+        .map(|column| {
+            let value = values.get(column).cloned().flatten();
+            format!(
+                "{}={}",
+                column,
+                value.as_deref().unwrap_or("__HIVE_DEFAULT_PARTITION__")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The number of active files and their total size in each partition
+fn partition_sizes(table: &DeltaTable, columns: &[String]) -> Result<HashMap<String, (i32, f64)>> {
+    let files: Vec<LogicalFileView> = block_on(async {
+        table
+            .get_active_add_actions_by_partitions(&[])
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(e.to_string()))?;
+
+    let mut sizes: HashMap<String, (i32, f64)> = HashMap::new();
+    for file in &files {
+        let add = file_view_to_add(file);
+        let entry = sizes
+            .entry(partition_name(columns, &add.partition_values))
+            .or_default();
+        entry.0 += 1;
+        entry.1 += add.size as f64;
+    }
+    Ok(sizes)
+}
+
+/// File counts of a partition for `partition_file_stats()`
+#[derive(Default)]
+struct PartitionFiles {
+    num_files: i32,
+    size_bytes: f64,
+    num_small_files: i32,
+    small_file_bytes: f64,
+}
+
+/// The files, bytes and small files (below `small_file_size` bytes) of each
+/// partition, as a data.frame sorted by the number of small files
+///
+/// The file list is split between the available cores, each counting the
+/// partitions of its share of the files.
+pub(crate) fn partition_file_stats(
+    table: &DeltaTable,
+    filters: &[PartitionFilter],
+    small_file_size: i64,
+) -> Result<Robj> {
+    let files: Vec<LogicalFileView> = block_on(async {
+        table
+            .get_active_add_actions_by_partitions(filters)
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(e.to_string()))?;
+    let columns = match table.snapshot() {
+        Ok(snapshot) => snapshot.metadata().partition_columns().to_vec(),
+        Err(_) => Vec::new(),
+    };
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = files.len().div_ceil(threads).max(1);
+    let counts = std::thread::scope(|scope| {
+        let workers: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let columns = &columns;
+                scope.spawn(move || {
+                    let mut counts: HashMap<String, PartitionFiles> = HashMap::new();
+                    for file in chunk {
+                        let add = file_view_to_add(file);
+                        let entry = counts
+                            .entry(partition_name(columns, &add.partition_values))
+                            .or_default();
+                        entry.num_files += 1;
+                        entry.size_bytes += add.size as f64;
+                        if add.size < small_file_size {
+                            entry.num_small_files += 1;
+                            entry.small_file_bytes += add.size as f64;
+                        }
+                    }
+                    counts
+                })
+            })
+            .collect();
+        let mut counts: HashMap<String, PartitionFiles> = HashMap::new();
+        for worker in workers {
+            for (partition, part) in worker.join().unwrap_or_default() {
+                let entry = counts.entry(partition).or_default();
+                entry.num_files += part.num_files;
+                entry.size_bytes += part.size_bytes;
+                entry.num_small_files += part.num_small_files;
+                entry.small_file_bytes += part.small_file_bytes;
+            }
+        }
+        counts
+    });
+
+    let mut counts: Vec<(String, PartitionFiles)> = counts.into_iter().collect();
+    counts.sort_by(|(a_name, a), (b_name, b)| {
+        b.num_small_files
+            .cmp(&a.num_small_files)
+            .then_with(|| a_name.cmp(b_name))
+    });
+    Ok(data_frame!(
+        partition = counts
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>(),
+        num_files = counts.iter().map(|(_, c)| c.num_files).collect::<Vec<_>>(),
+        size_bytes = counts.iter().map(|(_, c)| c.size_bytes).collect::<Vec<_>>(),
+        num_small_files = counts
+            .iter()
+            .map(|(_, c)| c.num_small_files)
+            .collect::<Vec<_>>(),
+        small_file_bytes = counts
+            .iter()
+            .map(|(_, c)| c.small_file_bytes)
+            .collect::<Vec<_>>()
+    )
+    .into_robj())
+}
+
+/// The partitions rewritten by an optimize, from the table `before` it to
+/// the table `after` it, with their files and bytes before and after
+///
+/// `elapsed_ms` is the time from `started_ms` (milliseconds since the epoch)
+/// until the last file of the partition was written.
+pub(crate) fn optimized_partitions(
+    before: &DeltaTable,
+    after: &DeltaTable,
+    started_ms: i64,
+) -> Result<Robj> {
+    let columns = match after.snapshot() {
+        Ok(snapshot) => snapshot.metadata().partition_columns().to_vec(),
+        Err(_) => Vec::new(),
+    };
+
+    // Partitions with rewritten files, and when their last file was written
+    let mut finished: BTreeMap<String, i64> = BTreeMap::new();
+    for action in commit_actions_since(after, before.version())? {
+        match action {
+            Action::Add(add) => {
+                let last = finished
+                    .entry(partition_name(&columns, &add.partition_values))
+                    .or_insert(started_ms);
+                *last = (*last).max(add.modification_time);
+            }
+            Action::Remove(remove) => {
+                let values = remove.partition_values.unwrap_or_default();
+                finished
+                    .entry(partition_name(&columns, &values))
+                    .or_insert(started_ms);
+            }
+            _ => {}
+        }
+    }
+
+    let sizes_before = partition_sizes(before, &columns)?;
+    let sizes_after = partition_sizes(after, &columns)?;
+    let size = |sizes: &HashMap<String, (i32, f64)>, name: &String| {
+        sizes.get(name).copied().unwrap_or_default()
+    };
+    let names: Vec<&String> = finished.keys().collect();
+
+    Ok(data_frame!(
+        partition = names.iter().map(|n| n.to_string()).collect::<Vec<_>>(),
+        files_before = names
+            .iter()
+            .map(|n| size(&sizes_before, n).0)
+            .collect::<Vec<_>>(),
+        files_after = names
+            .iter()
+            .map(|n| size(&sizes_after, n).0)
+            .collect::<Vec<_>>(),
+        bytes_before = names
+            .iter()
+            .map(|n| size(&sizes_before, n).1)
+            .collect::<Vec<_>>(),
+        bytes_after = names
+            .iter()
+            .map(|n| size(&sizes_after, n).1)
+            .collect::<Vec<_>>(),
+        elapsed_ms = finished
+            .values()
+            .map(|last| (last - started_ms).max(0) as f64)
+            .collect::<Vec<_>>()
+    ))
+}
+
+// ============================================================================
+// File Selection
+// ============================================================================
+
+/// Which files a compaction rewrites
+#[derive(Default)]
+pub(crate) struct FileSelection {
+    /// Only files smaller than this many bytes (default: the target size)
+    pub(crate) max_file_size: Option<i64>,
+    /// Only files written at least this many hours ago
+    pub(crate) min_file_age_hours: Option<f64>,
+    /// At most this many bytes of files in one run
+    pub(crate) max_rewrite_bytes: Option<i64>,
+}
+
+impl FileSelection {
+    /// Whether no option restricts the files, so delta-rs' optimizer is used
+    pub(crate) fn is_default(&self) -> bool {
+        self.max_file_size.is_none()
+            && self.min_file_age_hours.is_none()
+            && self.max_rewrite_bytes.is_none()
+    }
+
+    /// Choose the files to rewrite, grouped by partition
+    ///
+    /// Partitions are visited in name order and their files smallest first
+    /// until the byte budget is spent. A partition left with a single file is
+    /// skipped: rewriting it alone would not reduce the number of files.
+    fn select(
+        &self,
+        files: Vec<LogicalFileView>,
+        columns: &[String],
+        target_size: i64,
+    ) -> Vec<Vec<LogicalFileView>> {
+        let max_file_size = self.max_file_size.unwrap_or(target_size);
+        let written_before = self
+            .min_file_age_hours
+            .map(|hours| chrono::Utc::now().timestamp_millis() - (hours * 3_600_000.0) as i64);
+
+        let mut partitions: BTreeMap<String, Vec<LogicalFileView>> = BTreeMap::new();
+        for file in files {
+            if file.size() >= max_file_size {
+                continue;
+            }
+            if written_before.is_some_and(|before| file.modification_time() > before) {
+                continue;
+            }
+            let name = partition_name(columns, &file_view_to_add(&file).partition_values);
+            partitions.entry(name).or_default().push(file);
+        }
+
+        let mut budget = self.max_rewrite_bytes.unwrap_or(i64::MAX);
+        let mut groups = Vec::new();
+        for (_, mut files) in partitions {
+            files.sort_by_key(|file| file.size());
+            let mut group = Vec::new();
+            let mut bytes = 0;
+            for file in files {
+                if bytes + file.size() > budget {
+                    break;
+                }
+                bytes += file.size();
+                group.push(file);
+            }
+            if group.len() >= 2 {
+                budget -= bytes;
+                groups.push(group);
+            }
+        }
+        groups
+    }
+}
+
+/// Size statistics of the files added or removed by a compaction
+fn metric_details(sizes: &[i64]) -> MetricDetails {
+    let total_size: i64 = sizes.iter().sum();
+    MetricDetails {
+        avg: if sizes.is_empty() {
+            0.0
+        } else {
+            total_size as f64 / sizes.len() as f64
+        },
+        max: sizes.iter().copied().max().unwrap_or(0),
+        min: sizes.iter().copied().min().unwrap_or(0),
+        total_files: sizes.len(),
+        total_size,
+    }
+}
+
+/// Compact only the files chosen by `selection`
+///
+/// The files of each partition are scanned and written into new files of
+/// about `target_size` bytes, and the new and replaced files are committed
+/// together as one OPTIMIZE version that does not change the table's data.
+pub(crate) fn compact_selected(
+    table: &DeltaTable,
+    selection: &FileSelection,
+    filters: &[PartitionFilter],
+    target_size: Option<i64>,
+) -> Result<(DeltaTable, OptimizeMetrics)> {
+    let state = table
+        .snapshot()
+        .map_err(|e| Error::from(e.to_string()))?
+        .clone();
+    if PROTOCOL.can_read_from(&state).is_err() {
+        return Err(Error::from(
+            "Choosing the files to compact is not supported for tables with deletion \
+             vectors or other reader features",
+        ));
+    }
+    let target_size = target_size
+        .unwrap_or_else(|| state.snapshot().table_properties().target_file_size().get() as i64);
+    let columns = state.metadata().partition_columns().to_vec();
+
+    let files: Vec<LogicalFileView> = block_on(async {
+        table
+            .get_active_add_actions_by_partitions(filters)
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to list table files: {}", e)))?;
+    let total_considered_files = files.len();
+    let groups = selection.select(files, &columns, target_size);
+
+    let mut writer = RecordBatchWriter::for_table(table)
+        .map_err(|e| Error::from(format!("Failed to create writer: {}", e)))?;
+    let target_schema = writer.arrow_schema();
+    let mut adds = Vec::new();
+    let mut num_batches = 0;
+    for group in &groups {
+        let config = DeltaScanConfigBuilder::new()
+            .build(state.snapshot())
+            .map_err(|e| Error::from(e.to_string()))?;
+        let provider =
+            DeltaTableProvider::try_new(state.snapshot().clone(), table.log_store(), config)
+                .map_err(|e| Error::from(e.to_string()))?
+                .with_files(group.iter().map(file_view_to_add).collect());
+        let df = session_context(None)
+            .read_table(Arc::new(provider))
+            .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+
+        block_on(async {
+            let mut stream = debug::execute_stream(df).await?;
+            while let Some(batch) = stream.try_next().await? {
+                num_batches += 1;
+                let batch = cast_record_batch(&batch, target_schema.clone(), false, false)?;
+                writer.write(batch).await?;
+                if writer.buffer_len() as i64 >= target_size {
+                    adds.extend(writer.flush().await?);
+                }
+            }
+            adds.extend(writer.flush().await?);
+            Ok::<_, DeltaTableError>(())
+        })?
+        .map_err(|e| Error::from(format!("Failed to rewrite files: {}", e)))?;
+    }
+
+    let removed: Vec<&LogicalFileView> = groups.iter().flatten().collect();
+    let metrics = OptimizeMetrics {
+        num_files_added: adds.len() as u64,
+        num_files_removed: removed.len() as u64,
+        files_added: metric_details(&adds.iter().map(|add| add.size).collect::<Vec<_>>()),
+        files_removed: metric_details(&removed.iter().map(|file| file.size()).collect::<Vec<_>>()),
+        partitions_optimized: groups.len() as u64,
+        num_batches,
+        total_considered_files,
+        total_files_skipped: total_considered_files - removed.len(),
+        preserve_insertion_order: false,
+    };
+    if groups.is_empty() {
+        return Ok((table.clone(), metrics));
+    }
+
+    // Compaction rewrites rows without changing them
+    let mut actions: Vec<Action> = removed
+        .iter()
+        .map(|file| Action::Remove(file.remove_action(false)))
+        .collect();
+    actions.extend(adds.into_iter().map(|mut add| {
+        add.data_change = false;
+        Action::Add(add)
+    }));
+
+    let mut table = table.clone();
+    let commit = block_on(async {
+        CommitBuilder::from(commit_properties())
+            .with_actions(actions)
+            .build(
+                Some(&state),
+                table.log_store(),
+                DeltaOperation::Optimize {
+                    predicate: None,
+                    target_size,
+                },
+            )
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to commit compaction: {}", e)))?;
+    block_on(async { table.load_version(commit.version()).await })?
+        .map_err(|e| Error::from(e.to_string()))?;
+    Ok((table, metrics))
+}
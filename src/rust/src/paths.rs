@@ -0,0 +1,234 @@
+//! Table locations
+//!
+//! Turns the paths and URIs given in R into the URLs delta-rs opens. A
+//! string is only taken as a URL when it starts with a scheme followed by
+//! `://`, or with `file:`, so file names containing colons and Windows drive
+//! letters are never mistaken for URLs. Anything else is a local path: `~`
+//! is expanded to the home directory, Windows UNC shares become `file` URLs
+//! with a host, and relative paths are resolved against the `deltaR.base_dir`
+//! option (or the working directory) whether they exist yet or not.
+//!
+//! The option is read from R, so these functions must run on the R thread.
+
+use std::path::{Component, Path, PathBuf};
+
+use extendr_api::prelude::*;
+use url::Url;
+
+/// Whether `path` starts with a URL scheme
+///
+/// Single-letter schemes are Windows drive letters, not URL schemes.
+fn has_scheme(path: &str) -> bool {
+    if path.len() >= 5 && path[..5].eq_ignore_ascii_case("file:") {
+        return true;
+    }
+    let Some((scheme, _)) = path.split_once("://") else {
+        return false;
+    };
+    scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+/// Split a path on both kinds of separators, dropping `.` and resolving `..`
+fn normalized_segments(path: &str) -> Vec<&str> {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split(is_separator) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments
+}
+
+/// Strip the `\\?\` prefix of Windows verbatim paths, keeping UNC shares
+/// recognisable as such
+fn strip_verbatim(path: &str) -> std::borrow::Cow<'_, str> {
+    if let Some(rest) = path.strip_prefix(r"\\?\") {
+        if rest.len() >= 4 && rest[..4].eq_ignore_ascii_case(r"UNC\") {
+            return format!(r"\\{}", &rest[4..]).into();
+        }
+        return rest.into();
+    }
+    path.into()
+}
+
+/// Convert a UNC path (`\\server\share\dir`) to a `file://server/share/dir` URL
+///
+/// Forward-slash UNC paths (`//server/share`) are only recognised on Windows;
+/// elsewhere a leading `//` is an ordinary absolute path.
+fn unc_to_url(path: &str) -> Option<std::result::Result<Url, String>> {
+    let rest = path
+        .strip_prefix(r"\\")
+        .or_else(|| path.strip_prefix("//").filter(|_| cfg!(windows)))?;
+    let segments = normalized_segments(rest);
+    let (server, share) = segments.split_first()?;
+    if share.is_empty() {
+        return Some(Err(format!("UNC path has no share: {}", path)));
+    }
+    let url = Url::parse(&format!("file://{}/", server))
+        .map(|mut url| {
+            url.set_path(&share.join("/"));
+            url
+        })
+        .map_err(|e| format!("Invalid UNC path {}: {}", path, e));
+    Some(url)
+}
+
+/// Convert a Windows drive path (`C:\dir` or `C:/dir`) to a `file:///C:/dir` URL
+///
+/// Handled directly so drive paths give the same URL on every platform.
+fn drive_path_to_url(path: &str) -> Option<std::result::Result<Url, String>> {
+    let mut chars = path.chars();
+    let drive = chars.next().filter(|c| c.is_ascii_alphabetic())?;
+    if chars.next() != Some(':') || !chars.next().is_some_and(is_separator) {
+        return None;
+    }
+    let segments = normalized_segments(&path[2..]);
+    let url = Url::parse("file:///")
+        .map(|mut url| {
+            url.set_path(&format!("/{}:/{}", drive, segments.join("/")));
+            url
+        })
+        .map_err(|e| format!("Invalid path {}: {}", path, e));
+    Some(url)
+}
+
+/// Expand a leading `~` to the home directory
+fn expand_tilde(path: &str) -> std::result::Result<PathBuf, String> {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(is_separator) => rest,
+        _ => return Ok(PathBuf::from(path)),
+    };
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .ok_or_else(|| format!("Cannot expand '~' in {}: no home directory", path))?;
+    Ok(PathBuf::from(home).join(rest.trim_start_matches(is_separator)))
+}
+
+/// The directory relative paths are resolved against
+///
+/// The `deltaR.base_dir` option when set, the working directory otherwise.
+fn base_dir() -> std::result::Result<PathBuf, String> {
+    let cwd =
+        std::env::current_dir().map_err(|e| format!("Cannot read the working directory: {}", e))?;
+    let option = eval_string("getOption('deltaR.base_dir')")
+        .map_err(|e| format!("Cannot read the deltaR.base_dir option: {}", e))?;
+    if option.is_null() {
+        return Ok(cwd);
+    }
+    let dir = option
+        .as_str()
+        .ok_or("The deltaR.base_dir option must be a directory path")?;
+    let dir = expand_tilde(dir)?;
+    Ok(if dir.is_absolute() {
+        dir
+    } else {
+        cwd.join(dir)
+    })
+}
+
+/// Resolve `.` and `..` components without touching the filesystem
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Resolve symlinks in the part of `path` that exists
+///
+/// A table a write is about to create gets the same URL before and after
+/// its directory is created. Skipped on Windows, where canonical paths are
+/// verbatim (`\\?\C:\...`) paths.
+fn resolve_symlinks(path: PathBuf) -> PathBuf {
+    if cfg!(windows) {
+        return path;
+    }
+    let mut missing = Vec::new();
+    let mut existing = path.as_path();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |dir, name| dir.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return path,
+        }
+    }
+}
+
+/// Convert a table path or URI given in R to a URL
+pub(crate) fn path_to_url(path: &str) -> std::result::Result<Url, String> {
+    if has_scheme(path) {
+        return Url::parse(path).map_err(|e| format!("Invalid table URI {}: {}", path, e));
+    }
+
+    let path = strip_verbatim(path);
+    if let Some(url) = unc_to_url(&path).or_else(|| drive_path_to_url(&path)) {
+        return url;
+    }
+
+    let local = expand_tilde(&path)?;
+    let local = if local.is_absolute() {
+        local
+    } else {
+        base_dir()?.join(local)
+    };
+    let local = resolve_symlinks(normalize_lexically(&local));
+
+    Url::from_file_path(&local).map_err(|_| format!("Failed to create URL from path: {}", path))
+}
+
+/// Get the URL a table path or URI resolves to
+///
+/// @param path Path or URI of a table.
+#[extendr]
+pub fn table_uri(path: &str) -> Result<String> {
+    path_to_url(path).map(String::from).map_err(Error::from)
+}
+
+/// Get the local directory of a table, or NULL for remote tables
+///
+/// @param path Path or URI of a table.
+#[extendr]
+pub fn table_local_path(path: &str) -> Result<Nullable<String>> {
+    let url = path_to_url(path).map_err(Error::from)?;
+    if url.scheme() != "file" || url.host_str().is_some_and(|host| !host.is_empty()) {
+        return Ok(Nullable::Null);
+    }
+    Ok(url
+        .to_file_path()
+        .ok()
+        .map(|path| path.to_string_lossy().into_owned())
+        .into())
+}
+
+extendr_module! {
+    mod paths;
+    fn table_uri;
+    fn table_local_path;
+}
@@ -0,0 +1,436 @@
+//! Predicate building
+//!
+//! Merge, update and delete predicates are SQL expressions. Building them by
+//! pasting R values into strings breaks on names that need quoting and on
+//! values with quotes, and turns data into SQL. These helpers quote
+//! identifiers and render R values as literals of the SQL dialect DataFusion
+//! parses, so predicates built from data always mean what they say.
+//!
+//! Expressions can also be checked against the schema of a table, and of a
+//! merge source, before they are used: an invalid merge predicate otherwise
+//! only fails once the merge executes, and the functions they may call are
+//! listed from the session that evaluates them.
+
+use deltalake::arrow::datatypes::Schema;
+use deltalake::datafusion::common::{DFSchema, DataFusionError, SchemaError};
+use deltalake::datafusion::logical_expr::{Documentation, ExprSchemable};
+use extendr_api::prelude::*;
+
+use crate::read::{session_context, table_provider};
+use crate::sql::table_from_robj;
+use crate::write::reader_from_robj;
+
+/// Quote a column or table name as a SQL identifier
+///
+/// Quoting keeps the case of the name, which DataFusion otherwise folds to
+/// lower case, and any character, including spaces and quotes.
+pub(crate) fn quote_identifier(name: &str) -> Result<String> {
+    if name.is_empty() {
+        return Err(Error::from("Identifiers must be non-empty strings"));
+    }
+    Ok(format!("\"{}\"", name.replace('"', "\"\"")))
+}
+
+/// Quote a string as a SQL string literal
+fn quote_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn double_literal(value: f64) -> String {
+    if value.is_nan() {
+        "CAST('NaN' AS DOUBLE)".to_string()
+    } else if value.is_infinite() {
+        let sign = if value > 0.0 { "" } else { "-" };
+        format!("CAST('{}Infinity' AS DOUBLE)", sign)
+    } else {
+        // Debug formatting keeps a fraction or exponent, so the literal
+        // stays a double
+        format!("{:?}", value)
+    }
+}
+
+/// Render an R vector as SQL literals, one per element, with `None` for
+/// missing values
+pub(crate) fn literals(value: &Robj) -> Result<Vec<Option<String>>> {
+    let unsupported = || {
+        let class = value
+            .class()
+            .map(|c| c.collect::<Vec<_>>().join("/"))
+            .unwrap_or_else(|| format!("{:?}", value.rtype()));
+        Error::from(format!(
+            "Cannot use a value of type '{}' as a SQL literal",
+            class
+        ))
+    };
+
+    if value.inherits("factor") {
+        let levels: Vec<&str> = value.levels().ok_or_else(unsupported)?.collect();
+        let codes = value.as_integer_slice().ok_or_else(unsupported)?;
+        return Ok(codes
+            .iter()
+            .map(|code| {
+                (!code.is_na())
+                    .then(|| levels.get((*code - 1) as usize).map(|l| quote_string(l)))
+                    .flatten()
+            })
+            .collect());
+    }
+
+    // Dates are days since the epoch, as double or integer
+    if value.inherits("Date") {
+        let days: Vec<Option<f64>> = match value.rtype() {
+            Rtype::Integers => value
+                .as_integer_slice()
+                .ok_or_else(unsupported)?
+                .iter()
+                .map(|v| (!v.is_na()).then_some(*v as f64))
+                .collect(),
+            _ => value
+                .as_real_slice()
+                .ok_or_else(unsupported)?
+                .iter()
+                .map(|v| v.is_finite().then_some(*v))
+                .collect(),
+        };
+        return days
+            .into_iter()
+            .map(|days| {
+                days.map(|days| {
+                    chrono::DateTime::from_timestamp(days.floor() as i64 * 86_400, 0)
+                        .map(|d| format!("DATE '{}'", d.format("%Y-%m-%d")))
+                        .ok_or_else(|| Error::from(format!("Date out of range: {} days", days)))
+                })
+                .transpose()
+            })
+            .collect();
+    }
+
+    // POSIXct is seconds since the epoch; table timestamps are in UTC
+    if value.inherits("POSIXct") {
+        return value
+            .as_real_slice()
+            .ok_or_else(unsupported)?
+            .iter()
+            .map(|seconds| {
+                seconds
+                    .is_finite()
+                    .then(|| {
+                        chrono::DateTime::from_timestamp_micros(
+                            (seconds * 1_000_000.0).round() as i64
+                        )
+                        .map(|t| format!("TIMESTAMP '{}'", t.format("%Y-%m-%dT%H:%M:%S%.6fZ")))
+                        .ok_or_else(|| Error::from(format!("Timestamp out of range: {}", seconds)))
+                    })
+                    .transpose()
+            })
+            .collect();
+    }
+
+    if value.class().is_some() {
+        return Err(unsupported());
+    }
+
+    match value.rtype() {
+        Rtype::Logicals => Ok(value
+            .as_logical_slice()
+            .ok_or_else(unsupported)?
+            .iter()
+            .map(|v| (!v.is_na()).then(|| if v.is_true() { "TRUE" } else { "FALSE" }.to_string()))
+            .collect()),
+        Rtype::Integers => Ok(value
+            .as_integer_slice()
+            .ok_or_else(unsupported)?
+            .iter()
+            .map(|v| (!v.is_na()).then(|| v.to_string()))
+            .collect()),
+        Rtype::Doubles => Ok(value
+            .as_real_slice()
+            .ok_or_else(unsupported)?
+            .iter()
+            .map(|v| (!v.is_na()).then(|| double_literal(*v)))
+            .collect()),
+        Rtype::Strings => Ok(value
+            .as_str_iter()
+            .ok_or_else(unsupported)?
+            .map(|s| (!s.is_na()).then(|| quote_string(s)))
+            .collect()),
+        _ => Err(unsupported()),
+    }
+}
+
+/// The condition that `column` equals `value`, or is one of its elements
+///
+/// Missing values match missing values, which `=` and `IN` never do.
+fn condition(column: &str, value: &Robj) -> Result<String> {
+    let literals = literals(value)?;
+    if literals.is_empty() {
+        return Err(Error::from(format!(
+            "The value of column {} must not be empty",
+            column
+        )));
+    }
+    let missing = literals.iter().any(Option::is_none);
+    let present: Vec<String> = literals.into_iter().flatten().collect();
+    let matches = match present.len() {
+        0 => None,
+        1 => Some(format!("{} = {}", column, present[0])),
+        _ => Some(format!("{} IN ({})", column, present.join(", "))),
+    };
+    Ok(match (matches, missing) {
+        (Some(matches), false) => matches,
+        (Some(matches), true) => format!("({} OR {} IS NULL)", matches, column),
+        (None, _) => format!("{} IS NULL", column),
+    })
+}
+
+/// A column reference, qualified by a table alias when one is given
+fn column_ref(name: &str, qualifier: Option<&str>) -> Result<String> {
+    let column = quote_identifier(name)?;
+    match qualifier {
+        Some(table) => Ok(format!("{}.{}", quote_identifier(table)?, column)),
+        None => Ok(column),
+    }
+}
+
+/// Quote names as SQL identifiers
+///
+/// @param names Character vector of column or table names
+#[extendr]
+pub fn sql_quote_identifiers(names: Strings) -> Result<Strings> {
+    names
+        .iter()
+        .map(|name| {
+            if name.is_na() {
+                return Err(Error::from("Identifiers must not be NA"));
+            }
+            quote_identifier(name.as_str())
+        })
+        .collect::<Result<Vec<String>>>()
+        .map(Strings::from_values)
+}
+
+/// Render an R vector as SQL literals
+///
+/// Missing values become `NULL`.
+///
+/// @param value Vector of strings, numbers, logicals, factors, dates or
+///   date-times
+#[extendr]
+pub fn sql_literals(value: Robj) -> Result<Strings> {
+    Ok(Strings::from_values(
+        literals(&value)?
+            .into_iter()
+            .map(|l| l.unwrap_or_else(|| "NULL".to_string())),
+    ))
+}
+
+/// Build a predicate matching columns to values
+///
+/// Returns the conditions of `conditions` joined with AND.
+///
+/// @param conditions Named list of values, by column name
+/// @param qualifier Table alias to qualify the columns with, or NULL
+#[extendr]
+pub fn predicate_build(conditions: List, qualifier: Nullable<&str>) -> Result<String> {
+    let qualifier = qualifier.into_option();
+    let mut parts = Vec::new();
+    for (name, value) in conditions.iter() {
+        if name.is_empty() || name == "NA" {
+            return Err(Error::from("All conditions must be named by their column"));
+        }
+        parts.push(condition(&column_ref(name, qualifier)?, &value)?);
+    }
+    if parts.is_empty() {
+        return Err(Error::from("A predicate needs at least one condition"));
+    }
+    Ok(parts.join(" AND "))
+}
+
+/// Build a merge predicate joining target and source rows on key columns
+///
+/// Returns `target.col = source.col` for each key, joined with AND.
+///
+/// @param columns Key columns, named by their target column when the
+///   source column has another name
+/// @param source_alias Alias of the source
+/// @param target_alias Alias of the target
+#[extendr]
+pub fn merge_predicate_build(
+    columns: Strings,
+    source_alias: &str,
+    target_alias: &str,
+) -> Result<String> {
+    let names: Vec<String> = columns
+        .get_attrib("names")
+        .and_then(|n| {
+            n.as_str_vector()
+                .map(|v| v.iter().map(|s| s.to_string()).collect())
+        })
+        .unwrap_or_default();
+    let mut parts = Vec::new();
+    for (i, source) in columns.iter().enumerate() {
+        if source.is_na() {
+            return Err(Error::from("Key columns must not be NA"));
+        }
+        let target = names
+            .get(i)
+            .filter(|n| !n.is_empty() && *n != "NA")
+            .map(String::as_str)
+            .unwrap_or(source.as_str());
+        parts.push(format!(
+            "{} = {}",
+            column_ref(target, Some(target_alias))?,
+            column_ref(source.as_str(), Some(source_alias))?
+        ));
+    }
+    if parts.is_empty() {
+        return Err(Error::from(
+            "A merge predicate needs at least one key column",
+        ));
+    }
+    Ok(parts.join(" AND "))
+}
+
+// ============================================================================
+// Validation
+// ============================================================================
+
+/// What an expression failed on: its syntax, the columns it references, or
+/// the types of its operands and functions
+fn error_kind(error: &DataFusionError) -> &'static str {
+    match error.find_root() {
+        DataFusionError::SQL(..) => "syntax",
+        DataFusionError::SchemaError(e, _)
+            if matches!(
+                **e,
+                SchemaError::FieldNotFound { .. } | SchemaError::AmbiguousReference { .. }
+            ) =>
+        {
+            "column"
+        }
+        _ => "type",
+    }
+}
+
+/// Check an expression against the schema of a table, without running it
+///
+/// Returns a list with `valid`, the resolved `type` and `nullable`, the
+/// referenced `columns`, and the `error` and its `error_kind` when invalid.
+///
+/// @param table Table URI or DeltaTable handle
+/// @param expression SQL expression
+/// @param source Source data of a merge, or NULL
+/// @param source_alias Alias of the source columns
+/// @param target_alias Alias of the table columns
+/// @param storage_options Storage options for a table URI
+#[extendr]
+pub fn expr_validate(
+    table: Robj,
+    expression: &str,
+    source: Robj,
+    source_alias: &str,
+    target_alias: &str,
+    storage_options: Nullable<List>,
+) -> Result<List> {
+    let table = table_from_robj("table", &table, &storage_options)?;
+    let target_schema = table_provider(table)?.schema();
+    let mut schema = DFSchema::try_from_qualified_schema(target_alias, &target_schema)
+        .map_err(|e| Error::from(e.to_string()))?;
+    if !source.is_null() {
+        let source_schema: Schema = reader_from_robj(&source)?.schema().as_ref().clone();
+        let source_schema = DFSchema::try_from_qualified_schema(source_alias, &source_schema)
+            .map_err(|e| Error::from(e.to_string()))?;
+        schema.merge(&source_schema);
+    }
+
+    let ctx = session_context(None);
+    let checked = ctx.parse_sql_expr(expression, &schema).and_then(|expr| {
+        ctx.create_physical_expr(expr.clone(), &schema)?;
+        let data_type = expr.get_type(&schema)?;
+        let nullable = expr.nullable(&schema)?;
+        Ok((expr, data_type, nullable))
+    });
+
+    let result: Vec<(&str, Robj)> = match checked {
+        Ok((expr, data_type, nullable)) => {
+            let mut columns: Vec<String> = expr
+                .column_refs()
+                .into_iter()
+                .map(|c| c.flat_name())
+                .collect();
+            columns.sort();
+            vec![
+                ("valid", true.into()),
+                ("type", data_type.to_string().into()),
+                ("nullable", nullable.into()),
+                ("columns", columns.into()),
+                ("error", NULL.into()),
+                ("error_kind", NULL.into()),
+            ]
+        }
+        Err(e) => vec![
+            ("valid", false.into()),
+            ("type", NULL.into()),
+            ("nullable", NULL.into()),
+            ("columns", Strings::new(0).into()),
+            ("error", e.find_root().to_string().into()),
+            ("error_kind", error_kind(&e).into()),
+        ],
+    };
+    Ok(List::from_pairs(result))
+}
+
+// ============================================================================
+// Functions
+// ============================================================================
+
+/// List the functions of the session expressions are evaluated in
+///
+/// Returns a data.frame with the `name`, `kind` (scalar, aggregate or
+/// window), `canonical` name, `syntax` and `description` of each function,
+/// with a row for each alias.
+#[extendr]
+pub fn expr_functions() -> Robj {
+    let ctx = session_context(None);
+    let state = ctx.state();
+    let mut rows: Vec<(&str, String, String, String, String)> = Vec::new();
+    let mut add =
+        |kind: &'static str, name: &String, canonical: &str, doc: Option<&Documentation>| {
+            rows.push((
+                kind,
+                name.clone(),
+                canonical.to_string(),
+                doc.map(|d| d.syntax_example.clone()).unwrap_or_default(),
+                doc.map(|d| d.description.trim().to_string())
+                    .unwrap_or_default(),
+            ));
+        };
+    for (name, udf) in state.scalar_functions() {
+        add("scalar", name, udf.name(), udf.documentation());
+    }
+    for (name, udaf) in state.aggregate_functions() {
+        add("aggregate", name, udaf.name(), udaf.documentation());
+    }
+    for (name, udwf) in state.window_functions() {
+        add("window", name, udwf.name(), udwf.documentation());
+    }
+    rows.sort_by(|a, b| (&a.1, a.0).cmp(&(&b.1, b.0)));
+
+    data_frame!(
+        name = rows.iter().map(|r| r.1.clone()).collect::<Vec<_>>(),
+        kind = rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+        canonical = rows.iter().map(|r| r.2.clone()).collect::<Vec<_>>(),
+        syntax = rows.iter().map(|r| r.3.clone()).collect::<Vec<_>>(),
+        description = rows.iter().map(|r| r.4.clone()).collect::<Vec<_>>()
+    )
+}
+
+extendr_module! {
+    mod predicates;
+    fn expr_functions;
+    fn expr_validate;
+    fn sql_quote_identifiers;
+    fn sql_literals;
+    fn predicate_build;
+    fn merge_predicate_build;
+}
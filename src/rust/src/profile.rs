@@ -0,0 +1,363 @@
+//! Operation profiling
+//!
+//! With `profile = TRUE`, writes, merges and SQL queries report how long they
+//! spent in each stage, which tells whether a slow operation waits on
+//! storage (log replay, Parquet uploads, commits) or on compute (reading and
+//! converting the input, executing the plan).
+//!
+//! Stages driven by this crate are timed directly. Stages inside delta-rs
+//! are timed from the tracing spans it opens around them, collected by a
+//! minimal subscriber that only records spans while a profile is running.
+//! The same subscriber forwards delta-rs commit events to [crate::debug].
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::datatypes::SchemaRef;
+use deltalake::arrow::error::ArrowError;
+use deltalake::arrow::record_batch::RecordBatchReader;
+use deltalake::datafusion::physical_plan::ExecutionPlan;
+use extendr_api::prelude::*;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Metadata, Subscriber};
+
+/// Stages in the order they are reported
+const STAGE_ORDER: &[&str] = &[
+    "log_replay",
+    "scan",
+    "planning",
+    "execute",
+    "parquet_write",
+    "commit",
+    "total",
+];
+
+/// delta-rs spans reported as stages, with the stage they are reported as
+const SPAN_STAGES: &[(&str, &str)] = &[
+    ("upload_parquet_file", "parquet_write"),
+    ("commit_with_retries", "commit"),
+];
+
+fn span_stage(name: &str) -> Option<&'static str> {
+    SPAN_STAGES
+        .iter()
+        .find(|(span, _)| *span == name)
+        .map(|(_, stage)| *stage)
+}
+
+/// Module whose events report object store retries, counted for
+/// [crate::io_stats] whether or not a profile is running
+const RETRY_TARGET: &str = "object_store::client::retry";
+
+fn is_retry_event(metadata: &Metadata<'_>) -> bool {
+    metadata.is_event() && metadata.target().starts_with(RETRY_TARGET)
+}
+
+/// Module whose events report commit attempts, written to the
+/// [crate::debug] log while it is on
+const COMMIT_TARGET: &str = "deltalake_core::kernel::transaction";
+
+fn is_commit_event(metadata: &Metadata<'_>) -> bool {
+    metadata.is_event() && metadata.target().starts_with(COMMIT_TARGET)
+}
+
+// ============================================================================
+// Span Recording
+// ============================================================================
+
+/// Whether a profile is running
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Durations of the spans closed while a profile was running
+static SPAN_TIMINGS: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+struct OpenSpan {
+    stage: &'static str,
+    start: Instant,
+    refs: usize,
+}
+
+/// Records the wall time of the spans in [SPAN_STAGES], counts object
+/// store retries and forwards commit events to the debug log
+#[derive(Default)]
+struct SpanRecorder {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, OpenSpan>>,
+}
+
+impl Subscriber for SpanRecorder {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if is_retry_event(metadata) {
+            Interest::always()
+        } else if is_commit_event(metadata)
+            || (metadata.is_span() && span_stage(metadata.name()).is_some())
+        {
+            Interest::sometimes()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if is_retry_event(metadata) {
+            return true;
+        }
+        if is_commit_event(metadata) {
+            return crate::debug::enabled();
+        }
+        ACTIVE.load(Ordering::Relaxed)
+            && metadata.is_span()
+            && span_stage(metadata.name()).is_some()
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(stage) = span_stage(attributes.metadata().name()) {
+            let span = OpenSpan {
+                stage,
+                start: Instant::now(),
+                refs: 1,
+            };
+            self.spans.lock().unwrap().insert(id, span);
+        }
+        Id::from_u64(id)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        if is_retry_event(event.metadata()) {
+            crate::io_stats::record_retry();
+        } else if is_commit_event(event.metadata()) {
+            crate::debug::log_event(event);
+        }
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+
+    fn clone_span(&self, id: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            span.refs += 1;
+        }
+        id.clone()
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let Some(span) = spans.get_mut(&id.into_u64()) else {
+            return false;
+        };
+        span.refs -= 1;
+        if span.refs > 0 {
+            return false;
+        }
+        let span = spans.remove(&id.into_u64()).unwrap();
+        if ACTIVE.load(Ordering::Relaxed) {
+            SPAN_TIMINGS
+                .lock()
+                .unwrap()
+                .push((span.stage, span.start.elapsed()));
+        }
+        true
+    }
+}
+
+/// Install the span recorder as the global tracing subscriber
+pub(crate) fn install_recorder() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        // Fails only if another subscriber was installed first, in which
+        // case delta-rs stages are left out of profiles and retries are
+        // not counted
+        let _ = tracing::subscriber::set_global_default(SpanRecorder::default());
+    });
+}
+
+// ============================================================================
+// Profiles
+// ============================================================================
+
+/// Accumulated time and number of calls of a stage
+#[derive(Clone, Default)]
+struct StageTiming {
+    nanos: Arc<AtomicU64>,
+    calls: Arc<AtomicU64>,
+}
+
+impl StageTiming {
+    fn add(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Stage timings of one operation
+///
+/// A disabled profile times nothing, so operations can be instrumented
+/// unconditionally.
+pub(crate) struct Profile {
+    enabled: bool,
+    start: Instant,
+    stages: HashMap<&'static str, StageTiming>,
+}
+
+impl Profile {
+    /// Start profiling an operation if `enabled`
+    pub(crate) fn new(enabled: bool) -> Self {
+        if enabled {
+            install_recorder();
+            SPAN_TIMINGS.lock().unwrap().clear();
+            ACTIVE.store(true, Ordering::Relaxed);
+        }
+        Profile {
+            enabled,
+            start: Instant::now(),
+            stages: HashMap::new(),
+        }
+    }
+
+    /// Whether stages are being timed
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn stage(&mut self, stage: &'static str) -> StageTiming {
+        self.stages.entry(stage).or_default().clone()
+    }
+
+    /// Run `f`, adding its wall time to `stage`
+    pub(crate) fn time<T>(&mut self, stage: &'static str, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        self.stage(stage).add(start.elapsed());
+        result
+    }
+
+    /// Add a duration measured elsewhere, e.g. by delta-rs, to `stage`
+    pub(crate) fn add(&mut self, stage: &'static str, duration: Duration) {
+        if self.enabled {
+            self.stage(stage).add(duration);
+        }
+    }
+
+    /// Wrap a reader so the time spent producing its batches counts
+    /// towards `stage`
+    pub(crate) fn time_reader(
+        &mut self,
+        stage: &'static str,
+        reader: Box<dyn RecordBatchReader + Send + 'static>,
+    ) -> Box<dyn RecordBatchReader + Send + 'static> {
+        if !self.enabled {
+            return reader;
+        }
+        Box::new(TimedReader {
+            input: reader,
+            timing: self.stage(stage),
+        })
+    }
+
+    /// Add the time an executed plan's scans spent reading files to `stage`
+    pub(crate) fn add_scan_metrics(&mut self, stage: &'static str, plan: &Arc<dyn ExecutionPlan>) {
+        if !self.enabled {
+            return;
+        }
+        let timing = self.stage(stage);
+        let mut nodes = vec![plan.clone()];
+        while let Some(node) = nodes.pop() {
+            let scanning = node
+                .metrics()
+                .and_then(|metrics| metrics.sum_by_name("time_elapsed_scanning_total"));
+            if let Some(value) = scanning {
+                timing.add(Duration::from_nanos(value.as_usize() as u64));
+            }
+            nodes.extend(node.children().into_iter().cloned());
+        }
+    }
+
+    /// Stop profiling and return the stages as a data.frame
+    ///
+    /// Returns `None` for a disabled profile.
+    pub(crate) fn finish(mut self) -> Option<Robj> {
+        if !self.enabled {
+            return None;
+        }
+        let total = self.start.elapsed();
+        ACTIVE.store(false, Ordering::Relaxed);
+        let spans = std::mem::take(&mut *SPAN_TIMINGS.lock().unwrap());
+        for (stage, duration) in spans {
+            self.stage(stage).add(duration);
+        }
+        self.stage("total").add(total);
+
+        let mut names: Vec<&str> = Vec::new();
+        let mut seconds: Vec<f64> = Vec::new();
+        let mut calls: Vec<f64> = Vec::new();
+        for stage in STAGE_ORDER {
+            if let Some(timing) = self.stages.get(stage) {
+                names.push(stage);
+                seconds.push(timing.nanos.load(Ordering::Relaxed) as f64 / 1e9);
+                calls.push(timing.calls.load(Ordering::Relaxed) as f64);
+            }
+        }
+        let df = data_frame!(stage = names, seconds = seconds, calls = calls);
+        Some(df.into_robj())
+    }
+}
+
+impl Drop for Profile {
+    fn drop(&mut self) {
+        if self.enabled {
+            ACTIVE.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Add the profile of an operation to its result
+pub(crate) fn with_profile(result: List, profile: Option<Robj>) -> List {
+    let Some(profile) = profile else {
+        return result;
+    };
+    let pairs: Vec<(String, Robj)> = result
+        .iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .chain(std::iter::once(("profile".to_string(), profile)))
+        .collect();
+    List::from_pairs(pairs)
+}
+
+/// A reader that times how long its input takes to produce each batch
+struct TimedReader {
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    timing: StageTiming,
+}
+
+impl RecordBatchReader for TimedReader {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Iterator for TimedReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let batch = self.input.next();
+        if batch.is_some() {
+            self.timing.add(start.elapsed());
+        }
+        batch
+    }
+}
@@ -0,0 +1,1031 @@
+//! Read support for Delta Lake tables
+//!
+//! Scans run through DataFusion over the table provider, so column
+//! projections and filters are pushed down into the Parquet scan and files
+//! are skipped using the Delta log statistics. Results are handed back to R
+//! as Arrow streams that are pulled batch by batch.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use arrow::array::RecordBatch;
+use arrow::datatypes::{Schema as ArrowSchema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow_extendr::to::IntoArrowRobj;
+use delta_kernel::engine::arrow_conversion::TryFromKernel;
+use delta_kernel::engine::arrow_data::ArrowEngineData;
+use delta_kernel::scan::state::{transform_to_logical, ScanFile};
+use delta_kernel::scan::Scan;
+use delta_kernel::snapshot::{Snapshot as KernelSnapshot, SnapshotRef};
+use delta_kernel::{DeltaResult, Engine, EngineData, FileMeta};
+use deltalake::arrow::array::RecordBatchReader;
+use deltalake::datafusion::catalog::streaming::StreamingTable;
+use deltalake::datafusion::catalog::TableProvider;
+use deltalake::datafusion::dataframe::DataFrame;
+use deltalake::datafusion::error::{DataFusionError, Result as DataFusionResult};
+use deltalake::datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use deltalake::datafusion::functions::expr_fn::random;
+use deltalake::datafusion::physical_plan::display::DisplayableExecutionPlan;
+use deltalake::datafusion::physical_plan::stream::{
+    RecordBatchReceiverStreamBuilder, RecordBatchStreamAdapter,
+};
+use deltalake::datafusion::physical_plan::streaming::PartitionStream;
+use deltalake::datafusion::physical_plan::{
+    collect, displayable, ExecutionPlan, ExecutionPlanProperties,
+};
+use deltalake::datafusion::prelude::{col, lit, SessionContext};
+use deltalake::delta_datafusion::{
+    DeltaCdfTableProvider, DeltaScanConfigBuilder, DeltaSessionContext, DeltaTableProvider,
+};
+use deltalake::kernel::scalars::ScalarExt;
+use deltalake::kernel::schema::cast::cast_record_batch;
+use deltalake::kernel::transaction::PROTOCOL;
+use deltalake::kernel::{Action, Add, LogicalFileView};
+use deltalake::logstore::get_actions;
+use deltalake::{DeltaTable, DeltaTableBuilder};
+use extendr_api::prelude::*;
+use futures::{StreamExt, TryStreamExt};
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+
+use crate::execution::{ExecutionOptions, MaxInFlight, Prefetch};
+use crate::{block_on, debug, parse_storage_options, path_to_url, verify, Interrupted};
+
+// ============================================================================
+// Table Access
+// ============================================================================
+
+/// Resolve a version of a table, counting negative versions back from the latest
+///
+/// `-1` is the version before the latest one in the transaction log, `-2` the
+/// one before that, and so on. Other versions are returned unchanged.
+pub(crate) fn resolve_version(table: &DeltaTable, version: i64) -> Result<i64> {
+    if version >= 0 {
+        return Ok(version);
+    }
+    let latest = block_on(async { table.get_latest_version().await })?
+        .map_err(|e| Error::from(format!("Failed to read the transaction log: {}", e)))?;
+    let resolved = latest + version;
+    if resolved < 0 {
+        return Err(Error::from(format!(
+            "Version {} is before the first version of the table, whose latest version is {}",
+            version, latest
+        )));
+    }
+    Ok(resolved)
+}
+
+/// Open a Delta table, optionally at a specific version
+///
+/// Negative versions count back from the latest one, see [resolve_version].
+pub(crate) fn open_table(
+    table_uri: &str,
+    version: Option<i64>,
+    storage_options: &Nullable<List>,
+) -> Result<DeltaTable> {
+    let url = path_to_url(table_uri).map_err(Error::from)?;
+
+    let mut builder = DeltaTableBuilder::from_url(url).map_err(|e| Error::from(e.to_string()))?;
+    if let Nullable::NotNull(opts) = storage_options {
+        builder = builder.with_storage_options(parse_storage_options(opts)?);
+    }
+
+    let mut table = builder.build().map_err(|e| Error::from(e.to_string()))?;
+    let version = version.map(|v| resolve_version(&table, v)).transpose()?;
+    block_on(async {
+        match version {
+            Some(v) => table.load_version(v).await,
+            None => table.load().await,
+        }
+    })?
+    .map_err(|e| Error::from(e.to_string()))?;
+    Ok(table)
+}
+
+/// Create a DataFusion session for scanning Delta tables
+///
+/// `batch_size` caps the number of rows in each batch produced by the scan.
+pub(crate) fn session_context(batch_size: Option<usize>) -> SessionContext {
+    let mut state = DeltaSessionContext::new().state();
+    if let Some(rows) = batch_size {
+        state.config_mut().options_mut().execution.batch_size = rows;
+    }
+    SessionContext::new_with_state(state)
+}
+
+/// Scan a table within a session and return it as a DataFrame
+pub(crate) fn table_dataframe(ctx: &SessionContext, table: DeltaTable) -> Result<DataFrame> {
+    ctx.read_table(table_provider(table)?)
+        .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))
+}
+
+/// Choose the DataFusion table provider for a table
+///
+/// delta-rs only scans tables whose reader features it implements itself;
+/// tables with other reader features (e.g. `v2Checkpoint`, or deletion
+/// vectors) are scanned with delta-kernel instead, which checks the protocol
+/// on its own.
+pub(crate) fn table_provider(table: DeltaTable) -> Result<Arc<dyn TableProvider>> {
+    let snapshot = table.snapshot().map_err(|e| Error::from(e.to_string()))?;
+    if PROTOCOL.can_read_from(snapshot).is_ok() {
+        return Ok(Arc::new(table));
+    }
+    kernel_table_provider(&table, None)
+}
+
+/// Choose the DataFusion table provider for some of the files of a table
+///
+/// As [table_provider], for scans that only read `files` (e.g. the files
+/// added since a version, or a sample of the files).
+pub(crate) fn files_table_provider(
+    table: &DeltaTable,
+    files: Vec<Add>,
+) -> Result<Arc<dyn TableProvider>> {
+    let state = table.snapshot().map_err(|e| Error::from(e.to_string()))?;
+    if PROTOCOL.can_read_from(state).is_err() {
+        let paths = files.into_iter().map(|add| add.path).collect();
+        return kernel_table_provider(table, Some(Arc::new(paths)));
+    }
+
+    let config = DeltaScanConfigBuilder::new()
+        .build(state.snapshot())
+        .map_err(|e| Error::from(e.to_string()))?;
+    let provider = DeltaTableProvider::try_new(state.snapshot().clone(), table.log_store(), config)
+        .map_err(|e| Error::from(e.to_string()))?
+        .with_files(files);
+    Ok(Arc::new(provider))
+}
+
+// ============================================================================
+// Kernel Scans
+// ============================================================================
+
+/// A table scan executed by delta-kernel
+///
+/// The kernel replays the log itself (including v2 checkpoints and their
+/// sidecar files) and applies deletion vectors, so the scan returns exactly
+/// the table rows. Filters and projections run in DataFusion on top of it.
+struct KernelScanStream {
+    snapshot: SnapshotRef,
+    engine: Arc<dyn Engine>,
+    schema: SchemaRef,
+    /// Paths (as in the log) of the files to read, all files when `None`
+    files: Option<Arc<HashSet<String>>>,
+}
+
+impl std::fmt::Debug for KernelScanStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KernelScanStream")
+            .field("version", &self.snapshot.version())
+            .finish()
+    }
+}
+
+impl PartitionStream for KernelScanStream {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let mut builder = RecordBatchReceiverStreamBuilder::new(self.schema.clone(), 2);
+        let tx = builder.tx();
+        let snapshot = self.snapshot.clone();
+        let engine = self.engine.clone();
+        let schema = self.schema.clone();
+        let files = self.files.clone();
+
+        builder.spawn_blocking(move || {
+            let external = |e: delta_kernel::Error| DataFusionError::External(Box::new(e));
+            let scan = snapshot.scan_builder().build().map_err(external)?;
+            // Returns whether the stream still wants batches
+            let send = |data: Box<dyn EngineData>| -> DeltaResult<bool> {
+                let data = ArrowEngineData::try_from_engine_data(data)?;
+                let batch = cast_record_batch(data.record_batch(), schema.clone(), true, true)
+                    .map_err(|e| delta_kernel::Error::generic(e.to_string()))?;
+                Ok(tx.blocking_send(Ok(batch)).is_ok())
+            };
+            match files {
+                Some(files) => scan_files(&scan, engine, &files, send).map_err(external)?,
+                None => {
+                    for data in scan.execute(engine).map_err(external)? {
+                        if !send(data.map_err(external)?).map_err(external)? {
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        });
+        builder.build()
+    }
+}
+
+/// Read the rows of the files of a kernel scan whose path is in `files`
+///
+/// Does what `Scan::execute` does for all files: each file is read with the
+/// physical schema, transformed to the logical one (which renames the columns
+/// of tables with column mapping and adds partition values) and filtered by
+/// its deletion vector. Stops once `send` returns false. All of `files`
+/// must still be active.
+fn scan_files(
+    scan: &Scan,
+    engine: Arc<dyn Engine>,
+    files: &HashSet<String>,
+    mut send: impl FnMut(Box<dyn EngineData>) -> DeltaResult<bool>,
+) -> DeltaResult<()> {
+    let table_root = scan.table_root().clone();
+    let mut scan_files: Vec<ScanFile> = Vec::new();
+    for metadata in scan.scan_metadata(engine.as_ref())? {
+        scan_files = metadata?.visit_scan_files(scan_files, |found, file| found.push(file))?;
+    }
+
+    // Files removed since (e.g. by a compaction) are not in the scan
+    let scan_files: Vec<ScanFile> = scan_files
+        .into_iter()
+        .filter(|f| files.contains(&f.path))
+        .collect();
+    if scan_files.len() < files.len() {
+        return Err(delta_kernel::Error::generic(
+            "Some of the files to read are no longer part of the table",
+        ));
+    }
+
+    for file in scan_files {
+        let mut selection = file
+            .dv_info
+            .get_selection_vector(engine.as_ref(), &table_root)?;
+        let meta = FileMeta {
+            location: table_root.join(&file.path)?,
+            last_modified: file.modification_time,
+            size: file.size.max(0) as u64,
+        };
+        let reads = engine.parquet_handler().read_parquet_files(
+            &[meta],
+            scan.physical_schema().clone(),
+            None,
+        )?;
+        for data in reads {
+            let mut data = transform_to_logical(
+                engine.as_ref(),
+                data?,
+                scan.physical_schema(),
+                scan.logical_schema(),
+                file.transform.clone(),
+            )?;
+            // The selection vector covers the rows of the whole file and may
+            // be shorter than it; missing entries are selected
+            if let Some(vector) = selection.as_mut() {
+                let rest = vector.split_off(data.len().min(vector.len()));
+                data = data.apply_selection_vector(std::mem::replace(vector, rest))?;
+            }
+            if !send(data)? {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Build a provider that scans a table at its loaded version with delta-kernel
+///
+/// Only the files in `files` are read when it is given.
+fn kernel_table_provider(
+    table: &DeltaTable,
+    files: Option<Arc<HashSet<String>>>,
+) -> Result<Arc<dyn TableProvider>> {
+    let version = table
+        .version()
+        .ok_or_else(|| Error::from("Table has no loaded version"))?;
+    let engine = table.log_store().engine(None);
+    let snapshot = KernelSnapshot::builder_for(table.table_url().clone())
+        .at_version(version as u64)
+        .build(engine.as_ref())
+        .map_err(|e| Error::from(format!("Failed to load table: {}", e)))?;
+    let schema: SchemaRef = Arc::new(
+        ArrowSchema::try_from_kernel(snapshot.schema().as_ref())
+            .map_err(|e| Error::from(format!("Failed to convert table schema: {}", e)))?,
+    );
+
+    let stream = KernelScanStream {
+        snapshot,
+        engine,
+        schema: schema.clone(),
+        files,
+    };
+    let table = StreamingTable::try_new(schema, vec![Arc::new(stream)])
+        .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+    Ok(Arc::new(table))
+}
+
+/// Validate an optional batch size passed from R
+pub(crate) fn batch_size_from_r(batch_size: Nullable<i32>) -> Result<Option<usize>> {
+    match batch_size {
+        Nullable::NotNull(rows) if rows <= 0 => {
+            Err(Error::from("batch_size must be a positive number"))
+        }
+        Nullable::NotNull(rows) => Ok(Some(rows as usize)),
+        Nullable::Null => Ok(None),
+    }
+}
+
+// ============================================================================
+// Sampling
+// ============================================================================
+
+/// How many rows to sample from a table
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Sample {
+    /// Approximately this fraction of the rows
+    Fraction(f64),
+    /// At most this many rows
+    Rows(usize),
+}
+
+/// Validate the sampling options passed from R
+pub(crate) fn sample_from_r(
+    sample_fraction: Nullable<f64>,
+    sample_n: Nullable<i64>,
+) -> Result<Option<Sample>> {
+    match (sample_fraction, sample_n) {
+        (Nullable::NotNull(_), Nullable::NotNull(_)) => Err(Error::from(
+            "sample_fraction and sample_n cannot be combined",
+        )),
+        (Nullable::NotNull(fraction), Nullable::Null) => {
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                return Err(Error::from("sample_fraction must be in (0, 1]"));
+            }
+            Ok(Some(Sample::Fraction(fraction)))
+        }
+        (Nullable::Null, Nullable::NotNull(n)) => {
+            if n < 0 {
+                return Err(Error::from("sample_n must be non-negative"));
+            }
+            Ok(Some(Sample::Rows(n as usize)))
+        }
+        (Nullable::Null, Nullable::Null) => Ok(None),
+    }
+}
+
+/// Rebuild the Add action of an active file for a file-restricted scan
+pub(crate) fn file_view_to_add(file: &LogicalFileView) -> Add {
+    let partition_values = file
+        .partition_values()
+        .map(|data| {
+            data.fields()
+                .iter()
+                .zip(data.values().iter())
+                .map(|(field, value)| {
+                    let value = (!value.is_null()).then(|| value.serialize());
+                    (field.name().to_string(), value)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Add {
+        path: file.path().to_string(),
+        partition_values,
+        size: file.size(),
+        modification_time: file.modification_time(),
+        data_change: true,
+        stats: file.stats(),
+        tags: None,
+        deletion_vector: file.deletion_vector_descriptor(),
+        base_row_id: None,
+        default_row_commit_version: None,
+        clustering_provider: None,
+    }
+}
+
+/// Scan a random sample of a table
+///
+/// Files are sampled first: they are shuffled and taken until they hold about
+/// twice the requested number of rows (according to the log statistics), so
+/// only those files are read. Rows are then sampled from the selected files,
+/// either with a random filter (`Fraction`) or by keeping `n` random rows
+/// (`Rows`), so the result is not limited to a few whole files.
+pub(crate) fn sampled_table_dataframe(
+    ctx: &SessionContext,
+    table: DeltaTable,
+    sample: Sample,
+) -> Result<DataFrame> {
+    let files: Vec<LogicalFileView> = block_on(async {
+        table
+            .get_active_add_actions_by_partitions(&[])
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to list table files: {}", e)))?;
+
+    // Files without statistics count as an average file
+    let known: Vec<usize> = files.iter().filter_map(|f| f.num_records()).collect();
+    let default_rows = if known.is_empty() {
+        1
+    } else {
+        (known.iter().sum::<usize>() / known.len()).max(1)
+    };
+    let rows_of = |f: &LogicalFileView| f.num_records().unwrap_or(default_rows);
+    let total_rows: usize = files.iter().map(rows_of).sum();
+
+    let target_rows = match sample {
+        Sample::Fraction(fraction) => (fraction * total_rows as f64).ceil() as usize,
+        Sample::Rows(n) => n.min(total_rows),
+    };
+
+    // Shuffle by hashing the paths with a randomly seeded hasher
+    let hasher = RandomState::new();
+    let mut shuffled: Vec<(u64, &LogicalFileView)> = files
+        .iter()
+        .map(|f| (hasher.hash_one(f.path()), f))
+        .collect();
+    shuffled.sort_by_key(|(key, _)| *key);
+
+    let wanted_rows = target_rows.saturating_mul(2).min(total_rows);
+    let mut selected = Vec::new();
+    let mut selected_rows = 0;
+    for (_, file) in shuffled {
+        if selected_rows >= wanted_rows && !selected.is_empty() {
+            break;
+        }
+        selected_rows += rows_of(file);
+        selected.push(file_view_to_add(file));
+    }
+
+    let df = ctx
+        .read_table(files_table_provider(&table, selected)?)
+        .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+
+    let df = match sample {
+        Sample::Fraction(_) if selected_rows > target_rows => {
+            let probability = target_rows as f64 / selected_rows as f64;
+            df.filter(random().lt(lit(probability)))
+        }
+        Sample::Fraction(_) => Ok(df),
+        Sample::Rows(n) => df
+            .sort(vec![random().sort(true, false)])
+            .and_then(|df| df.limit(0, Some(n))),
+    };
+    df.map_err(|e| Error::from(format!("Failed to sample table: {}", e)))
+}
+
+/// Scan a table, sampling it first when requested
+pub(crate) fn scan_dataframe(
+    ctx: &SessionContext,
+    table: DeltaTable,
+    sample: Option<Sample>,
+) -> Result<DataFrame> {
+    match sample {
+        Some(sample) => sampled_table_dataframe(ctx, table, sample),
+        None => table_dataframe(ctx, table),
+    }
+}
+
+/// Apply a column projection and a SQL filter expression to a DataFrame
+pub(crate) fn project_and_filter(
+    mut df: DataFrame,
+    columns: &Nullable<Vec<String>>,
+    predicate: &Nullable<&str>,
+) -> Result<DataFrame> {
+    if let Nullable::NotNull(expr) = predicate {
+        let filter = df
+            .parse_sql_expr(expr)
+            .map_err(|e| Error::from(format!("Invalid predicate '{}': {}", expr, e)))?;
+        df = df
+            .filter(filter)
+            .map_err(|e| Error::from(format!("Invalid predicate '{}': {}", expr, e)))?;
+    }
+
+    if let Nullable::NotNull(cols) = columns {
+        let cols: Vec<&str> = cols.iter().map(String::as_str).collect();
+        df = df
+            .select_columns(&cols)
+            .map_err(|e| Error::from(format!("Invalid column selection: {}", e)))?;
+    }
+
+    Ok(df)
+}
+
+/// Skip `offset` rows and keep at most `limit` rows of a DataFrame
+///
+/// The limit is pushed down into the scan, which stops reading files once
+/// enough rows have been produced.
+pub(crate) fn limit_and_offset(
+    df: DataFrame,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<DataFrame> {
+    if limit.is_some_and(|n| n < 0) || offset.is_some_and(|n| n < 0) {
+        return Err(Error::from("limit and offset must be non-negative"));
+    }
+    if limit.is_none() && offset.unwrap_or(0) == 0 {
+        return Ok(df);
+    }
+
+    df.limit(offset.unwrap_or(0) as usize, limit.map(|n| n as usize))
+        .map_err(|e| Error::from(format!("Invalid limit: {}", e)))
+}
+
+// ============================================================================
+// Incremental Reads
+// ============================================================================
+
+/// Where the rows of an incremental read come from
+pub(crate) enum ChangeSource {
+    /// The files added by append-only commits
+    Appends,
+    /// The change data feed
+    ChangeDataFeed,
+}
+
+impl ChangeSource {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ChangeSource::Appends => "appends",
+            ChangeSource::ChangeDataFeed => "cdf",
+        }
+    }
+}
+
+/// The columns the change data feed adds to the table schema
+const CDF_COLUMNS: [&str; 3] = ["_change_type", "_commit_version", "_commit_timestamp"];
+
+/// Read the rows added to a table after `version`
+///
+/// When no commit after `version` removed data (only appends, and
+/// compactions that rewrite existing rows), the files added by those
+/// commits are scanned directly. Otherwise the change data feed is read,
+/// which requires `delta.enableChangeDataFeed`; its rows carry the
+/// `_change_type`, `_commit_version` and `_commit_timestamp` columns.
+///
+/// `columns` and `predicate` are pushed into either scan. The change data
+/// feed columns are left out of `columns` when scanning appends, so the
+/// same selection works for both sources. Without `include_preimage`, the
+/// `update_preimage` rows of the change data feed are filtered out.
+pub(crate) fn changes_since(
+    ctx: &SessionContext,
+    table: &DeltaTable,
+    version: i64,
+    columns: &Nullable<Vec<String>>,
+    predicate: &Nullable<&str>,
+    include_preimage: bool,
+) -> Result<(DataFrame, ChangeSource)> {
+    let state = table.snapshot().map_err(|e| Error::from(e.to_string()))?;
+    let current = state.version();
+    if version < 0 || version > current {
+        return Err(Error::from(format!(
+            "version must be between 0 and the table version ({})",
+            current
+        )));
+    }
+
+    let log_store = table.log_store();
+    let commits: Vec<(i64, Option<_>)> = block_on(async {
+        futures::stream::iter(version + 1..=current)
+            .map(|v| {
+                let log_store = log_store.clone();
+                async move { log_store.read_commit_entry(v).await.map(|bytes| (v, bytes)) }
+            })
+            .buffered(16)
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to read the transaction log: {}", e)))?;
+
+    let mut added: Vec<Add> = Vec::new();
+    let mut appends_only = true;
+    for (v, bytes) in commits {
+        let bytes = bytes.ok_or_else(|| {
+            Error::from(format!("Commit {} is no longer in the transaction log", v))
+        })?;
+        let actions = get_actions(v, &bytes)
+            .map_err(|e| Error::from(format!("Failed to read commit {}: {}", v, e)))?;
+        for action in actions {
+            match action {
+                // Compaction adds files with rows that were already there
+                Action::Add(add) if add.data_change => added.push(add),
+                Action::Remove(remove) if remove.data_change => appends_only = false,
+                _ => {}
+            }
+        }
+    }
+
+    if appends_only {
+        let df = ctx
+            .read_table(files_table_provider(table, added)?)
+            .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+        let columns = match columns {
+            Nullable::NotNull(cols) => Nullable::NotNull(
+                cols.iter()
+                    .filter(|c| !CDF_COLUMNS.contains(&c.as_str()))
+                    .cloned()
+                    .collect(),
+            ),
+            Nullable::Null => Nullable::Null,
+        };
+        let df = project_and_filter(df, &columns, predicate)?;
+        return Ok((df, ChangeSource::Appends));
+    }
+
+    if state.table_config().enable_change_data_feed != Some(true) {
+        return Err(Error::from(format!(
+            "Commits after version {} removed data and the table does not have the \
+             change data feed enabled (delta.enableChangeDataFeed)",
+            version
+        )));
+    }
+    let cdf = table.clone().scan_cdf().with_starting_version(version + 1);
+    let provider = DeltaCdfTableProvider::try_new(cdf)
+        .map_err(|e| Error::from(format!("Failed to read the change data feed: {}", e)))?;
+    let mut df = ctx
+        .read_table(Arc::new(provider))
+        .map_err(|e| Error::from(format!("Failed to read the change data feed: {}", e)))?;
+    if !include_preimage {
+        df = df
+            .filter(col("_change_type").not_eq(lit("update_preimage")))
+            .map_err(|e| Error::from(format!("Failed to read the change data feed: {}", e)))?;
+    }
+    let df = project_and_filter(df, columns, predicate)?;
+    Ok((df, ChangeSource::ChangeDataFeed))
+}
+
+// ============================================================================
+// Streaming Results
+// ============================================================================
+
+/// Drive a future for a RecordBatchReader created on `handle`
+///
+/// Readers exported to R are usually pulled from R, outside the runtime. When
+/// another operation consumes them (e.g. a write reading this stream), it
+/// already runs on the runtime, so the future is polled directly instead of
+/// starting a nested runtime.
+pub(crate) fn block_on_reader<F>(
+    handle: &Handle,
+    future: F,
+) -> std::result::Result<F::Output, Interrupted>
+where
+    F: std::future::Future + Send,
+    F::Output: Send + 'static,
+{
+    if Handle::try_current().is_ok() {
+        let _guard = handle.enter();
+        // Other tasks of the worker move to another thread meanwhile, as the
+        // stream may wait on tasks of its own (e.g. those reading ahead)
+        Ok(tokio::task::block_in_place(|| {
+            futures::executor::block_on(future)
+        }))
+    } else {
+        block_on(future)
+    }
+}
+
+/// A RecordBatchReader that pulls batches from a DataFusion stream
+///
+/// Each call to `next()` drives the stream on the package runtime, so only
+/// the batches in flight are held in memory. When the session sets a
+/// [Prefetch] depth, a background task keeps reading up to that many batches
+/// ahead, so the reads overlap with the work R does on each batch. With a
+/// [MaxInFlight] limit, the partitions are read in the background until that
+/// many batches wait for the consumer.
+pub(crate) struct DataFusionStreamReader {
+    schema: SchemaRef,
+    stream: SendableRecordBatchStream,
+    handle: Handle,
+}
+
+impl DataFusionStreamReader {
+    /// Start executing a DataFrame and wrap the resulting stream
+    pub(crate) fn try_new(df: DataFrame) -> Result<Self> {
+        let config = df.task_ctx().session_config().clone();
+        let prefetch = config.get_extension::<Prefetch>();
+        let max_in_flight = config.get_extension::<MaxInFlight>();
+        let (stream, handle) = block_on(async {
+            let stream = match max_in_flight.as_deref() {
+                Some(MaxInFlight(limit)) => match debug::physical_plan(df).await {
+                    Ok((plan, task_ctx)) => bounded_stream(plan, task_ctx, *limit),
+                    Err(e) => Err(e),
+                },
+                None => debug::execute_stream(df).await,
+            };
+            (stream, Handle::current())
+        })?;
+        let mut stream =
+            stream.map_err(|e| Error::from(format!("Failed to execute query: {}", e)))?;
+        if let (Some(Prefetch(depth)), None) = (prefetch.as_deref(), &max_in_flight) {
+            stream = prefetch_stream(stream, *depth, &handle);
+        }
+        Ok(Self {
+            schema: stream.schema(),
+            stream,
+            handle,
+        })
+    }
+}
+
+/// Read a stream ahead of its consumer on a background task
+///
+/// At most `depth` batches wait in the buffer; the task stops when the
+/// returned stream is dropped.
+fn prefetch_stream(
+    mut stream: SendableRecordBatchStream,
+    depth: usize,
+    handle: &Handle,
+) -> SendableRecordBatchStream {
+    let mut builder = RecordBatchReceiverStreamBuilder::new(stream.schema(), depth);
+    let tx = builder.tx();
+    builder.spawn_on(
+        async move {
+            while let Some(batch) = stream.next().await {
+                if tx.send(batch).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        },
+        handle,
+    );
+    builder.build()
+}
+
+/// Execute every partition of a plan with at most `limit` batches in flight
+///
+/// Each partition is read on a task that takes a permit before reading a
+/// batch, and the permit is returned when the consumer takes the batch, so
+/// the reads pause while `limit` batches wait instead of queueing more.
+fn bounded_stream(
+    plan: Arc<dyn ExecutionPlan>,
+    task_ctx: Arc<TaskContext>,
+    limit: usize,
+) -> DataFusionResult<SendableRecordBatchStream> {
+    let permits = Arc::new(Semaphore::new(limit));
+    let mut builder = RecordBatchReceiverStreamBuilder::new(plan.schema(), limit);
+    for partition in 0..plan.output_partitioning().partition_count() {
+        let mut stream = plan.execute(partition, task_ctx.clone())?;
+        let tx = builder.tx();
+        let permits = permits.clone();
+        builder.spawn(async move {
+            loop {
+                let Ok(permit) = permits.acquire().await else {
+                    break;
+                };
+                permit.forget();
+                let Some(batch) = stream.next().await else {
+                    break;
+                };
+                if tx.send(batch).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+    }
+    let stream = builder.build().inspect(move |_| permits.add_permits(1));
+    Ok(Box::pin(RecordBatchStreamAdapter::new(
+        plan.schema(),
+        stream,
+    )))
+}
+
+impl Iterator for DataFusionStreamReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The stream's C interface cannot unwind, so an interrupt ends the
+        // stream with an error
+        match block_on_reader(&self.handle, self.stream.next()) {
+            Ok(batch) => {
+                batch.map(|batch| batch.map_err(|e| ArrowError::ExternalError(Box::new(e))))
+            }
+            Err(e) => Some(Err(ArrowError::ExternalError(Box::new(e)))),
+        }
+    }
+}
+
+impl RecordBatchReader for DataFusionStreamReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+/// Describe how a DataFrame is executed
+///
+/// `mode` is one of `"logical"` (the optimized logical plan), `"physical"`
+/// (the physical plan, including the files each scan reads) or `"analyze"`
+/// (runs the query and annotates the physical plan with per-operator row
+/// counts and timings).
+pub(crate) fn explain_dataframe(df: DataFrame, mode: &str) -> Result<String> {
+    let plan_error = |e: DataFusionError| Error::from(format!("Failed to plan query: {}", e));
+
+    match mode {
+        "logical" => {
+            let plan = df.into_optimized_plan().map_err(plan_error)?;
+            let text = plan.display_indent().to_string();
+            Ok(text)
+        }
+        "physical" => {
+            let plan = block_on(async { df.create_physical_plan().await })?.map_err(plan_error)?;
+            let text = displayable(plan.as_ref()).indent(true).to_string();
+            Ok(text)
+        }
+        "analyze" => {
+            let task_ctx = Arc::new(df.task_ctx());
+            let plan = block_on(async { df.create_physical_plan().await })?.map_err(plan_error)?;
+            block_on(async { collect(plan.clone(), task_ctx).await })?
+                .map_err(|e| Error::from(format!("Failed to execute query: {}", e)))?;
+            let text = DisplayableExecutionPlan::with_metrics(plan.as_ref())
+                .indent(true)
+                .to_string();
+            Ok(text)
+        }
+        other => Err(Error::from(format!(
+            "Unknown explain mode '{}'. Use 'logical', 'physical' or 'analyze'",
+            other
+        ))),
+    }
+}
+
+/// Export a DataFrame to R: its results as an Arrow stream, or its plan as
+/// text when `explain` is set
+pub(crate) fn dataframe_to_robj(df: DataFrame, explain: Nullable<&str>) -> Result<Robj> {
+    match explain {
+        Nullable::NotNull(mode) => explain_dataframe(df, mode).map(|plan| plan.into_robj()),
+        Nullable::Null => dataframe_to_stream(df),
+    }
+}
+
+/// Execute a DataFrame and export the results to R as an Arrow stream
+pub(crate) fn dataframe_to_stream(df: DataFrame) -> Result<Robj> {
+    let reader: Box<dyn RecordBatchReader + Send> = Box::new(DataFusionStreamReader::try_new(df)?);
+    reader
+        .into_arrow_robj()
+        .map_err(|e| Error::from(format!("Failed to export Arrow stream: {}", e)))
+}
+
+// ============================================================================
+// Scans
+// ============================================================================
+
+/// Scan a Delta Lake table into an Arrow stream
+///
+/// Column projections and filters are pushed down into the Delta scan, so
+/// only the needed columns are read and files whose statistics cannot match
+/// the predicate are skipped.
+///
+/// @param table_uri Path to the Delta table
+/// @param columns Column names to read (optional, all columns by default)
+/// @param predicate SQL filter expression, e.g. "year = 2024 AND value > 10" (optional)
+/// @param version Table version to read, negative counting back from the latest (optional)
+/// @param batch_size Maximum number of rows per batch (optional)
+/// @param sample_fraction Approximate fraction of rows to sample (optional)
+/// @param sample_n Number of rows to sample (optional)
+/// @param limit Maximum number of rows to return (optional)
+/// @param offset Number of rows to skip (optional)
+/// @param explain Return the query plan instead of the data: "logical", "physical" or "analyze" (optional)
+/// @param verify_checksums Verify the data files before scanning them
+/// @param execution Named list of DataFusion execution options (optional)
+/// @param storage_options Storage backend options (optional)
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+pub fn delta_scan(
+    table_uri: &str,
+    columns: Nullable<Vec<String>>,
+    predicate: Nullable<&str>,
+    version: Nullable<i64>,
+    batch_size: Nullable<i32>,
+    sample_fraction: Nullable<f64>,
+    sample_n: Nullable<i64>,
+    limit: Nullable<i64>,
+    offset: Nullable<i64>,
+    explain: Nullable<&str>,
+    verify_checksums: bool,
+    execution: Nullable<List>,
+    storage_options: Nullable<List>,
+) -> Result<Robj> {
+    let sample = sample_from_r(sample_fraction, sample_n)?;
+    let mut execution = ExecutionOptions::from_list(&execution)?;
+    if let Some(rows) = batch_size_from_r(batch_size)? {
+        execution.batch_size = Some(rows);
+    }
+    let ctx = execution.session_context()?;
+    let table = open_table(table_uri, version.into_option(), &storage_options)?;
+    let verified = verify_checksums.then(|| table.clone());
+    let df = scan_dataframe(&ctx, table, sample)?;
+    let df = project_and_filter(df, &columns, &predicate)?;
+    let df = limit_and_offset(df, limit.into_option(), offset.into_option())?;
+    if let Some(table) = verified {
+        verify::verify_checksums(&table, &df)?;
+    }
+    dataframe_to_robj(df, explain)
+}
+
+// ============================================================================
+// Lazy Queries
+// ============================================================================
+
+/// A lazy query over a Delta table
+///
+/// Every method returns a new query; nothing is read until `collect()`.
+#[derive(Clone)]
+#[extendr]
+pub struct DeltaLazyFrameInternal {
+    inner: DataFrame,
+    /// The table whose files `collect()` verifies first, if requested
+    verified: Option<DeltaTable>,
+}
+
+#[extendr]
+impl DeltaLazyFrameInternal {
+    /// Keep only the given columns
+    fn select(&self, columns: Vec<String>) -> Result<DeltaLazyFrameInternal> {
+        let inner = project_and_filter(
+            self.inner.clone(),
+            &Nullable::NotNull(columns),
+            &Nullable::Null,
+        )?;
+        Ok(DeltaLazyFrameInternal {
+            inner,
+            verified: self.verified.clone(),
+        })
+    }
+
+    /// Keep only the rows matching a SQL filter expression
+    fn filter(&self, predicate: &str) -> Result<DeltaLazyFrameInternal> {
+        let inner = project_and_filter(
+            self.inner.clone(),
+            &Nullable::Null,
+            &Nullable::NotNull(predicate),
+        )?;
+        Ok(DeltaLazyFrameInternal {
+            inner,
+            verified: self.verified.clone(),
+        })
+    }
+
+    /// Keep at most `n` rows, after skipping `offset` rows
+    fn limit(&self, n: i64, offset: i64) -> Result<DeltaLazyFrameInternal> {
+        let inner = limit_and_offset(self.inner.clone(), Some(n), Some(offset))?;
+        Ok(DeltaLazyFrameInternal {
+            inner,
+            verified: self.verified.clone(),
+        })
+    }
+
+    /// Get the column names of the query result
+    fn columns(&self) -> Vec<String> {
+        self.inner
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect()
+    }
+
+    /// Execute the query and return the results as an Arrow stream
+    fn collect(&self) -> Result<Robj> {
+        if let Some(table) = &self.verified {
+            verify::verify_checksums(table, &self.inner)?;
+        }
+        dataframe_to_stream(self.inner.clone())
+    }
+
+    /// Get the query plan as text ("logical", "physical" or "analyze")
+    fn explain(&self, mode: &str) -> Result<String> {
+        explain_dataframe(self.inner.clone(), mode)
+    }
+}
+
+/// Open a lazy query over a Delta Lake table
+///
+/// @param table_uri Path to the Delta table
+/// @param version Table version to read, negative counting back from the latest (optional)
+/// @param sample_fraction Approximate fraction of rows to sample (optional)
+/// @param sample_n Number of rows to sample (optional)
+/// @param verify_checksums Verify the data files the query reads when it is collected
+/// @param execution Named list of DataFusion execution options (optional)
+/// @param storage_options Storage backend options (optional)
+#[extendr]
+pub fn delta_lazy_open(
+    table_uri: &str,
+    version: Nullable<i64>,
+    sample_fraction: Nullable<f64>,
+    sample_n: Nullable<i64>,
+    verify_checksums: bool,
+    execution: Nullable<List>,
+    storage_options: Nullable<List>,
+) -> Result<DeltaLazyFrameInternal> {
+    let sample = sample_from_r(sample_fraction, sample_n)?;
+    let ctx = ExecutionOptions::from_list(&execution)?.session_context()?;
+    let table = open_table(table_uri, version.into_option(), &storage_options)?;
+    let verified = verify_checksums.then(|| table.clone());
+    let inner = scan_dataframe(&ctx, table, sample)?;
+    Ok(DeltaLazyFrameInternal { inner, verified })
+}
+
+// Export the module functions
+extendr_module! {
+    mod read;
+    impl DeltaLazyFrameInternal;
+    fn delta_scan;
+    fn delta_lazy_open;
+}
@@ -0,0 +1,548 @@
+//! Delta Sharing client
+//!
+//! Reads tables shared through the Delta Sharing REST protocol. The server
+//! answers a query with pre-signed URLs of the Parquet files that make up
+//! the table (or its changes); the files are downloaded one at a time and
+//! streamed back to R, so a share never has to fit in memory.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use arrow::array::{new_null_array, ArrayRef, RecordBatch, StringArray};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    DataType as ArrowDataType, Field as ArrowField, Schema as ArrowSchema, SchemaRef,
+    TimeUnit as ArrowTimeUnit,
+};
+use arrow::error::ArrowError;
+use arrow_extendr::to::IntoArrowRobj;
+use deltalake::arrow::array::RecordBatchReader;
+use deltalake::kernel::StructType;
+use deltalake::parquet::arrow::arrow_reader::{
+    ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder,
+};
+use extendr_api::prelude::*;
+use serde_json::{json, Value};
+use tokio::runtime::Handle;
+use url::Url;
+
+use crate::read::block_on_reader;
+use crate::storage::http_client;
+use crate::{block_on, kernel_schema_to_arrow, parse_storage_options};
+
+// ============================================================================
+// Profiles and Requests
+// ============================================================================
+
+/// Credentials of a Delta Sharing profile file
+#[derive(Debug, Clone)]
+struct SharingProfile {
+    endpoint: String,
+    bearer_token: String,
+}
+
+impl SharingProfile {
+    /// Read a profile file as issued by the data provider
+    fn from_file(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::from(format!("Failed to read profile '{}': {}", path, e)))?;
+        let profile: Value = serde_json::from_str(&text)
+            .map_err(|e| Error::from(format!("Invalid profile '{}': {}", path, e)))?;
+
+        let version = profile["shareCredentialsVersion"].as_i64().unwrap_or(1);
+        if version != 1 {
+            return Err(Error::from(format!(
+                "Unsupported shareCredentialsVersion {}; only bearer token profiles (version 1) are supported",
+                version
+            )));
+        }
+
+        let field = |name: &str| {
+            profile[name]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| Error::from(format!("Profile '{}' has no '{}'", path, name)))
+        };
+
+        Ok(Self {
+            endpoint: field("endpoint")?.trim_end_matches('/').to_string(),
+            bearer_token: field("bearerToken")?,
+        })
+    }
+}
+
+/// A parsed response: the table version and the JSON lines of the body
+struct SharingResponse {
+    version: Option<i64>,
+    lines: Vec<Value>,
+}
+
+/// A table in a share, with a client for its endpoint
+#[derive(Debug, Clone)]
+struct SharingTable {
+    profile: SharingProfile,
+    share: String,
+    schema: String,
+    table: String,
+    client: reqwest::Client,
+}
+
+impl SharingTable {
+    /// URL of `endpoint` of the table, with the names escaped as path
+    /// segments
+    fn url(&self, endpoint: &str) -> Result<Url> {
+        let invalid = |reason: &str| {
+            Error::from(format!(
+                "Invalid Delta Sharing endpoint '{}': {}",
+                self.profile.endpoint, reason
+            ))
+        };
+        let mut url = Url::parse(&self.profile.endpoint).map_err(|e| invalid(&e.to_string()))?;
+        url.path_segments_mut()
+            .map_err(|_| invalid("not a base URL"))?
+            .pop_if_empty()
+            .extend([
+                "shares",
+                &self.share,
+                "schemas",
+                &self.schema,
+                "tables",
+                &self.table,
+                endpoint,
+            ]);
+        Ok(url)
+    }
+
+    /// Send a request and parse the newline-delimited JSON response
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> std::result::Result<SharingResponse, String> {
+        let response = request
+            .bearer_auth(&self.profile.bearer_token)
+            .send()
+            .await
+            .map_err(|e| format!("Delta Sharing request failed: {}", e))?;
+
+        let status = response.status();
+        let version = response
+            .headers()
+            .get("delta-table-version")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        let body = response
+            .text()
+            .await
+            .map_err(|e| format!("Delta Sharing request failed: {}", e))?;
+
+        if !status.is_success() {
+            return Err(format!(
+                "Delta Sharing server returned {} for {}.{}.{}: {}",
+                status, self.share, self.schema, self.table, body
+            ));
+        }
+
+        let lines = body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<Vec<Value>, _>>()
+            .map_err(|e| format!("Invalid Delta Sharing response: {}", e))?;
+        Ok(SharingResponse { version, lines })
+    }
+
+    fn metadata(&self) -> Result<SharingResponse> {
+        let request = self.client.get(self.url("metadata")?);
+        block_on(self.send(request))?.map_err(Error::from)
+    }
+
+    fn query(&self, body: Value) -> Result<SharingResponse> {
+        let request = self.client.post(self.url("query")?).json(&body);
+        block_on(self.send(request))?.map_err(Error::from)
+    }
+
+    fn changes(&self, params: Vec<(&str, String)>) -> Result<SharingResponse> {
+        let request = self.client.get(self.url("changes")?).query(&params);
+        block_on(self.send(request))?.map_err(Error::from)
+    }
+}
+
+/// Table metadata from the metaData line of a response
+struct SharedMetadata {
+    schema: StructType,
+    partition_columns: Vec<String>,
+}
+
+fn shared_metadata(response: &SharingResponse) -> Result<SharedMetadata> {
+    let metadata = response
+        .lines
+        .iter()
+        .find_map(|line| line.get("metaData"))
+        .ok_or_else(|| Error::from("Delta Sharing response has no table metadata"))?;
+
+    let schema_string = metadata["schemaString"]
+        .as_str()
+        .ok_or_else(|| Error::from("Delta Sharing metadata has no schema"))?;
+    let schema: StructType = serde_json::from_str(schema_string)
+        .map_err(|e| Error::from(format!("Invalid shared table schema: {}", e)))?;
+
+    let partition_columns = metadata["partitionColumns"]
+        .as_array()
+        .map(|cols| {
+            cols.iter()
+                .filter_map(|c| c.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(SharedMetadata {
+        schema,
+        partition_columns,
+    })
+}
+
+// ============================================================================
+// Streaming Shared Files
+// ============================================================================
+
+/// Change columns added to each row of a change data feed read
+struct ChangeInfo {
+    change_type: Option<&'static str>,
+    version: i64,
+    timestamp: i64,
+}
+
+/// A data file returned by the server
+struct SharedFile {
+    url: String,
+    partition_values: HashMap<String, Option<String>>,
+    change: Option<ChangeInfo>,
+}
+
+impl SharedFile {
+    fn from_json(file: &Value, change: Option<ChangeInfo>) -> Result<Self> {
+        let url = file["url"]
+            .as_str()
+            .ok_or_else(|| Error::from("Shared file has no URL"))?
+            .to_string();
+        let partition_values = file["partitionValues"]
+            .as_object()
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.as_str().map(str::to_string)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            url,
+            partition_values,
+            change,
+        })
+    }
+}
+
+/// A RecordBatchReader that downloads and decodes shared files one by one
+struct SharedFileReader {
+    client: reqwest::Client,
+    handle: Handle,
+    schema: SchemaRef,
+    partition_columns: Vec<String>,
+    files: VecDeque<SharedFile>,
+    current: Option<(ParquetRecordBatchReader, SharedFile)>,
+}
+
+impl SharedFileReader {
+    fn new(
+        table: &SharingTable,
+        metadata: &SharedMetadata,
+        files: Vec<SharedFile>,
+        with_changes: bool,
+    ) -> Self {
+        let mut fields: Vec<ArrowField> = kernel_schema_to_arrow(&metadata.schema)
+            .fields()
+            .iter()
+            .map(|f| f.as_ref().clone())
+            .collect();
+        if with_changes {
+            fields.push(ArrowField::new("_change_type", ArrowDataType::Utf8, true));
+            fields.push(ArrowField::new(
+                "_commit_version",
+                ArrowDataType::Int64,
+                true,
+            ));
+            fields.push(ArrowField::new(
+                "_commit_timestamp",
+                ArrowDataType::Timestamp(ArrowTimeUnit::Millisecond, Some("UTC".into())),
+                true,
+            ));
+        }
+
+        Self {
+            client: table.client.clone(),
+            handle: crate::RUNTIME.handle().clone(),
+            schema: Arc::new(ArrowSchema::new(fields)),
+            partition_columns: metadata.partition_columns.clone(),
+            files: files.into(),
+            current: None,
+        }
+    }
+
+    /// Download the next file and open a Parquet reader over it
+    fn open_next(&mut self) -> std::result::Result<bool, ArrowError> {
+        let Some(file) = self.files.pop_front() else {
+            return Ok(false);
+        };
+
+        let client = self.client.clone();
+        let url = file.url.clone();
+        let bytes = block_on_reader(&self.handle, async move {
+            client
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        })
+        .map_err(|e| ArrowError::ExternalError(Box::new(e)))?
+        .map_err(|e| ArrowError::ExternalError(Box::new(e)))?;
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes)
+            .and_then(|builder| builder.build())
+            .map_err(|e| ArrowError::ExternalError(Box::new(e)))?;
+        self.current = Some((reader, file));
+        Ok(true)
+    }
+
+    /// Align a file batch with the table schema
+    ///
+    /// Columns are matched by name and cast to the table types; partition
+    /// and change columns, which are not stored in the files, are filled in
+    /// from the file metadata.
+    fn conform(
+        &self,
+        batch: RecordBatch,
+        file: &SharedFile,
+    ) -> std::result::Result<RecordBatch, ArrowError> {
+        let rows = batch.num_rows();
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| -> std::result::Result<ArrayRef, ArrowError> {
+                let name = field.name();
+                if self.partition_columns.contains(name) {
+                    let value = file.partition_values.get(name).cloned().flatten();
+                    let strings: ArrayRef = Arc::new(StringArray::from(vec![value; rows]));
+                    return cast(&strings, field.data_type());
+                }
+                if let Some(change) = &file.change {
+                    match name.as_str() {
+                        "_change_type" if change.change_type.is_some() => {
+                            return Ok(Arc::new(StringArray::from(vec![change.change_type; rows])));
+                        }
+                        "_commit_version" => {
+                            let values: ArrayRef = Arc::new(arrow::array::Int64Array::from(vec![
+                                    change.version;
+                                    rows
+                                ]));
+                            return Ok(values);
+                        }
+                        "_commit_timestamp" => {
+                            let values: ArrayRef = Arc::new(arrow::array::Int64Array::from(vec![
+                                    change.timestamp;
+                                    rows
+                                ]));
+                            return cast(&values, field.data_type());
+                        }
+                        _ => {}
+                    }
+                }
+                match batch.column_by_name(name) {
+                    Some(column) => cast(column, field.data_type()),
+                    None => Ok(new_null_array(field.data_type(), rows)),
+                }
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        RecordBatch::try_new(self.schema.clone(), columns)
+    }
+}
+
+impl Iterator for SharedFileReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((reader, _)) = self.current.as_mut() {
+                match reader.next() {
+                    Some(Ok(batch)) => {
+                        let (_, file) = self.current.as_ref()?;
+                        return Some(self.conform(batch, file));
+                    }
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => self.current = None,
+                }
+            }
+            match self.open_next() {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl RecordBatchReader for SharedFileReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+fn reader_to_robj(reader: SharedFileReader) -> Result<Robj> {
+    let reader: Box<dyn RecordBatchReader + Send> = Box::new(reader);
+    reader
+        .into_arrow_robj()
+        .map_err(|e| Error::from(format!("Failed to export Arrow stream: {}", e)))
+}
+
+// ============================================================================
+// R Interface
+// ============================================================================
+
+/// A table shared through Delta Sharing
+#[extendr]
+pub struct DeltaSharingTableInternal {
+    inner: SharingTable,
+}
+
+#[extendr]
+impl DeltaSharingTableInternal {
+    /// Get the current version, schema fields and partition columns
+    fn metadata(&self) -> Result<List> {
+        let response = self.inner.metadata()?;
+        let metadata = shared_metadata(&response)?;
+        let columns: Vec<String> = metadata
+            .schema
+            .fields()
+            .map(|f| f.name().to_string())
+            .collect();
+
+        Ok(list!(
+            version = response.version,
+            columns = columns,
+            partition_columns = metadata.partition_columns
+        ))
+    }
+
+    /// Read the table, optionally at a version, as an Arrow stream
+    ///
+    /// `predicate_hints` and `limit_hint` are passed to the server, which may
+    /// use them to skip files; they do not filter the returned rows exactly.
+    fn scan(
+        &self,
+        version: Nullable<i64>,
+        predicate_hints: Nullable<Vec<String>>,
+        limit_hint: Nullable<i64>,
+    ) -> Result<Robj> {
+        let mut body = json!({});
+        if let Nullable::NotNull(v) = version {
+            body["version"] = json!(v);
+        }
+        if let Nullable::NotNull(hints) = predicate_hints {
+            body["predicateHints"] = json!(hints);
+        }
+        if let Nullable::NotNull(n) = limit_hint {
+            body["limitHint"] = json!(n);
+        }
+
+        let response = self.inner.query(body)?;
+        let metadata = shared_metadata(&response)?;
+        let files = response
+            .lines
+            .iter()
+            .filter_map(|line| line.get("file"))
+            .map(|file| SharedFile::from_json(file, None))
+            .collect::<Result<Vec<_>>>()?;
+
+        reader_to_robj(SharedFileReader::new(&self.inner, &metadata, files, false))
+    }
+
+    /// Read the change data feed between two versions as an Arrow stream
+    ///
+    /// Requires the provider to share the table with history.
+    fn changes(&self, starting_version: i64, ending_version: Nullable<i64>) -> Result<Robj> {
+        let mut params = vec![("startingVersion", starting_version.to_string())];
+        if let Nullable::NotNull(v) = ending_version {
+            params.push(("endingVersion", v.to_string()));
+        }
+
+        let response = self.inner.changes(params)?;
+        let metadata = shared_metadata(&response)?;
+
+        let mut files = Vec::new();
+        for line in &response.lines {
+            let (file, change_type) = if let Some(file) = line.get("add") {
+                (file, Some("insert"))
+            } else if let Some(file) = line.get("remove") {
+                (file, Some("delete"))
+            } else if let Some(file) = line.get("cdf") {
+                // CDC files carry their own _change_type column
+                (file, None)
+            } else {
+                continue;
+            };
+            let change = ChangeInfo {
+                change_type,
+                version: file["version"].as_i64().unwrap_or(-1),
+                timestamp: file["timestamp"].as_i64().unwrap_or(0),
+            };
+            files.push(SharedFile::from_json(file, Some(change))?);
+        }
+
+        reader_to_robj(SharedFileReader::new(&self.inner, &metadata, files, true))
+    }
+}
+
+/// Open a table from a Delta Sharing profile
+///
+/// @param profile Path to the profile file (JSON with endpoint and bearerToken)
+/// @param share Share name
+/// @param schema Schema name
+/// @param table Table name
+/// @param proxy Proxy options, as built by `proxy_storage_options()` (optional)
+#[extendr]
+pub fn delta_sharing_table_open(
+    profile: &str,
+    share: &str,
+    schema: &str,
+    table: &str,
+    proxy: Nullable<List>,
+) -> Result<DeltaSharingTableInternal> {
+    let proxy = match proxy {
+        Nullable::NotNull(ref opts) => parse_storage_options(opts)?,
+        Nullable::Null => HashMap::new(),
+    };
+    let client = http_client(&proxy).map_err(Error::from)?;
+
+    let inner = SharingTable {
+        profile: SharingProfile::from_file(profile)?,
+        share: share.to_string(),
+        schema: schema.to_string(),
+        table: table.to_string(),
+        client,
+    };
+
+    // Fail early on unknown tables or invalid credentials
+    inner.metadata()?;
+
+    Ok(DeltaSharingTableInternal { inner })
+}
+
+// Export the module functions
+extendr_module! {
+    mod sharing;
+    impl DeltaSharingTableInternal;
+    fn delta_sharing_table_open;
+}
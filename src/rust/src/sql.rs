@@ -0,0 +1,225 @@
+//! SQL queries over Delta Lake tables
+//!
+//! Tables are registered under user-chosen names in a DataFusion session, so
+//! a single query can join several tables, or several versions of the same
+//! table, without moving data through R. A session keeps its registered
+//! tables, including in-memory data from R, across queries.
+
+use std::sync::Arc;
+
+use arrow_extendr::to::IntoArrowRobj;
+use deltalake::arrow::array::{RecordBatch, RecordBatchIterator, RecordBatchReader};
+use deltalake::datafusion::datasource::MemTable;
+use deltalake::datafusion::execution::context::SQLOptions;
+use deltalake::datafusion::physical_plan::collect;
+use deltalake::datafusion::prelude::SessionContext;
+use deltalake::DeltaTable;
+use extendr_api::prelude::*;
+
+use crate::execution::ExecutionOptions;
+use crate::profile::Profile;
+use crate::read::{dataframe_to_robj, open_table, table_provider};
+use crate::write::reader_from_robj;
+use crate::{block_on, debug, DeltaTableInternal};
+
+/// Resolve an R table reference to a loaded Delta table
+///
+/// A reference is either a table URI (opened at its latest version) or a
+/// `DeltaTableInternal` handle (used at the version it was loaded at).
+pub(crate) fn table_from_robj(
+    name: &str,
+    table: &Robj,
+    storage_options: &Nullable<List>,
+) -> Result<DeltaTable> {
+    if let Some(uri) = table.as_str() {
+        return open_table(uri, None, storage_options);
+    }
+
+    <&DeltaTableInternal>::try_from(table)
+        .map(|handle| handle.inner.clone())
+        .map_err(|_| {
+            Error::from(format!(
+                "Table '{}' must be a table URI or a DeltaTable",
+                name
+            ))
+        })
+}
+
+/// Register every named table of `tables` in a session
+pub(crate) fn register_tables(
+    ctx: &SessionContext,
+    tables: &List,
+    storage_options: &Nullable<List>,
+) -> Result<()> {
+    for (name, table) in tables.iter() {
+        if name.is_empty() || name == "NA" {
+            return Err(Error::from("All tables must be named"));
+        }
+        let table = table_from_robj(name, &table, storage_options)?;
+        ctx.register_table(name, table_provider(table)?)
+            .map_err(|e| Error::from(format!("Failed to register table '{}': {}", name, e)))?;
+    }
+    Ok(())
+}
+
+/// Queries may only read: statements that create tables or views, write
+/// files (`COPY ... TO`), insert into tables or change session settings are
+/// rejected when they are planned
+pub(crate) fn read_only() -> SQLOptions {
+    SQLOptions::new()
+        .with_allow_ddl(false)
+        .with_allow_dml(false)
+        .with_allow_statements(false)
+}
+
+/// Plan a SQL query in a session and export its results or plan to R
+///
+/// A profiled query is run to completion before it is exported, and
+/// returned as a list with the `result` stream and the `profile`.
+fn run_sql(
+    ctx: &SessionContext,
+    query: &str,
+    explain: Nullable<&str>,
+    mut profile: Profile,
+) -> Result<Robj> {
+    let df = profile
+        .time("planning", || {
+            block_on(async { ctx.sql_with_options(query, read_only()).await })
+        })?
+        .map_err(|e| Error::from(format!("Failed to plan query: {}", e)))?;
+    if !profile.enabled() {
+        return dataframe_to_robj(df, explain);
+    }
+
+    let plan = profile
+        .time("planning", || {
+            block_on(async { df.clone().create_physical_plan().await })
+        })?
+        .map_err(|e| Error::from(format!("Failed to plan query: {}", e)))?;
+    debug::log_plans(&df, plan.as_ref());
+    let batches = profile
+        .time("execute", || {
+            block_on(async { collect(plan.clone(), ctx.task_ctx()).await })
+        })?
+        .map_err(|e| Error::from(format!("Query failed: {}", e)))?;
+    profile.add_scan_metrics("scan", &plan);
+
+    let reader: Box<dyn RecordBatchReader + Send> = Box::new(RecordBatchIterator::new(
+        batches.into_iter().map(Ok),
+        plan.schema(),
+    ));
+    let result = reader
+        .into_arrow_robj()
+        .map_err(|e| Error::from(format!("Failed to export Arrow stream: {}", e)))?;
+    let profile = profile.finish().unwrap_or_else(|| ().into_robj());
+    Ok(list!(result = result, profile = profile).into_robj())
+}
+
+/// Run a SQL query over one or more Delta Lake tables
+///
+/// Each element of `tables` is registered under its name and can be used in
+/// the query. Filters and projections are pushed down into each table scan.
+///
+/// @param query SQL query
+/// @param tables Named list of table URIs or DeltaTableInternal handles
+/// @param explain Return the query plan instead of the data: "logical", "physical" or "analyze" (optional)
+/// @param storage_options Storage backend options used to open table URIs (optional)
+/// @param execution Named list of DataFusion execution options (optional)
+/// @param profile Whether to run the query to completion and return the time spent in each stage
+#[extendr]
+pub fn delta_sql_query(
+    query: &str,
+    tables: List,
+    explain: Nullable<&str>,
+    storage_options: Nullable<List>,
+    execution: Nullable<List>,
+    profile: bool,
+) -> Result<Robj> {
+    let mut profile = Profile::new(profile);
+    let ctx = ExecutionOptions::from_list(&execution)?.session_context()?;
+    profile.time("log_replay", || {
+        register_tables(&ctx, &tables, &storage_options)
+    })?;
+    run_sql(&ctx, query, explain, profile)
+}
+
+// ============================================================================
+// Sessions
+// ============================================================================
+
+/// A DataFusion session with named tables that persist across queries
+#[extendr]
+pub struct DeltaSessionInternal {
+    ctx: SessionContext,
+}
+
+#[extendr]
+impl DeltaSessionInternal {
+    /// Create an empty session
+    ///
+    /// @param execution Named list of DataFusion execution options (optional)
+    fn new(execution: Nullable<List>) -> Result<Self> {
+        Ok(DeltaSessionInternal {
+            ctx: ExecutionOptions::from_list(&execution)?.session_context()?,
+        })
+    }
+
+    /// Register Delta tables (URIs or DeltaTableInternal handles) by name
+    fn register_tables(&self, tables: List, storage_options: Nullable<List>) -> Result<()> {
+        register_tables(&self.ctx, &tables, &storage_options)
+    }
+
+    /// Register in-memory data (a data.frame or Arrow stream) as a table
+    ///
+    /// The data is read into memory once, so it should be small, e.g. a
+    /// lookup table to join against Delta tables.
+    fn register_data(&self, name: &str, data: Robj) -> Result<()> {
+        let reader = reader_from_robj(&data)?;
+        let schema = reader.schema();
+        let batches = reader
+            .collect::<std::result::Result<Vec<RecordBatch>, _>>()
+            .map_err(|e| Error::from(format!("Failed to read data for '{}': {}", name, e)))?;
+
+        let table = MemTable::try_new(schema, vec![batches])
+            .map_err(|e| Error::from(format!("Failed to register '{}': {}", name, e)))?;
+        self.ctx
+            .register_table(name, Arc::new(table))
+            .map_err(|e| Error::from(format!("Failed to register '{}': {}", name, e)))?;
+        Ok(())
+    }
+
+    /// Remove a table from the session
+    fn deregister(&self, name: &str) -> Result<()> {
+        self.ctx
+            .deregister_table(name)
+            .map_err(|e| Error::from(format!("Failed to remove '{}': {}", name, e)))?;
+        Ok(())
+    }
+
+    /// Get the names of the registered tables
+    fn tables(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .ctx
+            .catalog("datafusion")
+            .and_then(|catalog| catalog.schema("public"))
+            .map(|schema| schema.table_names())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    /// Run a SQL query over the registered tables
+    ///
+    /// With `profile`, the query is run to completion and returned with the
+    /// time spent in each stage.
+    fn sql(&self, query: &str, explain: Nullable<&str>, profile: bool) -> Result<Robj> {
+        run_sql(&self.ctx, query, explain, Profile::new(profile))
+    }
+}
+
+// Export the module functions
+extendr_module! {
+    mod sql;
+    impl DeltaSessionInternal;
+    fn delta_sql_query;
+}
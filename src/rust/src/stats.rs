@@ -0,0 +1,176 @@
+//! Recompute file statistics
+//!
+//! Rebuilds the statistics of a table's add actions from the Parquet footers
+//! of its files, without reading or rewriting any data. Tables converted
+//! from plain Parquet, or written by engines that skip statistics, get file
+//! skipping this way.
+
+use std::collections::HashMap;
+
+use deltalake::kernel::transaction::CommitBuilder;
+use deltalake::kernel::{Action, CommitInfo, LogicalFileView};
+use deltalake::parquet::arrow::async_reader::{
+    ParquetObjectReader, ParquetRecordBatchStreamBuilder,
+};
+use deltalake::protocol::DeltaOperation;
+use deltalake::table::config::TablePropertiesExt;
+use deltalake::writer::create_add;
+use deltalake::{DeltaTable, Path};
+use extendr_api::prelude::*;
+use futures::{StreamExt, TryStreamExt};
+use indexmap::IndexMap;
+
+use crate::commit::commit_properties;
+use crate::read::file_view_to_add;
+use crate::{block_on, engine_info, file_concurrency};
+
+/// Operation recorded in the `commitInfo` of statistics recomputes
+pub(crate) const ANALYZE_OPERATION: &str = "ANALYZE";
+
+/// Outcome of a statistics recompute
+pub(crate) struct AnalyzeMetrics {
+    pub(crate) num_files_analyzed: usize,
+    pub(crate) num_files_skipped: usize,
+}
+
+/// Whether a file's add action is missing its statistics
+fn has_statistics(file: &LogicalFileView) -> bool {
+    file.num_records().is_some()
+}
+
+/// The `commitInfo` of an ANALYZE commit
+///
+/// delta-rs only writes its own when the actions have none, so this one
+/// records the engine and client versions of the commits delta-rs makes.
+fn analyze_commit_info(missing_only: bool, metrics: &AnalyzeMetrics) -> Action {
+    let operation_metrics = serde_json::json!({
+        "numFilesAnalyzed": metrics.num_files_analyzed,
+        "numFilesSkipped": metrics.num_files_skipped,
+    });
+    Action::CommitInfo(CommitInfo {
+        timestamp: Some(chrono::Utc::now().timestamp_millis()),
+        operation: Some(ANALYZE_OPERATION.to_string()),
+        operation_parameters: Some(HashMap::from([(
+            "missingOnly".to_string(),
+            serde_json::Value::Bool(missing_only),
+        )])),
+        is_blind_append: Some(false),
+        info: HashMap::from([
+            (
+                "clientVersion".to_string(),
+                format!("delta-rs.{}", deltalake::crate_version()).into(),
+            ),
+            ("operationMetrics".to_string(), operation_metrics),
+        ]),
+        engine_info: Some(engine_info()),
+        ..Default::default()
+    })
+}
+
+/// Recompute the statistics of a table's files and commit them
+///
+/// Each analyzed file gets a new add action with `dataChange = false`, so
+/// readers and streaming consumers see no change in the table's data. With
+/// `missing_only`, files that already have statistics are left untouched;
+/// files with deletion vectors are always left untouched.
+pub(crate) fn recompute_statistics(
+    table: &DeltaTable,
+    missing_only: bool,
+) -> Result<(DeltaTable, AnalyzeMetrics)> {
+    let snapshot = table
+        .snapshot()
+        .map_err(|e| Error::from(e.to_string()))?
+        .clone();
+    let properties = snapshot.snapshot().table_properties();
+    let num_indexed_cols = properties.num_indexed_cols();
+    let stats_columns: Option<Vec<String>> = properties
+        .data_skipping_stats_columns
+        .as_ref()
+        .map(|cols| cols.iter().map(|c| c.to_string()).collect());
+
+    let files: Vec<LogicalFileView> = block_on(async {
+        table
+            .get_active_add_actions_by_partitions(&[])
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to list table files: {}", e)))?;
+
+    // Footer statistics of files with deletion vectors also cover the deleted
+    // rows, so those files keep the statistics they were written with
+    let (selected, skipped): (Vec<_>, Vec<_>) = files.into_iter().partition(|file| {
+        file.deletion_vector_descriptor().is_none() && (!missing_only || !has_statistics(file))
+    });
+    let metrics = AnalyzeMetrics {
+        num_files_analyzed: selected.len(),
+        num_files_skipped: skipped.len(),
+    };
+    if selected.is_empty() {
+        return Ok((table.clone(), metrics));
+    }
+
+    // Footers are read and parsed on the runtime's worker threads
+    let store = table.log_store().object_store(None);
+    let mut actions: Vec<Action> = block_on(async {
+        futures::stream::iter(selected.into_iter().map(|file| {
+            let store = store.clone();
+            let stats_columns = stats_columns.clone();
+            tokio::spawn(async move {
+                let reader = ParquetObjectReader::new(store, Path::from(file.path().as_ref()))
+                    .with_file_size(file.size() as u64);
+                let builder = ParquetRecordBatchStreamBuilder::new(reader).await?;
+
+                let partition_values: IndexMap<String, _> = file
+                    .partition_values()
+                    .map(|data| {
+                        data.fields()
+                            .iter()
+                            .zip(data.values().iter())
+                            .map(|(field, value)| (field.name().to_string(), value.clone()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let analyzed = create_add(
+                    &partition_values,
+                    file.path().to_string(),
+                    file.size(),
+                    builder.metadata(),
+                    num_indexed_cols,
+                    &stats_columns,
+                )?;
+
+                // Keep everything but the statistics from the original action
+                let mut add = file_view_to_add(&file);
+                add.stats = analyzed.stats;
+                add.data_change = false;
+                Ok::<_, deltalake::DeltaTableError>(Action::Add(add))
+            })
+        }))
+        .buffered(file_concurrency(table))
+        .map(|joined| joined.map_err(|e| deltalake::DeltaTableError::Generic(e.to_string()))?)
+        .try_collect()
+        .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to read file statistics: {}", e)))?;
+
+    // delta-rs has no dedicated operation for this. Like a compaction it only
+    // changes file metadata, so its conflicts are checked as those of an
+    // OPTIMIZE, but the commit records itself as an ANALYZE.
+    let operation = DeltaOperation::Optimize {
+        predicate: None,
+        target_size: 0,
+    };
+    actions.push(analyze_commit_info(missing_only, &metrics));
+    let commit = block_on(async {
+        CommitBuilder::from(commit_properties())
+            .with_actions(actions)
+            .build(Some(&snapshot), table.log_store(), operation)
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to commit statistics: {}", e)))?;
+
+    let mut table = table.clone();
+    block_on(async { table.load_version(commit.version()).await })?
+        .map_err(|e| Error::from(e.to_string()))?;
+    Ok((table, metrics))
+}
@@ -0,0 +1,1046 @@
+//! Typed storage options
+//!
+//! Builds the string options delta-rs passes to its object stores from typed
+//! settings, so that inconsistent settings are reported before any request
+//! is sent. Mis-addressed S3 requests otherwise fail with errors that do not
+//! name the setting at fault: an `http://` MinIO endpoint without
+//! `allow_http`, virtual-hosted addressing against an appliance that only
+//! serves path-style requests, or a directory bucket opened without S3
+//! Express One Zone.
+//!
+//! Azure bearer tokens (managed identity, client secret, Azure CLI) are
+//! cached by the object store and fetched again shortly before they expire,
+//! so long operations keep working. A SAS token may be an R function instead
+//! of a string: it is called for a fresh token each time a table is opened,
+//! and again through [crate::azure] when the current token is about to
+//! expire during an operation. A Databricks token or client secret may also
+//! be a function, called each time a table is opened.
+
+use std::collections::HashMap;
+
+use deltalake::logstore::LogStore;
+use extendr_api::prelude::*;
+use url::Url;
+
+use crate::{aws, gcs, unity};
+
+/// How requests address the bucket
+#[derive(Clone, Copy, PartialEq)]
+enum AddressingStyle {
+    /// `https://endpoint/bucket/key`
+    Path,
+    /// `https://bucket.endpoint/key`
+    Virtual,
+}
+
+/// S3 settings given to `s3_storage_options()`
+#[derive(Default)]
+struct S3Options {
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+    region: Option<String>,
+    endpoint: Option<Url>,
+    addressing_style: Option<AddressingStyle>,
+    s3_express: bool,
+    allow_http: Option<bool>,
+    profile: Option<String>,
+    role_arn: Option<String>,
+    external_id: Option<String>,
+    role_session_name: Option<String>,
+    role_session_duration: Option<u64>,
+    server_side_encryption: Option<String>,
+    sse_kms_key_id: Option<String>,
+    sse_bucket_key: Option<bool>,
+    sse_customer_key: Option<String>,
+}
+
+fn string_option(name: &str, value: &Robj) -> Result<String> {
+    value
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| Error::from(format!("S3 option '{}' must be a non-empty string", name)))
+}
+
+fn bool_option(name: &str, value: &Robj) -> Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| Error::from(format!("S3 option '{}' must be TRUE or FALSE", name)))
+}
+
+impl S3Options {
+    /// Parse S3 settings from a named R list, skipping `NULL` elements
+    fn from_list(options: &List) -> Result<Self> {
+        let mut s3 = S3Options::default();
+        for (name, value) in options.iter() {
+            if value.is_null() {
+                continue;
+            }
+            match name {
+                "access_key_id" => s3.access_key_id = Some(string_option(name, &value)?),
+                "secret_access_key" => s3.secret_access_key = Some(string_option(name, &value)?),
+                "session_token" => s3.session_token = Some(string_option(name, &value)?),
+                "region" => s3.region = Some(string_option(name, &value)?),
+                "endpoint" => {
+                    let endpoint = string_option(name, &value)?;
+                    let url = Url::parse(&endpoint).map_err(|e| {
+                        Error::from(format!("Invalid S3 endpoint '{}': {}", endpoint, e))
+                    })?;
+                    s3.endpoint = Some(url);
+                }
+                "addressing_style" => {
+                    s3.addressing_style = match string_option(name, &value)?.as_str() {
+                        "path" => Some(AddressingStyle::Path),
+                        "virtual" => Some(AddressingStyle::Virtual),
+                        "auto" => None,
+                        other => {
+                            return Err(Error::from(format!(
+                                "S3 option 'addressing_style' must be \"auto\", \"path\" or \"virtual\", not \"{}\"",
+                                other
+                            )));
+                        }
+                    }
+                }
+                "s3_express" => s3.s3_express = bool_option(name, &value)?,
+                "allow_http" => s3.allow_http = Some(bool_option(name, &value)?),
+                "profile" => s3.profile = Some(string_option(name, &value)?),
+                "role_arn" => s3.role_arn = Some(string_option(name, &value)?),
+                "external_id" => s3.external_id = Some(string_option(name, &value)?),
+                "role_session_name" => s3.role_session_name = Some(string_option(name, &value)?),
+                "role_session_duration" => {
+                    let seconds = value
+                        .as_real()
+                        .or_else(|| value.as_integer().map(f64::from))
+                        .filter(|n| n.fract() == 0.0 && *n >= 0.0)
+                        .ok_or_else(|| {
+                            Error::from(
+                                "S3 option 'role_session_duration' must be a whole number of seconds",
+                            )
+                        })?;
+                    s3.role_session_duration = Some(seconds as u64);
+                }
+                "server_side_encryption" => {
+                    s3.server_side_encryption = Some(string_option(name, &value)?)
+                }
+                "sse_kms_key_id" => s3.sse_kms_key_id = Some(string_option(name, &value)?),
+                "sse_bucket_key" => s3.sse_bucket_key = Some(bool_option(name, &value)?),
+                "sse_customer_key" => s3.sse_customer_key = Some(string_option(name, &value)?),
+                other => {
+                    return Err(Error::from(format!("Unknown S3 option: '{}'", other)));
+                }
+            }
+        }
+        Ok(s3)
+    }
+
+    /// Check that the settings are consistent with each other
+    fn validate(&self) -> Result<()> {
+        if self.access_key_id.is_some() != self.secret_access_key.is_some() {
+            return Err(Error::from(
+                "S3 options 'access_key_id' and 'secret_access_key' must be given together",
+            ));
+        }
+        if self.session_token.is_some() && self.access_key_id.is_none() {
+            return Err(Error::from(
+                "S3 option 'session_token' requires 'access_key_id' and 'secret_access_key'",
+            ));
+        }
+
+        if let Some(endpoint) = &self.endpoint {
+            match endpoint.scheme() {
+                "https" => {}
+                "http" if self.allow_http == Some(true) => {}
+                "http" => {
+                    return Err(Error::from(format!(
+                        "S3 endpoint '{}' uses http://; set allow_http = TRUE to allow unencrypted requests",
+                        endpoint
+                    )));
+                }
+                other => {
+                    return Err(Error::from(format!(
+                        "S3 endpoint '{}' must use http:// or https://, not {}://",
+                        endpoint, other
+                    )));
+                }
+            }
+            if endpoint.host_str().is_none_or(str::is_empty) {
+                return Err(Error::from(format!(
+                    "S3 endpoint '{}' has no host",
+                    endpoint
+                )));
+            }
+            if endpoint.query().is_some() || endpoint.fragment().is_some() {
+                return Err(Error::from(format!(
+                    "S3 endpoint '{}' must not have a query or fragment",
+                    endpoint
+                )));
+            }
+        }
+
+        if self.s3_express {
+            if self.region.is_none() {
+                return Err(Error::from(
+                    "S3 Express One Zone requires 'region', the region of the directory bucket",
+                ));
+            }
+            if self.addressing_style == Some(AddressingStyle::Path) {
+                return Err(Error::from(
+                    "S3 Express One Zone directory buckets only support virtual-hosted addressing",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The settings as delta-rs storage options
+    fn into_options(self) -> Vec<(&'static str, String)> {
+        let flag = |b: bool| if b { "true" } else { "false" }.to_string();
+        let mut options = Vec::new();
+        if let Some(key) = self.access_key_id {
+            options.push(("aws_access_key_id", key));
+        }
+        if let Some(secret) = self.secret_access_key {
+            options.push(("aws_secret_access_key", secret));
+        }
+        if let Some(token) = self.session_token {
+            options.push(("aws_session_token", token));
+        }
+        if let Some(region) = self.region {
+            options.push(("aws_region", region));
+        }
+        if let Some(endpoint) = self.endpoint {
+            options.push((
+                "aws_endpoint_url",
+                endpoint.as_str().trim_end_matches('/').to_string(),
+            ));
+        }
+        if let Some(style) = self.addressing_style {
+            options.push((
+                "aws_virtual_hosted_style_request",
+                flag(style == AddressingStyle::Virtual),
+            ));
+        }
+        if self.s3_express {
+            options.push(("aws_s3_express", flag(true)));
+        }
+        if let Some(allow) = self.allow_http {
+            options.push(("aws_allow_http", flag(allow)));
+        }
+        if let Some(profile) = self.profile {
+            options.push((aws::PROFILE, profile));
+        }
+        if let Some(arn) = self.role_arn {
+            options.push((aws::ROLE_ARN, arn));
+        }
+        if let Some(id) = self.external_id {
+            options.push((aws::EXTERNAL_ID, id));
+        }
+        if let Some(name) = self.role_session_name {
+            options.push((aws::SESSION_NAME, name));
+        }
+        if let Some(seconds) = self.role_session_duration {
+            options.push((aws::SESSION_DURATION, seconds.to_string()));
+        }
+
+        // A KMS key or customer key implies its encryption type
+        let sse = self.server_side_encryption.or_else(|| {
+            if self.sse_kms_key_id.is_some() || self.sse_bucket_key.is_some() {
+                Some("aws:kms".to_string())
+            } else {
+                self.sse_customer_key.as_ref().map(|_| "sse-c".to_string())
+            }
+        });
+        if let Some(sse) = sse {
+            options.push((aws::SSE, sse));
+        }
+        if let Some(key) = self.sse_kms_key_id {
+            options.push((aws::SSE_KMS_KEY_ID, key));
+        }
+        if let Some(enabled) = self.sse_bucket_key {
+            options.push((aws::SSE_BUCKET_KEY, flag(enabled)));
+        }
+        if let Some(key) = self.sse_customer_key {
+            options.push((aws::SSE_CUSTOMER_KEY, key));
+        }
+        options
+    }
+}
+
+/// Append the additional options given to a builder to its typed options
+///
+/// Additional options are passed on as given, and may not repeat an option
+/// set by the typed settings.
+fn with_extra_options(typed: Vec<(&'static str, Robj)>, extra: &List, store: &str) -> Result<List> {
+    let mut pairs: Vec<(String, Robj)> = typed
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+    for (name, value) in extra.iter() {
+        if name.is_empty() {
+            return Err(Error::from("Additional storage options must be named"));
+        }
+        let key = name.to_ascii_lowercase();
+        if pairs.iter().any(|(set, _)| *set == key) {
+            return Err(Error::from(format!(
+                "Storage option '{}' is already set by a typed {} option",
+                name, store
+            )));
+        }
+        let value = value.as_str().ok_or_else(|| {
+            Error::from(format!("Storage option '{}' must be a single string", name))
+        })?;
+        pairs.push((name.to_string(), value.into_robj()));
+    }
+    Ok(List::from_pairs(pairs))
+}
+
+/// Build S3 storage options from typed settings
+///
+/// Returns a named list of strings for `storage_options`.
+///
+/// @param options Named list of typed S3 settings
+/// @param extra Named list of additional storage options
+#[extendr]
+pub fn s3_storage_options_build(options: List, extra: List) -> Result<List> {
+    let s3 = S3Options::from_list(&options)?;
+    s3.validate()?;
+
+    let typed = s3.into_options();
+    let map: HashMap<String, String> = typed
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect();
+    aws::validate_options(&map).map_err(|e| Error::from(e.to_string()))?;
+
+    let typed = typed
+        .into_iter()
+        .map(|(key, value)| (key, value.into_robj()))
+        .collect();
+    with_extra_options(typed, &extra, "S3")
+}
+
+// ============================================================================
+// Azure
+// ============================================================================
+
+/// How requests to Azure are authorized
+#[derive(Clone, Copy, PartialEq)]
+enum AzureAuth {
+    /// Whatever object_store finds: keys and tokens from the environment,
+    /// then the managed identity of the host
+    Default,
+    ManagedIdentity,
+    ClientSecret,
+    Sas,
+    AzureCli,
+    AccountKey,
+}
+
+impl AzureAuth {
+    fn parse(auth: &str) -> Result<Self> {
+        match auth {
+            "default" => Ok(AzureAuth::Default),
+            "managed_identity" => Ok(AzureAuth::ManagedIdentity),
+            "client_secret" => Ok(AzureAuth::ClientSecret),
+            "sas" => Ok(AzureAuth::Sas),
+            "azure_cli" => Ok(AzureAuth::AzureCli),
+            "account_key" => Ok(AzureAuth::AccountKey),
+            other => Err(Error::from(format!(
+                "Azure option 'auth' must be one of \"default\", \"managed_identity\", \"client_secret\", \"sas\", \"azure_cli\" or \"account_key\", not \"{}\"",
+                other
+            ))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AzureAuth::Default => "default",
+            AzureAuth::ManagedIdentity => "managed_identity",
+            AzureAuth::ClientSecret => "client_secret",
+            AzureAuth::Sas => "sas",
+            AzureAuth::AzureCli => "azure_cli",
+            AzureAuth::AccountKey => "account_key",
+        }
+    }
+
+    /// The credential settings this mode uses
+    fn settings(self) -> &'static [&'static str] {
+        match self {
+            AzureAuth::Default | AzureAuth::AzureCli => &[],
+            AzureAuth::ManagedIdentity => &["client_id", "msi_resource_id", "msi_endpoint"],
+            AzureAuth::ClientSecret => &["client_id", "client_secret", "tenant_id"],
+            AzureAuth::Sas => &["sas_token"],
+            AzureAuth::AccountKey => &["account_key"],
+        }
+    }
+}
+
+/// Azure settings given to `azure_storage_options()`
+struct AzureOptions {
+    auth: AzureAuth,
+    account_name: Option<String>,
+    /// Credential settings, by name
+    credentials: Vec<(&'static str, Robj)>,
+}
+
+/// Credential settings, with the storage option each one sets
+const AZURE_CREDENTIALS: &[(&str, &str)] = &[
+    ("client_id", "azure_client_id"),
+    ("client_secret", "azure_client_secret"),
+    ("tenant_id", "azure_tenant_id"),
+    ("msi_resource_id", "azure_msi_resource_id"),
+    ("msi_endpoint", "azure_msi_endpoint"),
+    ("sas_token", "azure_storage_sas_key"),
+    ("account_key", "azure_storage_account_key"),
+];
+
+impl AzureOptions {
+    /// Parse Azure settings from a named R list, skipping `NULL` elements
+    fn from_list(options: &List) -> Result<Self> {
+        let mut auth = AzureAuth::Default;
+        let mut account_name = None;
+        let mut credentials = Vec::new();
+        for (name, value) in options.iter() {
+            if value.is_null() {
+                continue;
+            }
+            match name {
+                "auth" => auth = AzureAuth::parse(&azure_string(name, &value)?)?,
+                "account_name" => account_name = Some(azure_string(name, &value)?),
+                // A SAS token may be a function returning the current token
+                "sas_token" if value.is_function() => credentials.push(("sas_token", value)),
+                name => {
+                    let Some((setting, _)) = AZURE_CREDENTIALS.iter().find(|(s, _)| *s == name)
+                    else {
+                        return Err(Error::from(format!("Unknown Azure option: '{}'", name)));
+                    };
+                    azure_string(name, &value)?;
+                    credentials.push((setting, value));
+                }
+            }
+        }
+        Ok(AzureOptions {
+            auth,
+            account_name,
+            credentials,
+        })
+    }
+
+    fn has(&self, setting: &str) -> bool {
+        self.credentials.iter().any(|(s, _)| *s == setting)
+    }
+
+    /// Check that the credentials given are those the auth mode uses
+    fn validate(&self) -> Result<()> {
+        let used = self.auth.settings();
+        if let Some((unused, _)) = self.credentials.iter().find(|(s, _)| !used.contains(s)) {
+            return Err(Error::from(format!(
+                "Azure option '{}' is not used with auth = \"{}\"",
+                unused,
+                self.auth.name()
+            )));
+        }
+        let required: &[&str] = match self.auth {
+            AzureAuth::ClientSecret => &["client_id", "client_secret", "tenant_id"],
+            AzureAuth::Sas => &["sas_token"],
+            AzureAuth::AccountKey => &["account_key"],
+            _ => &[],
+        };
+        if let Some(missing) = required.iter().find(|s| !self.has(s)) {
+            return Err(Error::from(format!(
+                "Azure auth = \"{}\" requires '{}'",
+                self.auth.name(),
+                missing
+            )));
+        }
+        if self.has("msi_resource_id") && self.has("client_id") {
+            return Err(Error::from(
+                "Azure options 'client_id' and 'msi_resource_id' both select a user-assigned identity; give only one",
+            ));
+        }
+        if let Some((_, endpoint)) = self.credentials.iter().find(|(s, _)| *s == "msi_endpoint") {
+            let endpoint = endpoint.as_str().unwrap_or_default();
+            Url::parse(endpoint).map_err(|e| {
+                Error::from(format!(
+                    "Invalid managed identity endpoint '{}': {}",
+                    endpoint, e
+                ))
+            })?;
+        }
+        // Check the token now rather than on the first request
+        if let Some((_, sas)) = self.credentials.iter().find(|(s, _)| *s == "sas_token") {
+            storage_option_value("azure_storage_sas_key", sas)?;
+        }
+        Ok(())
+    }
+
+    /// The settings as delta-rs storage options
+    fn into_options(self) -> Vec<(&'static str, Robj)> {
+        let mut options = Vec::new();
+        if let Some(account) = self.account_name {
+            options.push(("azure_storage_account_name", account.into_robj()));
+        }
+        if self.auth == AzureAuth::AzureCli {
+            options.push(("azure_use_azure_cli", "true".into_robj()));
+        }
+        for (setting, value) in self.credentials {
+            let key = AZURE_CREDENTIALS
+                .iter()
+                .find(|(s, _)| *s == setting)
+                .map(|(_, key)| *key)
+                .unwrap();
+            options.push((key, value));
+        }
+        options
+    }
+}
+
+fn azure_string(name: &str, value: &Robj) -> Result<String> {
+    value
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| {
+            Error::from(format!(
+                "Azure option '{}' must be a non-empty string",
+                name
+            ))
+        })
+}
+
+/// Whether `key` is one of the storage options holding an Azure SAS token
+pub(crate) fn is_sas_key(key: &str) -> bool {
+    matches!(
+        key.to_ascii_lowercase().as_str(),
+        "azure_storage_sas_key" | "azure_storage_sas_token" | "sas_key" | "sas_token"
+    )
+}
+
+/// The decoded query parameters of a SAS token
+///
+/// A `+` is kept as is rather than read as a space, as signatures are base64.
+pub(crate) fn sas_token_fields(token: &str) -> Vec<(String, String)> {
+    let query = token.trim_start_matches('?').replace('+', "%2B");
+    url::form_urlencoded::parse(query.as_bytes())
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect()
+}
+
+/// When a SAS token expires, from its `se` field
+pub(crate) fn sas_token_expiry(
+    fields: &[(String, String)],
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let expiry = &fields.iter().find(|(k, _)| k == "se")?.1;
+    // Expiry times may omit the time or the seconds
+    chrono::DateTime::parse_from_rfc3339(expiry)
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .ok()
+        .or_else(|| {
+            chrono::NaiveDate::parse_from_str(expiry, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .map(|t| t.and_utc())
+        })
+        .or_else(|| {
+            chrono::NaiveDateTime::parse_from_str(expiry, "%Y-%m-%dT%H:%MZ")
+                .ok()
+                .map(|t| t.and_utc())
+        })
+}
+
+/// Check that a SAS token is signed and has not expired
+fn check_sas_token(token: &str) -> Result<()> {
+    let fields = sas_token_fields(token);
+    if !fields.iter().any(|(k, _)| k == "sig") {
+        return Err(Error::from(
+            "Azure SAS token has no signature ('sig'); pass the query string of the SAS URL",
+        ));
+    }
+    if let Some(expires) = sas_token_expiry(&fields).filter(|t| *t <= chrono::Utc::now()) {
+        return Err(Error::from(format!(
+            "Azure SAS token expired at {}",
+            expires.to_rfc3339()
+        )));
+    }
+    Ok(())
+}
+
+/// Get the value of a storage option given in R
+///
+/// Functions are called for the current value, so that a SAS token callback
+/// supplies a fresh token each time a table is opened (and, through
+/// [crate::azure], before that token expires). SAS tokens are checked
+/// before they are used, so an expired token is reported as such instead of
+/// as a failed request. Options that are not strings are skipped.
+pub(crate) fn storage_option_value(key: &str, value: &Robj) -> Result<Option<String>> {
+    let value = match value.as_function() {
+        Some(callback) => {
+            let current = callback.call(pairlist!()).map_err(|e| {
+                Error::from(format!("Storage option '{}' callback failed: {}", key, e))
+            })?;
+            let Some(current) = current.as_str().filter(|s| !s.is_empty()) else {
+                return Err(Error::from(format!(
+                    "Storage option '{}' callback must return a single string",
+                    key
+                )));
+            };
+            current.to_string()
+        }
+        None => match value.as_str() {
+            Some(value) => value.to_string(),
+            None => return Ok(None),
+        },
+    };
+    if is_sas_key(key) {
+        check_sas_token(&value)?;
+    }
+    Ok(Some(value))
+}
+
+/// Build Azure storage options from typed settings
+///
+/// Returns a named list for `storage_options`, whose SAS token may be a
+/// function returning the current token.
+///
+/// @param options Named list of typed Azure settings
+/// @param extra Named list of additional storage options
+#[extendr]
+pub fn azure_storage_options_build(options: List, extra: List) -> Result<List> {
+    let azure = AzureOptions::from_list(&options)?;
+    azure.validate()?;
+    with_extra_options(azure.into_options(), &extra, "Azure")
+}
+
+// ============================================================================
+// Google Cloud Storage
+// ============================================================================
+
+/// Build GCS storage options from typed settings
+///
+/// Returns a named list of strings for `storage_options`. Impersonation and
+/// workload identity federation are carried out by [crate::gcs].
+///
+/// @param options Named list of typed GCS settings
+/// @param extra Named list of additional storage options
+#[extendr]
+pub fn gcs_storage_options_build(options: List, extra: List) -> Result<List> {
+    let gcs_string = |name: &str, value: &Robj| -> Result<String> {
+        value
+            .as_str()
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| Error::from(format!("GCS option '{}' must be a non-empty string", name)))
+    };
+    let gcs_strings = |name: &str, value: &Robj| -> Result<Vec<String>> {
+        value
+            .as_str_vector()
+            .filter(|v| !v.is_empty() && v.iter().all(|s| !s.is_empty()))
+            .map(|v| v.into_iter().map(str::to_string).collect())
+            .ok_or_else(|| Error::from(format!("GCS option '{}' must be a character vector", name)))
+    };
+
+    let mut typed: Vec<(&'static str, String)> = Vec::new();
+    for (name, value) in options.iter() {
+        if value.is_null() {
+            continue;
+        }
+        let (key, value) = match name {
+            "service_account_path" => {
+                let path = gcs_string(name, &value)?;
+                if !std::path::Path::new(&path).is_file() {
+                    return Err(Error::from(format!(
+                        "Service account key file not found: {}",
+                        path
+                    )));
+                }
+                ("google_service_account_path", path)
+            }
+            "impersonate_service_account" => {
+                (gcs::IMPERSONATE_SERVICE_ACCOUNT, gcs_string(name, &value)?)
+            }
+            "delegates" => (
+                gcs::IMPERSONATE_DELEGATES,
+                gcs_strings(name, &value)?.join(","),
+            ),
+            "lifetime" => {
+                let seconds = value
+                    .as_real()
+                    .or_else(|| value.as_integer().map(f64::from))
+                    .filter(|n| n.fract() == 0.0)
+                    .ok_or_else(|| {
+                        Error::from("GCS option 'lifetime' must be a whole number of seconds")
+                    })?;
+                (gcs::IMPERSONATE_LIFETIME, format!("{}", seconds))
+            }
+            "scopes" => (gcs::SCOPES, gcs_strings(name, &value)?.join(" ")),
+            "audience" => (gcs::WORKLOAD_IDENTITY_AUDIENCE, gcs_string(name, &value)?),
+            "subject_token_file" => {
+                let path = gcs_string(name, &value)?;
+                if !std::path::Path::new(&path).is_file() {
+                    return Err(Error::from(format!(
+                        "Subject token file not found: {}",
+                        path
+                    )));
+                }
+                (gcs::SUBJECT_TOKEN_FILE, path)
+            }
+            "subject_token_type" => (gcs::SUBJECT_TOKEN_TYPE, gcs_string(name, &value)?),
+            other => {
+                return Err(Error::from(format!("Unknown GCS option: '{}'", other)));
+            }
+        };
+        typed.push((key, value));
+    }
+
+    let map: HashMap<String, String> = typed
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect();
+    gcs::validate_options(&map).map_err(|e| Error::from(e.to_string()))?;
+
+    let typed = typed
+        .into_iter()
+        .map(|(key, value)| (key, value.into_robj()))
+        .collect();
+    with_extra_options(typed, &extra, "GCS")
+}
+
+// ============================================================================
+// Unity Catalog
+// ============================================================================
+
+/// Build Unity Catalog storage options from typed settings
+///
+/// Returns a named list for `storage_options`, whose token and client secret
+/// may be functions returning their current value. Requests to the workspace
+/// and credential vending are carried out by [crate::unity].
+///
+/// @param options Named list of typed Unity Catalog settings
+/// @param extra Named list of additional storage options
+#[extendr]
+pub fn unity_catalog_storage_options_build(options: List, extra: List) -> Result<List> {
+    let uc_string = |name: &str, value: &Robj| -> Result<String> {
+        value
+            .as_str()
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "Unity Catalog option '{}' must be a non-empty string",
+                    name
+                ))
+            })
+    };
+    let uc_secret = |name: &str, value: Robj| -> Result<Robj> {
+        if value.is_function() {
+            Ok(value)
+        } else {
+            uc_string(name, &value).map(|s| s.into_robj())
+        }
+    };
+
+    let mut auth = "default".to_string();
+    let mut typed: Vec<(&'static str, Robj)> = Vec::new();
+    let mut credentials: Vec<&str> = Vec::new();
+    for (name, value) in options.iter() {
+        if value.is_null() {
+            continue;
+        }
+        match name {
+            "workspace_url" => {
+                let url = unity::workspace_url(&uc_string(name, &value)?).map_err(Error::from)?;
+                typed.push((unity::HOST, url.into_robj()));
+            }
+            "auth" => auth = uc_string(name, &value)?,
+            "token" => {
+                typed.push((unity::TOKEN, uc_secret(name, value)?));
+                credentials.push(name);
+            }
+            "client_id" => {
+                typed.push((unity::CLIENT_ID, uc_string(name, &value)?.into_robj()));
+                credentials.push(name);
+            }
+            "client_secret" => {
+                typed.push((unity::CLIENT_SECRET, uc_secret(name, value)?));
+                credentials.push(name);
+            }
+            "access" => {
+                let access = uc_string(name, &value)?;
+                access.parse::<unity::Access>().map_err(Error::from)?;
+                typed.push((unity::ACCESS, access.into_robj()));
+            }
+            other => {
+                return Err(Error::from(format!(
+                    "Unknown Unity Catalog option: '{}'",
+                    other
+                )));
+            }
+        }
+    }
+
+    // Credentials of the other mode would be silently ignored
+    let auth_type = match auth.as_str() {
+        "default" => None,
+        "pat" => Some(unity::AuthType::Pat),
+        "oauth_m2m" => Some(unity::AuthType::OAuthM2m),
+        other => {
+            return Err(Error::from(format!(
+                "Unity Catalog auth must be \"default\", \"pat\" or \"oauth_m2m\", not \"{}\"",
+                other
+            )));
+        }
+    };
+    let used: &[&str] = match auth_type {
+        Some(unity::AuthType::Pat) => &["token"],
+        Some(unity::AuthType::OAuthM2m) => &["client_id", "client_secret"],
+        None => &["token", "client_id", "client_secret"],
+    };
+    if let Some(unused) = credentials.iter().find(|c| !used.contains(c)) {
+        return Err(Error::from(format!(
+            "Unity Catalog option '{}' is not used with auth = \"{}\"",
+            unused, auth
+        )));
+    }
+    if auth_type.is_none() && credentials.contains(&"token") && credentials.len() > 1 {
+        return Err(Error::from(
+            "Give either a Unity Catalog token or a service principal's client id and secret, or choose one with 'auth'",
+        ));
+    }
+    match auth_type {
+        Some(unity::AuthType::Pat) => typed.push((unity::AUTH_TYPE, "pat".into_robj())),
+        Some(unity::AuthType::OAuthM2m) => typed.push((unity::AUTH_TYPE, "oauth-m2m".into_robj())),
+        None => {}
+    }
+    with_extra_options(typed, &extra, "Unity Catalog")
+}
+
+// ============================================================================
+// Proxies
+// ============================================================================
+
+/// Storage options of the proxy, as read by object_store for every store
+const PROXY_URL: &str = "proxy_url";
+const PROXY_EXCLUDES: &str = "proxy_excludes";
+const PROXY_CA_CERTIFICATE: &str = "proxy_ca_certificate";
+
+/// Look up an option by its name or the name with a store prefix, as
+/// object_store accepts both (`proxy_url`, `aws_proxy_url`, ...)
+fn client_option<'a>(options: &'a HashMap<String, String>, name: &str) -> Option<&'a String> {
+    options.iter().find_map(|(key, value)| {
+        let key = key.to_ascii_lowercase();
+        let key = ["aws_", "azure_", "google_"]
+            .iter()
+            .find_map(|prefix| key.strip_prefix(prefix))
+            .unwrap_or(&key);
+        (key == name).then_some(value)
+    })
+}
+
+/// Create an HTTP client that goes through the proxy of the storage options
+///
+/// Used for the requests deltaR sends itself, such as token exchanges, so
+/// that they reach the same endpoints as the object store requests.
+pub(crate) fn http_client(
+    options: &HashMap<String, String>,
+) -> std::result::Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(url) = client_option(options, PROXY_URL) {
+        let mut proxy =
+            reqwest::Proxy::all(url).map_err(|e| format!("Invalid proxy URL {}: {}", url, e))?;
+        if let Some(excludes) = client_option(options, PROXY_EXCLUDES) {
+            proxy = proxy.no_proxy(reqwest::NoProxy::from_string(excludes));
+        }
+        builder = builder.proxy(proxy);
+        if let Some(pem) = client_option(options, PROXY_CA_CERTIFICATE) {
+            for certificate in reqwest::Certificate::from_pem_bundle(pem.as_bytes())
+                .map_err(|e| format!("Invalid proxy CA certificate: {}", e))?
+            {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
+
+/// Build proxy storage options from typed settings
+///
+/// Returns a named list of strings for `storage_options`, understood by the
+/// S3, Azure and GCS object stores alike.
+///
+/// @param options Named list of typed proxy settings
+#[extendr]
+pub fn proxy_storage_options_build(options: List) -> Result<List> {
+    let mut proxy_url = None;
+    let mut excludes = None;
+    let mut ca_bundle = None;
+    for (name, value) in options.iter() {
+        if value.is_null() {
+            continue;
+        }
+        match name {
+            "proxy_url" => {
+                let url = value
+                    .as_str()
+                    .ok_or_else(|| Error::from("'proxy_url' must be a single string"))?;
+                let parsed = Url::parse(url)
+                    .map_err(|e| Error::from(format!("Invalid proxy URL '{}': {}", url, e)))?;
+                if !matches!(parsed.scheme(), "http" | "https") {
+                    return Err(Error::from(format!(
+                        "Proxy URL '{}' must use http:// or https://",
+                        url
+                    )));
+                }
+                if parsed.host_str().is_none_or(str::is_empty) {
+                    return Err(Error::from(format!("Proxy URL '{}' has no host", url)));
+                }
+                proxy_url = Some(url.to_string());
+            }
+            "no_proxy" => {
+                let hosts = value
+                    .as_str_vector()
+                    .ok_or_else(|| Error::from("'no_proxy' must be a character vector"))?;
+                if let Some(bad) = hosts
+                    .iter()
+                    .find(|h| h.is_empty() || h.contains("://") || h.contains(char::is_whitespace))
+                {
+                    return Err(Error::from(format!(
+                        "'no_proxy' entries must be host names, domains or IP ranges, not '{}'",
+                        bad
+                    )));
+                }
+                excludes = Some(hosts.join(","));
+            }
+            "ca_bundle" => {
+                let path = value
+                    .as_str()
+                    .ok_or_else(|| Error::from("'ca_bundle' must be a single string"))?;
+                let pem = std::fs::read_to_string(path)
+                    .map_err(|e| Error::from(format!("Cannot read CA bundle {}: {}", path, e)))?;
+                let certificates = reqwest::Certificate::from_pem_bundle(pem.as_bytes())
+                    .map_err(|e| Error::from(format!("Invalid CA bundle {}: {}", path, e)))?;
+                if certificates.is_empty() {
+                    return Err(Error::from(format!(
+                        "CA bundle {} holds no PEM certificate",
+                        path
+                    )));
+                }
+                ca_bundle = Some(pem);
+            }
+            other => {
+                return Err(Error::from(format!("Unknown proxy option: '{}'", other)));
+            }
+        }
+    }
+
+    let Some(proxy_url) = proxy_url else {
+        if excludes.is_some() || ca_bundle.is_some() {
+            return Err(Error::from(
+                "'no_proxy' and 'ca_bundle' apply to connections through 'proxy_url'; \
+                 without a proxy, add the CA to the system certificate store",
+            ));
+        }
+        return Ok(List::new(0));
+    };
+    let mut pairs = vec![(PROXY_URL, proxy_url)];
+    if let Some(excludes) = excludes {
+        pairs.push((PROXY_EXCLUDES, excludes));
+    }
+    if let Some(pem) = ca_bundle {
+        pairs.push((PROXY_CA_CERTIFICATE, pem));
+    }
+    Ok(List::from_pairs(
+        pairs
+            .into_iter()
+            .map(|(key, value)| (key, value.into_robj())),
+    ))
+}
+
+// ============================================================================
+// Introspection
+// ============================================================================
+
+/// Parts of option names whose values are credentials
+const SECRET_OPTION_PARTS: &[&str] = &[
+    "secret",
+    "token",
+    "password",
+    "key",
+    "sas",
+    "signature",
+    "credential",
+];
+
+/// Value shown in place of a credential
+const REDACTED: &str = "<redacted>";
+
+/// The value of a storage option as shown, with credentials redacted
+fn shown_option_value(key: &str, value: &str) -> String {
+    let key = key.to_ascii_lowercase();
+    if SECRET_OPTION_PARTS.iter().any(|part| key.contains(part)) {
+        REDACTED.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// How commits to a log store are kept from overwriting each other
+///
+/// Most stores create a log file only if it does not exist yet. Local tables
+/// with `locking_provider = "file"` hold a file lock instead, S3 tables may
+/// lock through DynamoDB or copy-if-not-exists, and S3 tables allowed to
+/// rename unsafely have no protection against concurrent writers at all.
+fn locking_mode(log_store: &dyn LogStore) -> &'static str {
+    let options = &log_store.config().options().raw;
+    match log_store.name().as_str() {
+        "LockingLogStore" => "file_lock",
+        "S3DynamoDbLogStore" => "dynamodb",
+        "S3LogStore" => "copy_if_not_exists",
+        _ => {
+            let scheme = log_store.config().location().scheme();
+            let unsafe_rename = aws::option(options, "aws_s3_allow_unsafe_rename")
+                .cloned()
+                .or_else(|| std::env::var("AWS_S3_ALLOW_UNSAFE_RENAME").ok())
+                .is_some_and(|v| {
+                    ["1", "true", "on", "yes", "y"].contains(&v.to_ascii_lowercase().as_str())
+                });
+            if matches!(scheme, "s3" | "s3a") && unsafe_rename {
+                "unsafe_rename"
+            } else {
+                "put_if_absent"
+            }
+        }
+    }
+}
+
+/// Describe the storage of a table
+///
+/// Returns the URI scheme of the table, the name of its log store, the
+/// storage options it was opened with (credentials redacted) and the
+/// locking mode of its commits.
+pub(crate) fn storage_info(log_store: &dyn LogStore) -> List {
+    let mut options: Vec<(&String, &String)> = log_store.config().options().raw.iter().collect();
+    options.sort();
+    let options = List::from_pairs(
+        options
+            .into_iter()
+            .map(|(key, value)| (key.as_str(), shown_option_value(key, value).into_robj())),
+    );
+    list!(
+        scheme = log_store.config().location().scheme(),
+        log_store = log_store.name(),
+        locking = locking_mode(log_store),
+        storage_options = options
+    )
+}
+
+extendr_module! {
+    mod storage;
+    fn s3_storage_options_build;
+    fn azure_storage_options_build;
+    fn gcs_storage_options_build;
+    fn unity_catalog_storage_options_build;
+    fn proxy_storage_options_build;
+}
@@ -0,0 +1,418 @@
+//! Multi-operation transactions
+//!
+//! Stages several operations on a table (deletes, appends and table
+//! property changes) and commits all of their actions as a single version,
+//! so readers never see the table between two of them. The operations are
+//! built in R and passed to a single Rust function, as for MERGE.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use deltalake::arrow::array::{Array, Int64Array, StringArray};
+use deltalake::arrow::datatypes::DataType;
+use deltalake::datafusion::functions_aggregate::expr_fn::count;
+use deltalake::datafusion::prelude::{cast, ident, lit, Expr};
+use deltalake::delta_datafusion::{DeltaDataChecker, DeltaScanConfigBuilder, DeltaTableProvider};
+use deltalake::kernel::schema::cast_record_batch;
+use deltalake::kernel::transaction::{CommitBuilder, PROTOCOL};
+use deltalake::kernel::{Action, LogicalFileView, MetadataExt};
+use deltalake::protocol::{DeltaOperation, SaveMode};
+use deltalake::writer::{DeltaWriter, RecordBatchWriter};
+use deltalake::{DeltaTable, Path};
+use extendr_api::prelude::*;
+use futures::TryStreamExt;
+
+use crate::commit::{
+    append_metrics, check_append_only, commit_properties, new_commit_actions, operation_result,
+    AppTransaction,
+};
+use crate::constraints::with_constraint_checks;
+use crate::context::{ErrorContext, OperationResult, RaisedError};
+use crate::read::{file_view_to_add, open_table, session_context};
+use crate::write::reader_from_robj;
+use crate::{block_on, debug};
+
+/// Table properties whose values change the table protocol
+///
+/// Setting them may need a protocol upgrade, which a transaction does not
+/// commit, so they cannot be staged.
+const PROTOCOL_PROPERTIES: &[&str] = &[
+    "delta.minReaderVersion",
+    "delta.minWriterVersion",
+    "delta.enableChangeDataFeed",
+    "delta.enableDeletionVectors",
+];
+
+/// The operations staged in a transaction, grouped by kind
+#[derive(Default)]
+struct StagedOperations {
+    delete_predicates: Vec<String>,
+    appends: Vec<Robj>,
+    properties: HashMap<String, String>,
+}
+
+impl StagedOperations {
+    /// Read the operations passed from R
+    ///
+    /// Each element is a list with an `op` of `"delete"` (with a
+    /// `predicate`), `"append"` (with `data`) or `"set_properties"` (with a
+    /// named list of `properties`).
+    fn from_list(operations: &List) -> Result<Self> {
+        let mut staged = StagedOperations::default();
+        for (_, operation) in operations.iter() {
+            let operation = operation
+                .as_list()
+                .ok_or_else(|| Error::from("Each transaction operation must be a list"))?;
+            let fields: HashMap<&str, Robj> = operation.iter().collect();
+            let op = fields.get("op").and_then(|op| op.as_str()).unwrap_or("");
+            match op {
+                "delete" => {
+                    let predicate = fields
+                        .get("predicate")
+                        .and_then(|p| p.as_str())
+                        .ok_or_else(|| Error::from("A delete needs a predicate"))?;
+                    staged.delete_predicates.push(predicate.to_string());
+                }
+                "append" => {
+                    let data = fields
+                        .get("data")
+                        .ok_or_else(|| Error::from("An append needs data"))?;
+                    staged.appends.push(data.clone());
+                }
+                "set_properties" => {
+                    let properties = fields
+                        .get("properties")
+                        .and_then(|p| p.as_list())
+                        .ok_or_else(|| Error::from("Table properties must be a named list"))?;
+                    for (key, value) in properties.iter() {
+                        let value = value.as_str().ok_or_else(|| {
+                            Error::from(format!("Table property '{}' must be a string", key))
+                        })?;
+                        if PROTOCOL_PROPERTIES.contains(&key) {
+                            return Err(Error::from(format!(
+                                "Table property '{}' changes the table protocol and cannot be \
+                                 set in a transaction",
+                                key
+                            )));
+                        }
+                        staged.properties.insert(key.to_string(), value.to_string());
+                    }
+                }
+                other => {
+                    return Err(Error::from(format!(
+                        "Unknown transaction operation: '{}'",
+                        other
+                    )))
+                }
+            }
+        }
+        Ok(staged)
+    }
+
+    /// The combined predicate of all deletes
+    fn delete_predicate(&self) -> Option<String> {
+        match self.delete_predicates.len() {
+            0 => None,
+            1 => Some(self.delete_predicates[0].clone()),
+            _ => Some(
+                self.delete_predicates
+                    .iter()
+                    .map(|p| format!("({})", p))
+                    .collect::<Vec<_>>()
+                    .join(" OR "),
+            ),
+        }
+    }
+}
+
+/// Files holding rows that match a delete predicate
+struct DeleteCandidates {
+    files: Vec<LogicalFileView>,
+    num_deleted_rows: u64,
+}
+
+/// Find the files with rows matching `predicate`, and count those rows
+fn delete_candidates(table: &DeltaTable, predicate: &str) -> Result<DeleteCandidates> {
+    let snapshot = table
+        .snapshot()
+        .map_err(|e| Error::from(e.to_string()))?
+        .snapshot()
+        .clone();
+    let config = DeltaScanConfigBuilder::new()
+        .with_file_column(true)
+        .build(&snapshot)
+        .map_err(|e| Error::from(e.to_string()))?;
+    let file_column = config
+        .file_column_name
+        .clone()
+        .ok_or_else(|| Error::from("Failed to add the file path column to the scan"))?;
+    let provider = DeltaTableProvider::try_new(snapshot, table.log_store(), config)
+        .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+
+    let ctx = session_context(None);
+    let df = ctx
+        .read_table(Arc::new(provider))
+        .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+    let filter = df
+        .parse_sql_expr(predicate)
+        .map_err(|e| Error::from(format!("Invalid predicate '{}': {}", predicate, e)))?;
+
+    let batches = block_on(async {
+        df.filter(filter)?
+            .aggregate(
+                vec![cast(ident(&file_column), DataType::Utf8).alias(&file_column)],
+                vec![count(lit(1)).alias("num_rows")],
+            )?
+            .collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to find the rows to delete: {}", e)))?;
+
+    // The path column holds the (URL encoded) path of the add action
+    let mut matched: HashSet<String> = HashSet::new();
+    let mut num_deleted_rows = 0;
+    for batch in &batches {
+        let paths = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| Error::from("Unexpected file path column type"))?;
+        let num_rows = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| Error::from("Unexpected row count type"))?;
+        for i in 0..batch.num_rows() {
+            let path = Path::from_url_path(paths.value(i))
+                .map_err(|e| Error::from(format!("Invalid file path: {}", e)))?;
+            matched.insert(path.to_string());
+            num_deleted_rows += num_rows.value(i) as u64;
+        }
+    }
+
+    let files: Vec<LogicalFileView> = block_on(async {
+        table
+            .get_active_add_actions_by_partitions(&[])
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to list table files: {}", e)))?;
+    let files = files
+        .into_iter()
+        .filter(|file| matched.contains(file.path().as_ref()))
+        .collect();
+
+    Ok(DeleteCandidates {
+        files,
+        num_deleted_rows,
+    })
+}
+
+/// Commit the operations of a transaction as a single table version
+///
+/// Deletes apply to the rows in the table when the transaction starts, never
+/// to rows appended by the same transaction: the files holding matching
+/// rows are rewritten without them. Appended data is checked against the
+/// table's constraints and written into new files. Property changes update
+/// the table metadata. All resulting actions are committed together.
+///
+/// @param table_uri Path to the Delta table
+/// @param operations List of staged operations
+/// @param storage_options Storage backend options (optional)
+/// @param app_transaction Application transaction to record, as list(app_id, version) (optional)
+#[extendr]
+pub fn delta_transaction_commit(
+    table_uri: &str,
+    operations: List,
+    storage_options: Nullable<List>,
+    app_transaction: Nullable<List>,
+) -> OperationResult<List> {
+    let context = ErrorContext::new("TRANSACTION", table_uri);
+    if operations.is_empty() {
+        return Err(Error::from("The transaction has no operations").into());
+    }
+    let staged = StagedOperations::from_list(&operations)?;
+
+    let table = open_table(table_uri, None, &storage_options)?;
+    context.set_version(table.version());
+    if !staged.delete_predicates.is_empty() {
+        check_append_only(&table, "a delete")?;
+    }
+    let app = AppTransaction::from_r(&table, &app_transaction)?;
+    if let Some(app) = app.as_ref().filter(|app| app.skipped) {
+        return Ok(app.skipped_result(&table, "TRANSACTION")?);
+    }
+    let read_version = table.version();
+    let state = table
+        .snapshot()
+        .map_err(|e| Error::from(e.to_string()))?
+        .clone();
+
+    let mut writer = RecordBatchWriter::for_table(&table)
+        .map_err(|e| Error::from(format!("Failed to create writer: {}", e)))?;
+    let target_schema = writer.arrow_schema();
+    let mut actions: Vec<Action> = Vec::new();
+
+    // Deletes: rewrite the files holding matching rows without those rows
+    let delete_predicate = staged.delete_predicate();
+    let mut num_deleted_rows = 0;
+    let mut num_copied_rows = 0;
+    if let Some(predicate) = &delete_predicate {
+        if PROTOCOL.can_read_from(&state).is_err() {
+            return Err(Error::from(
+                "Deletes in a transaction are not supported for tables with deletion \
+             vectors or other reader features",
+            )
+            .into());
+        }
+        // Rewritten files would be read from the change data feed as
+        // deletes of all their rows and inserts of the rows kept
+        if state.table_config().enable_change_data_feed == Some(true) {
+            return Err(Error::from(
+                "Deletes in a transaction are not supported for tables with the change \
+             data feed enabled (delta.enableChangeDataFeed)",
+            )
+            .into());
+        }
+        let candidates = delete_candidates(&table, predicate)?;
+        num_deleted_rows = candidates.num_deleted_rows;
+
+        if !candidates.files.is_empty() {
+            let config = DeltaScanConfigBuilder::new()
+                .build(state.snapshot())
+                .map_err(|e| Error::from(e.to_string()))?;
+            let provider =
+                DeltaTableProvider::try_new(state.snapshot().clone(), table.log_store(), config)
+                    .map_err(|e| Error::from(e.to_string()))?
+                    .with_files(candidates.files.iter().map(file_view_to_add).collect());
+            let df = session_context(None)
+                .read_table(Arc::new(provider))
+                .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+            // Rows for which the predicate is NULL do not match it
+            let keep: Expr = df
+                .parse_sql_expr(predicate)
+                .map_err(|e| Error::from(format!("Invalid predicate '{}': {}", predicate, e)))?
+                .is_not_true();
+
+            num_copied_rows = block_on(async {
+                let mut stream = debug::execute_stream(df.filter(keep)?).await?;
+                let mut rows = 0;
+                while let Some(batch) = stream.try_next().await? {
+                    rows += batch.num_rows() as u64;
+                    let batch = cast_record_batch(&batch, target_schema.clone(), false, false)?;
+                    writer.write(batch).await?;
+                }
+                Ok::<_, deltalake::DeltaTableError>(rows)
+            })?
+            .map_err(|e| Error::from(format!("Failed to rewrite files: {}", e)))?;
+
+            actions.extend(
+                candidates
+                    .files
+                    .iter()
+                    .map(|file| Action::Remove(file.remove_action(true))),
+            );
+        }
+    }
+
+    // Appends: check the data against the table constraints and write it
+    let checker = DeltaDataChecker::new(state.snapshot());
+    let mut num_appended_rows = 0;
+    for data in &staged.appends {
+        let (reader, constraint_report) = with_constraint_checks(reader_from_robj(data)?, &table)?;
+        num_appended_rows += block_on(async {
+            let mut rows = 0;
+            for batch in reader {
+                let batch = cast_record_batch(&batch?, target_schema.clone(), false, false)?;
+                checker.check_batch(&batch).await?;
+                rows += batch.num_rows() as u64;
+                writer.write(batch).await?;
+            }
+            Ok::<_, deltalake::DeltaTableError>(rows)
+        })?
+        .map_err(|e| {
+            match constraint_report
+                .as_ref()
+                .and_then(|report| report.violations())
+            {
+                Some(violations) => violations,
+                None => RaisedError::caused_by(format!("Failed to write data: {}", e), &e),
+            }
+        })?;
+    }
+
+    let adds = block_on(async { writer.flush().await })?
+        .map_err(|e| Error::from(format!("Failed to write data: {}", e)))?;
+    let num_added_files = adds.len();
+    let num_removed_files = actions.len();
+    actions.extend(adds.into_iter().map(Action::Add));
+
+    // Property changes: commit an updated metadata action
+    if !staged.properties.is_empty() {
+        let mut metadata = state.metadata().clone();
+        for (key, value) in &staged.properties {
+            metadata = metadata
+                .add_config_key(key.clone(), value.clone())
+                .map_err(|e| Error::from(format!("Invalid table property '{}': {}", key, e)))?;
+        }
+        actions.push(Action::Metadata(metadata));
+    }
+
+    let operation = match (&delete_predicate, staged.appends.is_empty()) {
+        (Some(predicate), true) => DeltaOperation::Delete {
+            predicate: Some(predicate.clone()),
+        },
+        (Some(predicate), false) => DeltaOperation::Write {
+            mode: SaveMode::Overwrite,
+            partition_by: None,
+            predicate: Some(predicate.clone()),
+        },
+        (None, false) => DeltaOperation::Write {
+            mode: SaveMode::Append,
+            partition_by: None,
+            predicate: None,
+        },
+        (None, true) => DeltaOperation::SetTableProperties {
+            properties: staged.properties.clone(),
+        },
+    };
+
+    // The application transaction is recorded even when the operations
+    // change nothing, so a retry is skipped
+    let mut table = table;
+    if !actions.is_empty() || app.is_some() {
+        let properties = app
+            .as_ref()
+            .map(|app| app.commit_properties())
+            .unwrap_or_else(commit_properties);
+        let commit = block_on(async {
+            CommitBuilder::from(properties)
+                .with_actions(actions)
+                .build(Some(&state), table.log_store(), operation)
+                .await
+        })?
+        .map_err(|e| Error::from(format!("Failed to commit transaction: {}", e)))?;
+        block_on(async { table.load_version(commit.version()).await })?
+            .map_err(|e| Error::from(e.to_string()))?;
+    }
+
+    let metrics = list!(
+        num_operations = operations.len() as i32,
+        num_deleted_rows = num_deleted_rows as f64,
+        num_copied_rows = num_copied_rows as f64,
+        num_appended_rows = num_appended_rows as f64,
+        num_added_files = num_added_files as i32,
+        num_removed_files = num_removed_files as i32
+    );
+    let actions = new_commit_actions(&table, read_version)?;
+    let result = operation_result(&table, &actions, "TRANSACTION", metrics)?;
+    Ok(match &app {
+        Some(app) => append_metrics(result, app.metrics())?,
+        None => result,
+    })
+}
+
+extendr_module! {
+    mod transaction;
+    fn delta_transaction_commit;
+}
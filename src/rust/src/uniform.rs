@@ -0,0 +1,136 @@
+//! Delta UniForm
+//!
+//! Tables with UniForm (`delta.universalFormat.enabledFormats`) also carry
+//! Iceberg metadata, written under `metadata/` by the engines that support
+//! it, so Iceberg readers can query them. deltaR does not write Iceberg
+//! metadata: commits keep the UniForm properties and protocol of the table,
+//! and the Iceberg metadata records the Delta version it was converted from,
+//! so readers can tell when it has fallen behind.
+
+use deltalake::{DeltaTable, ObjectMeta, Path};
+use extendr_api::prelude::*;
+use futures::TryStreamExt;
+
+use crate::block_on;
+
+/// Table property listing the formats UniForm maintains metadata for
+const ENABLED_FORMATS: &str = "delta.universalFormat.enabledFormats";
+
+/// Iceberg table property recording the Delta version the metadata was
+/// converted from
+const DELTA_VERSION_PROPERTY: &str = "delta-version";
+
+/// Formats UniForm is enabled for, e.g. `["iceberg"]`
+pub(crate) fn uniform_formats(table: &DeltaTable) -> Vec<String> {
+    table
+        .state
+        .as_ref()
+        .and_then(|state| {
+            state
+                .metadata()
+                .configuration()
+                .get(ENABLED_FORMATS)
+                .cloned()
+        })
+        .map(|formats| {
+            formats
+                .split(',')
+                .map(|format| format.trim().to_lowercase())
+                .filter(|format| !format.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether data files of the table must be readable by Iceberg readers:
+/// UniForm keeps Iceberg metadata for it, or Iceberg compatibility is on
+pub(crate) fn iceberg_compatible(table: &DeltaTable) -> bool {
+    let Some(state) = table.state.as_ref() else {
+        return false;
+    };
+    let configuration = state.metadata().configuration();
+    uniform_formats(table)
+        .iter()
+        .any(|format| format == "iceberg")
+        || ["delta.enableIcebergCompatV1", "delta.enableIcebergCompatV2"]
+            .iter()
+            .any(|key| configuration.get(*key).is_some_and(|v| v == "true"))
+}
+
+/// UniForm details of a table for `metadata()`, `NULL` without UniForm
+///
+/// Lists the enabled formats, the location of the latest Iceberg metadata
+/// file and the Delta version it was converted from (`NA` when there is no
+/// Iceberg metadata yet).
+pub(crate) fn uniform_metadata(table: &DeltaTable) -> Result<Robj> {
+    let formats = uniform_formats(table);
+    if formats.is_empty() {
+        return Ok(().into_robj());
+    }
+
+    let (location, delta_version) = match latest_iceberg_metadata(table)? {
+        Some(file) => {
+            let version = iceberg_delta_version(table, &file.location)?;
+            let root = table.table_url().as_str().trim_end_matches('/');
+            (Some(format!("{}/{}", root, file.location)), version)
+        }
+        None => (None, None),
+    };
+
+    Ok(list!(
+        enabled_formats = formats,
+        iceberg_metadata_location = location.map_or(Rstr::na(), |l| Rstr::from(l.as_str())),
+        iceberg_delta_version = delta_version
+            .map(|v| Rfloat::from(v as f64))
+            .unwrap_or_else(Rfloat::na)
+    )
+    .into_robj())
+}
+
+/// The newest Iceberg metadata file under `metadata/`
+///
+/// Metadata files are named after their sequence number
+/// (`00003-<uuid>.metadata.json` or `v3.metadata.json`); files without one
+/// are ordered by modification time.
+fn latest_iceberg_metadata(table: &DeltaTable) -> Result<Option<ObjectMeta>> {
+    let store = table.log_store().object_store(None);
+    let files: Vec<ObjectMeta> = block_on(async {
+        store
+            .list(Some(&Path::from("metadata")))
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to list Iceberg metadata: {}", e)))?;
+
+    Ok(files
+        .into_iter()
+        .filter(|file| {
+            file.location
+                .filename()
+                .is_some_and(|name| name.ends_with(".metadata.json"))
+        })
+        .max_by_key(|file| (sequence_number(&file.location), file.last_modified)))
+}
+
+fn sequence_number(location: &Path) -> Option<u64> {
+    location
+        .filename()?
+        .trim_start_matches('v')
+        .split(['-', '.'])
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Delta version an Iceberg metadata file was converted from
+fn iceberg_delta_version(table: &DeltaTable, location: &Path) -> Result<Option<i64>> {
+    let store = table.log_store().object_store(None);
+    let bytes = block_on(async { store.get(location).await?.bytes().await })?
+        .map_err(|e| Error::from(format!("Failed to read Iceberg metadata: {}", e)))?;
+    let metadata: serde_json::Value = serde_json::from_slice(&bytes)
+        .map_err(|e| Error::from(format!("Invalid Iceberg metadata {}: {}", location, e)))?;
+
+    Ok(metadata["properties"][DELTA_VERSION_PROPERTY]
+        .as_str()
+        .and_then(|version| version.parse().ok()))
+}
@@ -0,0 +1,803 @@
+//! Unity Catalog tables
+//!
+//! Tables registered in Unity Catalog are opened by name, as
+//! `uc://catalog.schema.table`. The workspace is asked for the table's
+//! storage location and for temporary credentials scoped to that table, so
+//! users authenticate to Databricks only and never handle cloud keys.
+//! Requests to the workspace are authenticated with a personal access token
+//! or, for service principals, with OAuth machine-to-machine tokens issued
+//! for their client id and secret.
+//!
+//! Credentials that can write are requested first; when the principal lacks
+//! `MODIFY` on the table, read-only credentials are requested instead.
+//! Credentials are cached and vended again five minutes before they expire,
+//! so long operations keep working.
+//!
+//! Tables are opened through a `uc` object store factory that builds the
+//! S3, Azure or GCS store of the storage location with the vended
+//! credentials, rooted at the table.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use deltalake::logstore::object_store::aws::{
+    resolve_bucket_region, AmazonS3Builder, AmazonS3ConfigKey, AwsCredential,
+};
+use deltalake::logstore::object_store::azure::{
+    AzureConfigKey, AzureCredential, MicrosoftAzureBuilder,
+};
+use deltalake::logstore::object_store::client::SpawnedReqwestConnector;
+use deltalake::logstore::object_store::gcp::{
+    GcpCredential, GoogleCloudStorageBuilder, GoogleConfigKey,
+};
+use deltalake::logstore::object_store::prefix::PrefixStore;
+use deltalake::logstore::object_store::Error as ObjectStoreError;
+use deltalake::logstore::object_store::{ClientOptions, CredentialProvider, ObjectStoreScheme};
+use deltalake::logstore::{
+    default_logstore, logstore_factories, object_store_factories, LogStore, LogStoreFactory,
+    ObjectStoreFactory, ObjectStoreRef, StorageConfig,
+};
+use deltalake::{DeltaResult, DeltaTableError, Path};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::catalog::CatalogEntry;
+
+/// Scheme of table URIs naming a Unity Catalog table
+pub(crate) const SCHEME: &str = "uc";
+
+/// Storage options read by this module
+pub(crate) const HOST: &str = "databricks_host";
+pub(crate) const TOKEN: &str = "databricks_token";
+pub(crate) const CLIENT_ID: &str = "databricks_client_id";
+pub(crate) const CLIENT_SECRET: &str = "databricks_client_secret";
+pub(crate) const AUTH_TYPE: &str = "databricks_auth_type";
+pub(crate) const ACCESS: &str = "unity_catalog_access";
+
+/// Environment variables read when the options are not given, as by the
+/// Databricks CLI and SDKs
+const HOST_VAR: &str = "DATABRICKS_HOST";
+const TOKEN_VAR: &str = "DATABRICKS_TOKEN";
+const CLIENT_ID_VAR: &str = "DATABRICKS_CLIENT_ID";
+const CLIENT_SECRET_VAR: &str = "DATABRICKS_CLIENT_SECRET";
+
+/// Scope of the OAuth tokens of service principals
+const OAUTH_SCOPE: &str = "all-apis";
+
+/// Credentials are vended again when they expire in less than this
+const MIN_TTL: Duration = Duration::from_secs(300);
+/// Lifetime assumed for credentials vended without an expiration time
+const DEFAULT_TTL: Duration = Duration::from_secs(3600);
+
+// ============================================================================
+// Settings
+// ============================================================================
+
+/// Access the vended credentials grant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Access {
+    /// Read and write where the principal may modify the table, read only
+    /// otherwise
+    Auto,
+    Read,
+    ReadWrite,
+}
+
+impl FromStr for Access {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "auto" => Ok(Access::Auto),
+            "read" => Ok(Access::Read),
+            "read_write" => Ok(Access::ReadWrite),
+            other => Err(format!(
+                "Unity Catalog access must be \"auto\", \"read\" or \"read_write\", not \"{}\"",
+                other
+            )),
+        }
+    }
+}
+
+/// How requests to the workspace are authenticated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AuthType {
+    /// A personal access token
+    Pat,
+    /// OAuth machine-to-machine: tokens issued to a service principal for
+    /// its client id and secret
+    OAuthM2m,
+}
+
+impl FromStr for AuthType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "pat" => Ok(AuthType::Pat),
+            "oauth-m2m" => Ok(AuthType::OAuthM2m),
+            other => Err(format!(
+                "Databricks auth type must be \"pat\" or \"oauth-m2m\", not \"{}\"",
+                other
+            )),
+        }
+    }
+}
+
+/// Look up an option regardless of its case
+fn option<'a>(options: &'a HashMap<String, String>, key: &str) -> Option<&'a String> {
+    options
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v)
+}
+
+/// An option, or the environment variable standing in for it
+fn option_or_env(options: &HashMap<String, String>, key: &str, var: &str) -> Option<String> {
+    option(options, key)
+        .cloned()
+        .or_else(|| std::env::var(var).ok())
+        .filter(|value| !value.is_empty())
+}
+
+/// The URL of a workspace given as a URL or as a host name
+pub(crate) fn workspace_url(host: &str) -> Result<String, String> {
+    let host = host.trim().trim_end_matches('/');
+    let url = if host.contains("://") {
+        host.to_string()
+    } else {
+        format!("https://{}", host)
+    };
+    let parsed =
+        Url::parse(&url).map_err(|e| format!("Invalid Databricks workspace '{}': {}", host, e))?;
+    match parsed.scheme() {
+        "https" | "http" if parsed.host_str().is_some() => Ok(url),
+        _ => Err(format!(
+            "Invalid Databricks workspace '{}': expected an https:// URL",
+            host
+        )),
+    }
+}
+
+/// Check the options of this module before any request is sent
+pub(crate) fn validate_options(options: &HashMap<String, String>) -> DeltaResult<()> {
+    let invalid = DeltaTableError::Generic;
+    let host = option_or_env(options, HOST, HOST_VAR).ok_or_else(|| {
+        invalid(format!(
+            "Unity Catalog tables need a Databricks workspace: set the '{}' storage option or {}",
+            HOST, HOST_VAR
+        ))
+    })?;
+    workspace_url(&host).map_err(invalid)?;
+    auth_type(options).map_err(invalid)?;
+    if let Some(access) = option(options, ACCESS) {
+        access.parse::<Access>().map_err(invalid)?;
+    }
+    Ok(())
+}
+
+/// The way requests are authenticated: as set, else with a token when one is
+/// given, else as a service principal
+fn auth_type(options: &HashMap<String, String>) -> Result<AuthType, String> {
+    let token = option_or_env(options, TOKEN, TOKEN_VAR).is_some();
+    let client = option_or_env(options, CLIENT_ID, CLIENT_ID_VAR).is_some()
+        && option_or_env(options, CLIENT_SECRET, CLIENT_SECRET_VAR).is_some();
+    let auth_type = match option(options, AUTH_TYPE) {
+        Some(auth_type) => auth_type.parse()?,
+        None if token => AuthType::Pat,
+        None if client => AuthType::OAuthM2m,
+        None => {
+            return Err(format!(
+                "Unity Catalog tables need Databricks credentials: a token ('{}' or {}), or the \
+                 client id and secret of a service principal ('{}' and '{}', or {} and {})",
+                TOKEN, TOKEN_VAR, CLIENT_ID, CLIENT_SECRET, CLIENT_ID_VAR, CLIENT_SECRET_VAR
+            ));
+        }
+    };
+    match auth_type {
+        AuthType::Pat if !token => Err(format!(
+            "Databricks personal access token authentication needs '{}' or {}",
+            TOKEN, TOKEN_VAR
+        )),
+        AuthType::OAuthM2m if !client => Err(format!(
+            "Databricks OAuth M2M authentication needs '{}' and '{}', or {} and {}",
+            CLIENT_ID, CLIENT_SECRET, CLIENT_ID_VAR, CLIENT_SECRET_VAR
+        )),
+        auth_type => Ok(auth_type),
+    }
+}
+
+/// The full name of the table a `uc://` URI names
+fn table_name(url: &Url) -> DeltaResult<String> {
+    let name = url.host_str().unwrap_or_default();
+    let parts: Vec<&str> = name.split('.').collect();
+    if parts.len() != 3 || parts.iter().any(|part| part.is_empty()) || url.path().len() > 1 {
+        return Err(DeltaTableError::Generic(format!(
+            "Invalid Unity Catalog table URI '{}': expected uc://catalog.schema.table",
+            url
+        )));
+    }
+    Ok(name.to_string())
+}
+
+// ============================================================================
+// Workspace Requests
+// ============================================================================
+
+/// A failed request to the workspace
+struct RequestError {
+    /// Whether the principal lacks the privilege the request needs
+    denied: bool,
+    message: String,
+}
+
+impl RequestError {
+    fn failed(message: String) -> Self {
+        RequestError {
+            denied: false,
+            message,
+        }
+    }
+}
+
+/// Send a request and parse its JSON response
+async fn json_request(request: reqwest::RequestBuilder, what: &str) -> Result<Value, RequestError> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| RequestError::failed(format!("{} request failed: {}", what, e)))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| RequestError::failed(format!("{} request failed: {}", what, e)))?;
+    let value: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+    if !status.is_success() {
+        let message = value["message"]
+            .as_str()
+            .or_else(|| value["error_description"].as_str())
+            .unwrap_or(&body);
+        return Err(RequestError {
+            denied: status == reqwest::StatusCode::FORBIDDEN
+                || value["error_code"] == "PERMISSION_DENIED",
+            message: format!("{} returned {}: {}", what, status, message),
+        });
+    }
+    if value.is_null() {
+        return Err(RequestError::failed(format!(
+            "Invalid {} response: {}",
+            what, body
+        )));
+    }
+    Ok(value)
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    token: String,
+    expires: Instant,
+}
+
+/// Credentials requests to the workspace are authenticated with
+#[derive(Debug)]
+enum Auth {
+    Token(String),
+    OAuthM2m {
+        client_id: String,
+        client_secret: String,
+        cached: Mutex<Option<CachedToken>>,
+    },
+}
+
+/// A workspace and the credentials its requests are sent with
+#[derive(Debug)]
+struct Workspace {
+    url: String,
+    auth: Auth,
+    client: reqwest::Client,
+}
+
+impl Workspace {
+    fn from_options(options: &HashMap<String, String>) -> DeltaResult<Self> {
+        validate_options(options)?;
+        let host = option_or_env(options, HOST, HOST_VAR).unwrap_or_default();
+        let auth = match auth_type(options).map_err(DeltaTableError::Generic)? {
+            AuthType::Pat => {
+                Auth::Token(option_or_env(options, TOKEN, TOKEN_VAR).unwrap_or_default())
+            }
+            AuthType::OAuthM2m => Auth::OAuthM2m {
+                client_id: option_or_env(options, CLIENT_ID, CLIENT_ID_VAR).unwrap_or_default(),
+                client_secret: option_or_env(options, CLIENT_SECRET, CLIENT_SECRET_VAR)
+                    .unwrap_or_default(),
+                cached: Mutex::new(None),
+            },
+        };
+        Ok(Workspace {
+            url: workspace_url(&host).map_err(DeltaTableError::Generic)?,
+            auth,
+            client: crate::storage::http_client(options).map_err(DeltaTableError::Generic)?,
+        })
+    }
+
+    /// The bearer token of the next request
+    ///
+    /// Service principal tokens are cached and requested again five minutes
+    /// before they expire.
+    async fn bearer_token(&self) -> Result<String, RequestError> {
+        let (client_id, client_secret, cached) = match &self.auth {
+            Auth::Token(token) => return Ok(token.clone()),
+            Auth::OAuthM2m {
+                client_id,
+                client_secret,
+                cached,
+            } => (client_id, client_secret, cached),
+        };
+        if let Some(cached) = cached.lock().unwrap().as_ref() {
+            if cached.expires.saturating_duration_since(Instant::now()) > MIN_TTL {
+                return Ok(cached.token.clone());
+            }
+        }
+
+        let fetched = Instant::now();
+        let request = self
+            .client
+            .post(format!("{}/oidc/v1/token", self.url))
+            .basic_auth(client_id, Some(client_secret))
+            .form(&[("grant_type", "client_credentials"), ("scope", OAUTH_SCOPE)]);
+        let what = format!("Databricks OAuth token request for {}", client_id);
+        let response = json_request(request, &what).await?;
+        let token = response["access_token"]
+            .as_str()
+            .ok_or_else(|| RequestError::failed(format!("{} response has no access token", what)))?
+            .to_string();
+        let expires_in = response["expires_in"].as_u64().unwrap_or(3600);
+        *cached.lock().unwrap() = Some(CachedToken {
+            token: token.clone(),
+            expires: fetched + Duration::from_secs(expires_in),
+        });
+        Ok(token)
+    }
+
+    /// Send an authenticated request to the Unity Catalog API and parse its
+    /// JSON response
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+        what: &str,
+    ) -> Result<Value, RequestError> {
+        let token = self.bearer_token().await?;
+        json_request(request.bearer_auth(token), what).await
+    }
+
+    /// Look up the id and storage location of a table
+    async fn table(&self, name: &str) -> Result<(String, Url), String> {
+        let url = format!("{}/api/2.1/unity-catalog/tables/{}", self.url, name);
+        let what = format!("Unity Catalog lookup of {}", name);
+        let table = self
+            .send(self.client.get(url), &what)
+            .await
+            .map_err(|e| e.message)?;
+
+        let format = table["data_source_format"].as_str().unwrap_or_default();
+        if !format.eq_ignore_ascii_case("delta") {
+            return Err(format!(
+                "Unity Catalog table {} is not a Delta table (format: {})",
+                name,
+                if format.is_empty() { "unknown" } else { format }
+            ));
+        }
+        let id = table["table_id"]
+            .as_str()
+            .ok_or_else(|| format!("{} response has no table id", what))?;
+        let location = table["storage_location"]
+            .as_str()
+            .ok_or_else(|| format!("Unity Catalog table {} has no storage location", name))?;
+        let location = Url::parse(location)
+            .map_err(|e| format!("Invalid storage location '{}': {}", location, e))?;
+        Ok((id.to_string(), location))
+    }
+
+    /// Get every item of a paginated Unity Catalog listing
+    async fn list(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+        field: &str,
+        what: &str,
+    ) -> Result<Vec<Value>, String> {
+        let url = format!("{}/api/2.1/unity-catalog/{}", self.url, endpoint);
+        let mut items = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = self.client.get(&url).query(query);
+            if let Some(token) = &page_token {
+                request = request.query(&[("page_token", token)]);
+            }
+            let mut page = self.send(request, what).await.map_err(|e| e.message)?;
+            if let Value::Array(page_items) = page[field].take() {
+                items.extend(page_items);
+            }
+            match page["next_page_token"].as_str().filter(|t| !t.is_empty()) {
+                Some(token) => page_token = Some(token.to_string()),
+                None => return Ok(items),
+            }
+        }
+    }
+}
+
+/// A string field of a listed item
+fn field(item: &Value, name: &str) -> Option<String> {
+    item[name].as_str().map(str::to_string)
+}
+
+/// List the schemas of a catalog
+pub(crate) async fn list_schemas(
+    options: &HashMap<String, String>,
+    catalog: &str,
+) -> Result<Vec<CatalogEntry>, String> {
+    let workspace = Workspace::from_options(options).map_err(|e| e.to_string())?;
+    let what = format!("Unity Catalog listing of the schemas of {}", catalog);
+    let schemas = workspace
+        .list("schemas", &[("catalog_name", catalog)], "schemas", &what)
+        .await?;
+    Ok(schemas
+        .iter()
+        .map(|schema| CatalogEntry {
+            name: field(schema, "name").unwrap_or_default(),
+            full_name: field(schema, "full_name"),
+            location: field(schema, "storage_root"),
+            format: None,
+            kind: None,
+            comment: field(schema, "comment"),
+        })
+        .collect())
+}
+
+/// List the tables of a schema
+pub(crate) async fn list_tables(
+    options: &HashMap<String, String>,
+    catalog: &str,
+    schema: &str,
+) -> Result<Vec<CatalogEntry>, String> {
+    let workspace = Workspace::from_options(options).map_err(|e| e.to_string())?;
+    let what = format!(
+        "Unity Catalog listing of the tables of {}.{}",
+        catalog, schema
+    );
+    let query = [("catalog_name", catalog), ("schema_name", schema)];
+    let tables = workspace.list("tables", &query, "tables", &what).await?;
+    Ok(tables
+        .iter()
+        .map(|table| CatalogEntry {
+            name: field(table, "name").unwrap_or_default(),
+            full_name: field(table, "full_name"),
+            location: field(table, "storage_location"),
+            format: field(table, "data_source_format"),
+            kind: field(table, "table_type"),
+            comment: field(table, "comment"),
+        })
+        .collect())
+}
+
+/// Run a future to completion from the synchronous object store factory,
+/// which may be called inside or outside the runtime
+fn wait<F>(future: F) -> DeltaResult<F::Output>
+where
+    F: std::future::Future + Send,
+    F::Output: Send + 'static,
+{
+    Ok(crate::block_on(future)?)
+}
+
+// ============================================================================
+// Credential Vending
+// ============================================================================
+
+/// Credentials vended for one of the clouds
+#[derive(Debug, Clone)]
+enum Vended {
+    Aws(Arc<AwsCredential>),
+    Azure(Arc<AzureCredential>),
+    Gcp(Arc<GcpCredential>),
+}
+
+#[derive(Debug)]
+struct CachedCredential {
+    credential: Vended,
+    expires: SystemTime,
+}
+
+/// Vends temporary credentials for a table
+#[derive(Debug)]
+struct CredentialVendor {
+    workspace: Workspace,
+    table_id: String,
+    access: Access,
+    /// Whether write credentials were refused, so that `Auto` only asks
+    /// for read credentials from then on
+    read_only: AtomicBool,
+    cached: Mutex<Option<CachedCredential>>,
+}
+
+fn credential_error(message: impl Into<String>) -> ObjectStoreError {
+    ObjectStoreError::Generic {
+        store: "UnityCatalog",
+        source: message.into().into(),
+    }
+}
+
+impl CredentialVendor {
+    async fn request(&self, operation: &str) -> Result<Value, RequestError> {
+        let url = format!(
+            "{}/api/2.1/unity-catalog/temporary-table-credentials",
+            self.workspace.url
+        );
+        let body = json!({ "table_id": self.table_id, "operation": operation });
+        let request = self.workspace.client.post(url).json(&body);
+        self.workspace
+            .send(request, "Unity Catalog credential vending")
+            .await
+    }
+
+    async fn fetch(&self) -> Result<CachedCredential, ObjectStoreError> {
+        let response = match self.access {
+            Access::Read => self.request("READ").await,
+            Access::ReadWrite => self.request("READ_WRITE").await,
+            Access::Auto if self.read_only.load(Ordering::Relaxed) => self.request("READ").await,
+            Access::Auto => match self.request("READ_WRITE").await {
+                Err(e) if e.denied => {
+                    self.read_only.store(true, Ordering::Relaxed);
+                    self.request("READ").await
+                }
+                response => response,
+            },
+        }
+        .map_err(|e| credential_error(e.message))?;
+
+        let text = |value: &Value, field: &str| -> Result<String, ObjectStoreError> {
+            value[field]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| credential_error(format!("Vended credentials have no '{}'", field)))
+        };
+        let credential = if let Some(aws) = response.get("aws_temp_credentials") {
+            Vended::Aws(Arc::new(AwsCredential {
+                key_id: text(aws, "access_key_id")?,
+                secret_key: text(aws, "secret_access_key")?,
+                token: aws["session_token"].as_str().map(str::to_string),
+            }))
+        } else if let Some(azure) = response.get("azure_user_delegation_sas") {
+            let sas = text(azure, "sas_token")?;
+            let pairs = url::form_urlencoded::parse(sas.trim_start_matches('?').as_bytes())
+                .into_owned()
+                .collect();
+            Vended::Azure(Arc::new(AzureCredential::SASToken(pairs)))
+        } else if let Some(gcp) = response.get("gcp_oauth_token") {
+            Vended::Gcp(Arc::new(GcpCredential {
+                bearer: text(gcp, "oauth_token")?,
+            }))
+        } else {
+            return Err(credential_error(
+                "Unity Catalog vended credentials for an unsupported cloud",
+            ));
+        };
+        let expires = response["expiration_time"]
+            .as_u64()
+            .map(|ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms))
+            .unwrap_or_else(|| SystemTime::now() + DEFAULT_TTL);
+        Ok(CachedCredential {
+            credential,
+            expires,
+        })
+    }
+
+    async fn get(&self) -> Result<Vended, ObjectStoreError> {
+        if let Some(cached) = self.cached.lock().unwrap().as_ref() {
+            let fresh = cached
+                .expires
+                .duration_since(SystemTime::now())
+                .is_ok_and(|ttl| ttl > MIN_TTL);
+            if fresh {
+                return Ok(cached.credential.clone());
+            }
+        }
+        let credential = self.fetch().await?;
+        let result = credential.credential.clone();
+        *self.cached.lock().unwrap() = Some(credential);
+        Ok(result)
+    }
+}
+
+/// The credentials of one cloud from a [CredentialVendor]
+#[derive(Debug)]
+struct VendedCredentials<C> {
+    vendor: Arc<CredentialVendor>,
+    pick: fn(Vended) -> Option<Arc<C>>,
+}
+
+#[async_trait]
+impl<C: std::fmt::Debug + Send + Sync + 'static> CredentialProvider for VendedCredentials<C> {
+    type Credential = C;
+
+    async fn get_credential(&self) -> Result<Arc<C>, ObjectStoreError> {
+        let vended = self.vendor.get().await?;
+        (self.pick)(vended).ok_or_else(|| {
+            credential_error("Unity Catalog vended credentials for another cloud than the table's")
+        })
+    }
+}
+
+// ============================================================================
+// Object Store Factory
+// ============================================================================
+
+/// Build the store of a storage location with vended credentials
+fn location_store(
+    location: &Url,
+    vendor: Arc<CredentialVendor>,
+    config: &StorageConfig,
+) -> DeltaResult<ObjectStoreRef> {
+    let build_error = |e: ObjectStoreError| DeltaTableError::Generic(e.to_string());
+    let store: ObjectStoreRef = match location.scheme() {
+        "s3" | "s3a" => {
+            let mut builder = AmazonS3Builder::new()
+                .with_url(location.to_string())
+                .with_retry(config.retry.clone())
+                .with_credentials(Arc::new(VendedCredentials {
+                    vendor,
+                    pick: |vended| match vended {
+                        Vended::Aws(credential) => Some(credential),
+                        _ => None,
+                    },
+                }));
+            if let Some(runtime) = &config.runtime {
+                builder =
+                    builder.with_http_connector(SpawnedReqwestConnector::new(runtime.get_handle()));
+            }
+            for (key, value) in &config.raw {
+                if let Ok(key) = AmazonS3ConfigKey::from_str(&key.to_ascii_lowercase()) {
+                    builder = builder.with_config(key, value.clone());
+                }
+            }
+            // Vended credentials do not say where the bucket is
+            let region = builder
+                .get_config_value(&AmazonS3ConfigKey::Region)
+                .or_else(|| std::env::var("AWS_REGION").ok());
+            let region = match region {
+                Some(region) => region,
+                None => {
+                    let bucket = location.host_str().unwrap_or_default();
+                    wait(resolve_bucket_region(bucket, &ClientOptions::default()))?
+                        .map_err(build_error)?
+                }
+            };
+            Arc::new(builder.with_region(region).build().map_err(build_error)?)
+        }
+        "abfss" | "abfs" | "az" | "adl" | "azure" => {
+            let mut builder = MicrosoftAzureBuilder::new()
+                .with_url(location.to_string())
+                .with_retry(config.retry.clone())
+                .with_credentials(Arc::new(VendedCredentials {
+                    vendor,
+                    pick: |vended| match vended {
+                        Vended::Azure(credential) => Some(credential),
+                        _ => None,
+                    },
+                }));
+            if let Some(runtime) = &config.runtime {
+                builder =
+                    builder.with_http_connector(SpawnedReqwestConnector::new(runtime.get_handle()));
+            }
+            for (key, value) in &config.raw {
+                if let Ok(key) = AzureConfigKey::from_str(&key.to_ascii_lowercase()) {
+                    builder = builder.with_config(key, value.clone());
+                }
+            }
+            Arc::new(builder.build().map_err(build_error)?)
+        }
+        "gs" => {
+            let mut builder = GoogleCloudStorageBuilder::new()
+                .with_url(location.to_string())
+                .with_retry(config.retry.clone())
+                .with_credentials(Arc::new(VendedCredentials {
+                    vendor,
+                    pick: |vended| match vended {
+                        Vended::Gcp(credential) => Some(credential),
+                        _ => None,
+                    },
+                }));
+            if let Some(runtime) = &config.runtime {
+                builder =
+                    builder.with_http_connector(SpawnedReqwestConnector::new(runtime.get_handle()));
+            }
+            for (key, value) in &config.raw {
+                if let Ok(key) = GoogleConfigKey::from_str(&key.to_ascii_lowercase()) {
+                    builder = builder.with_config(key, value.clone());
+                }
+            }
+            Arc::new(builder.build().map_err(build_error)?)
+        }
+        other => {
+            return Err(DeltaTableError::Generic(format!(
+                "Unity Catalog tables stored on '{}' are not supported",
+                other
+            )));
+        }
+    };
+
+    let (_, path) =
+        ObjectStoreScheme::parse(location).map_err(|e| DeltaTableError::Generic(e.to_string()))?;
+    Ok(Arc::new(PrefixStore::new(store, path)))
+}
+
+/// Opens `uc` URIs through the store of the table's storage location
+#[derive(Debug, Default)]
+struct UnityCatalogFactory;
+
+impl ObjectStoreFactory for UnityCatalogFactory {
+    fn parse_url_opts(
+        &self,
+        url: &Url,
+        config: &StorageConfig,
+    ) -> DeltaResult<(ObjectStoreRef, Path)> {
+        let name = table_name(url)?;
+        let workspace = Workspace::from_options(&config.raw)?;
+        let access = option(&config.raw, ACCESS)
+            .map(|access| access.parse::<Access>())
+            .transpose()
+            .map_err(DeltaTableError::Generic)?
+            .unwrap_or(Access::Auto);
+        let (table_id, location) =
+            wait(workspace.table(&name))?.map_err(DeltaTableError::Generic)?;
+
+        let vendor = Arc::new(CredentialVendor {
+            workspace,
+            table_id,
+            access,
+            read_only: AtomicBool::new(false),
+            cached: Mutex::new(None),
+        });
+        // Vend the first credentials now, so a table the principal cannot
+        // read fails to open rather than on its first request
+        wait(vendor.get())?.map_err(|e| DeltaTableError::Generic(e.to_string()))?;
+
+        let store = location_store(&location, vendor, config)?;
+        Ok((store, Path::default()))
+    }
+}
+
+/// Commits to Unity Catalog tables with conditional puts, as to any store
+/// without a log store of its own
+#[derive(Debug, Default)]
+struct UnityLogStoreFactory;
+
+impl LogStoreFactory for UnityLogStoreFactory {
+    fn with_options(
+        &self,
+        prefixed_store: ObjectStoreRef,
+        root_store: ObjectStoreRef,
+        location: &Url,
+        options: &StorageConfig,
+    ) -> DeltaResult<Arc<dyn LogStore>> {
+        Ok(default_logstore(
+            prefixed_store,
+            root_store,
+            location,
+            options,
+        ))
+    }
+}
+
+/// Route `uc` URIs through [UnityCatalogFactory]
+///
+/// Called before the client cache wraps the registered factories, so that
+/// a table is looked up once per session and set of options.
+pub(crate) fn register_handlers() {
+    let url = Url::parse(&format!("{}://", SCHEME)).unwrap();
+    object_store_factories().insert(url.clone(), Arc::new(UnityCatalogFactory));
+    logstore_factories().insert(url, Arc::new(UnityLogStoreFactory));
+}
@@ -0,0 +1,132 @@
+//! Checksum verification of data files
+//!
+//! Before a verified scan, the data files its physical plan reads are
+//! checked against the object store and read once in full: the size the
+//! store reports must be the size recorded in the log, the Parquet footer
+//! must parse, and every page must decode, which checks the CRC-32 checksums
+//! of the pages that have one (writers such as Spark record them by default).
+//! The first file that fails is reported by its path, instead of a decoding
+//! error somewhere in the middle of a scan.
+//!
+//! Files skipped by the scan's partition and statistics pruning are not
+//! read. Scans that delta-kernel runs list their files themselves, so all
+//! files of the version are verified for them. The log records neither
+//! ETags nor MD5 digests of the files, so there is nothing to compare those
+//! with and they are not checked.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use deltalake::datafusion::dataframe::DataFrame;
+use deltalake::datafusion::datasource::physical_plan::ParquetSource;
+use deltalake::datafusion::datasource::source::DataSourceExec;
+use deltalake::datafusion::physical_plan::streaming::StreamingTableExec;
+use deltalake::datafusion::physical_plan::ExecutionPlan;
+use deltalake::kernel::LogicalFileView;
+use deltalake::logstore::ObjectStoreRef;
+use deltalake::parquet::arrow::async_reader::{
+    ParquetObjectReader, ParquetRecordBatchStreamBuilder,
+};
+use deltalake::{DeltaTable, Path};
+use extendr_api::prelude::*;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::{block_on, file_concurrency};
+
+/// Check one data file, returning why it failed verification
+async fn verify_file(
+    store: ObjectStoreRef,
+    location: Path,
+    size: u64,
+) -> std::result::Result<(), String> {
+    let meta = store.head(&location).await.map_err(|e| e.to_string())?;
+    if meta.size != size {
+        return Err(format!(
+            "size is {} bytes, but the log records {} bytes",
+            meta.size, size
+        ));
+    }
+
+    let reader = ParquetObjectReader::new(store, location).with_file_size(size);
+    let mut stream = ParquetRecordBatchStreamBuilder::new(reader)
+        .await
+        .map_err(|e| e.to_string())?
+        .build()
+        .map_err(|e| e.to_string())?;
+    while let Some(batch) = stream.next().await {
+        batch.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Collect the data files a physical plan reads, with their size in the log
+///
+/// Returns false when part of the plan is a delta-kernel scan, whose files
+/// are not in the plan.
+fn planned_files(plan: &Arc<dyn ExecutionPlan>, files: &mut Vec<(Path, u64)>) -> bool {
+    if plan.as_any().is::<StreamingTableExec>() {
+        return false;
+    }
+    if let Some(exec) = plan.as_any().downcast_ref::<DataSourceExec>() {
+        if let Some((config, _)) = exec.downcast_to_file_source::<ParquetSource>() {
+            for file in config.file_groups.iter().flat_map(|group| group.iter()) {
+                files.push((file.object_meta.location.clone(), file.object_meta.size));
+            }
+        }
+    }
+    plan.children()
+        .into_iter()
+        .all(|child| planned_files(child, files))
+}
+
+/// Verify the checksums of the data files a scan of a table reads
+///
+/// `df` is the scan, with its projections, filters and limits; the files
+/// the table's loaded version holds are verified when the plan does not
+/// list them. Files are verified concurrently on the runtime's workers; the
+/// error names the first file found to be missing, truncated or corrupt.
+pub(crate) fn verify_checksums(table: &DeltaTable, df: &DataFrame) -> Result<()> {
+    let plan = block_on(df.clone().create_physical_plan())?
+        .map_err(|e| Error::from(format!("Failed to plan the scan: {}", e)))?;
+    let mut files = Vec::new();
+    if !planned_files(&plan, &mut files) {
+        files = table_files(table)?;
+    }
+    // A file may be split into ranges scanned by several partitions
+    let mut seen = HashSet::new();
+    files.retain(|(location, _)| seen.insert(location.clone()));
+
+    let store = table.log_store().object_store(None);
+    block_on(async {
+        futures::stream::iter(files.into_iter().map(|(location, size)| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let path = location.to_string();
+                verify_file(store, location, size)
+                    .await
+                    .map_err(|e| format!("{}: {}", path, e))
+            })
+        }))
+        .buffer_unordered(file_concurrency(table))
+        .map(|joined| joined.map_err(|e| e.to_string())?)
+        .try_collect::<Vec<()>>()
+        .await
+    })?
+    .map_err(|e| Error::from(format!("Checksum verification failed for {}", e)))?;
+    Ok(())
+}
+
+/// List the data files of a table's loaded version, with their size in the log
+fn table_files(table: &DeltaTable) -> Result<Vec<(Path, u64)>> {
+    let files: Vec<LogicalFileView> = block_on(async {
+        table
+            .get_active_add_actions_by_partitions(&[])
+            .try_collect()
+            .await
+    })?
+    .map_err(|e| Error::from(format!("Failed to list table files: {}", e)))?;
+    Ok(files
+        .iter()
+        .map(|file| (Path::from(file.path().as_ref()), file.size() as u64))
+        .collect())
+}
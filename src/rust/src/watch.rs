@@ -0,0 +1,238 @@
+//! Commit polling
+//!
+//! Detects new versions of a table by reading the commit files that follow
+//! a known version, without loading the table snapshot. When nothing was
+//! committed, a poll costs a single request for a file that does not exist.
+//! Commit files are never rewritten, so a file that was read once never
+//! needs to be requested again.
+
+use bytes::Bytes;
+use deltalake::kernel::{Action, CommitInfo};
+use deltalake::logstore::{get_actions, LogStoreRef};
+use deltalake::DeltaTableBuilder;
+use extendr_api::prelude::*;
+
+use crate::{block_on, parse_storage_options, path_to_url};
+
+/// Get the log store of a table without loading its snapshot
+fn table_log_store(table_uri: &str, storage_options: &Nullable<List>) -> Result<LogStoreRef> {
+    let url = path_to_url(table_uri).map_err(Error::from)?;
+    let mut builder = DeltaTableBuilder::from_url(url).map_err(|e| Error::from(e.to_string()))?;
+    if let Nullable::NotNull(opts) = storage_options {
+        builder = builder.with_storage_options(parse_storage_options(opts)?);
+    }
+    let table = builder.build().map_err(|e| Error::from(e.to_string()))?;
+    Ok(table.log_store())
+}
+
+/// Read a commit file, with its commit info, if it exists
+fn read_commit(log_store: &LogStoreRef, version: i64) -> Result<Option<(Bytes, CommitInfo)>> {
+    let bytes = block_on(async { log_store.read_commit_entry(version).await })?
+        .map_err(|e| Error::from(format!("Failed to read commit {}: {}", version, e)))?;
+    let Some(bytes) = bytes else {
+        return Ok(None);
+    };
+    let info = get_actions(version, &bytes)
+        .map_err(|e| Error::from(format!("Failed to read commit {}: {}", version, e)))?
+        .into_iter()
+        .find_map(|action| match action {
+            Action::CommitInfo(info) => Some(info),
+            _ => None,
+        })
+        .unwrap_or_default();
+    Ok(Some((bytes, info)))
+}
+
+/// The top-level fields of a commit info, as R values
+struct CommitFields {
+    timestamp: Rfloat,
+    operation: Rstr,
+    user_name: Rstr,
+    parameters: Rstr,
+}
+
+impl From<&CommitInfo> for CommitFields {
+    fn from(info: &CommitInfo) -> Self {
+        CommitFields {
+            timestamp: info
+                .timestamp
+                .map(|ts| Rfloat::from(ts as f64))
+                .unwrap_or_else(Rfloat::na),
+            operation: info
+                .operation
+                .as_deref()
+                .map(Rstr::from)
+                .unwrap_or_else(Rstr::na),
+            user_name: info
+                .user_name
+                .as_deref()
+                .map(Rstr::from)
+                .unwrap_or_else(Rstr::na),
+            parameters: info
+                .operation_parameters
+                .as_ref()
+                .and_then(|params| serde_json::to_string(params).ok())
+                .map(|json| Rstr::from(json.as_str()))
+                .unwrap_or_else(Rstr::na),
+        }
+    }
+}
+
+/// Get the latest version of a Delta table from its transaction log
+///
+/// @param table_uri Path to the Delta table
+/// @param storage_options Storage backend options (optional)
+#[extendr]
+pub fn delta_latest_version(table_uri: &str, storage_options: Nullable<List>) -> Result<f64> {
+    let log_store = table_log_store(table_uri, &storage_options)?;
+    block_on(async { log_store.get_latest_version(0).await })?
+        .map(|v| v as f64)
+        .map_err(|e| Error::from(format!("Failed to read the transaction log: {}", e)))
+}
+
+/// List the commits made after a version of a Delta table
+///
+/// Returns a data.frame with the `version`, `timestamp` (milliseconds since
+/// the epoch), `operation`, `user_name` and `operation_parameters` (as JSON)
+/// of every commit after `after_version`, oldest first.
+///
+/// @param table_uri Path to the Delta table
+/// @param after_version Last version already seen
+/// @param storage_options Storage backend options (optional)
+#[extendr]
+pub fn delta_commits_after(
+    table_uri: &str,
+    after_version: i64,
+    storage_options: Nullable<List>,
+) -> Result<Robj> {
+    let log_store = table_log_store(table_uri, &storage_options)?;
+
+    let mut versions: Vec<f64> = Vec::new();
+    let mut timestamps: Vec<Rfloat> = Vec::new();
+    let mut operations: Vec<Rstr> = Vec::new();
+    let mut user_names: Vec<Rstr> = Vec::new();
+    let mut parameters: Vec<Rstr> = Vec::new();
+
+    let mut version = after_version + 1;
+    while let Some((_, info)) = read_commit(&log_store, version)? {
+        let fields = CommitFields::from(&info);
+        versions.push(version as f64);
+        timestamps.push(fields.timestamp);
+        operations.push(fields.operation);
+        user_names.push(fields.user_name);
+        parameters.push(fields.parameters);
+        version += 1;
+    }
+
+    let df = data_frame!(
+        version = versions,
+        timestamp = Doubles::from_values(timestamps),
+        operation = Strings::from_values(operations),
+        user_name = Strings::from_values(user_names),
+        operation_parameters = Strings::from_values(parameters)
+    );
+    Ok(df.into_robj())
+}
+
+/// A reader of the commits of a table, in order, as they are made
+#[extendr]
+pub struct DeltaLogTailInternal {
+    log_store: LogStoreRef,
+    /// The next version to read
+    next_version: i64,
+}
+
+#[extendr]
+impl DeltaLogTailInternal {
+    /// Open the transaction log of a table for tailing
+    ///
+    /// @param table_uri Path to the Delta table
+    /// @param from_version First version to read, or NULL for the version
+    ///   after the latest one
+    /// @param storage_options Storage backend options (optional)
+    fn new(
+        table_uri: &str,
+        from_version: Nullable<f64>,
+        storage_options: Nullable<List>,
+    ) -> Result<Self> {
+        let log_store = table_log_store(table_uri, &storage_options)?;
+        let next_version = match from_version {
+            Nullable::NotNull(version) => {
+                let version = version as i64;
+                // A commit that was cleaned up from the log would be waited
+                // for forever, while the table moves on past it
+                let latest = block_on(async { log_store.get_latest_version(version).await })?;
+                if matches!(latest, Ok(latest) if latest >= version)
+                    && read_commit(&log_store, version)?.is_none()
+                {
+                    return Err(Error::from(format!(
+                        "Commit {} is no longer in the transaction log, it was cleaned up \
+                         after its log retention duration",
+                        version
+                    )));
+                }
+                version
+            }
+            Nullable::Null => {
+                block_on(async { log_store.get_latest_version(0).await })?.map_err(|e| {
+                    Error::from(format!("Failed to read the transaction log: {}", e))
+                })? + 1
+            }
+        };
+        Ok(DeltaLogTailInternal {
+            log_store,
+            next_version,
+        })
+    }
+
+    /// The next version to read
+    fn next_version(&self) -> f64 {
+        self.next_version as f64
+    }
+
+    /// Read the commits made since the last read, up to `max_commits`
+    ///
+    /// Returns a list of commit records, oldest first. Each record has the
+    /// `version`, `timestamp` (milliseconds since the epoch), `operation`,
+    /// `user_name` and `operation_parameters` (as JSON) of the commit, and
+    /// its `actions` as a character vector of JSON lines.
+    fn poll(&mut self, max_commits: i32) -> Result<List> {
+        let mut records: Vec<Robj> = Vec::new();
+        while records.len() < max_commits as usize {
+            let Some((bytes, info)) = read_commit(&self.log_store, self.next_version)? else {
+                break;
+            };
+            let actions: Vec<&str> = std::str::from_utf8(&bytes)
+                .map_err(|e| {
+                    Error::from(format!(
+                        "Failed to read commit {}: {}",
+                        self.next_version, e
+                    ))
+                })?
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .collect();
+            let fields = CommitFields::from(&info);
+            records.push(
+                list!(
+                    version = self.next_version as f64,
+                    timestamp = fields.timestamp,
+                    operation = fields.operation,
+                    user_name = fields.user_name,
+                    operation_parameters = fields.parameters,
+                    actions = actions
+                )
+                .into_robj(),
+            );
+            self.next_version += 1;
+        }
+        Ok(List::from_values(records))
+    }
+}
+
+extendr_module! {
+    mod watch;
+    fn delta_latest_version;
+    fn delta_commits_after;
+    impl DeltaLogTailInternal;
+}
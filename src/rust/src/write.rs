@@ -6,16 +6,19 @@
 
 use std::any::Any;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
-use arrow::array::RecordBatch;
-use arrow::datatypes::SchemaRef;
+use arrow::array::{new_null_array, RecordBatch, RecordBatchIterator};
+use arrow::datatypes::{DataType, FieldRef, Schema as ArrowSchema, SchemaRef, TimeUnit};
 use arrow::error::ArrowError;
-use arrow::ffi_stream::ArrowArrayStreamReader;
+use arrow::ffi_stream::{ArrowArrayStreamReader, FFI_ArrowArrayStream};
 use arrow_extendr::from::FromArrowRobj;
+use delta_kernel::table_features::ColumnMappingMode;
 use deltalake::arrow::array::RecordBatchReader;
 use deltalake::datafusion::catalog::{Session, TableProvider};
+use deltalake::datafusion::common::{Column, ScalarValue};
 use deltalake::datafusion::datasource::provider_as_source;
 use deltalake::datafusion::datasource::TableType;
 use deltalake::datafusion::logical_expr::LogicalPlan;
@@ -23,16 +26,35 @@ use deltalake::datafusion::logical_expr::LogicalPlanBuilder;
 use deltalake::datafusion::logical_expr::TableProviderFilterPushDown;
 use deltalake::datafusion::physical_plan::memory::{LazyBatchGenerator, LazyMemoryExec};
 use deltalake::datafusion::physical_plan::ExecutionPlan;
-use deltalake::datafusion::prelude::Expr;
+use deltalake::datafusion::prelude::{cast, lit, CsvReadOptions, Expr, ParquetReadOptions};
 use deltalake::kernel::schema::cast_record_batch;
 use deltalake::kernel::{ArrayType, DataType as KernelDT, MapType, PrimitiveType, StructType};
+use deltalake::logstore::store_for;
 use deltalake::operations::write::WriteBuilder;
+use deltalake::parquet::basic::Compression;
+use deltalake::parquet::file::properties::{EnabledStatistics, WriterProperties};
+use deltalake::parquet::schema::types::ColumnPath;
 use deltalake::protocol::SaveMode;
 use deltalake::DeltaTable;
 use extendr_api::prelude::*;
 use parking_lot::RwLock;
 use std::str::FromStr;
 
+use crate::column_mapping::{
+    column_mapping_mode, requests_column_mapping, with_column_mapping, write_column_mapped,
+};
+use crate::commit::{
+    append_metrics, check_append_only, commit_properties, new_commit_actions, operation_result,
+    AppTransaction,
+};
+use crate::constraints::{violation_error, with_constraint_checks};
+use crate::context::{column_error, ErrorContext, OperationResult, RaisedError, RaisedResult};
+use crate::direct_write::{supports_direct_write, write_direct};
+use crate::execution::ExecutionOptions;
+use crate::expectations::{null_policy, with_expectations, with_null_checks, ViolationPolicy};
+use crate::profile::{with_profile, Profile};
+use crate::read::{project_and_filter, table_dataframe, table_provider};
+use crate::sql::table_from_robj;
 use crate::{block_on, parse_storage_options, path_to_url};
 
 /// Error type for type conversion failures
@@ -240,6 +262,620 @@ pub(crate) fn maybe_lazy_cast_reader(
     }
 }
 
+// ============================================================================
+// Column Alignment
+// ============================================================================
+
+/// A RecordBatchReader that puts the columns of each batch in the order of a
+/// table's schema, filling the columns missing from the input with nulls.
+///
+/// Columns not in the table follow the table's columns, for schema evolution.
+struct AlignedReader {
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    schema: SchemaRef,
+    /// Index of each output column in the input; `None` when it is null-filled
+    sources: Vec<Option<usize>>,
+}
+
+impl RecordBatchReader for AlignedReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl Iterator for AlignedReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = match self.input.next()? {
+            Ok(batch) => batch,
+            Err(e) => return Some(Err(e)),
+        };
+        let columns = self
+            .sources
+            .iter()
+            .zip(self.schema.fields())
+            .map(|(source, field)| match source {
+                Some(i) => batch.column(*i).clone(),
+                None => new_null_array(field.data_type(), batch.num_rows()),
+            })
+            .collect();
+        Some(RecordBatch::try_new(self.schema.clone(), columns))
+    }
+}
+
+/// Returns a boxed reader whose columns follow the order of `table_schema`.
+///
+/// Nullable table columns missing from the input are filled with nulls; a
+/// missing non-nullable column is an error. If the input already has the
+/// table's columns in order, it is returned unchanged.
+fn align_columns_reader(
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    table_schema: &ArrowSchema,
+) -> RaisedResult<Box<dyn RecordBatchReader + Send + 'static>> {
+    let input_schema = input.schema();
+    let mut fields: Vec<FieldRef> = Vec::new();
+    let mut sources = Vec::new();
+    for field in table_schema.fields() {
+        match input_schema.index_of(field.name()) {
+            Ok(i) => {
+                fields.push(input_schema.fields()[i].clone());
+                sources.push(Some(i));
+            }
+            Err(_) if field.is_nullable() => {
+                fields.push(field.clone());
+                sources.push(None);
+            }
+            Err(_) => {
+                return Err(column_error(
+                    field.name(),
+                    format!(
+                        "Column '{}' is missing from the data and is not nullable",
+                        field.name()
+                    ),
+                ))
+            }
+        }
+    }
+    for (i, field) in input_schema.fields().iter().enumerate() {
+        if table_schema.field_with_name(field.name()).is_err() {
+            fields.push(field.clone());
+            sources.push(Some(i));
+        }
+    }
+
+    if sources
+        .iter()
+        .enumerate()
+        .all(|(i, source)| *source == Some(i))
+    {
+        return Ok(input);
+    }
+    Ok(Box::new(AlignedReader {
+        input,
+        schema: Arc::new(ArrowSchema::new_with_metadata(
+            fields,
+            input_schema.metadata().clone(),
+        )),
+        sources,
+    }))
+}
+
+// ============================================================================
+// Column Renaming
+// ============================================================================
+
+/// A RecordBatchReader that gives the columns of each batch other names
+struct RenamedReader {
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    schema: SchemaRef,
+}
+
+impl RecordBatchReader for RenamedReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl Iterator for RenamedReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = match self.input.next()? {
+            Ok(batch) => batch,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(RecordBatch::try_new(
+            self.schema.clone(),
+            batch.columns().to_vec(),
+        ))
+    }
+}
+
+/// Returns a boxed reader whose columns are renamed to the table's columns
+/// they match.
+///
+/// `mapping` maps table columns to the input columns that hold them. With
+/// `case_insensitive`, the other input columns are renamed to the table
+/// column whose name only differs in case. Input columns that match no table
+/// column keep their names. If no column is renamed, the input is returned
+/// unchanged.
+pub(crate) fn rename_columns_reader(
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    table_schema: &ArrowSchema,
+    mapping: &[(String, String)],
+    case_insensitive: bool,
+) -> RaisedResult<Box<dyn RecordBatchReader + Send + 'static>> {
+    let input_schema = input.schema();
+    let mut names: Vec<Option<String>> = vec![None; input_schema.fields().len()];
+    for (target, source) in mapping {
+        let i = input_schema.index_of(source).map_err(|_| {
+            column_error(
+                source,
+                format!(
+                    "Column '{}' of column_mapping is not in the source data",
+                    source
+                ),
+            )
+        })?;
+        if table_schema.field_with_name(target).is_err() {
+            return Err(column_error(
+                target,
+                format!("Column '{}' of column_mapping is not in the table", target),
+            ));
+        }
+        names[i] = Some(target.clone());
+    }
+    if case_insensitive {
+        for (i, field) in input_schema.fields().iter().enumerate() {
+            if names[i].is_some() || table_schema.field_with_name(field.name()).is_ok() {
+                continue;
+            }
+            let lower = field.name().to_lowercase();
+            let matches: Vec<&String> = table_schema
+                .fields()
+                .iter()
+                .map(|f| f.name())
+                .filter(|name| name.to_lowercase() == lower)
+                .collect();
+            match matches.as_slice() {
+                [] => {}
+                [name] => names[i] = Some(name.to_string()),
+                _ => {
+                    return Err(column_error(
+                        field.name(),
+                        format!(
+                            "Column '{}' matches several table columns when ignoring case: {}",
+                            field.name(),
+                            matches
+                                .iter()
+                                .map(|name| format!("'{}'", name))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(renamed_reader(input, &names)?)
+}
+
+/// Returns a boxed reader with the columns given a new name in `names`
+/// renamed, or the input itself when no column is.
+fn renamed_reader(
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    names: &[Option<String>],
+) -> Result<Box<dyn RecordBatchReader + Send + 'static>> {
+    if names.iter().all(Option::is_none) {
+        return Ok(input);
+    }
+
+    let input_schema = input.schema();
+    let fields: Vec<FieldRef> = input_schema
+        .fields()
+        .iter()
+        .zip(names)
+        .map(|(field, name)| match name {
+            Some(name) => Arc::new(field.as_ref().clone().with_name(name)),
+            None => field.clone(),
+        })
+        .collect();
+    let mut seen: HashMap<&str, &str> = HashMap::new();
+    for (field, original) in fields.iter().zip(input_schema.fields()) {
+        if let Some(other) = seen.insert(field.name(), original.name()) {
+            return Err(Error::from(format!(
+                "Columns '{}' and '{}' of the data would both be named '{}'",
+                other,
+                original.name(),
+                field.name()
+            )));
+        }
+    }
+    Ok(Box::new(RenamedReader {
+        input,
+        schema: Arc::new(ArrowSchema::new_with_metadata(
+            fields,
+            input_schema.metadata().clone(),
+        )),
+    }))
+}
+
+/// Characters Delta does not allow in column names, unless the table uses
+/// column mapping
+const INVALID_NAME_CHARS: &[char] = &[' ', ',', ';', '{', '}', '(', ')', '\n', '\t', '='];
+
+/// Columns renamed by a write, as (name, new name) pairs
+type RenamedColumns = Vec<(String, String)>;
+
+/// Returns a boxed reader whose column names are valid for Delta.
+///
+/// With `sanitize`, every invalid character of a name is replaced by an
+/// underscore, and the renamed columns are returned as (name, new name)
+/// pairs; otherwise a name with an invalid character is an error.
+pub(crate) fn sanitize_columns_reader(
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    sanitize: bool,
+) -> Result<(Box<dyn RecordBatchReader + Send + 'static>, RenamedColumns)> {
+    let input_schema = input.schema();
+    let invalid: Vec<&String> = input_schema
+        .fields()
+        .iter()
+        .map(|field| field.name())
+        .filter(|name| name.contains(INVALID_NAME_CHARS))
+        .collect();
+    if invalid.is_empty() {
+        return Ok((input, Vec::new()));
+    }
+    if !sanitize {
+        return Err(Error::from(format!(
+            "Invalid column names: {}. Column names cannot contain spaces or any of ,;{{}}()=, \
+             nor newlines or tabs; use on_invalid_names = 'sanitize' to replace these \
+             characters with underscores",
+            invalid
+                .iter()
+                .map(|name| format!("'{}'", name.replace('\n', "\\n").replace('\t', "\\t")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    let mut renamed = Vec::new();
+    let names: Vec<Option<String>> = input_schema
+        .fields()
+        .iter()
+        .map(|field| {
+            if !field.name().contains(INVALID_NAME_CHARS) {
+                return None;
+            }
+            let name = field.name().replace(INVALID_NAME_CHARS, "_");
+            renamed.push((field.name().clone(), name.clone()));
+            Some(name)
+        })
+        .collect();
+    Ok((renamed_reader(input, &names)?, renamed))
+}
+
+// ============================================================================
+// Type Widening
+// ============================================================================
+
+/// Whether every value of type `from` can be stored as `to` without loss
+///
+/// These are the type changes of the Delta type widening feature: wider
+/// integers, integers and floats to doubles, dates to timestamps without
+/// time zone and decimals with more integer and fractional digits. Dates
+/// also widen to timestamps with a time zone, which POSIXct columns always
+/// have: the feature leaves that change out, but widening rewrites the
+/// table's rows rather than recording a type change.
+fn widens_to(from: &DataType, to: &DataType) -> bool {
+    use DataType::*;
+    match (from, to) {
+        (Int8, Int16 | Int32 | Int64) | (Int16, Int32 | Int64) | (Int32, Int64) => true,
+        (Int8 | Int16 | Int32 | Float32, Float64) => true,
+        (Date32, Timestamp(_, _)) => true,
+        (Decimal128(p1, s1), Decimal128(p2, s2)) => {
+            (p1, s1) != (p2, s2)
+                && s2 >= s1
+                && (*p2 as i16 - *s2 as i16) >= (*p1 as i16 - *s1 as i16)
+        }
+        _ => false,
+    }
+}
+
+/// The schema of the table once its columns are widened to the types of the
+/// data, or `None` when no column of the data is wider than the table's
+///
+/// Data columns narrower than the table's are cast by the writer as usual.
+/// Columns not in the table are added when `merge` (`schema_mode = "merge"`).
+fn widened_schema(
+    table_schema: &ArrowSchema,
+    data_schema: &ArrowSchema,
+    merge: bool,
+) -> RaisedResult<Option<(SchemaRef, Vec<String>)>> {
+    let mut fields: Vec<FieldRef> = Vec::new();
+    let mut widened = Vec::new();
+    for field in table_schema.fields() {
+        match data_schema.field_with_name(field.name()) {
+            Ok(data_field) if widens_to(field.data_type(), data_field.data_type()) => {
+                // Delta timestamps are stored in microseconds
+                let data_type = match data_field.data_type() {
+                    DataType::Timestamp(_, tz) => DataType::Timestamp(
+                        TimeUnit::Microsecond,
+                        tz.as_ref().map(|_| "UTC".into()),
+                    ),
+                    other => other.clone(),
+                };
+                fields.push(Arc::new(field.as_ref().clone().with_data_type(data_type)));
+                widened.push(field.name().clone());
+            }
+            _ => fields.push(field.clone()),
+        }
+    }
+    if widened.is_empty() {
+        return Ok(None);
+    }
+    for field in data_schema.fields() {
+        if table_schema.field_with_name(field.name()).is_err() {
+            if !merge {
+                return Err(column_error(
+                    field.name(),
+                    format!(
+                        "Column '{}' is not in the table; use schema_mode = 'merge' to add it",
+                        field.name()
+                    ),
+                ));
+            }
+            fields.push(field.clone());
+        }
+    }
+    Ok(Some((Arc::new(ArrowSchema::new(fields)), widened)))
+}
+
+/// A plan of the rows of `table` followed by the rows of `source`, both cast
+/// to `schema`, for rewriting the table with widened column types
+fn widening_plan(
+    table: &DeltaTable,
+    source: LogicalPlan,
+    schema: &ArrowSchema,
+) -> Result<LogicalPlan> {
+    let cast_to = |plan: &LogicalPlan| -> Vec<Expr> {
+        schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let value = if plan.schema().has_column_with_unqualified_name(field.name()) {
+                    Expr::Column(Column::from_name(field.name()))
+                } else {
+                    lit(ScalarValue::Null)
+                };
+                cast(value, field.data_type().clone()).alias(field.name())
+            })
+            .collect()
+    };
+
+    let provider = table_provider(table.clone())?;
+    let existing = LogicalPlanBuilder::scan("target", provider_as_source(provider), None)
+        .and_then(|builder| builder.build())
+        .map_err(|e| Error::from(format!("Failed to scan table: {}", e)))?;
+    let existing_columns = cast_to(&existing);
+    let source_columns = cast_to(&source);
+    let source = LogicalPlanBuilder::from(source)
+        .project(source_columns)
+        .and_then(|builder| builder.build())
+        .map_err(|e| Error::from(e.to_string()))?;
+    LogicalPlanBuilder::from(existing)
+        .project(existing_columns)
+        .and_then(|builder| builder.union(source))
+        .and_then(|builder| builder.build())
+        .map_err(|e| Error::from(format!("Failed to build logical plan: {}", e)))
+}
+
+// ============================================================================
+// File Statistics
+// ============================================================================
+
+/// Statistics options of a write, applied by its Parquet writers
+///
+/// The min/max statistics of the files end up in the add actions of the
+/// log, so a long text column adds its longest values to every commit
+/// unless they are truncated or skipped.
+pub(crate) struct StatsOptions {
+    /// Most bytes kept of the minimum and maximum of string columns
+    truncate_length: Option<usize>,
+    /// Top-level columns written without statistics
+    skip_columns: Vec<String>,
+}
+
+impl StatsOptions {
+    /// Parse the statistics options given from R
+    fn from_r(truncate_length: Nullable<i32>, skip_columns: Nullable<Vec<String>>) -> Result<Self> {
+        let truncate_length = match truncate_length {
+            Nullable::NotNull(length) if length <= 0 => {
+                return Err(Error::from(
+                    "stats_truncate_length must be a positive number",
+                ))
+            }
+            Nullable::NotNull(length) => Some(length as usize),
+            Nullable::Null => None,
+        };
+        Ok(StatsOptions {
+            truncate_length,
+            skip_columns: skip_columns.into_option().unwrap_or_default(),
+        })
+    }
+
+    /// Check that the skipped columns are columns of the data
+    fn check_columns(&self, schema: &ArrowSchema) -> RaisedResult<()> {
+        match self
+            .skip_columns
+            .iter()
+            .find(|column| schema.field_with_name(column).is_err())
+        {
+            Some(column) => Err(column_error(
+                column,
+                format!(
+                    "Column '{}' of skip_stats_columns is not in the data",
+                    column
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Parquet writer properties applying the options, or `None` to keep
+    /// the writer's defaults
+    ///
+    /// `physical_name` gives the name a column has in the files, which
+    /// differs from its name in tables with column mapping.
+    pub(crate) fn writer_properties(
+        &self,
+        physical_name: impl Fn(&str) -> String,
+    ) -> Option<WriterProperties> {
+        if self.truncate_length.is_none() && self.skip_columns.is_empty() {
+            return None;
+        }
+        // Same compression as the delta-rs writers use by default
+        let mut builder = WriterProperties::builder().set_compression(Compression::SNAPPY);
+        if let Some(length) = self.truncate_length {
+            builder = builder.set_statistics_truncate_length(Some(length));
+        }
+        for column in &self.skip_columns {
+            builder = builder.set_column_statistics_enabled(
+                ColumnPath::from(physical_name(column)),
+                EnabledStatistics::None,
+            );
+        }
+        Some(builder.build())
+    }
+}
+
+// ============================================================================
+// Row-count Rechunking
+// ============================================================================
+
+/// A RecordBatchReader that regroups its input into batches of exactly
+/// `rows` rows (the last batch may be shorter).
+///
+/// Combined with a matching `write_batch_size`, this lets every output file
+/// hold up to `rows` rows regardless of how the input stream was chunked.
+struct RechunkReader {
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    rows: usize,
+    buffer: Vec<RecordBatch>,
+    buffered_rows: usize,
+    done: bool,
+}
+
+impl RechunkReader {
+    fn new(input: Box<dyn RecordBatchReader + Send + 'static>, rows: usize) -> Self {
+        Self {
+            input,
+            rows,
+            buffer: Vec::new(),
+            buffered_rows: 0,
+            done: false,
+        }
+    }
+
+    /// Concatenate the buffered batches and split off the first `len` rows
+    fn take(&mut self, len: usize) -> std::result::Result<RecordBatch, ArrowError> {
+        let batch = arrow::compute::concat_batches(&self.input.schema(), &self.buffer)?;
+        self.buffer.clear();
+        if len < batch.num_rows() {
+            self.buffer.push(batch.slice(len, batch.num_rows() - len));
+        }
+        self.buffered_rows = batch.num_rows() - len;
+        Ok(batch.slice(0, len))
+    }
+}
+
+impl RecordBatchReader for RechunkReader {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Iterator for RechunkReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done && self.buffered_rows < self.rows {
+            match self.input.next() {
+                Some(Ok(batch)) => {
+                    self.buffered_rows += batch.num_rows();
+                    self.buffer.push(batch);
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => self.done = true,
+            }
+        }
+
+        if self.buffered_rows == 0 {
+            return None;
+        }
+        let len = self.rows.min(self.buffered_rows);
+        Some(self.take(len))
+    }
+}
+
+/// A RecordBatchReader that splits input batches longer than `rows` rows
+///
+/// Data frames from R usually arrive as a single batch; slicing them (which
+/// does not copy) lets every later stage of a write, such as casts and
+/// checks, work on `rows` rows at a time.
+struct SliceReader {
+    input: Box<dyn RecordBatchReader + Send + 'static>,
+    rows: usize,
+    current: Option<RecordBatch>,
+    offset: usize,
+}
+
+impl SliceReader {
+    fn new(input: Box<dyn RecordBatchReader + Send + 'static>, rows: usize) -> Self {
+        Self {
+            input,
+            rows,
+            current: None,
+            offset: 0,
+        }
+    }
+}
+
+impl RecordBatchReader for SliceReader {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Iterator for SliceReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(batch) = &self.current {
+                if self.offset < batch.num_rows() {
+                    let len = self.rows.min(batch.num_rows() - self.offset);
+                    let slice = batch.slice(self.offset, len);
+                    self.offset += len;
+                    return Some(Ok(slice));
+                }
+            }
+            match self.input.next()? {
+                Ok(batch) if batch.num_rows() <= self.rows => {
+                    self.current = None;
+                    return Some(Ok(batch));
+                }
+                Ok(batch) => {
+                    self.current = Some(batch);
+                    self.offset = 0;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
 /// Convert a RecordBatchReader into a LazyTableProvider for use with DataFusion
 pub(crate) fn to_lazy_table(
     source: Box<dyn RecordBatchReader + Send + 'static>,
@@ -248,10 +884,424 @@ pub(crate) fn to_lazy_table(
     let arrow_stream_batch_generator: Arc<RwLock<dyn LazyBatchGenerator>> =
         Arc::new(RwLock::new(ArrowStreamBatchGenerator::new(source)));
 
-    Ok(Arc::new(LazyTableProvider::try_new(
-        schema.clone(),
-        vec![arrow_stream_batch_generator],
-    )?))
+    Ok(Arc::new(LazyTableProvider::try_new(
+        schema.clone(),
+        vec![arrow_stream_batch_generator],
+    )?))
+}
+
+// ============================================================================
+// Arrow Input Conversion
+// ============================================================================
+
+/// Import an R object exposing `export_to_c()` for an ArrowArrayStream
+/// (an `arrow::RecordBatchReader`) without copying the underlying buffers.
+fn import_arrow_stream(robj: &Robj) -> Result<ArrowArrayStreamReader> {
+    let stream = FFI_ArrowArrayStream::empty();
+    let c_stream_ptr = &stream as *const FFI_ArrowArrayStream as usize;
+
+    robj.dollar("export_to_c")?
+        .as_function()
+        .ok_or_else(|| Error::from("export_to_c() method is not available"))?
+        .call(pairlist!(c_stream_ptr.to_string()))?;
+
+    ArrowArrayStreamReader::try_new(stream)
+        .map_err(|e| Error::from(format!("Failed to read Arrow stream: {:?}", e)))
+}
+
+/// Convert R data into a RecordBatchReader
+///
+/// Accepted inputs, in order of preference:
+/// - `nanoarrow_array_stream` and `arrow::RecordBatchReader`: imported through
+///   the C stream interface, zero-copy
+/// - `arrow::Table`: exposed as a RecordBatchReader on the R side, zero-copy
+/// - `arrow::RecordBatch`: imported through the C data interface, zero-copy
+/// - `data.frame`: converted to a stream with nanoarrow, or column by column in
+///   Rust when nanoarrow is not installed
+pub(crate) fn reader_from_robj(
+    robj: &Robj,
+) -> RaisedResult<Box<dyn RecordBatchReader + Send + 'static>> {
+    if robj.inherits("nanoarrow_array_stream") {
+        let reader = ArrowArrayStreamReader::from_arrow_robj(robj)
+            .map_err(|e| Error::from(format!("Failed to read Arrow stream: {:?}", e)))?;
+        return Ok(Box::new(reader));
+    }
+
+    if robj.inherits("RecordBatchReader") {
+        return Ok(Box::new(import_arrow_stream(robj)?));
+    }
+
+    if robj.inherits("Table") {
+        let reader = R!("arrow::as_record_batch_reader")?
+            .as_function()
+            .ok_or_else(|| Error::from("arrow::as_record_batch_reader() is not available"))?
+            .call(pairlist!(robj))?;
+        return Ok(Box::new(import_arrow_stream(&reader)?));
+    }
+
+    if robj.inherits("RecordBatch") {
+        let batch = RecordBatch::from_arrow_robj(robj)
+            .map_err(|e| Error::from(format!("Failed to read Arrow RecordBatch: {:?}", e)))?;
+        let schema = batch.schema();
+        return Ok(Box::new(RecordBatchIterator::new(vec![Ok(batch)], schema)));
+    }
+
+    if robj.inherits("data.frame") {
+        // Minimal installations may not have nanoarrow; fall back to reading
+        // the columns directly. The internal deltaR.native_data_frames option
+        // forces the fallback, so it can be tested where nanoarrow is installed
+        let forced = R!("isTRUE(getOption('deltaR.native_data_frames'))")?
+            .as_bool()
+            .unwrap_or(false);
+        let has_nanoarrow = R!("requireNamespace('nanoarrow', quietly = TRUE)")?
+            .as_bool()
+            .unwrap_or(false);
+        if forced || !has_nanoarrow {
+            return data_frame_to_reader(robj);
+        }
+
+        let stream = R!("nanoarrow::as_nanoarrow_array_stream")?
+            .as_function()
+            .ok_or_else(|| Error::from("nanoarrow::as_nanoarrow_array_stream() is not available"))?
+            .call(pairlist!(robj))?;
+        let reader = ArrowArrayStreamReader::from_arrow_robj(&stream)
+            .map_err(|e| Error::from(format!("Failed to read Arrow stream: {:?}", e)))?;
+        return Ok(Box::new(reader));
+    }
+
+    Err(Error::from(format!(
+        "Unsupported data of class '{}'. Expected a data.frame, nanoarrow_array_stream, \
+        or an arrow Table, RecordBatch or RecordBatchReader",
+        robj.class()
+            .map(|c| c.collect::<Vec<_>>().join("/"))
+            .unwrap_or_default()
+    ))
+    .into())
+}
+
+// ============================================================================
+// Native data.frame Conversion
+// ============================================================================
+
+/// Number of rows per RecordBatch produced from a data.frame
+const DATA_FRAME_BATCH_ROWS: usize = 65_536;
+
+/// Convert an R data.frame to a RecordBatchReader without nanoarrow or arrow
+///
+/// The columns are read through extendr and copied into Arrow arrays once;
+/// the resulting batch is then sliced (zero-copy) into chunks of
+/// `DATA_FRAME_BATCH_ROWS` rows. Supported column types are logical, integer,
+/// double, character, factor, Date and POSIXct.
+fn data_frame_to_reader(df: &Robj) -> RaisedResult<Box<dyn RecordBatchReader + Send + 'static>> {
+    use arrow::datatypes::{Field, Schema};
+
+    let columns = df
+        .as_list()
+        .ok_or_else(|| Error::from("Expected a data.frame"))?;
+
+    let mut fields = Vec::with_capacity(columns.len());
+    let mut arrays = Vec::with_capacity(columns.len());
+    for (name, column) in columns.iter() {
+        let array = r_column_to_array(name, &column)?;
+        fields.push(Field::new(name, array.data_type().clone(), true));
+        arrays.push(array);
+    }
+
+    if arrays.is_empty() {
+        return Err(Error::from("Cannot write a data.frame with no columns").into());
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), arrays)
+        .map_err(|e| Error::from(format!("Failed to build RecordBatch: {}", e)))?;
+
+    let num_rows = batch.num_rows();
+    let batches: Vec<_> = (0..num_rows.max(1))
+        .step_by(DATA_FRAME_BATCH_ROWS)
+        .map(|offset| Ok(batch.slice(offset, DATA_FRAME_BATCH_ROWS.min(num_rows - offset))))
+        .collect();
+
+    Ok(Box::new(RecordBatchIterator::new(batches, schema)))
+}
+
+/// Convert a single data.frame column to an Arrow array
+fn r_column_to_array(name: &str, column: &Robj) -> RaisedResult<arrow::array::ArrayRef> {
+    use arrow::array::{
+        BooleanArray, Date32Array, Float64Array, Int32Array, StringArray, TimestampMicrosecondArray,
+    };
+
+    let unsupported = || {
+        let class = column
+            .class()
+            .map(|c| c.collect::<Vec<_>>().join("/"))
+            .unwrap_or_else(|| format!("{:?}", column.rtype()));
+        column_error(
+            name,
+            format!(
+                "Column '{}' has unsupported type '{}'. Install the nanoarrow package to write it",
+                name, class
+            ),
+        )
+    };
+
+    // Factors are stored as integer codes into their levels
+    if column.inherits("factor") {
+        let levels: Vec<&str> = column.levels().ok_or_else(unsupported)?.collect();
+        let codes = column.as_integer_slice().ok_or_else(unsupported)?;
+        let array: StringArray = codes
+            .iter()
+            .map(|code| {
+                if code.is_na() {
+                    None
+                } else {
+                    levels.get((*code - 1) as usize).copied()
+                }
+            })
+            .collect();
+        return Ok(Arc::new(array));
+    }
+
+    // Dates are days since the epoch, stored as double (or occasionally integer)
+    if column.inherits("Date") {
+        let array: Date32Array = match column.rtype() {
+            Rtype::Integers => column
+                .as_integer_slice()
+                .ok_or_else(unsupported)?
+                .iter()
+                .map(|v| (!v.is_na()).then_some(*v))
+                .collect(),
+            _ => column
+                .as_real_slice()
+                .ok_or_else(unsupported)?
+                .iter()
+                .map(|v| v.is_finite().then(|| v.floor() as i32))
+                .collect(),
+        };
+        return Ok(Arc::new(array));
+    }
+
+    // POSIXct is seconds since the epoch; keep the time zone when one is set
+    if column.inherits("POSIXct") {
+        let tz = column
+            .get_attrib("tzone")
+            .and_then(|tz| tz.as_str().map(str::to_string))
+            .filter(|tz| !tz.is_empty())
+            .unwrap_or_else(|| "UTC".to_string());
+        let array: TimestampMicrosecondArray = column
+            .as_real_slice()
+            .ok_or_else(unsupported)?
+            .iter()
+            .map(|v| v.is_finite().then(|| (v * 1_000_000.0).round() as i64))
+            .collect();
+        return Ok(Arc::new(array.with_timezone(tz)));
+    }
+
+    // Any other classed vector (integer64, difftime, ...) needs nanoarrow
+    if column.class().is_some() {
+        return Err(unsupported());
+    }
+
+    match column.rtype() {
+        Rtype::Logicals => {
+            let array: BooleanArray = column
+                .as_logical_slice()
+                .ok_or_else(unsupported)?
+                .iter()
+                .map(|v| (!v.is_na()).then(|| v.is_true()))
+                .collect();
+            Ok(Arc::new(array))
+        }
+        Rtype::Integers => {
+            let array: Int32Array = column
+                .as_integer_slice()
+                .ok_or_else(unsupported)?
+                .iter()
+                .map(|v| (!v.is_na()).then_some(*v))
+                .collect();
+            Ok(Arc::new(array))
+        }
+        Rtype::Doubles => {
+            let array: Float64Array = column
+                .as_real_slice()
+                .ok_or_else(unsupported)?
+                .iter()
+                .map(|v| (!v.is_na()).then_some(*v))
+                .collect();
+            Ok(Arc::new(array))
+        }
+        Rtype::Strings => {
+            let array: StringArray = column
+                .as_str_iter()
+                .ok_or_else(unsupported)?
+                .map(|v| (!v.is_na()).then_some(v))
+                .collect();
+            Ok(Arc::new(array))
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+// ============================================================================
+// Dynamic Partition Overwrite
+// ============================================================================
+
+/// Build a `replace_where` predicate matching every partition present in `batches`
+///
+/// The partitions have to be known before the write starts, so the caller
+/// holds the whole input in memory as `batches`. Returns `None` when the
+/// batches contain no rows, in which case there is nothing to replace.
+fn dynamic_partition_predicate(
+    batches: &[RecordBatch],
+    partition_columns: &[String],
+) -> RaisedResult<Option<String>> {
+    use arrow::util::display::array_value_to_string;
+    use std::collections::BTreeSet;
+
+    let mut partitions: BTreeSet<Vec<Option<String>>> = BTreeSet::new();
+    let mut numeric = vec![false; partition_columns.len()];
+
+    for batch in batches {
+        let columns = partition_columns
+            .iter()
+            .map(|name| {
+                batch.column_by_name(name).ok_or_else(|| {
+                    column_error(
+                        name,
+                        format!("Partition column '{}' is missing from the data", name),
+                    )
+                })
+            })
+            .collect::<RaisedResult<Vec<_>>>()?;
+
+        for (i, column) in columns.iter().enumerate() {
+            numeric[i] = column.data_type().is_numeric()
+                || column.data_type().is_null()
+                || matches!(column.data_type(), arrow::datatypes::DataType::Boolean);
+        }
+
+        for row in 0..batch.num_rows() {
+            let values = columns
+                .iter()
+                .map(|column| {
+                    if column.is_null(row) {
+                        Ok(None)
+                    } else {
+                        array_value_to_string(column, row).map(Some)
+                    }
+                })
+                .collect::<std::result::Result<Vec<_>, ArrowError>>()
+                .map_err(|e| Error::from(format!("Failed to read partition values: {}", e)))?;
+            partitions.insert(values);
+        }
+    }
+
+    if partitions.is_empty() {
+        return Ok(None);
+    }
+
+    let literal = |value: &str, is_numeric: bool| {
+        if is_numeric {
+            value.to_string()
+        } else {
+            format!("'{}'", value.replace('\'', "''"))
+        }
+    };
+    let quoted: Vec<String> = partition_columns
+        .iter()
+        .map(|name| format!("\"{}\"", name.replace('"', "\"\"")))
+        .collect();
+
+    // A single partition column is matched with one IN list, which stays
+    // small however many partitions the data touches
+    if let [column] = quoted.as_slice() {
+        let values: Vec<String> = partitions
+            .iter()
+            .filter_map(|values| values[0].as_deref())
+            .map(|value| literal(value, numeric[0]))
+            .collect();
+        let mut conditions = Vec::new();
+        if !values.is_empty() {
+            conditions.push(format!("{} IN ({})", column, values.join(", ")));
+        }
+        if partitions.iter().any(|values| values[0].is_none()) {
+            conditions.push(format!("{} IS NULL", column));
+        }
+        return Ok(Some(conditions.join(" OR ")));
+    }
+
+    let predicate = partitions
+        .iter()
+        .map(|values| {
+            let conditions = quoted
+                .iter()
+                .zip(values)
+                .zip(&numeric)
+                .map(|((column, value), is_numeric)| match value {
+                    None => format!("{} IS NULL", column),
+                    Some(v) => format!("{} = {}", column, literal(v, *is_numeric)),
+                })
+                .collect::<Vec<_>>();
+            format!("({})", conditions.join(" AND "))
+        })
+        .collect::<Vec<_>>()
+        .join(" OR ");
+
+    Ok(Some(predicate))
+}
+
+// ============================================================================
+// Write Results
+// ============================================================================
+
+/// Summarize the commit created by a write
+///
+/// Metrics come from the commit's `operationMetrics` and the added files
+/// from its add actions. When the write did not commit a new version
+/// (e.g. `mode = "ignore"` on an existing table) all counts are zero.
+pub(crate) fn write_result(table: &DeltaTable, read_version: Option<i64>) -> Result<List> {
+    use deltalake::kernel::Action;
+
+    let num_files = table
+        .get_file_uris()
+        .map(|iter| iter.count() as i32)
+        .unwrap_or(0);
+
+    let actions = new_commit_actions(table, read_version)?;
+
+    let mut paths: Vec<String> = Vec::new();
+    let mut sizes: Vec<f64> = Vec::new();
+    let mut operation_metrics = None;
+    for action in &actions {
+        match action {
+            Action::Add(add) => {
+                paths.push(add.path.clone());
+                sizes.push(add.size as f64);
+            }
+            Action::CommitInfo(info) => operation_metrics = info.info.get("operationMetrics"),
+            _ => {}
+        }
+    }
+
+    let metric = |name: &str| -> f64 {
+        operation_metrics
+            .and_then(|m| m.get(name))
+            .and_then(|v| v.as_f64())
+            .unwrap_or(0.0)
+    };
+    let num_added_bytes: f64 = sizes.iter().sum();
+
+    let metrics = list!(
+        num_files = num_files,
+        num_added_rows = metric("num_added_rows"),
+        num_added_files = paths.len() as i32,
+        num_removed_files = metric("num_removed_files") as i32,
+        num_partitions = metric("num_partitions") as i32,
+        num_added_bytes = num_added_bytes,
+        execution_time_ms = metric("execution_time_ms"),
+        add_files = data_frame!(path = paths, size = sizes)
+    );
+
+    operation_result(table, &actions, "WRITE", metrics)
 }
 
 // ============================================================================
@@ -267,70 +1317,449 @@ pub(crate) fn to_lazy_table(
 /// - Memory-efficient streaming writes
 ///
 /// @param table_uri Path to the Delta table (will be created if it doesn't exist)
-/// @param stream Arrow data stream (nanoarrow_array_stream)
+/// @param stream Data to write: a nanoarrow_array_stream, arrow Table, RecordBatch,
+/// RecordBatchReader, or data.frame
 /// @param mode Save mode: "append", "overwrite", "error", or "ignore"
 /// @param partition_by Column names to partition by (optional)
 /// @param name Table name (optional, used when creating new table)
 /// @param description Table description (optional, used when creating new table)
 /// @param storage_options Storage backend options (optional)
 /// @param schema_mode How to handle schema evolution: "overwrite" or "merge" (optional)
+/// @param align_columns Whether to reorder the columns to the table's and fill
+/// missing nullable columns with nulls
+/// @param type_widening Whether an append widens the table's column types to
+/// wider types of the data, rewriting the table
+/// @param on_null What to do with rows holding nulls in the table's non-nullable
+/// columns: "fail" or "drop"
+/// @param on_invalid_names What to do with column names holding characters
+/// Delta does not allow: "fail" or "sanitize"
 /// @param target_file_size Target file size in bytes (optional)
+/// @param partition_overwrite_mode "static" (default) replaces the whole table on
+/// overwrite; "dynamic" only replaces the partitions present in the data
+/// @param max_rows_per_file Maximum number of rows per output file (optional)
+/// @param stats_truncate_length Most bytes kept of string min/max statistics (optional)
+/// @param skip_stats_columns Columns written without statistics (optional)
+/// @param target_partitions Number of DataFusion partitions used to execute the write (optional)
+/// @param encoding_parallelism Number of writers encoding Parquet files at once (optional)
+/// @param fast_append Whether plain appends skip DataFusion even with a single writer
+/// @param configuration Table configuration properties (optional, only for a new table)
+/// @param expectations Named list of SQL expressions every written row must satisfy (optional)
+/// @param on_violation What to do with rows violating an expectation: "fail", "drop" or "warn"
+/// @param app_transaction Application transaction to record, as list(app_id, version) (optional)
+/// @param execution Named list of DataFusion execution options (optional)
+/// @param profile Whether to return the time spent in each stage of the write
 #[extendr]
+#[allow(clippy::too_many_arguments)]
 pub fn delta_write(
     table_uri: &str,
     stream: Robj,
     mode: &str,
-    partition_by: Nullable<Vec<String>>,
+    mut partition_by: Nullable<Vec<String>>,
     name: Nullable<&str>,
     description: Nullable<&str>,
     storage_options: Nullable<List>,
     schema_mode: Nullable<&str>,
+    align_columns: bool,
+    type_widening: bool,
+    on_null: &str,
+    on_invalid_names: &str,
     target_file_size: Nullable<i64>,
-) -> Result<List> {
+    partition_overwrite_mode: Nullable<&str>,
+    max_rows_per_file: Nullable<i64>,
+    stats_truncate_length: Nullable<i32>,
+    skip_stats_columns: Nullable<Vec<String>>,
+    target_partitions: Nullable<i32>,
+    encoding_parallelism: Nullable<i32>,
+    fast_append: bool,
+    configuration: Nullable<List>,
+    expectations: Nullable<List>,
+    on_violation: &str,
+    app_transaction: Nullable<List>,
+    execution: Nullable<List>,
+    profile: bool,
+) -> OperationResult<List> {
+    let context = ErrorContext::new("WRITE", table_uri);
+    let mut profile = Profile::new(profile);
+
     // Parse save mode
     let save_mode = SaveMode::from_str(mode).map_err(|e| Error::from(e.to_string()))?;
+    let policy = ViolationPolicy::from_str(on_violation)?;
+    let null_policy = null_policy(on_null)?;
+    let sanitize_names = match on_invalid_names {
+        "fail" => false,
+        "sanitize" => true,
+        other => {
+            return Err(Error::from(format!(
+                "Invalid on_invalid_names: {}. Expected 'fail' or 'sanitize'",
+                other
+            ))
+            .into())
+        }
+    };
+
+    let dynamic_overwrite = match partition_overwrite_mode {
+        Nullable::NotNull("dynamic") => true,
+        Nullable::NotNull("static") | Nullable::Null => false,
+        Nullable::NotNull(other) => {
+            return Err(Error::from(format!(
+                "Invalid partition_overwrite_mode: {}. Expected 'static' or 'dynamic'",
+                other
+            ))
+            .into())
+        }
+    };
+    if dynamic_overwrite && save_mode != SaveMode::Overwrite {
+        return Err(Error::from(
+            "partition_overwrite_mode = 'dynamic' requires mode = 'overwrite'",
+        )
+        .into());
+    }
+    let encoding_parallelism = match encoding_parallelism {
+        Nullable::NotNull(writers) if writers <= 0 => {
+            return Err(Error::from("encoding_parallelism must be a positive number").into())
+        }
+        Nullable::NotNull(writers) => writers as usize,
+        Nullable::Null => 1,
+    };
+    let stats = StatsOptions::from_r(stats_truncate_length, skip_stats_columns)?;
+
+    // Convert R data to a RecordBatchReader, in batches of at most the
+    // execution batch size
+    let mut execution = ExecutionOptions::from_list(&execution)?;
+    if let Nullable::NotNull(partitions) = target_partitions {
+        if partitions <= 0 {
+            return Err(Error::from("target_partitions must be a positive number").into());
+        }
+        execution.target_partitions = Some(partitions as usize);
+    }
+    let mut boxed_reader = reader_from_robj(&stream)?;
+    if let Some(rows) = execution.batch_size {
+        boxed_reader = Box::new(SliceReader::new(boxed_reader, rows));
+    }
 
-    // Convert R Arrow stream to ArrowArrayStreamReader
-    let reader = ArrowArrayStreamReader::from_arrow_robj(&stream)
-        .map_err(|e| Error::from(format!("Failed to read Arrow stream: {:?}", e)))?;
+    // Check expectations before anything else sees the rows, so dropped rows
+    // do not count towards the partitions replaced by a dynamic overwrite
+    let mut summary = None;
+    if let Nullable::NotNull(ref expectations) = expectations {
+        let (reader, expectation_summary) = with_expectations(boxed_reader, expectations, policy)?;
+        boxed_reader = reader;
+        summary = Some(expectation_summary);
+    }
+    let mut boxed_reader = profile.time_reader("scan", boxed_reader);
 
     // Get the schema from the reader before we consume it
-    let batch_schema = reader.schema();
-
-    // Box the reader for use as a RecordBatchReader
-    let boxed_reader: Box<dyn RecordBatchReader + Send + 'static> = Box::new(reader);
+    let mut batch_schema = boxed_reader.schema();
 
     // Parse URL
     let url = path_to_url(table_uri).map_err(Error::from)?;
+    let options = match storage_options {
+        Nullable::NotNull(ref opts) => Some(parse_storage_options(opts)?),
+        Nullable::Null => None,
+    };
 
     // Open or create the table
-    let table = block_on(async {
-        match storage_options {
-            Nullable::NotNull(ref opts) => {
-                let options = parse_storage_options(opts);
-                DeltaTable::try_from_url_with_storage_options(url, options).await
+    let table = profile
+        .time("log_replay", || {
+            block_on(async {
+                match options.clone() {
+                    Some(options) => {
+                        DeltaTable::try_from_url_with_storage_options(url, options).await
+                    }
+                    None => DeltaTable::try_from_url(url).await,
+                }
+            })?
+        })
+        .map_err(|e| Error::from(e.to_string()))?;
+    context.set_version(table.version());
+
+    if save_mode == SaveMode::Overwrite {
+        check_append_only(&table, "an overwrite")?;
+    }
+
+    // delta-rs only applies the configuration when it creates the table
+    if table.state.is_some() && matches!(configuration, Nullable::NotNull(_)) {
+        return Err(Error::from(
+            "stats_columns and num_indexed_cols only apply when creating a table; \
+             use set_stats_columns() to change them on an existing table",
+        )
+        .into());
+    }
+
+    // Tables with column mapping are written by hand, which only supports
+    // plain appends and overwrites
+    let column_mapping = column_mapping_mode(&table) != ColumnMappingMode::None;
+    if column_mapping {
+        for (option, used) in [
+            ("schema_mode", matches!(schema_mode, Nullable::NotNull(_))),
+            ("partition_overwrite_mode = 'dynamic'", dynamic_overwrite),
+            (
+                "max_rows_per_file",
+                matches!(max_rows_per_file, Nullable::NotNull(_)),
+            ),
+        ] {
+            if used {
+                return Err(Error::from(format!(
+                    "{} is not supported for tables with column mapping",
+                    option
+                ))
+                .into());
             }
-            Nullable::Null => DeltaTable::try_from_url(url).await,
         }
-    })
-    .map_err(|e| Error::from(e.to_string()))?;
+    }
+
+    // Check the column names, which column mapping tables store as
+    // metadata and can therefore hold any character
+    let mut renamed_columns = None;
+    if !column_mapping {
+        let (reader, renamed) = sanitize_columns_reader(boxed_reader, sanitize_names)?;
+        boxed_reader = reader;
+        batch_schema = boxed_reader.schema();
+        if let Nullable::NotNull(ref mut cols) = partition_by {
+            for col in cols.iter_mut() {
+                if let Some((_, new_name)) = renamed.iter().find(|(name, _)| name == col) {
+                    *col = new_name.clone();
+                }
+            }
+        }
+        if sanitize_names {
+            let (names, new_names): (Vec<String>, Vec<String>) = renamed.into_iter().unzip();
+            renamed_columns = Some(list!(
+                renamed_columns = data_frame!(name = names, new_name = new_names)
+            ));
+        }
+    }
+
+    stats.check_columns(batch_schema.as_ref())?;
+
+    // Reorder the columns to the table's and null-fill the missing ones
+    if align_columns {
+        if let Some(state) = table.state.as_ref() {
+            boxed_reader =
+                align_columns_reader(boxed_reader, state.snapshot().arrow_schema().as_ref())?;
+            batch_schema = boxed_reader.schema();
+        }
+    }
+
+    // Check the table's non-nullable columns for nulls before they reach the
+    // Parquet encoder
+    let mut null_summary = None;
+    if let Some(state) = table.state.as_ref() {
+        let (reader, summary) = with_null_checks(
+            boxed_reader,
+            state.snapshot().arrow_schema().as_ref(),
+            null_policy,
+        );
+        boxed_reader = reader;
+        null_summary = summary;
+    }
+
+    // Check the rows against the table's constraints, so a violation names
+    // the constraint and the rows breaking it
+    let (reader, constraint_report) = with_constraint_checks(boxed_reader, &table)?;
+    boxed_reader = reader;
+    let write_failed = |e: deltalake::DeltaTableError| {
+        let message = format!("Write failed: {}", e);
+        match constraint_report
+            .as_ref()
+            .and_then(|report| report.violations())
+        {
+            Some(violations) => violations,
+            None => match null_summary.as_ref().and_then(|summary| summary.failure()) {
+                Some(failure) => failure.with_message(message),
+                None => RaisedError::caused_by(message, &e),
+            },
+        }
+    };
+
+    // Widen the table's columns to wider types of the data: the table is
+    // rewritten with the new types in the same commit as the appended rows
+    let widened = match table.state.as_ref() {
+        Some(state) if type_widening && save_mode == SaveMode::Append => widened_schema(
+            state.snapshot().arrow_schema().as_ref(),
+            batch_schema.as_ref(),
+            matches!(schema_mode, Nullable::NotNull("merge")),
+        )?,
+        _ => None,
+    };
+    if widened.is_some() {
+        check_append_only(&table, "widening column types")?;
+        if column_mapping {
+            return Err(Error::from(
+                "Widening column types is not supported for tables with column mapping",
+            )
+            .into());
+        }
+    }
 
     // Create WriteBuilder using the pattern from Python delta-rs
     let mut write_builder = WriteBuilder::new(
         table.log_store(),
         table.state.as_ref().map(|s| s.snapshot().clone()),
     )
-    .with_save_mode(save_mode);
+    .with_save_mode(save_mode)
+    .with_commit_properties(commit_properties());
+
+    // Streaming writers record the batch they commit as an application
+    // transaction; a batch at or below the recorded version was already
+    // written (e.g. before a restart) and is skipped
+    let mut app_metrics = None;
+    let mut app_txn = None;
+    if let Some(app) = AppTransaction::from_r(&table, &app_transaction)? {
+        if app.skipped {
+            let result = write_result(&table, table.version())?;
+            return Ok(with_profile(
+                append_metrics(result, app.metrics())?,
+                profile.finish(),
+            ));
+        }
+        write_builder = write_builder.with_commit_properties(app.commit_properties());
+        app_metrics = Some(app.metrics());
+        app_txn = Some(app.txn);
+    }
+
+    // Plain appends and overwrites of existing tables with the table's
+    // columns are written directly, with several writers or to skip
+    // planning a small append, unless the write sets up its DataFusion
+    // session
+    let same_layout = match table.state.as_ref() {
+        Some(state) => {
+            let columns = |schema: &ArrowSchema| {
+                schema
+                    .fields()
+                    .iter()
+                    .map(|field| field.name().clone())
+                    .collect::<Vec<_>>()
+            };
+            let partitioning = match &partition_by {
+                Nullable::NotNull(columns) => state.metadata().partition_columns() == columns,
+                Nullable::Null => true,
+            };
+            partitioning
+                && columns(batch_schema.as_ref())
+                    == columns(state.snapshot().arrow_schema().as_ref())
+        }
+        None => false,
+    };
+    let direct = (encoding_parallelism > 1 || (fast_append && save_mode == SaveMode::Append))
+        && matches!(save_mode, SaveMode::Append | SaveMode::Overwrite)
+        && matches!(schema_mode, Nullable::Null)
+        && !execution.needs_session()
+        && !dynamic_overwrite
+        && matches!(max_rows_per_file, Nullable::Null)
+        && widened.is_none()
+        && same_layout
+        && supports_direct_write(&table);
+
+    if column_mapping || direct {
+        let target_file_size = match target_file_size {
+            Nullable::NotNull(size) if size > 0 => Some(size as usize),
+            _ => None,
+        };
+        let read_version = table.version();
+        let table = profile
+            .time("execute", || {
+                if column_mapping {
+                    write_column_mapped(
+                        table,
+                        boxed_reader,
+                        save_mode,
+                        target_file_size,
+                        &stats,
+                        options,
+                        app_txn,
+                    )
+                } else {
+                    write_direct(
+                        table,
+                        boxed_reader,
+                        save_mode,
+                        target_file_size,
+                        stats.writer_properties(str::to_string),
+                        encoding_parallelism,
+                        execution.max_in_flight_batches,
+                        app_txn,
+                    )
+                }
+            })
+            .map_err(write_failed)?;
+        let mut result = write_result(&table, read_version)?;
+        let metrics = [
+            summary.map(|summary| summary.to_metrics()),
+            app_metrics,
+            null_summary.map(|summary| summary.to_metrics()),
+            renamed_columns,
+        ];
+        for metrics in metrics.into_iter().flatten() {
+            result = append_metrics(result, metrics)?;
+        }
+        return Ok(with_profile(result, profile.finish()));
+    }
+
+    // Dynamic overwrite: only replace the partitions that appear in the data.
+    // The whole input is held in memory to find those partitions up front.
+    if dynamic_overwrite {
+        if let Some(state) = table.state.as_ref() {
+            let partition_columns = state.metadata().partition_columns().to_vec();
+            if partition_columns.is_empty() {
+                return Err(Error::from(
+                    "partition_overwrite_mode = 'dynamic' requires a partitioned table",
+                )
+                .into());
+            }
+
+            let batches = boxed_reader
+                .by_ref()
+                .collect::<std::result::Result<Vec<_>, ArrowError>>()
+                .map_err(|e| Error::from(format!("Failed to read Arrow stream: {}", e)))?;
+
+            match dynamic_partition_predicate(&batches, &partition_columns)? {
+                Some(predicate) => write_builder = write_builder.with_replace_where(predicate),
+                None => write_builder = write_builder.with_save_mode(SaveMode::Append),
+            }
+
+            boxed_reader = Box::new(RecordBatchIterator::new(
+                batches.into_iter().map(Ok),
+                batch_schema.clone(),
+            ));
+        }
+    }
+
+    // Cap rows per file: the writer cuts a new file after every write batch
+    // once the (tiny) target size is reached, so each input batch becomes one file
+    let mut reader = maybe_lazy_cast_reader(boxed_reader, batch_schema.clone());
+    if let Nullable::NotNull(rows) = max_rows_per_file {
+        if rows <= 0 {
+            return Err(Error::from("max_rows_per_file must be a positive number").into());
+        }
+        if matches!(target_file_size, Nullable::NotNull(_)) {
+            return Err(
+                Error::from("max_rows_per_file cannot be combined with target_file_size").into(),
+            );
+        }
+        reader = Box::new(RechunkReader::new(reader, rows as usize));
+        write_builder = write_builder
+            .with_write_batch_size(rows as usize)
+            .with_target_file_size(1);
+    }
+
+    // Set the execution runtime if provided
+    if !execution.is_default() {
+        let ctx = execution.session_context()?;
+        write_builder = write_builder.with_session_state(Arc::new(ctx.state()));
+    }
 
-    // Apply lazy schema casting if needed and convert to LazyTableProvider
-    let table_provider = to_lazy_table(maybe_lazy_cast_reader(boxed_reader, batch_schema.clone()))
+    // Convert to LazyTableProvider
+    let table_provider = to_lazy_table(reader)
         .map_err(|e| Error::from(format!("Failed to create table provider: {}", e)))?;
 
     // Build a LogicalPlan from the table provider
-    let plan = LogicalPlanBuilder::scan("source", provider_as_source(table_provider), None)
+    let mut plan = LogicalPlanBuilder::scan("source", provider_as_source(table_provider), None)
         .map_err(|e| Error::from(format!("Failed to create logical plan: {}", e)))?
         .build()
         .map_err(|e| Error::from(format!("Failed to build logical plan: {}", e)))?;
+    if let Some((ref schema, _)) = widened {
+        plan = widening_plan(&table, plan, schema)?;
+    }
 
     // Set the input execution plan
     write_builder = write_builder.with_input_execution_plan(Arc::new(plan));
@@ -350,6 +1779,19 @@ pub fn delta_write(
         write_builder = write_builder.with_description(desc);
     }
 
+    // Set configuration if provided (as raw key-value pairs)
+    if let Nullable::NotNull(ref config) = configuration {
+        let config_map: HashMap<String, Option<String>> = config
+            .iter()
+            .filter_map(|(key, value)| {
+                value
+                    .as_str()
+                    .map(|v| (key.to_string(), Some(v.to_string())))
+            })
+            .collect();
+        write_builder = write_builder.with_configuration(config_map);
+    }
+
     // Set schema mode if provided
     if let Nullable::NotNull(sm) = schema_mode {
         let schema_mode_enum = deltalake::operations::write::SchemaMode::from_str(sm)
@@ -357,30 +1799,370 @@ pub fn delta_write(
         write_builder = write_builder.with_schema_mode(schema_mode_enum);
     }
 
+    // Rewrite the table with its widened schema and partitioning
+    if widened.is_some() {
+        if let Some(state) = table.state.as_ref() {
+            write_builder = write_builder
+                .with_save_mode(SaveMode::Overwrite)
+                .with_schema_mode(deltalake::operations::write::SchemaMode::Overwrite)
+                .with_partition_columns(state.metadata().partition_columns().to_vec());
+        }
+    }
+
     // Set target file size if provided
     if let Nullable::NotNull(size) = target_file_size {
         if size > 0 {
             write_builder = write_builder.with_target_file_size(size as usize);
         }
     }
+    if let Some(properties) = stats.writer_properties(str::to_string) {
+        write_builder = write_builder.with_writer_properties(properties);
+    }
 
     // Execute the write using DataFusion's async execution
-    let table = block_on(async { write_builder.await })
-        .map_err(|e| Error::from(format!("Write failed: {}", e)))?;
+    let read_version = table.version();
+    let table = profile
+        .time("execute", || block_on(async { write_builder.await })?)
+        .map_err(write_failed)?;
+
+    // Return version, file count and commit metrics
+    let mut result = write_result(&table, read_version)?;
+    if let Some(summary) = summary {
+        result = append_metrics(result, summary.to_metrics())?;
+    }
+    if let Some(metrics) = app_metrics {
+        result = append_metrics(result, metrics)?;
+    }
+    if let Some(summary) = null_summary {
+        result = append_metrics(result, summary.to_metrics())?;
+    }
+    if let Some((_, columns)) = widened {
+        result = append_metrics(result, list!(widened_columns = columns))?;
+    }
+    if let Some(metrics) = renamed_columns {
+        result = append_metrics(result, metrics)?;
+    }
+    Ok(with_profile(result, profile.finish()))
+}
 
-    // Return result info as a list
-    let version = table.version().unwrap_or(-1);
-    let num_files = table
-        .get_file_uris()
-        .map(|iter| iter.count() as i32)
-        .unwrap_or(0);
+// ============================================================================
+// File Ingestion
+// ============================================================================
+
+/// Write Parquet or CSV files into a Delta Lake table
+///
+/// The files are scanned by DataFusion and streamed straight into the
+/// WriteBuilder, so the data never passes through R memory.
+///
+/// @param table_uri Path to the Delta table (will be created if it doesn't exist)
+/// @param paths Paths or URIs of the files to ingest
+/// @param format File format: "parquet" or "csv"
+/// @param mode Save mode: "append", "overwrite", "error", or "ignore"
+/// @param partition_by Column names to partition by (optional)
+/// @param storage_options Storage backend options (optional)
+/// @param execution Named list of DataFusion execution options (optional)
+#[extendr]
+pub fn delta_write_files(
+    table_uri: &str,
+    paths: Vec<String>,
+    format: &str,
+    mode: &str,
+    partition_by: Nullable<Vec<String>>,
+    storage_options: Nullable<List>,
+    execution: Nullable<List>,
+) -> OperationResult<List> {
+    let context = ErrorContext::new("WRITE", table_uri);
+    // Parse save mode
+    let save_mode = SaveMode::from_str(mode).map_err(|e| Error::from(e.to_string()))?;
+
+    if paths.is_empty() {
+        return Err(Error::from("At least one file path must be provided").into());
+    }
+
+    let options: HashMap<String, String> = match storage_options {
+        Nullable::NotNull(ref opts) => parse_storage_options(opts)?,
+        Nullable::Null => HashMap::new(),
+    };
+
+    // Resolve every input path to a URL DataFusion can list
+    let file_urls: Vec<url::Url> = paths
+        .iter()
+        .map(|p| path_to_url(p))
+        .collect::<std::result::Result<_, _>>()
+        .map_err(Error::from)?;
+
+    // Parse URL
+    let url = path_to_url(table_uri).map_err(Error::from)?;
+
+    // Open or create the table
+    let table = block_on(async {
+        DeltaTable::try_from_url_with_storage_options(url, options.clone()).await
+    })?
+    .map_err(|e| Error::from(e.to_string()))?;
+    context.set_version(table.version());
+    if save_mode == SaveMode::Overwrite {
+        check_append_only(&table, "an overwrite")?;
+    }
+
+    // Register an object store for each remote bucket/container the files live in
+    let ctx = ExecutionOptions::from_list(&execution)?.session_context()?;
+    for file_url in file_urls.iter().filter(|u| u.scheme() != "file") {
+        let store = store_for(file_url, options.clone())
+            .map_err(|e| Error::from(format!("Failed to create object store: {}", e)))?;
+        let root = url::Url::parse(&format!("{}://{}", file_url.scheme(), file_url.authority()))
+            .map_err(|e| Error::from(format!("Invalid file URI: {}", e)))?;
+        ctx.register_object_store(&root, store);
+    }
+
+    let file_uris: Vec<String> = file_urls.iter().map(|u| u.to_string()).collect();
+
+    // Scan the files lazily; nothing is read until the write executes
+    let source = block_on(async {
+        match format {
+            "parquet" => {
+                ctx.read_parquet(file_uris, ParquetReadOptions::default())
+                    .await
+            }
+            "csv" => ctx.read_csv(file_uris, CsvReadOptions::new()).await,
+            other => Err(
+                deltalake::datafusion::error::DataFusionError::Configuration(format!(
+                    "Unsupported file format: {}. Expected 'parquet' or 'csv'",
+                    other
+                )),
+            ),
+        }
+    })?
+    .map_err(|e| Error::from(format!("Failed to scan input files: {}", e)))?;
+
+    let mut write_builder = WriteBuilder::new(
+        table.log_store(),
+        table.state.as_ref().map(|s| s.snapshot().clone()),
+    )
+    .with_save_mode(save_mode)
+    .with_commit_properties(commit_properties())
+    .with_input_execution_plan(Arc::new(source.logical_plan().clone()))
+    .with_session_state(Arc::new(ctx.state()));
+
+    // Set partition columns if provided
+    if let Nullable::NotNull(cols) = partition_by {
+        write_builder = write_builder.with_partition_columns(cols);
+    }
+
+    // Execute the write using DataFusion's async execution
+    let read_version = table.version();
+    let table = block_on(async { write_builder.await })?.map_err(|e| {
+        let message = e.to_string();
+        match violation_error(&table, &message, "rows of the files") {
+            Some(violations) => violations,
+            None => RaisedError::caused_by(format!("Write failed: {}", message), &e),
+        }
+    })?;
+
+    // Return version, file count and commit metrics
+    Ok(write_result(&table, read_version)?)
+}
+
+// ============================================================================
+// Table-to-Table Appends
+// ============================================================================
+
+/// How the rows of an appended table are projected onto the target's columns
+///
+/// Every column of `reference` (the target's schema, or the first source's
+/// for a new table) is taken from the source cast to its type, or filled
+/// with nulls when the source lacks a nullable column. Source columns not in
+/// `reference` are an error; with `schema_mode = 'merge'` the caller adds
+/// them to `reference` first. A source column whose Delta type differs from
+/// the target's must widen into it, so no value is cast with loss.
+fn append_projection(reference: &[FieldRef], source: &ArrowSchema) -> RaisedResult<Vec<Expr>> {
+    let mut exprs = Vec::new();
+    for field in reference {
+        let value = match source.field_with_name(field.name()) {
+            Ok(source_field) => {
+                let (from, to) = (source_field.data_type(), field.data_type());
+                let same_type = from == to
+                    || matches!(
+                        (arrow_type_to_kernel(from), arrow_type_to_kernel(to)),
+                        (Ok(a), Ok(b)) if a == b
+                    );
+                if !same_type && !widens_to(from, to) {
+                    return Err(column_error(
+                        field.name(),
+                        format!(
+                            "Column '{}' has type {} in the source but {} in the target",
+                            field.name(),
+                            from,
+                            to
+                        ),
+                    ));
+                }
+                Expr::Column(Column::from_name(field.name()))
+            }
+            Err(_) if field.is_nullable() => lit(ScalarValue::Null),
+            Err(_) => {
+                return Err(Error::from(format!(
+                    "Non-nullable column '{}' of the target is missing from the source",
+                    field.name()
+                ))
+                .into())
+            }
+        };
+        exprs.push(cast(value, field.data_type().clone()).alias(field.name()));
+    }
+    if let Some(extra) = source
+        .fields()
+        .iter()
+        .find(|field| !reference.iter().any(|r| r.name() == field.name()))
+    {
+        return Err(column_error(
+            extra.name(),
+            format!(
+                "Column '{}' is not in the target; use schema_mode = 'merge' to add it",
+                extra.name()
+            ),
+        ));
+    }
+    Ok(exprs)
+}
+
+/// Append the rows of Delta tables to another Delta table
+///
+/// The sources are scanned by DataFusion and streamed straight into the
+/// WriteBuilder, so the rows never pass through R memory. Their columns are
+/// checked against the target's before anything is written.
+///
+/// @param sources List of table URIs or DeltaTableInternal handles to append
+/// @param table_uri Path to the target Delta table (will be created if it doesn't exist)
+/// @param predicate SQL filter expression selecting the rows of the sources (optional)
+/// @param columns Columns of the sources to append (optional)
+/// @param schema_mode "merge" to add source columns missing from the target (optional)
+/// @param storage_options Storage backend options (optional)
+/// @param execution Named list of DataFusion execution options (optional)
+#[extendr]
+pub fn delta_append_tables(
+    sources: List,
+    table_uri: &str,
+    predicate: Nullable<&str>,
+    columns: Nullable<Vec<String>>,
+    schema_mode: Nullable<&str>,
+    storage_options: Nullable<List>,
+    execution: Nullable<List>,
+) -> OperationResult<List> {
+    let context = ErrorContext::new("WRITE", table_uri);
+    let merge = match schema_mode {
+        Nullable::NotNull("merge") => true,
+        Nullable::Null => false,
+        Nullable::NotNull(other) => {
+            return Err(
+                Error::from(format!("Invalid schema_mode: {}. Expected 'merge'", other)).into(),
+            )
+        }
+    };
+    if sources.is_empty() {
+        return Err(Error::from("At least one source table must be provided").into());
+    }
+
+    // Scan every source lazily; nothing is read until the write executes
+    let ctx = ExecutionOptions::from_list(&execution)?.session_context()?;
+    let mut scans = Vec::new();
+    let mut partition_columns = Vec::new();
+    for (i, (_, source)) in sources.iter().enumerate() {
+        let source = table_from_robj(&format!("source {}", i + 1), &source, &storage_options)?;
+        if i == 0 {
+            if let Some(state) = source.state.as_ref() {
+                partition_columns = state.metadata().partition_columns().to_vec();
+            }
+        }
+        let df = table_dataframe(&ctx, source)?;
+        scans.push(project_and_filter(df, &columns, &predicate)?);
+    }
+
+    // Open or create the target
+    let options: HashMap<String, String> = match storage_options {
+        Nullable::NotNull(ref opts) => parse_storage_options(opts)?,
+        Nullable::Null => HashMap::new(),
+    };
+    let url = path_to_url(table_uri).map_err(Error::from)?;
+    let table =
+        block_on(async { DeltaTable::try_from_url_with_storage_options(url, options).await })?
+            .map_err(|e| Error::from(e.to_string()))?;
+    context.set_version(table.version());
+
+    // Project every source onto the target's columns, followed by the
+    // columns added with schema_mode = 'merge'
+    let mut reference: Vec<FieldRef> = match table.state.as_ref() {
+        Some(state) => state
+            .snapshot()
+            .arrow_schema()
+            .fields()
+            .iter()
+            .cloned()
+            .collect(),
+        None => scans[0]
+            .schema()
+            .as_arrow()
+            .fields()
+            .iter()
+            .cloned()
+            .collect(),
+    };
+    if merge {
+        for scan in &scans {
+            for field in scan.schema().as_arrow().fields() {
+                if !reference.iter().any(|r| r.name() == field.name()) {
+                    reference.push(Arc::new(field.as_ref().clone().with_nullable(true)));
+                }
+            }
+        }
+    }
+    let mut source: Option<LogicalPlanBuilder> = None;
+    for (i, scan) in scans.into_iter().enumerate() {
+        let projection = append_projection(&reference, scan.schema().as_arrow())
+            .map_err(|e| Error::from(format!("Source {}: {}", i + 1, e)))?;
+        let plan = scan
+            .select(projection)
+            .map_err(|e| Error::from(format!("Failed to build logical plan: {}", e)))?
+            .into_unoptimized_plan();
+        source = Some(
+            match source {
+                Some(builder) => builder.union(plan),
+                None => Ok(LogicalPlanBuilder::from(plan)),
+            }
+            .map_err(|e| Error::from(format!("Failed to build logical plan: {}", e)))?,
+        );
+    }
+    let plan = source
+        .map(|builder| builder.build())
+        .transpose()
+        .map_err(|e| Error::from(format!("Failed to build logical plan: {}", e)))?
+        .ok_or_else(|| Error::from("At least one source table must be provided"))?;
+
+    let mut write_builder = WriteBuilder::new(
+        table.log_store(),
+        table.state.as_ref().map(|s| s.snapshot().clone()),
+    )
+    .with_save_mode(SaveMode::Append)
+    .with_commit_properties(commit_properties())
+    .with_input_execution_plan(Arc::new(plan))
+    .with_session_state(Arc::new(ctx.state()));
+    if table.state.is_none() && !partition_columns.is_empty() {
+        write_builder = write_builder.with_partition_columns(partition_columns);
+    }
+    if merge {
+        write_builder =
+            write_builder.with_schema_mode(deltalake::operations::write::SchemaMode::Merge);
+    }
 
-    let mut result = List::new(2);
-    result.set_elt(0, version.into_robj())?;
-    result.set_elt(1, num_files.into_robj())?;
-    result.set_names(["version", "num_files"])?;
+    let read_version = table.version();
+    let table = block_on(async { write_builder.await })?.map_err(|e| {
+        let message = e.to_string();
+        match violation_error(&table, &message, "rows of the sources") {
+            Some(violations) => violations,
+            None => RaisedError::caused_by(format!("Write failed: {}", message), &e),
+        }
+    })?;
 
-    Ok(result)
+    Ok(write_result(&table, read_version)?)
 }
 
 // ============================================================================
@@ -405,12 +2187,12 @@ pub fn delta_create(
     description: Nullable<&str>,
     storage_options: Nullable<List>,
     configuration: Nullable<List>,
-) -> Result<i64> {
+) -> OperationResult<i64> {
+    let _context = ErrorContext::new("CREATE TABLE", table_uri);
     use arrow::datatypes::Schema as ArrowSchema;
     use arrow_extendr::from::FromArrowRobj;
     use deltalake::kernel::StructField;
     use deltalake::operations::create::CreateBuilder;
-    use std::collections::HashMap;
 
     // Convert R Arrow schema to Rust Arrow schema
     let arrow_schema = ArrowSchema::from_arrow_robj(&schema)
@@ -421,7 +2203,7 @@ pub fn delta_create(
 
     // Parse storage options
     let opts: HashMap<String, String> = match storage_options {
-        Nullable::NotNull(ref opts) => parse_storage_options(opts),
+        Nullable::NotNull(ref opts) => parse_storage_options(opts)?,
         Nullable::Null => HashMap::new(),
     };
 
@@ -439,7 +2221,25 @@ pub fn delta_create(
         })
         .collect();
 
-    let columns = columns?;
+    let mut columns = columns.map_err(Error::from)?;
+
+    // Table properties as raw key-value pairs
+    let mut config_map: HashMap<String, Option<String>> = match configuration {
+        Nullable::NotNull(ref config) => config
+            .iter()
+            .filter_map(|(key, value)| {
+                value
+                    .as_str()
+                    .map(|v| (key.to_string(), Some(v.to_string())))
+            })
+            .collect(),
+        Nullable::Null => HashMap::new(),
+    };
+
+    // Column mapping needs the physical name and id of every column
+    if requests_column_mapping(&config_map) {
+        columns = with_column_mapping(columns, &mut config_map);
+    }
 
     // Build the create operation
     let mut create_builder = CreateBuilder::new()
@@ -466,21 +2266,13 @@ pub fn delta_create(
         create_builder = create_builder.with_comment(desc);
     }
 
-    // Set configuration if provided (as raw key-value pairs)
-    if let Nullable::NotNull(ref config) = configuration {
-        let config_map: HashMap<String, Option<String>> = config
-            .iter()
-            .filter_map(|(key, value)| {
-                value
-                    .as_str()
-                    .map(|v| (key.to_string(), Some(v.to_string())))
-            })
-            .collect();
+    // Set configuration if provided
+    if !config_map.is_empty() {
         create_builder = create_builder.with_configuration(config_map);
     }
 
     // Execute the create
-    let table = block_on(async { create_builder.await })
+    let table = block_on(async { create_builder.await })?
         .map_err(|e| Error::from(format!("Create failed: {}", e)))?;
 
     Ok(table.version().unwrap_or(0))
@@ -735,5 +2527,7 @@ fn arrow_type_to_kernel(
 extendr_module! {
     mod write;
     fn delta_write;
+    fn delta_write_files;
+    fn delta_append_tables;
     fn delta_create;
 }
@@ -0,0 +1,120 @@
+//! Buffers of persistent writers
+//!
+//! A `DeltaWriter` in R collects the rows of many small writes and commits
+//! them together once a row, byte or age threshold is reached. The rows are
+//! held here, as Arrow batches in Rust memory, until they are handed back
+//! to R as a single Arrow stream for the commit. The buffer is only cleared
+//! once that commit succeeded, so a failed commit can be retried.
+
+use std::time::Instant;
+
+use arrow::array::{RecordBatch, RecordBatchIterator, RecordBatchReader};
+use arrow::datatypes::SchemaRef;
+use arrow_extendr::to::IntoArrowRobj;
+use extendr_api::prelude::*;
+
+use crate::write::reader_from_robj;
+
+/// Rows buffered by a DeltaWriter since its last commit
+#[extendr]
+pub struct DeltaWriterBufferInternal {
+    batches: Vec<RecordBatch>,
+    schema: Option<SchemaRef>,
+    num_rows: usize,
+    num_bytes: usize,
+    /// When the oldest buffered row was added
+    since: Option<Instant>,
+}
+
+#[extendr]
+impl DeltaWriterBufferInternal {
+    /// Create an empty buffer
+    fn new() -> Self {
+        DeltaWriterBufferInternal {
+            batches: Vec::new(),
+            schema: None,
+            num_rows: 0,
+            num_bytes: 0,
+            since: None,
+        }
+    }
+
+    /// Add the rows of a data.frame or Arrow stream to the buffer
+    ///
+    /// All buffered rows are committed as one stream, so they must share a
+    /// schema: data with other columns or types is refused until the buffer
+    /// is cleared.
+    fn push(&mut self, data: Robj) -> Result<()> {
+        let reader = reader_from_robj(&data)?;
+        let schema = reader.schema();
+        if let Some(buffered) = &self.schema {
+            if buffered.fields() != schema.fields() {
+                return Err(Error::from(
+                    "The data has a different schema than the rows buffered since the last \
+                     commit; call flush() before changing the columns or their types",
+                ));
+            }
+        }
+        let batches = reader
+            .collect::<std::result::Result<Vec<RecordBatch>, _>>()
+            .map_err(|e| Error::from(format!("Failed to read data: {}", e)))?;
+
+        for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+            self.num_rows += batch.num_rows();
+            self.num_bytes += batch.get_array_memory_size();
+            self.batches.push(batch);
+        }
+        if self.schema.is_none() {
+            self.schema = Some(schema);
+        }
+        if self.since.is_none() && self.num_rows > 0 {
+            self.since = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Number of buffered rows
+    fn num_rows(&self) -> f64 {
+        self.num_rows as f64
+    }
+
+    /// Arrow memory size of the buffered rows, in bytes
+    fn num_bytes(&self) -> f64 {
+        self.num_bytes as f64
+    }
+
+    /// Seconds since the oldest buffered row was added (0 when empty)
+    fn age(&self) -> f64 {
+        self.since
+            .map_or(0.0, |since| since.elapsed().as_secs_f64())
+    }
+
+    /// Get the buffered rows as an Arrow stream, leaving them in the buffer
+    fn stream(&self) -> Result<Robj> {
+        let schema = self
+            .schema
+            .clone()
+            .ok_or_else(|| Error::from("The buffer holds no rows"))?;
+        let reader: Box<dyn RecordBatchReader + Send> = Box::new(RecordBatchIterator::new(
+            self.batches.clone().into_iter().map(Ok),
+            schema,
+        ));
+        reader
+            .into_arrow_robj()
+            .map_err(|e| Error::from(format!("Failed to export Arrow stream: {}", e)))
+    }
+
+    /// Empty the buffer, after its rows were committed
+    fn clear(&mut self) {
+        self.batches.clear();
+        self.schema = None;
+        self.num_rows = 0;
+        self.num_bytes = 0;
+        self.since = None;
+    }
+}
+
+extendr_module! {
+    mod writer;
+    impl DeltaWriterBufferInternal;
+}